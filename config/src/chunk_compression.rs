@@ -0,0 +1,17 @@
+/// How chunk files are compressed
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChunkCompression {
+    None,
+    Gzip,
+    /// Compresses about as well as gzip but is a lot faster
+    Zstd,
+    /// The fastest, at the cost of larger files
+    Lz4,
+}
+
+impl Default for ChunkCompression {
+    fn default() -> Self {
+        ChunkCompression::Gzip
+    }
+}
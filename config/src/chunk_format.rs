@@ -0,0 +1,17 @@
+/// How the documents in a chunk file are serialized
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChunkFormat {
+    /// Readable with any tool, but the slowest to parse
+    Json,
+    /// A compact binary format, much faster to parse than json
+    MessagePack,
+    /// A compact binary format, like MessagePack but standardized
+    Cbor,
+}
+
+impl Default for ChunkFormat {
+    fn default() -> Self {
+        ChunkFormat::Json
+    }
+}
@@ -1,3 +1,10 @@
+use crate::{
+    ChunkCompression,
+    ChunkFormat,
+    EncryptionCipher,
+    StoreKind,
+    WalFsync,
+};
 use colored::*;
 use config::Config as CConfig;
 use failure::Error;
@@ -11,11 +18,86 @@ use std::{
 #[serde(rename_all = "camelCase")]
 pub struct Config {
     pub data_folder: String,
+    /// Where the data in the data folder is persisted
+    pub store: StoreKind,
     pub port: u16,
     pub host: String,
     pub log_level: String,
     #[serde(with = "serde_humanize_rs")]
     pub save_interval: Duration,
+    /// How many times a webhook delivery is attempted before it is moved to
+    /// the dead letter log
+    pub webhook_max_attempts: u32,
+    /// The delay before the first retry of a webhook delivery, it is doubled
+    /// for every following attempt
+    #[serde(with = "serde_humanize_rs")]
+    pub webhook_retry_backoff: Duration,
+    /// How long a webhook receiver gets to answer a delivery attempt before
+    /// the attempt counts as failed
+    #[serde(with = "serde_humanize_rs")]
+    pub webhook_timeout: Duration,
+    /// How many changes can wait for delivery to a single webhook. Changes
+    /// that do not fit are moved to the dead letter log right away
+    pub webhook_queue_size: usize,
+    /// When the write-ahead log is synced to disk
+    pub wal_fsync: WalFsync,
+    /// How often the write-ahead log is synced when `wal_fsync` is `interval`
+    #[serde(with = "serde_humanize_rs")]
+    pub wal_fsync_interval: Duration,
+    /// How many chunks are read at once while documents are loaded on
+    /// startup. Higher is faster, but more raw chunk data is held in memory
+    /// at the same time
+    pub load_concurrency: usize,
+    /// Once the documents in the cache take up more megabytes than this, the
+    /// least recently used collections are evicted and read back from the
+    /// store when they are used again. Without it everything stays in memory
+    pub cache_memory_budget_mb: Option<usize>,
+    /// A warning is logged when the documents in the cache take up more
+    /// megabytes than this, checked on startup and after every save
+    pub cache_memory_warning_mb: Option<usize>,
+    /// How often the store is compacted in the background
+    #[serde(with = "serde_humanize_rs")]
+    pub compaction_interval: Duration,
+    /// How documents are serialized in chunk files. Changing this does not
+    /// rewrite existing files, they are converted as they are compacted
+    pub chunk_format: ChunkFormat,
+    pub chunk_compression: ChunkCompression,
+    /// The bucket the S3 store keeps its objects in
+    pub s3_bucket: String,
+    pub s3_region: String,
+    /// Set to use another S3 compatible service than AWS, like MinIO
+    pub s3_endpoint: Option<String>,
+    /// Put in front of every object key, so that several databases can share
+    /// a bucket
+    pub s3_prefix: String,
+    /// If left out the credentials are taken from the environment, the same
+    /// way as for the AWS tools
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    /// A file holding the key the file store encrypts its files with, as 32
+    /// bytes encoded in base64. Without a key nothing is encrypted. Files
+    /// written without a key are refused once there is one, an existing data
+    /// folder is encrypted with `shelf rotate-key`
+    pub encryption_key_file: Option<String>,
+    /// The name of an environment variable holding the key, used instead of
+    /// `encryption_key_file`
+    pub encryption_key_env: Option<String>,
+    pub encryption_cipher: EncryptionCipher,
+    /// Upgrades a data folder written by an older version when the file
+    /// store starts, after copying it to a backup. Turn off to only upgrade
+    /// with `shelf upgrade-data`
+    pub auto_upgrade_data: bool,
+    /// Backups requested through the admin api have to carry this token, as
+    /// `Authorization: Bearer <token>`. Without it backups can not be
+    /// requested
+    pub admin_token: Option<String>,
+    /// The folder backups requested through the admin api are written to,
+    /// they can not be written anywhere else. Without it backups can not be
+    /// requested
+    pub backup_folder: Option<String>,
+    /// Imports sent to the admin api that are larger than this many megabytes
+    /// are rejected, the whole import is held in memory until it is committed
+    pub import_max_size_mb: usize,
 }
 
 /// The configuration object for the Shelf database. This struct holds all
@@ -60,10 +142,27 @@ impl Config {
 
     fn set_defaults(config: &mut CConfig) -> Result<(), Error> {
         config.set_default("dataFolder", ".shelf_data")?;
+        config.set_default("store", "file")?;
         config.set_default("port", 5600)?;
         config.set_default("host", "127.0.0.1")?;
         config.set_default("logLevel", "info")?;
         config.set_default("saveInterval", "30s")?;
+        config.set_default("webhookMaxAttempts", 5)?;
+        config.set_default("webhookRetryBackoff", "1s")?;
+        config.set_default("webhookTimeout", "10s")?;
+        config.set_default("webhookQueueSize", 1000)?;
+        config.set_default("walFsync", "batched")?;
+        config.set_default("walFsyncInterval", "100ms")?;
+        config.set_default("compactionInterval", "1h")?;
+        config.set_default("loadConcurrency", 4)?;
+        config.set_default("chunkFormat", "json")?;
+        config.set_default("chunkCompression", "gzip")?;
+        config.set_default("s3Bucket", "")?;
+        config.set_default("s3Region", "us-east-1")?;
+        config.set_default("s3Prefix", "")?;
+        config.set_default("encryptionCipher", "aes256Gcm")?;
+        config.set_default("autoUpgradeData", true)?;
+        config.set_default("importMaxSizeMb", 256)?;
 
         Ok(())
     }
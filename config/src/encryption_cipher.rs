@@ -0,0 +1,16 @@
+/// How files are encrypted when an encryption key is configured
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EncryptionCipher {
+    /// AES-256 in GCM mode, fast on processors with AES instructions
+    Aes256Gcm,
+    /// Faster than AES where there are no AES instructions, like on many ARM
+    /// processors
+    ChaCha20Poly1305,
+}
+
+impl Default for EncryptionCipher {
+    fn default() -> Self {
+        EncryptionCipher::Aes256Gcm
+    }
+}
@@ -7,6 +7,18 @@ extern crate slog;
 #[macro_use]
 extern crate serde_derive;
 
+mod chunk_compression;
+mod chunk_format;
 mod config;
+mod encryption_cipher;
+mod store_kind;
+mod wal_fsync;
 
-pub use self::config::Config;
+pub use self::{
+    chunk_compression::ChunkCompression,
+    chunk_format::ChunkFormat,
+    config::Config,
+    encryption_cipher::EncryptionCipher,
+    store_kind::StoreKind,
+    wal_fsync::WalFsync,
+};
@@ -0,0 +1,18 @@
+/// Where the data is persisted
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StoreKind {
+    /// Chunk files in the data folder
+    File,
+    /// A single SQLite database in the data folder, which can be inspected
+    /// with any SQLite tool
+    Sqlite,
+    /// Chunk objects in an S3 compatible bucket, see the `s3` settings
+    S3,
+}
+
+impl Default for StoreKind {
+    fn default() -> Self {
+        StoreKind::File
+    }
+}
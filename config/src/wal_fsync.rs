@@ -0,0 +1,19 @@
+/// Decides when the write-ahead log is synced to disk
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WalFsync {
+    /// Every write is synced on its own before it is acknowledged
+    Always,
+    /// Writes are acknowledged once synced, but writes that arrive together
+    /// share one sync
+    Batched,
+    /// Writes are acknowledged right away and synced every `walFsyncInterval`,
+    /// a crash may lose the writes made since the last sync
+    Interval,
+}
+
+impl Default for WalFsync {
+    fn default() -> Self {
+        WalFsync::Batched
+    }
+}
@@ -1,7 +1,10 @@
 use crate::{
     CacheSchema,
+    Change,
+    MemoryUsage,
     Schema,
     Store,
+    Transaction,
 };
 use failure::Error;
 use futures::{
@@ -9,6 +12,7 @@ use futures::{
     stream::BoxStream,
 };
 use slog::Logger;
+use std::sync::Arc;
 use tokio::sync::broadcast::Receiver;
 use uuid::Uuid;
 
@@ -30,6 +34,11 @@ pub trait Cache: Send + Sync + 'static {
         store: &'a S,
     ) -> BoxFuture<Result<(), Error>>;
 
+    /// Hands the cache the store it is loaded from, for caches that read
+    /// documents back from the store after dropping them from memory. Called
+    /// before `load`
+    fn attach_store<S: Store>(&self, _store: Arc<S>) {}
+
     /// Retrieves all schemas
     fn schemas(&self) -> BoxStream<Self::CacheSchema>;
 
@@ -47,11 +56,33 @@ pub trait Cache: Send + Sync + 'static {
         new_graphql_schema: &'a str,
     ) -> BoxFuture<'a, Result<(), Error>>;
 
+    /// Applies all writes of a transaction while holding the locks of every
+    /// collection involved. Either all writes are applied or none of them.
+    /// The outcome is appended to the write-ahead log of the store before
+    /// any of it is visible, so nothing is seen that a crash could lose.
+    /// Returns the outcome as it was appended to the log, along with the
+    /// versions it adds to `@versioned` collections
+    fn apply_transaction<'a, S: Store>(
+        &'a self,
+        logger: &'a Logger,
+        transaction: &'a Transaction,
+        store: &'a S,
+    ) -> BoxFuture<'a, Result<Transaction, Error>>;
+
     /// Gets the current size in bytes from the cache
     fn cache_size(&self) -> BoxFuture<usize>;
 
+    /// Breaks the size of the cache down by schema and collection
+    fn memory_usage(&self) -> BoxFuture<MemoryUsage>;
+
     /// Tells if this cache is empty
     fn is_empty(&self) -> BoxFuture<bool>;
 
+    /// Notifies whenever a schema has been added or replaced
     fn on_schema_updates(&self) -> Receiver<()>;
+
+    /// Subscribes to all changes made to the cache from this point on. This
+    /// covers schemas, collections and documents, and every change carries a
+    /// sequence number so gaps can be detected
+    fn changes(&self) -> Receiver<Change>;
 }
@@ -2,25 +2,38 @@ use crate::{
     Collection,
     Document,
     DocumentResult,
+    SnapshotDocuments,
 };
+use failure::Error;
 use futures::future::BoxFuture;
 use std::sync::Arc;
 use uuid::Uuid;
 
+/// Reads and writes can fail for caches that keep part of the documents in
+/// the store, when the documents can not be read back in
 pub trait CacheCollection: 'static + Send + Sync + Clone {
-    fn set_document(&self, document: Document) -> BoxFuture<()>;
+    /// Inserts the document, or replaces it if a document with the same id
+    /// already exists
+    fn set_document(&self, document: Document) -> BoxFuture<Result<(), Error>>;
+
+    /// Removes a document, returns the removed document if it existed
+    fn delete_document(&self, id: Uuid) -> BoxFuture<Result<Option<Arc<Document>>, Error>>;
     fn inner_collection(&self) -> BoxFuture<Collection>;
     fn set_collection(&self, collection: Collection) -> BoxFuture<()>;
-    fn documents<'a>(&'a self) -> BoxFuture<'a, Box<dyn DocumentResult + 'a>>;
-    fn document(&self, id: Uuid) -> BoxFuture<Option<Arc<Document>>>;
+    fn documents<'a>(&'a self) -> BoxFuture<'a, Result<Box<dyn DocumentResult + 'a>, Error>>;
+    fn document(&self, id: Uuid) -> BoxFuture<Result<Option<Arc<Document>>, Error>>;
     fn find_first_by_field<'a>(
         &'a self,
         field_name: &'a str,
         field_value: &'a str,
-    ) -> BoxFuture<'a, Option<Arc<Document>>>;
+    ) -> BoxFuture<'a, Result<Option<Arc<Document>>, Error>>;
     fn find_by_field<'a>(
         &'a self,
         field_name: &'a str,
         field_value: &'a str,
-    ) -> BoxFuture<'a, Box<dyn DocumentResult + 'a>>;
+    ) -> BoxFuture<'a, Result<Box<dyn DocumentResult + 'a>, Error>>;
+    /// Copies the documents for a snapshot without reading anything from the
+    /// store. Documents that are only in the store are left there, unchanged
+    /// until the snapshot has read them
+    fn snapshot_documents(&self) -> BoxFuture<SnapshotDocuments>;
 }
@@ -0,0 +1,61 @@
+use crate::{
+    Change,
+    ChangeKind,
+};
+use chrono::Utc;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use tokio::sync::broadcast::{
+    channel,
+    Receiver,
+    Sender,
+};
+
+/// Hands out sequence numbers and broadcasts changes to all subscribers. Cache
+/// implementations share a single feed between all their schemas and
+/// collections, cloning it is cheap
+#[derive(Clone)]
+pub struct ChangeFeed {
+    sender: Sender<Change>,
+    sequence: Arc<Mutex<u64>>,
+}
+
+impl ChangeFeed {
+    /// Creates a new feed, `capacity` is the number of changes a subscriber
+    /// can lag behind before it starts missing changes
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = channel(capacity);
+        Self {
+            sender,
+            sequence: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Publishes a change and returns the sequence number it was given
+    pub fn publish(&self, kind: ChangeKind) -> u64 {
+        // The lock is held while sending so that subscribers always see the
+        // changes in sequence order
+        let mut sequence = self.sequence.lock().expect("Change feed lock poisoned");
+        *sequence += 1;
+
+        // Sending only fails if there are no subscribers, which is fine
+        let _ = self.sender.send(Change {
+            sequence: *sequence,
+            timestamp: Utc::now(),
+            kind,
+        });
+
+        *sequence
+    }
+
+    pub fn subscribe(&self) -> Receiver<Change> {
+        self.sender.subscribe()
+    }
+
+    /// The sequence number of the latest published change
+    pub fn last_sequence(&self) -> u64 {
+        *self.sequence.lock().expect("Change feed lock poisoned")
+    }
+}
@@ -1,11 +1,13 @@
 mod cache;
 mod cache_collection;
 mod cache_schema;
+mod change_feed;
 mod document_result;
 
 pub use self::{
     cache::Cache,
     cache_collection::CacheCollection,
     cache_schema::CacheSchema,
+    change_feed::ChangeFeed,
     document_result::DocumentResult,
 };
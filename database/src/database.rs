@@ -1,20 +1,42 @@
 use crate::{
     cache::Cache,
     store::Store,
+    util::{
+        save_versions,
+        validate_document,
+    },
     CacheCollection,
     CacheSchema,
+    Collection,
+    CompactionReport,
+    DeadLetter,
     Document,
+    DocumentVersion,
+    MemoryUsage,
     Schema,
+    Snapshot,
+    SnapshotCollection,
+    SnapshotSchema,
+    Transaction,
+    TransactionVersion,
+    TransactionWrite,
+    Webhook,
+};
+use chrono::{
+    DateTime,
+    Utc,
 };
 use failure::Error;
 use shelf_config::Config;
 use slog::Logger;
 use std::{
     collections::HashMap,
+    mem,
     ops::{
         Add,
         Deref,
     },
+    path::Path,
     sync::{
         atomic::{
             AtomicBool,
@@ -25,6 +47,10 @@ use std::{
     time::Duration,
 };
 use tokio::{
+    sync::{
+        Mutex,
+        RwLock,
+    },
     task::JoinHandle,
     time::{
         interval_at,
@@ -37,11 +63,22 @@ pub struct Database<C: Cache, S: Store> {
     cache: Arc<C>,
     store: Arc<S>,
     run_save: Arc<AtomicBool>,
+    save_lock: Arc<Mutex<()>>,
+    /// Held for reading while a transaction or a schema is applied to the
+    /// cache, and for writing while a snapshot is captured or the write-ahead
+    /// log is rotated
+    commit_lock: Arc<RwLock<()>>,
+    /// Versions of committed transactions that could not be stored yet. The
+    /// write-ahead log is kept until they are
+    pending_history: Arc<Mutex<Vec<TransactionVersion>>>,
+    webhooks: Arc<RwLock<Vec<Webhook>>>,
 }
 
 impl<C: Cache, S: Store> Database<C, S> {
     pub async fn new(logger: &Logger, config: &Config, store: S, cache: C) -> Result<Self, Error> {
-        cache.load(&logger, &store).await?;
+        let store = Arc::new(store);
+        cache.attach_store(Arc::clone(&store));
+        cache.load(&logger, store.deref()).await?;
 
         if cache.is_empty().await {
             warn!(logger, "No schemas found, creating initial setup...");
@@ -65,11 +102,8 @@ impl<C: Cache, S: Store> Database<C, S> {
                     serde_json::to_value("Model S").unwrap(),
                 );
                 collection
-                    .set_document(Document {
-                        id: Uuid::new_v4(),
-                        fields: model_s,
-                    })
-                    .await;
+                    .set_document(Document::new(Uuid::new_v4(), model_s))
+                    .await?;
 
                 let mut model_x = HashMap::new();
                 model_x.insert("brand".to_string(), serde_json::to_value("Tesla").unwrap());
@@ -78,11 +112,8 @@ impl<C: Cache, S: Store> Database<C, S> {
                     serde_json::to_value("Model X").unwrap(),
                 );
                 collection
-                    .set_document(Document {
-                        id: Uuid::new_v4(),
-                        fields: model_x,
-                    })
-                    .await;
+                    .set_document(Document::new(Uuid::new_v4(), model_x))
+                    .await?;
 
                 let mut model_3 = HashMap::new();
                 model_3.insert("brand".to_string(), serde_json::to_value("Tesla").unwrap());
@@ -91,11 +122,8 @@ impl<C: Cache, S: Store> Database<C, S> {
                     serde_json::to_value("Model 3").unwrap(),
                 );
                 collection
-                    .set_document(Document {
-                        id: Uuid::new_v4(),
-                        fields: model_3,
-                    })
-                    .await;
+                    .set_document(Document::new(Uuid::new_v4(), model_3))
+                    .await?;
 
                 let mut model_y = HashMap::new();
                 model_y.insert("brand".to_string(), serde_json::to_value("Tesla").unwrap());
@@ -104,14 +132,11 @@ impl<C: Cache, S: Store> Database<C, S> {
                     serde_json::to_value("Model Y").unwrap(),
                 );
                 collection
-                    .set_document(Document {
-                        id: Uuid::new_v4(),
-                        fields: model_y,
-                    })
-                    .await;
+                    .set_document(Document::new(Uuid::new_v4(), model_y))
+                    .await?;
             }
 
-            cache.save(&logger, &store).await?;
+            cache.save(&logger, store.deref()).await?;
         }
 
         info!(
@@ -119,24 +144,51 @@ impl<C: Cache, S: Store> Database<C, S> {
             "Current cache size is: {}",
             pretty_bytes::converter::convert(cache.cache_size().await as f64)
         );
+        let memory_warning = config.cache_memory_warning_mb.map(|i| i * 1024 * 1024);
+        let over_memory_warning = match memory_warning {
+            Some(limit) => {
+                Self::check_memory_usage(&logger, &cache.memory_usage().await, limit, false)
+            }
+            None => false,
+        };
+
+        let webhooks = store.get_webhooks(&logger).await?;
+        info!(logger, "Found {} registered webhooks", webhooks.len());
 
         let cache = Arc::new(cache);
-        let store = Arc::new(store);
 
         let run_save = Arc::new(AtomicBool::new(true));
+        let save_lock = Arc::new(Mutex::new(()));
+        let commit_lock = Arc::new(RwLock::new(()));
+        let pending_history = Arc::new(Mutex::new(vec![]));
 
         Self::start_save_loop(
             &logger,
             Arc::clone(&run_save),
+            Arc::clone(&save_lock),
+            Arc::clone(&commit_lock),
+            Arc::clone(&pending_history),
             &cache,
             &store,
             config.save_interval,
+            memory_warning.map(|i| (i, over_memory_warning)),
+        );
+        Self::start_compaction_loop(
+            &logger,
+            Arc::clone(&run_save),
+            Arc::clone(&save_lock),
+            &store,
+            config.compaction_interval,
         );
 
         Ok(Self {
             cache,
             store,
             run_save,
+            save_lock,
+            commit_lock,
+            pending_history,
+            webhooks: Arc::new(RwLock::new(webhooks)),
         })
     }
 
@@ -145,16 +197,299 @@ impl<C: Cache, S: Store> Database<C, S> {
     }
 
     pub async fn save(&self, logger: &Logger) -> Result<(), Error> {
-        self.cache.save(&logger, self.store.deref()).await?;
+        let _guard = self.save_lock.lock().await;
+        Self::checkpoint(
+            &logger,
+            self.cache.deref(),
+            self.store.deref(),
+            &self.commit_lock,
+            &self.pending_history,
+        )
+        .await
+    }
+
+    /// Adds or replaces a schema together with its collections. Waits while a
+    /// snapshot is captured, so that a snapshot never holds part of a schema
+    pub async fn insert_schema(
+        &self,
+        logger: &Logger,
+        schema: Schema,
+        new_graphql_schema: &str,
+    ) -> Result<(), Error> {
+        let _guard = self.commit_lock.read().await;
+        self.cache
+            .insert_schema(&logger, schema, new_graphql_schema)
+            .await
+    }
+
+    /// Compacts the store. Saves wait until the compaction is done
+    pub async fn compact(&self, logger: &Logger) -> Result<CompactionReport, Error> {
+        let _guard = self.save_lock.lock().await;
+        self.store.compact(&logger).await
+    }
+
+    /// Captures a consistent copy of everything in the database. Commits wait
+    /// while it is captured, which only copies pointers to the documents.
+    /// Collections that are not in memory are left in the store, and can not
+    /// be read back in until the snapshot has read them
+    pub async fn snapshot(&self) -> Snapshot {
+        let webhooks = self.webhooks().await;
+        let _guard = self.commit_lock.write().await;
+        Snapshot::capture(self.cache.deref(), webhooks).await
+    }
+
+    /// Captures a consistent copy of a single schema, `None` if it does not
+    /// exist. Commits wait while it is captured, like for `snapshot`
+    pub async fn schema_snapshot(&self, schema_name: &str) -> Option<SnapshotSchema> {
+        let _guard = self.commit_lock.write().await;
+        let schema = self.cache.schema_by_name(schema_name).await?;
+        Some(SnapshotSchema::capture(&schema).await)
+    }
+
+    /// Captures a copy of a single collection, `None` if the schema or the
+    /// collection does not exist. Only that collection is read and commits
+    /// are not held up
+    pub async fn collection_snapshot(
+        &self,
+        schema_name: &str,
+        collection_name: &str,
+    ) -> Option<SnapshotCollection> {
+        let schema = self.cache.schema_by_name(schema_name).await?;
+        let collection = schema.collection_by_name(collection_name).await?;
+        Some(SnapshotCollection::capture(&collection).await)
+    }
+
+    /// Returns the documents of a captured collection, reading them from the
+    /// store if they were not in memory when it was captured
+    ///
+    /// # Errors
+    /// Returns an error if the documents can not be read from the store
+    pub async fn snapshot_documents(
+        &self,
+        logger: &Logger,
+        schema: &Schema,
+        collection: &SnapshotCollection,
+    ) -> Result<Vec<Arc<Document>>, Error> {
+        collection
+            .read_documents(&logger, self.store.deref(), schema)
+            .await
+    }
+
+    /// Captures a snapshot and writes it to `path`, while the database keeps
+    /// running. See `Snapshot::write` for the supported targets
+    pub async fn backup(&self, logger: &Logger, path: &Path) -> Result<Snapshot, Error> {
+        let snapshot = self.snapshot().await;
+        snapshot.write(&logger, self.store.deref(), path).await?;
+
+        info!(logger, "💾 Wrote backup"; "path" => path.display().to_string(), "collections" => snapshot.collection_count(), "documents" => snapshot.document_count());
+        Ok(snapshot)
+    }
+
+    /// Flushes the cache to the store and drops the write-ahead log segments
+    /// that are covered by it. The log is rotated before the cache is read, so
+    /// writes committed during the flush are kept in the new segment. No
+    /// commit may be in between writing to the log and marking its documents
+    /// as changed while the log is rotated, or the closed segment would hold
+    /// writes the save does not see. History that could not be stored when
+    /// it was committed is stored before the log is dropped
+    async fn checkpoint(
+        logger: &Logger,
+        cache: &C,
+        store: &S,
+        commit_lock: &RwLock<()>,
+        pending_history: &Mutex<Vec<TransactionVersion>>,
+    ) -> Result<(), Error> {
+        let segment = {
+            let _guard = commit_lock.write().await;
+            store.rotate_wal(&logger).await?
+        };
+        cache.save(&logger, store).await?;
+
+        let pending = mem::replace(&mut *pending_history.lock().await, vec![]);
+        if let Err(err) = save_versions(&logger, cache, store, &pending).await {
+            pending_history.lock().await.extend(pending);
+            return Err(err);
+        }
+
+        store.truncate_wal(&logger, segment).await?;
+        Ok(())
+    }
+
+    /// Validates all writes in the transaction, appends their outcome to the
+    /// write-ahead log and then applies them as one unit
+    ///
+    /// # Errors
+    /// Returns an error, without having applied anything, if any of the writes
+    /// are invalid or the write-ahead log can not be appended to
+    pub async fn commit(&self, logger: &Logger, transaction: Transaction) -> Result<(), Error> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        let logger = logger.new(o!("transaction_id" => transaction.id.to_string()));
+
+        for write in transaction.writes() {
+            if let TransactionWrite::Set {
+                schema_id,
+                collection_id,
+                document,
+                ..
+            } = write
+            {
+                let schema = match self.cache.schema(*schema_id).await {
+                    Some(schema) => schema,
+                    None => bail!("The schema {} does not exist", schema_id),
+                };
+                let collection = match schema.collection(*collection_id).await {
+                    Some(collection) => collection,
+                    None => bail!("The collection {} does not exist", collection_id),
+                };
+                validate_document(
+                    &schema.inner_schema().await,
+                    &collection.inner_collection().await.name,
+                    document,
+                )?;
+            }
+        }
+
+        let outcome = {
+            let _guard = self.commit_lock.read().await;
+            self.cache
+                .apply_transaction(&logger, &transaction, self.store.deref())
+                .await?
+        };
+        debug!(
+            logger,
+            "Transaction written to the write-ahead log and applied to cache"
+        );
+
+        // The transaction is committed at this point, the versions are in the
+        // write-ahead log and are stored again before it is truncated
+        if let Err(err) = save_versions(
+            &logger,
+            self.cache.deref(),
+            self.store.deref(),
+            outcome.versions(),
+        )
+        .await
+        {
+            error!(logger, "Failed to store the history of a committed transaction, it is stored at the next save"; "error" => format!("{}", err));
+            self.pending_history
+                .lock()
+                .await
+                .extend_from_slice(outcome.versions());
+        }
         Ok(())
     }
 
+    /// Returns every stored version of a document in a `@versioned`
+    /// collection, ordered by revision
+    pub async fn history(
+        &self,
+        logger: &Logger,
+        schema_id: Uuid,
+        collection_id: Uuid,
+        id: Uuid,
+    ) -> Result<Vec<DocumentVersion>, Error> {
+        match self.inner_collection(schema_id, collection_id).await {
+            Some((schema, collection)) => {
+                self.store
+                    .get_history(&logger, &schema, &collection, id)
+                    .await
+            }
+            None => bail!("The collection {} does not exist", collection_id),
+        }
+    }
+
+    /// Reconstructs a document as it was at the given time. If the history
+    /// does not go back that far, the current document is used when it has
+    /// not been written since
+    pub async fn document_as_of(
+        &self,
+        logger: &Logger,
+        schema_id: Uuid,
+        collection_id: Uuid,
+        id: Uuid,
+        as_of: DateTime<Utc>,
+    ) -> Result<Option<Document>, Error> {
+        let history = self.history(&logger, schema_id, collection_id, id).await?;
+        if let Some(document) = DocumentVersion::as_of(&history, as_of) {
+            return Ok(document.cloned());
+        }
+
+        let current = match self.cache.schema(schema_id).await {
+            Some(schema) => match schema.collection(collection_id).await {
+                Some(collection) => collection.document(id).await?,
+                None => None,
+            },
+            None => None,
+        };
+
+        Ok(current
+            .filter(|doc| doc.updated_at <= as_of)
+            .map(|doc| Document::clone(&doc)))
+    }
+
+    async fn inner_collection(
+        &self,
+        schema_id: Uuid,
+        collection_id: Uuid,
+    ) -> Option<(Schema, Collection)> {
+        let schema = self.cache.schema(schema_id).await?;
+        let collection = schema.collection(collection_id).await?;
+        Some((
+            schema.inner_schema().await,
+            collection.inner_collection().await,
+        ))
+    }
+
+    pub async fn webhooks(&self) -> Vec<Webhook> {
+        self.webhooks.read().await.clone()
+    }
+
+    /// Adds a webhook and persists it right away
+    pub async fn register_webhook(&self, logger: &Logger, webhook: Webhook) -> Result<(), Error> {
+        let mut webhooks = self.webhooks.write().await;
+        let mut updated = webhooks.clone();
+        updated.push(webhook);
+
+        self.store.save_webhooks(&logger, &updated).await?;
+        *webhooks = updated;
+        Ok(())
+    }
+
+    /// Removes a webhook, returns false if there was no webhook with that id
+    pub async fn remove_webhook(&self, logger: &Logger, id: Uuid) -> Result<bool, Error> {
+        let mut webhooks = self.webhooks.write().await;
+        if !webhooks.iter().any(|i| i.id == id) {
+            return Ok(false);
+        }
+
+        let updated: Vec<_> = webhooks.iter().filter(|i| i.id != id).cloned().collect();
+
+        self.store.save_webhooks(&logger, &updated).await?;
+        *webhooks = updated;
+        Ok(true)
+    }
+
+    pub async fn save_dead_letter(
+        &self,
+        logger: &Logger,
+        dead_letter: &DeadLetter,
+    ) -> Result<(), Error> {
+        self.store.save_dead_letter(&logger, dead_letter).await
+    }
+
     pub fn start_save_loop(
         logger: &Logger,
         run_save: Arc<AtomicBool>,
+        save_lock: Arc<Mutex<()>>,
+        commit_lock: Arc<RwLock<()>>,
+        pending_history: Arc<Mutex<Vec<TransactionVersion>>>,
         cache: &Arc<C>,
         store: &Arc<S>,
         duration: Duration,
+        mut memory_warning: Option<(usize, bool)>,
     ) -> JoinHandle<()> {
         let logger = logger.new(o!("save_interval" => format!("{:#?}", duration)));
         let cache = Arc::clone(cache);
@@ -168,9 +503,78 @@ impl<C: Cache, S: Store> Database<C, S> {
             while run_save.load(Ordering::Relaxed) {
                 interval.tick().await;
                 debug!(logger, "Saving data...");
-                if let Err(err) = cache.save(&logger, store.deref()).await {
+                let _guard = save_lock.lock().await;
+                if let Err(err) = Self::checkpoint(
+                    &logger,
+                    cache.deref(),
+                    store.deref(),
+                    &commit_lock,
+                    &pending_history,
+                )
+                .await
+                {
                     error!(logger, "Failed to save data"; "error" => format!("{}", err));
                 }
+                if let Some((limit, over)) = &mut memory_warning {
+                    *over = Self::check_memory_usage(
+                        &logger,
+                        &cache.memory_usage().await,
+                        *limit,
+                        *over,
+                    );
+                }
+            }
+        })
+    }
+
+    /// Warns once when the cache grows above the limit, and tells when it is
+    /// back below it. Returns if the cache is above the limit
+    fn check_memory_usage(
+        logger: &Logger,
+        usage: &MemoryUsage,
+        limit: usize,
+        was_over: bool,
+    ) -> bool {
+        let over = usage.size > limit;
+        if over && !was_over {
+            let largest = usage.schemas.first();
+            warn!(logger, "The cache takes up more memory than the warning limit";
+                "cache_size" => pretty_bytes::converter::convert(usage.size as f64),
+                "limit" => pretty_bytes::converter::convert(limit as f64),
+                "largest_schema" => largest.map(|i| i.name.as_str()).unwrap_or_default(),
+                "largest_schema_size" => pretty_bytes::converter::convert(largest.map_or(0, |i| i.size) as f64));
+        } else if !over && was_over {
+            info!(logger, "The cache is back below the memory warning limit"; "cache_size" => pretty_bytes::converter::convert(usage.size as f64));
+        }
+        over
+    }
+
+    fn start_compaction_loop(
+        logger: &Logger,
+        run_save: Arc<AtomicBool>,
+        save_lock: Arc<Mutex<()>>,
+        store: &Arc<S>,
+        duration: Duration,
+    ) -> JoinHandle<()> {
+        let logger = logger.new(o!("compaction_interval" => format!("{:#?}", duration)));
+        let store = Arc::clone(store);
+
+        info!(logger, "🧹 Starting compaction loop");
+
+        tokio::spawn(async move {
+            let mut interval = interval_at(Instant::now().add(duration), duration);
+
+            while run_save.load(Ordering::Relaxed) {
+                interval.tick().await;
+                let _guard = save_lock.lock().await;
+                match store.compact(&logger).await {
+                    Ok(report) => {
+                        debug!(logger, "Compacted store"; "files_before" => report.files_before, "files_after" => report.files_after);
+                    }
+                    Err(err) => {
+                        error!(logger, "Failed to compact store"; "error" => format!("{}", err));
+                    }
+                }
             }
         })
     }
@@ -182,6 +586,10 @@ impl<C: Cache, S: Store> Clone for Database<C, S> {
             cache: Arc::clone(&self.cache),
             store: Arc::clone(&self.store),
             run_save: Arc::clone(&self.run_save),
+            save_lock: Arc::clone(&self.save_lock),
+            commit_lock: Arc::clone(&self.commit_lock),
+            pending_history: Arc::clone(&self.pending_history),
+            webhooks: Arc::clone(&self.webhooks),
         }
     }
 }
@@ -12,6 +12,7 @@ extern crate failure;
 mod cache;
 mod database;
 mod model;
+mod snapshot;
 mod store;
 pub mod test;
 pub(crate) mod util;
@@ -20,5 +21,17 @@ pub use self::{
     cache::*,
     database::Database,
     model::*,
+    snapshot::{
+        RestoreReport,
+        Snapshot,
+        SnapshotCollection,
+        SnapshotDocuments,
+        SnapshotSchema,
+    },
     store::Store,
+    util::{
+        keep_newest,
+        save_versions,
+        validate_document,
+    },
 };
@@ -0,0 +1,83 @@
+use crate::{
+    Collection,
+    Document,
+    Schema,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A single change that has been applied to the cache. Every change gets a
+/// sequence number that is strictly increasing, so consumers can tell if they
+/// have missed something
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub kind: ChangeKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ChangeKind {
+    /// A schema was added or its definition was changed
+    SchemaUpdated { schema: Schema },
+
+    /// A collection was added or its metadata was changed
+    #[serde(rename_all = "camelCase")]
+    CollectionUpdated {
+        schema_id: Uuid,
+        collection: Collection,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    DocumentInserted {
+        schema_id: Uuid,
+        collection_id: Uuid,
+        after: Arc<Document>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    DocumentUpdated {
+        schema_id: Uuid,
+        collection_id: Uuid,
+        before: Arc<Document>,
+        after: Arc<Document>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    DocumentDeleted {
+        schema_id: Uuid,
+        collection_id: Uuid,
+        before: Arc<Document>,
+    },
+}
+
+impl ChangeKind {
+    /// The schema this change belongs to
+    pub fn schema_id(&self) -> Uuid {
+        match self {
+            ChangeKind::SchemaUpdated { schema } => schema.id,
+            ChangeKind::CollectionUpdated { schema_id, .. }
+            | ChangeKind::DocumentInserted { schema_id, .. }
+            | ChangeKind::DocumentUpdated { schema_id, .. }
+            | ChangeKind::DocumentDeleted { schema_id, .. } => *schema_id,
+        }
+    }
+
+    /// The collection this change belongs to, if it is a collection or document
+    /// change
+    pub fn collection_id(&self) -> Option<Uuid> {
+        match self {
+            ChangeKind::SchemaUpdated { .. } => None,
+            ChangeKind::CollectionUpdated { collection, .. } => Some(collection.id),
+            ChangeKind::DocumentInserted { collection_id, .. }
+            | ChangeKind::DocumentUpdated { collection_id, .. }
+            | ChangeKind::DocumentDeleted { collection_id, .. } => Some(*collection_id),
+        }
+    }
+}
@@ -25,9 +25,9 @@ impl Collection {
     }
 
     pub fn get_size(&self) -> usize {
-        let mut size = mem::size_of::<Self>();
+        let mut size = mem::size_of::<Self>() + self.name.capacity();
         if let Some(desc) = &self.description {
-            size += mem::size_of_val(desc);
+            size += desc.capacity();
         }
         size
     }
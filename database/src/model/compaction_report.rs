@@ -0,0 +1,16 @@
+/// What a compaction of the store did
+#[derive(Clone, Debug, Default)]
+pub struct CompactionReport {
+    /// The number of collections that were looked at
+    pub collections: usize,
+    pub files_before: usize,
+    pub files_after: usize,
+}
+
+impl CompactionReport {
+    pub fn add(&mut self, other: &Self) {
+        self.collections += other.collections;
+        self.files_before += other.files_before;
+        self.files_after += other.files_after;
+    }
+}
@@ -1,3 +1,9 @@
+use crate::util::fields_heap_size;
+use chrono::{
+    DateTime,
+    TimeZone,
+    Utc,
+};
 use serde_json::Value;
 use std::{
     collections::HashMap,
@@ -6,19 +12,60 @@ use std::{
 use uuid::Uuid;
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Document {
     pub id: Uuid,
     pub fields: HashMap<String, Value>,
+    /// Bumped by the cache every time the document is written, starts at 1
+    /// once the document has been stored
+    #[serde(default)]
+    pub revision: u64,
+    /// Documents written before timestamps were kept get the epoch, so that
+    /// looking them up as of a time sees them as having always been there,
+    /// instead of as written when they were read
+    #[serde(default = "epoch")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "epoch")]
+    pub updated_at: DateTime<Utc>,
+}
+
+fn epoch() -> DateTime<Utc> {
+    Utc.timestamp(0, 0)
 }
 
 impl Document {
-    pub fn get_size(&self) -> usize {
-        let mut size = mem::size_of::<Self>();
-        for (key, value) in &self.fields {
-            size += mem::size_of_val(&key);
-            size += mem::size_of_val(&value);
+    pub fn new(id: Uuid, fields: HashMap<String, Value>) -> Self {
+        let now = Utc::now();
+        Self {
+            id,
+            fields,
+            revision: 0,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Stamps the revision and timestamps of a document that is about to
+    /// replace `previous`, or be inserted if there is no previous document
+    pub fn revise(&mut self, previous: Option<&Document>) {
+        let now = Utc::now();
+        match previous {
+            Some(previous) => {
+                self.revision = previous.revision + 1;
+                self.created_at = previous.created_at;
+            }
+            None => {
+                self.revision = 1;
+                self.created_at = now;
+            }
         }
-        size
+        self.updated_at = now;
+    }
+
+    /// An estimate of the memory the document takes up, including the
+    /// contents of its fields
+    pub fn get_size(&self) -> usize {
+        mem::size_of::<Self>() + fields_heap_size(&self.fields)
     }
 }
 
@@ -27,3 +74,35 @@ impl Into<Value> for Document {
         serde_json::to_value(&self).expect("This is always serializable")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::Document;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    #[test]
+    fn revise_should_bump_the_revision_and_keep_created_at() {
+        let mut first = Document::new(Uuid::nil(), HashMap::new());
+        first.revise(None);
+
+        let mut second = Document::new(Uuid::nil(), HashMap::new());
+        second.revise(Some(&first));
+
+        assert_eq!(first.revision, 1);
+        assert_eq!(second.revision, 2);
+        assert_eq!(second.created_at, first.created_at);
+        assert!(second.updated_at >= first.updated_at);
+    }
+
+    #[test]
+    fn documents_without_revision_should_deserialize() {
+        let doc: Document =
+            serde_json::from_str(r#"{"id":"00000000-0000-0000-0000-000000000000","fields":{}}"#)
+                .unwrap();
+
+        assert_eq!(doc.revision, 0);
+        assert_eq!(doc.created_at.timestamp(), 0);
+        assert_eq!(doc.updated_at.timestamp(), 0);
+    }
+}
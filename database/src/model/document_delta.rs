@@ -0,0 +1,21 @@
+use crate::Document;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The document writes made to a collection since it was last saved. Only
+/// the latest state of every touched document is included
+#[derive(Clone, Debug, Default)]
+pub struct DocumentDelta {
+    pub upserted: Vec<Arc<Document>>,
+    pub deleted: Vec<Uuid>,
+}
+
+impl DocumentDelta {
+    pub fn is_empty(&self) -> bool {
+        self.upserted.is_empty() && self.deleted.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.upserted.len() + self.deleted.len()
+    }
+}
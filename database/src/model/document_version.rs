@@ -0,0 +1,102 @@
+use crate::{
+    ChangeKind,
+    Document,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use uuid::Uuid;
+
+/// One entry in the history of a document in a `@versioned` collection. Every
+/// write adds a version, a delete is recorded as a version without document
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentVersion {
+    pub document_id: Uuid,
+    pub revision: u64,
+    pub timestamp: DateTime<Utc>,
+    pub document: Option<Document>,
+}
+
+impl DocumentVersion {
+    /// Returns the version a document change results in, `None` if the
+    /// change is not about a document
+    pub fn from_change(kind: &ChangeKind) -> Option<Self> {
+        match kind {
+            ChangeKind::DocumentInserted { after, .. }
+            | ChangeKind::DocumentUpdated { after, .. } => Some(Self {
+                document_id: after.id,
+                revision: after.revision,
+                timestamp: after.updated_at,
+                document: Some(Document::clone(after)),
+            }),
+            ChangeKind::DocumentDeleted { before, .. } => Some(Self {
+                document_id: before.id,
+                revision: before.revision + 1,
+                timestamp: Utc::now(),
+                document: None,
+            }),
+            ChangeKind::SchemaUpdated { .. } | ChangeKind::CollectionUpdated { .. } => None,
+        }
+    }
+
+    /// Picks the document as it was at `as_of` from a history ordered by
+    /// revision. The outer `None` means the history does not reach that far
+    /// back, the inner that the document did not exist at that time
+    pub fn as_of(history: &[DocumentVersion], as_of: DateTime<Utc>) -> Option<Option<&Document>> {
+        history
+            .iter()
+            .rev()
+            .find(|i| i.timestamp <= as_of)
+            .map(|i| i.document.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        Document,
+        DocumentVersion,
+    };
+    use chrono::{
+        Duration,
+        Utc,
+    };
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn version(revision: u64, minutes_ago: i64, deleted: bool) -> DocumentVersion {
+        DocumentVersion {
+            document_id: Uuid::nil(),
+            revision,
+            timestamp: Utc::now() - Duration::minutes(minutes_ago),
+            document: if deleted {
+                None
+            } else {
+                Some(Document::new(Uuid::nil(), HashMap::new()))
+            },
+        }
+    }
+
+    #[test]
+    fn as_of_should_return_the_latest_version_before_the_time() {
+        let history = vec![
+            version(1, 30, false),
+            version(2, 20, false),
+            version(3, 10, true),
+        ];
+
+        let found = DocumentVersion::as_of(&history, Utc::now() - Duration::minutes(15));
+        assert!(matches!(found, Some(Some(_))), "The document should exist");
+
+        let deleted = DocumentVersion::as_of(&history, Utc::now());
+        assert!(
+            matches!(deleted, Some(None)),
+            "The document should be deleted"
+        );
+
+        let before = DocumentVersion::as_of(&history, Utc::now() - Duration::minutes(60));
+        assert!(before.is_none(), "The history does not go that far back");
+    }
+}
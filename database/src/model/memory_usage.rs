@@ -0,0 +1,68 @@
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+/// How much memory the documents of a cache take up, the largest schemas
+/// first. Schemas and collections of the same size are listed by name
+#[derive(Clone, Debug, Default)]
+pub struct MemoryUsage {
+    /// In bytes
+    pub size: usize,
+    pub schemas: Vec<SchemaMemoryUsage>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SchemaMemoryUsage {
+    pub id: Uuid,
+    pub name: String,
+    /// In bytes
+    pub size: usize,
+    /// The largest collections first
+    pub collections: Vec<CollectionMemoryUsage>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CollectionMemoryUsage {
+    pub id: Uuid,
+    pub name: String,
+    pub documents: usize,
+    /// In bytes
+    pub size: usize,
+    /// Evicted collections only keep the ids of their documents in memory
+    pub evicted: bool,
+}
+
+impl MemoryUsage {
+    pub fn new(mut schemas: Vec<SchemaMemoryUsage>) -> Self {
+        schemas.sort_by(|a, b| {
+            b.size
+                .cmp(&a.size)
+                .then_with(|| by_name(&a.name, a.id, &b.name, b.id))
+        });
+        Self {
+            size: schemas.iter().map(|i| i.size).sum(),
+            schemas,
+        }
+    }
+}
+
+impl SchemaMemoryUsage {
+    pub fn new(id: Uuid, name: String, mut collections: Vec<CollectionMemoryUsage>) -> Self {
+        collections.sort_by(|a, b| {
+            b.size
+                .cmp(&a.size)
+                .then_with(|| by_name(&a.name, a.id, &b.name, b.id))
+        });
+        Self {
+            id,
+            name,
+            size: collections.iter().map(|i| i.size).sum(),
+            collections,
+        }
+    }
+}
+
+/// The ids decide between entries with the same name, names are not unique in
+/// data written before they were checked
+fn by_name(a_name: &str, a_id: Uuid, b_name: &str, b_id: Uuid) -> Ordering {
+    a_name.cmp(b_name).then(a_id.cmp(&b_id))
+}
@@ -1,9 +1,40 @@
+mod change;
 mod collection;
+mod compaction_report;
 mod document;
+mod document_delta;
+mod document_version;
+mod memory_usage;
+mod revision_conflict;
 mod schema;
+mod transaction;
+mod webhook;
 
 pub use self::{
+    change::{
+        Change,
+        ChangeKind,
+    },
     collection::Collection,
+    compaction_report::CompactionReport,
     document::Document,
+    document_delta::DocumentDelta,
+    document_version::DocumentVersion,
+    memory_usage::{
+        CollectionMemoryUsage,
+        MemoryUsage,
+        SchemaMemoryUsage,
+    },
+    revision_conflict::RevisionConflict,
     schema::Schema,
+    transaction::{
+        Transaction,
+        TransactionVersion,
+        TransactionWrite,
+    },
+    webhook::{
+        DeadLetter,
+        Webhook,
+        WebhookEvent,
+    },
 };
@@ -0,0 +1,29 @@
+use uuid::Uuid;
+
+/// Returned when a write expected a document to be at another revision than
+/// it currently is, meaning someone else has written it in the meantime
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Revision conflict on document {}, expected revision {} but it is at {:?}",
+    id, expected, actual
+)]
+pub struct RevisionConflict {
+    pub id: Uuid,
+    pub expected: u64,
+    /// The current revision, `None` if the document does not exist
+    pub actual: Option<u64>,
+}
+
+impl RevisionConflict {
+    /// Fails if `actual` does not match what the write expected
+    pub fn check(id: Uuid, expected: Option<u64>, actual: Option<u64>) -> Result<(), Self> {
+        match expected {
+            Some(expected) if actual != Some(expected) => Err(Self {
+                id,
+                expected,
+                actual,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
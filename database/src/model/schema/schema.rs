@@ -1,5 +1,6 @@
 use crate::util::{
     extract_graphql_schema,
+    has_versioned_directive,
     ExtractedData,
 };
 use chrono::{
@@ -49,6 +50,19 @@ impl Schema {
         self.definition().map(|d| extract_graphql_schema(&d))
     }
 
+    /// Tells if the collection is marked with `@versioned`, meaning previous
+    /// versions of its documents should be kept
+    pub fn is_versioned(&self, collection_name: &str) -> bool {
+        self.types()
+            .and_then(|types| {
+                types
+                    .collections
+                    .into_iter()
+                    .find(|i| i.name == collection_name)
+            })
+            .map_or(false, |coll| has_versioned_directive(&coll.directives))
+    }
+
     pub fn current_migration_version(&self) -> Option<u32> {
         let mut highest = None;
         for i in self.graphql_schemas.keys() {
@@ -0,0 +1,197 @@
+use crate::{
+    ChangeKind,
+    Document,
+    DocumentVersion,
+};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// A set of writes that should be applied all together, or not at all. Writes
+/// are applied in the order they were staged
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    pub id: Uuid,
+    writes: Vec<TransactionWrite>,
+    /// The versions the writes add to `@versioned` collections, only set in
+    /// the write-ahead log. The history survives a crash this way, even if
+    /// it was not stored when the transaction was committed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    versions: Vec<TransactionVersion>,
+}
+
+/// A version that a transaction adds to the history of a collection
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionVersion {
+    pub schema_id: Uuid,
+    pub collection_id: Uuid,
+    pub version: DocumentVersion,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TransactionWrite {
+    #[serde(rename_all = "camelCase")]
+    Set {
+        schema_id: Uuid,
+        collection_id: Uuid,
+        document: Document,
+        /// The write is rejected if the document is not at this revision
+        expected_revision: Option<u64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Delete {
+        schema_id: Uuid,
+        collection_id: Uuid,
+        id: Uuid,
+        /// The write is rejected if the document is not at this revision
+        expected_revision: Option<u64>,
+    },
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            writes: vec![],
+            versions: vec![],
+        }
+    }
+
+    /// Stages an insert or replace of a document
+    pub fn set(
+        &mut self,
+        schema_id: Uuid,
+        collection_id: Uuid,
+        document: Document,
+        expected_revision: Option<u64>,
+    ) {
+        self.writes.push(TransactionWrite::Set {
+            schema_id,
+            collection_id,
+            document,
+            expected_revision,
+        });
+    }
+
+    /// Stages a delete of a document
+    pub fn delete(
+        &mut self,
+        schema_id: Uuid,
+        collection_id: Uuid,
+        id: Uuid,
+        expected_revision: Option<u64>,
+    ) {
+        self.writes.push(TransactionWrite::Delete {
+            schema_id,
+            collection_id,
+            id,
+            expected_revision,
+        });
+    }
+
+    pub fn writes(&self) -> &[TransactionWrite] {
+        &self.writes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn versions(&self) -> &[TransactionVersion] {
+        &self.versions
+    }
+
+    /// The outcome of applying this transaction, as stored in the write-ahead
+    /// log. The documents carry the revisions they got when applied, which
+    /// makes replaying an entry twice harmless. Changes to the collections in
+    /// `versioned` are kept as versions as well
+    pub fn outcome(&self, changes: &[ChangeKind], versioned: &HashSet<Uuid>) -> Self {
+        let mut entry = Self::new();
+        entry.id = self.id;
+
+        for change in changes {
+            if let Some(collection_id) = change.collection_id().filter(|i| versioned.contains(i)) {
+                if let Some(version) = DocumentVersion::from_change(change) {
+                    entry.versions.push(TransactionVersion {
+                        schema_id: change.schema_id(),
+                        collection_id,
+                        version,
+                    });
+                }
+            }
+
+            match change {
+                ChangeKind::DocumentInserted {
+                    schema_id,
+                    collection_id,
+                    after,
+                }
+                | ChangeKind::DocumentUpdated {
+                    schema_id,
+                    collection_id,
+                    after,
+                    ..
+                } => entry.set(*schema_id, *collection_id, Document::clone(after), None),
+                ChangeKind::DocumentDeleted {
+                    schema_id,
+                    collection_id,
+                    before,
+                } => entry.delete(*schema_id, *collection_id, before.id, None),
+                ChangeKind::SchemaUpdated { .. } | ChangeKind::CollectionUpdated { .. } => {}
+            }
+        }
+
+        entry
+    }
+
+    /// Returns the latest staged write for a document, this lets reads within
+    /// the transaction see its own writes
+    pub fn staged(&self, collection_id: Uuid, id: Uuid) -> Option<&TransactionWrite> {
+        self.writes
+            .iter()
+            .rev()
+            .find(|i| i.collection_id() == collection_id && i.document_id() == id)
+    }
+}
+
+impl Default for Transaction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransactionWrite {
+    pub fn schema_id(&self) -> Uuid {
+        match self {
+            TransactionWrite::Set { schema_id, .. }
+            | TransactionWrite::Delete { schema_id, .. } => *schema_id,
+        }
+    }
+
+    pub fn collection_id(&self) -> Uuid {
+        match self {
+            TransactionWrite::Set { collection_id, .. }
+            | TransactionWrite::Delete { collection_id, .. } => *collection_id,
+        }
+    }
+
+    pub fn document_id(&self) -> Uuid {
+        match self {
+            TransactionWrite::Set { document, .. } => document.id,
+            TransactionWrite::Delete { id, .. } => *id,
+        }
+    }
+
+    pub fn expected_revision(&self) -> Option<u64> {
+        match self {
+            TransactionWrite::Set {
+                expected_revision, ..
+            }
+            | TransactionWrite::Delete {
+                expected_revision, ..
+            } => *expected_revision,
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::ChangeKind;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// A registered receiver of document changes. Changes are posted as signed
+/// json to the url of the webhook
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub id: Uuid,
+    pub url: String,
+    /// Shared secret used to sign the payloads with HMAC-SHA256
+    pub secret: String,
+    /// Only changes in this schema will be delivered, all schemas if none
+    pub schema_id: Option<Uuid>,
+    /// Only changes in this collection will be delivered, all collections if
+    /// none
+    pub collection_id: Option<Uuid>,
+    /// The events to deliver, all document events if empty
+    pub events: Vec<WebhookEvent>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEvent {
+    DocumentInserted,
+    DocumentUpdated,
+    DocumentDeleted,
+}
+
+/// A delivery that could not be made even after retrying
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub delivery_id: Uuid,
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub payload: Value,
+    pub attempts: u32,
+    pub last_error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn new(
+        url: String,
+        secret: String,
+        schema_id: Option<Uuid>,
+        collection_id: Option<Uuid>,
+        events: Vec<WebhookEvent>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            url,
+            secret,
+            schema_id,
+            collection_id,
+            events,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Tells if a change should be delivered to this webhook
+    pub fn matches(&self, change: &ChangeKind) -> bool {
+        let event = match WebhookEvent::from_change(change) {
+            Some(event) => event,
+            None => return false,
+        };
+
+        if let Some(schema_id) = self.schema_id {
+            if schema_id != change.schema_id() {
+                return false;
+            }
+        }
+
+        if let Some(collection_id) = self.collection_id {
+            if Some(collection_id) != change.collection_id() {
+                return false;
+            }
+        }
+
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+impl WebhookEvent {
+    pub fn from_change(change: &ChangeKind) -> Option<Self> {
+        match change {
+            ChangeKind::DocumentInserted { .. } => Some(WebhookEvent::DocumentInserted),
+            ChangeKind::DocumentUpdated { .. } => Some(WebhookEvent::DocumentUpdated),
+            ChangeKind::DocumentDeleted { .. } => Some(WebhookEvent::DocumentDeleted),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::DocumentInserted => "documentInserted",
+            WebhookEvent::DocumentUpdated => "documentUpdated",
+            WebhookEvent::DocumentDeleted => "documentDeleted",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        ChangeKind,
+        Document,
+        Webhook,
+        WebhookEvent,
+    };
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+    use uuid::Uuid;
+
+    fn inserted(schema_id: Uuid, collection_id: Uuid) -> ChangeKind {
+        ChangeKind::DocumentInserted {
+            schema_id,
+            collection_id,
+            after: Arc::new(Document::new(Uuid::new_v4(), HashMap::new())),
+        }
+    }
+
+    #[test]
+    fn it_matches_everything_without_filters() {
+        let webhook = Webhook::new(
+            "http://localhost".to_string(),
+            "secret".to_string(),
+            None,
+            None,
+            vec![],
+        );
+
+        assert!(webhook.matches(&inserted(Uuid::new_v4(), Uuid::new_v4())));
+    }
+
+    #[test]
+    fn it_does_not_match_other_collections() {
+        let collection_id = Uuid::new_v4();
+        let webhook = Webhook::new(
+            "http://localhost".to_string(),
+            "secret".to_string(),
+            None,
+            Some(collection_id),
+            vec![],
+        );
+
+        assert!(webhook.matches(&inserted(Uuid::nil(), collection_id)));
+        assert!(!webhook.matches(&inserted(Uuid::nil(), Uuid::new_v4())));
+    }
+
+    #[test]
+    fn it_does_not_match_other_events() {
+        let webhook = Webhook::new(
+            "http://localhost".to_string(),
+            "secret".to_string(),
+            None,
+            None,
+            vec![WebhookEvent::DocumentDeleted],
+        );
+
+        assert!(!webhook.matches(&inserted(Uuid::nil(), Uuid::nil())));
+    }
+}
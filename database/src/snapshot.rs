@@ -0,0 +1,818 @@
+use crate::{
+    Cache,
+    CacheCollection,
+    CacheSchema,
+    Collection,
+    Document,
+    DocumentDelta,
+    DocumentVersion,
+    Schema,
+    Store,
+    Webhook,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use failure::Error;
+use flate2::{
+    read::GzDecoder,
+    write::GzEncoder,
+    Compression,
+};
+use futures::{
+    channel::mpsc,
+    executor::block_on,
+    stream,
+    SinkExt,
+    StreamExt,
+};
+use slog::Logger;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fs,
+    fs::File,
+    io::{
+        Read,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+use tokio::task;
+use uuid::Uuid;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const DOCUMENTS_FOLDER: &str = "documents";
+const HISTORY_FOLDER: &str = "history";
+/// Bumped whenever the layout of a snapshot changes. Version 2 added the
+/// history of `@versioned` collections and put the manifest first
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// A consistent copy of all schemas, collections, documents and webhooks at
+/// one point in time
+pub struct Snapshot {
+    pub created_at: DateTime<Utc>,
+    pub schemas: Vec<SnapshotSchema>,
+    pub webhooks: Vec<Webhook>,
+}
+
+pub struct SnapshotSchema {
+    pub schema: Schema,
+    pub collections: Vec<SnapshotCollection>,
+}
+
+pub struct SnapshotCollection {
+    pub collection: Collection,
+    documents: SnapshotDocuments,
+}
+
+/// The documents of a collection as a snapshot captured them
+pub enum SnapshotDocuments {
+    /// Copied from memory
+    Resident(Vec<Arc<Document>>),
+    /// Only in the store, which keeps them as they are for as long as the pin
+    /// is held
+    Stored {
+        count: usize,
+        pin: Mutex<Option<Box<dyn Send + Sync>>>,
+    },
+}
+
+impl SnapshotDocuments {
+    pub fn stored(count: usize, pin: Box<dyn Send + Sync>) -> Self {
+        Self::Stored {
+            count,
+            pin: Mutex::new(Some(pin)),
+        }
+    }
+}
+
+impl SnapshotSchema {
+    /// Copies a schema and all its collections. The caller has to make sure
+    /// nothing is written to the schema while this runs
+    pub async fn capture<S: CacheSchema>(schema: &S) -> Self {
+        let mut collections = vec![];
+
+        let cache_collections: Vec<_> = schema.collections().collect().await;
+        for collection in cache_collections {
+            collections.push(SnapshotCollection::capture(&collection).await);
+        }
+
+        Self {
+            schema: schema.inner_schema().await,
+            collections,
+        }
+    }
+}
+
+impl SnapshotCollection {
+    pub fn new(collection: Collection, documents: Vec<Arc<Document>>) -> Self {
+        Self {
+            collection,
+            documents: SnapshotDocuments::Resident(documents),
+        }
+    }
+
+    /// Copies a single collection, which is always consistent on its own since
+    /// writes to a collection are published all at once. Documents that are
+    /// only in the store are not read until `read_documents`
+    pub async fn capture<C: CacheCollection>(collection: &C) -> Self {
+        Self {
+            collection: collection.inner_collection().await,
+            documents: collection.snapshot_documents().await,
+        }
+    }
+
+    pub fn document_count(&self) -> usize {
+        match &self.documents {
+            SnapshotDocuments::Resident(documents) => documents.len(),
+            SnapshotDocuments::Stored { count, .. } => *count,
+        }
+    }
+
+    fn is_stored(&self) -> bool {
+        matches!(self.documents, SnapshotDocuments::Stored { .. })
+    }
+
+    /// Returns the captured documents, reading them from the store if they
+    /// were not in memory. The store is only kept from changing them until
+    /// they are read, so they can only be read from the store once
+    ///
+    /// # Errors
+    /// Returns an error if the documents can not be read, or have already
+    /// been read from the store
+    pub async fn read_documents<S: Store>(
+        &self,
+        logger: &Logger,
+        store: &S,
+        schema: &Schema,
+    ) -> Result<Vec<Arc<Document>>, Error> {
+        let (count, pin) = match &self.documents {
+            SnapshotDocuments::Resident(documents) => return Ok(documents.clone()),
+            SnapshotDocuments::Stored { count, pin } => (*count, pin),
+        };
+        if pin.lock().expect("Lock was poisoned").is_none() {
+            bail!(
+                "The documents of the collection {} have already been read from the store",
+                self.collection.name
+            );
+        }
+
+        let documents = store.get_documents(&logger, schema, &self.collection).await;
+        // The collection can be read back in and written to again
+        pin.lock().expect("Lock was poisoned").take();
+        let documents = documents?;
+
+        if documents.len() != count {
+            bail!(
+                "The store holds {} documents of the collection {}, but {} were captured",
+                documents.len(),
+                self.collection.name,
+                count
+            );
+        }
+        Ok(documents.into_iter().map(Arc::new).collect())
+    }
+}
+
+/// What was read back from a snapshot by `Snapshot::restore`
+pub struct RestoreReport {
+    pub created_at: DateTime<Utc>,
+    pub collections: usize,
+    pub documents: usize,
+}
+
+/// Everything but the documents and the history, which are kept in one file
+/// per collection next to the manifest
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    created_at: DateTime<Utc>,
+    schemas: Vec<ManifestSchema>,
+    webhooks: Vec<Webhook>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestSchema {
+    schema: Schema,
+    collections: Vec<Collection>,
+}
+
+/// Where a snapshot is written, decided by the file name
+#[derive(Clone, Copy)]
+enum Target {
+    Folder,
+    Tar,
+    TarGz,
+}
+
+#[derive(PartialEq)]
+enum EntryKind {
+    Documents,
+    History,
+}
+
+/// A file of a snapshot, named relative to the root of the snapshot
+type Entry = Result<(PathBuf, Vec<u8>), Error>;
+
+impl Snapshot {
+    /// Copies everything in the cache. The caller has to make sure nothing is
+    /// written to the cache while this runs
+    pub async fn capture<C: Cache>(cache: &C, webhooks: Vec<Webhook>) -> Self {
+        let mut schemas = vec![];
+
+        let cache_schemas: Vec<_> = cache.schemas().collect().await;
+        for schema in cache_schemas {
+            schemas.push(SnapshotSchema::capture(&schema).await);
+        }
+
+        Self {
+            created_at: Utc::now(),
+            schemas,
+            webhooks,
+        }
+    }
+
+    pub fn collection_count(&self) -> usize {
+        self.schemas.iter().map(|i| i.collections.len()).sum()
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.schemas
+            .iter()
+            .flat_map(|i| &i.collections)
+            .map(SnapshotCollection::document_count)
+            .sum()
+    }
+
+    /// Writes the snapshot to a folder, or to a tar archive if the path ends
+    /// with `.tar`, `.tar.gz` or `.tgz`. The documents are written a
+    /// collection at a time, together with the history of `@versioned`
+    /// collections which is read from the store. Every file is sealed by the
+    /// store, so the snapshot is encrypted whenever the store is. Existing
+    /// files are never overwritten
+    ///
+    /// # Errors
+    /// Returns an error if the path already exists or can not be written. A
+    /// failed write leaves nothing at the path
+    pub async fn write<S: Store>(
+        &self,
+        logger: &Logger,
+        store: &S,
+        path: &Path,
+    ) -> Result<(), Error> {
+        if path.exists() {
+            bail!("{} already exists", path.display());
+        }
+
+        // Everything is written next to the path first and moved into place
+        // once complete
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push("~tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        match self
+            .write_files(logger, store, &temp_path, target(path))
+            .await
+        {
+            Ok(()) => {
+                fs::rename(&temp_path, path)?;
+                sync_parent(path)
+            }
+            Err(err) => {
+                if temp_path.is_dir() {
+                    let _ = fs::remove_dir_all(&temp_path);
+                } else {
+                    let _ = fs::remove_file(&temp_path);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn write_files<S: Store>(
+        &self,
+        logger: &Logger,
+        store: &S,
+        path: &Path,
+        target: Target,
+    ) -> Result<(), Error> {
+        let manifest = Manifest {
+            version: SNAPSHOT_VERSION,
+            created_at: self.created_at,
+            schemas: self
+                .schemas
+                .iter()
+                .map(|i| ManifestSchema {
+                    schema: i.schema.clone(),
+                    collections: i.collections.iter().map(|i| i.collection.clone()).collect(),
+                })
+                .collect(),
+            webhooks: self.webhooks.clone(),
+        };
+
+        // The manifest goes first, so that a snapshot can be restored while
+        // it is read
+        let mut writer = SnapshotWriter::create(path.to_owned(), target).await?;
+        let data = store.seal_backup(serde_json::to_vec_pretty(&manifest)?)?;
+        writer = writer.add(PathBuf::from(MANIFEST_FILE), data).await?;
+
+        // Collections only in the store can not be read back in until they are
+        // written, so they go first
+        let mut collections: Vec<_> = self
+            .schemas
+            .iter()
+            .flat_map(|schema| schema.collections.iter().map(move |i| (schema, i)))
+            .collect();
+        collections.sort_by_key(|(_, collection)| !collection.is_stored());
+
+        for (schema, collection) in collections {
+            let id = collection.collection.id;
+            let documents = collection
+                .read_documents(logger, store, &schema.schema)
+                .await?;
+            let count = documents.len();
+            let data = task::spawn_blocking(move || serde_json::to_vec(&documents)).await??;
+            writer = writer
+                .add(documents_path(id), store.seal_backup(data)?)
+                .await?;
+
+            if schema.schema.is_versioned(&collection.collection.name) {
+                let mut history = store
+                    .get_collection_history(&logger, &schema.schema, &collection.collection)
+                    .await?;
+                // Versions written after the snapshot was captured are
+                // left out, the documents they belong to are too
+                history.retain(|i| i.timestamp <= self.created_at);
+                let data = task::spawn_blocking(move || serde_json::to_vec(&history)).await??;
+                writer = writer
+                    .add(history_path(id), store.seal_backup(data)?)
+                    .await?;
+            }
+
+            trace!(logger, "Wrote collection {} to snapshot", collection.collection.name; "documents" => count);
+        }
+
+        writer.finish().await
+    }
+
+    /// Restores a snapshot written by `write` into the store, a collection
+    /// at a time so that a large snapshot is never held in memory. The store
+    /// has to be empty, so that nothing is mixed with the restored data
+    ///
+    /// # Errors
+    /// Returns an error if the store already holds data, or if the snapshot
+    /// is incomplete or can not be read
+    pub async fn restore<S: Store>(
+        logger: &Logger,
+        store: &S,
+        path: &Path,
+    ) -> Result<RestoreReport, Error> {
+        if !store.get_schemas(&logger).await?.is_empty() {
+            bail!(
+                "The store already holds data, a snapshot can only be restored into an empty store"
+            );
+        }
+
+        let mut entries = read_entries(path.to_owned());
+
+        // Snapshots written before version 2 have the manifest last
+        let mut early = vec![];
+        let manifest: Manifest = loop {
+            match entries.next().await {
+                Some(entry) => {
+                    let (name, data) = entry?;
+                    if name == Path::new(MANIFEST_FILE) {
+                        break serde_json::from_slice(&store.open_backup(data)?)?;
+                    }
+                    early.push(Ok((name, data)));
+                }
+                None => {
+                    bail!("The snapshot has no manifest, it is either incomplete or not a snapshot")
+                }
+            }
+        };
+        if manifest.version > SNAPSHOT_VERSION {
+            bail!(
+                "The snapshot was written by a newer version of shelf, version {} is not supported",
+                manifest.version
+            );
+        }
+
+        let mut collections = HashMap::new();
+        for schema in &manifest.schemas {
+            store.save_schema(&logger, &schema.schema).await?;
+            for collection in &schema.collections {
+                store
+                    .save_collection(&logger, &schema.schema, collection)
+                    .await?;
+                collections.insert(collection.id, (&schema.schema, collection));
+            }
+        }
+        store.save_webhooks(&logger, &manifest.webhooks).await?;
+        store.flush(&logger).await?;
+
+        let mut report = RestoreReport {
+            created_at: manifest.created_at,
+            collections: 0,
+            documents: 0,
+        };
+        let mut restored = HashSet::new();
+        let mut entries = stream::iter(early).chain(entries);
+        while let Some(entry) = entries.next().await {
+            let (name, data) = entry?;
+            let (kind, id) = match entry_kind(&name) {
+                Some(found) => found,
+                None => continue,
+            };
+            let (schema, collection) = match collections.get(&id) {
+                Some(found) => *found,
+                None => bail!(
+                    "The snapshot holds {} which belongs to no collection in the manifest",
+                    name.display()
+                ),
+            };
+            let data = store.open_backup(data)?;
+
+            if kind == EntryKind::History {
+                let versions: Vec<DocumentVersion> = serde_json::from_slice(&data)?;
+                store
+                    .save_history(&logger, schema, collection, &versions)
+                    .await?;
+                continue;
+            }
+
+            let documents: Vec<Arc<Document>> =
+                task::spawn_blocking(move || serde_json::from_slice(&data)).await??;
+            report.collections += 1;
+            report.documents += documents.len();
+            restored.insert(id);
+
+            let delta = DocumentDelta {
+                upserted: documents,
+                deleted: vec![],
+            };
+            store.save_delta(&logger, schema, collection, delta).await?;
+            store.flush(&logger).await?;
+            debug!(logger, "Restored collection {}", collection.name; "schema_name" => &schema.name);
+        }
+
+        if let Some((_, collection)) = collections.values().find(|i| !restored.contains(&i.1.id)) {
+            bail!(
+                "The snapshot is missing the documents of the collection {}",
+                collection.name
+            );
+        }
+
+        Ok(report)
+    }
+}
+
+/// Writes the files of a snapshot one at a time, syncing each of them to
+/// disk. The writes are made on a blocking thread
+enum SnapshotWriter {
+    Folder(PathBuf),
+    Tar(tar::Builder<File>),
+    TarGz(tar::Builder<GzEncoder<File>>),
+}
+
+impl SnapshotWriter {
+    async fn create(path: PathBuf, target: Target) -> Result<Self, Error> {
+        task::spawn_blocking(move || -> Result<Self, Error> {
+            Ok(match target {
+                Target::Folder => {
+                    fs::create_dir_all(path.join(DOCUMENTS_FOLDER))?;
+                    fs::create_dir_all(path.join(HISTORY_FOLDER))?;
+                    Self::Folder(path)
+                }
+                Target::Tar => Self::Tar(tar::Builder::new(File::create(&path)?)),
+                Target::TarGz => Self::TarGz(tar::Builder::new(GzEncoder::new(
+                    File::create(&path)?,
+                    Compression::default(),
+                ))),
+            })
+        })
+        .await?
+    }
+
+    async fn add(mut self, name: PathBuf, data: Vec<u8>) -> Result<Self, Error> {
+        task::spawn_blocking(move || -> Result<Self, Error> {
+            match &mut self {
+                Self::Folder(path) => {
+                    let mut file = File::create(path.join(&name))?;
+                    file.write_all(&data)?;
+                    file.sync_all()?;
+                }
+                Self::Tar(builder) => append(builder, &name, &data)?,
+                Self::TarGz(builder) => append(builder, &name, &data)?,
+            }
+            Ok(self)
+        })
+        .await?
+    }
+
+    /// Completes the snapshot and syncs what is not on disk yet
+    async fn finish(self) -> Result<(), Error> {
+        task::spawn_blocking(move || -> Result<(), Error> {
+            match self {
+                Self::Folder(path) => {
+                    sync_dir(&path.join(DOCUMENTS_FOLDER))?;
+                    sync_dir(&path.join(HISTORY_FOLDER))?;
+                    sync_dir(&path)?;
+                }
+                Self::Tar(builder) => builder.into_inner()?.sync_all()?,
+                Self::TarGz(builder) => builder.into_inner()?.finish()?.sync_all()?,
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
+
+fn append<W: Write>(builder: &mut tar::Builder<W>, name: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+fn sync_dir(path: &Path) -> Result<(), Error> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+fn sync_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => sync_dir(Path::new(".")),
+        Some(parent) => sync_dir(parent),
+        None => Ok(()),
+    }
+}
+
+fn documents_path(collection_id: Uuid) -> PathBuf {
+    Path::new(DOCUMENTS_FOLDER).join(format!("{}.json", collection_id))
+}
+
+fn history_path(collection_id: Uuid) -> PathBuf {
+    Path::new(HISTORY_FOLDER).join(format!("{}.json", collection_id))
+}
+
+/// Tells what a file of a snapshot holds, and for which collection
+fn entry_kind(name: &Path) -> Option<(EntryKind, Uuid)> {
+    let kind = match name.parent()?.to_str()? {
+        DOCUMENTS_FOLDER => EntryKind::Documents,
+        HISTORY_FOLDER => EntryKind::History,
+        _ => return None,
+    };
+    let id = Uuid::parse_str(name.file_stem()?.to_str()?).ok()?;
+    Some((kind, id))
+}
+
+fn target(path: &Path) -> Target {
+    let name = path
+        .file_name()
+        .map(|i| i.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Target::TarGz
+    } else if name.ends_with(".tar") {
+        Target::Tar
+    } else {
+        Target::Folder
+    }
+}
+
+/// Reads the files of a snapshot on a blocking thread and hands them over
+/// one at a time, the next file is only read once the previous one is taken
+fn read_entries(path: PathBuf) -> mpsc::Receiver<Entry> {
+    let (mut sender, receiver) = mpsc::channel(0);
+
+    task::spawn_blocking(move || {
+        let result = match target(&path) {
+            Target::Folder => read_folder(&path, &mut sender),
+            Target::Tar => File::open(&path)
+                .map_err(Error::from)
+                .and_then(|file| read_tar(file, &mut sender)),
+            Target::TarGz => File::open(&path)
+                .map_err(Error::from)
+                .and_then(|file| read_tar(GzDecoder::new(file), &mut sender)),
+        };
+        if let Err(err) = result {
+            // Nobody listening means the restore has already failed
+            let _ = block_on(sender.send(Err(err)));
+        }
+    });
+
+    receiver
+}
+
+fn send_entry(sender: &mut mpsc::Sender<Entry>, name: PathBuf, data: Vec<u8>) -> Result<(), Error> {
+    block_on(sender.send(Ok((name, data))))
+        .map_err(|_| format_err!("The snapshot is no longer being read"))
+}
+
+fn read_folder(path: &Path, sender: &mut mpsc::Sender<Entry>) -> Result<(), Error> {
+    let mut names = vec![];
+    if path.join(MANIFEST_FILE).is_file() {
+        names.push(PathBuf::from(MANIFEST_FILE));
+    }
+    for folder in &[DOCUMENTS_FOLDER, HISTORY_FOLDER] {
+        if path.join(folder).is_dir() {
+            for entry in fs::read_dir(path.join(folder))? {
+                names.push(Path::new(folder).join(entry?.file_name()));
+            }
+        }
+    }
+
+    for name in names {
+        let data = fs::read(path.join(&name))?;
+        send_entry(sender, name, data)?;
+    }
+
+    Ok(())
+}
+
+fn read_tar<R: Read>(reader: R, sender: &mut mpsc::Sender<Entry>) -> Result<(), Error> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_path_buf();
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+        send_entry(sender, name, data)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        snapshot::{
+            SnapshotCollection,
+            SnapshotSchema,
+        },
+        test::TestStore,
+        Collection,
+        Document,
+        DocumentVersion,
+        Schema,
+        Snapshot,
+        Store,
+    };
+    use chrono::{
+        Duration,
+        Utc,
+    };
+    use slog::{
+        Discard,
+        Logger,
+    };
+    use std::{
+        collections::HashMap,
+        fs,
+        sync::Arc,
+    };
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    const TEST_GRAPHQL_SCHEMA: &str = r#"
+        directive @collection on OBJECT
+        directive @versioned on OBJECT
+
+        scalar Uuid
+
+        type Car @collection @versioned {
+            id: Uuid!
+        }
+    "#;
+
+    /// A snapshot of a versioned collection, with its history in the store
+    async fn snapshot(logger: &Logger, store: &TestStore) -> Snapshot {
+        let mut schema = Schema::new(Uuid::new_v4(), "shelf", None);
+        schema
+            .graphql_schemas
+            .insert(0, TEST_GRAPHQL_SCHEMA.to_string());
+        let collection = Collection::new("Car".to_string(), None);
+        let documents = vec![
+            Arc::new(Document::new(Uuid::new_v4(), HashMap::new())),
+            Arc::new(Document::new(Uuid::new_v4(), HashMap::new())),
+        ];
+
+        let created_at = Utc::now();
+        let version = |document: &Document, timestamp| DocumentVersion {
+            document_id: document.id,
+            revision: 1,
+            timestamp,
+            document: Some(document.clone()),
+        };
+        let versions = vec![
+            version(&documents[0], created_at),
+            // Written after the snapshot was captured
+            version(&documents[1], created_at + Duration::seconds(1)),
+        ];
+        store
+            .save_history(&logger, &schema, &collection, &versions)
+            .await
+            .unwrap();
+
+        Snapshot {
+            created_at,
+            schemas: vec![SnapshotSchema {
+                schema,
+                collections: vec![SnapshotCollection::new(collection, documents)],
+            }],
+            webhooks: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshots_should_restore_the_same_from_folders_and_archives() {
+        let logger = Logger::root(Discard, o!());
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+        let store = TestStore::default();
+        let snapshot = snapshot(&logger, &store).await;
+        let schema = &snapshot.schemas[0].schema;
+        let collection = &snapshot.schemas[0].collections[0].collection;
+
+        for name in &["folder", "archive.tar", "archive.tar.gz"] {
+            snapshot
+                .write(&logger, &store, &path.join(name))
+                .await
+                .unwrap();
+
+            let restored = TestStore::default();
+            let report = Snapshot::restore(&logger, &restored, &path.join(name))
+                .await
+                .unwrap();
+
+            assert_eq!(report.created_at, snapshot.created_at);
+            assert_eq!(report.collections, 1);
+            assert_eq!(report.documents, 2);
+            assert_eq!(
+                restored
+                    .get_documents(&logger, schema, collection)
+                    .await
+                    .unwrap()
+                    .len(),
+                2
+            );
+            assert_eq!(
+                restored
+                    .get_collection_history(&logger, schema, collection)
+                    .await
+                    .unwrap()
+                    .len(),
+                1,
+                "Only the history up to the snapshot should be kept"
+            );
+            assert!(
+                snapshot
+                    .write(&logger, &store, &path.join(name))
+                    .await
+                    .is_err(),
+                "An existing snapshot should never be overwritten"
+            );
+        }
+
+        assert!(path.join("folder").join("manifest.json").is_file());
+        let tar = fs::read(path.join("archive.tar")).unwrap();
+        assert_eq!(&tar[257..262], b"ustar");
+        let tar_gz = fs::read(path.join("archive.tar.gz")).unwrap();
+        assert_eq!(&tar_gz[..2], &[0x1f, 0x8b]);
+    }
+
+    #[tokio::test]
+    async fn restore_should_refuse_a_store_that_holds_data() {
+        let logger = Logger::root(Discard, o!());
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("backup.tar");
+        let store = TestStore::default();
+        let snapshot = snapshot(&logger, &store).await;
+        snapshot.write(&logger, &store, &path).await.unwrap();
+
+        let target = TestStore::default();
+        let existing = Schema::new(Uuid::new_v4(), "existing", None);
+        target.save_schema(&logger, &existing).await.unwrap();
+
+        assert!(Snapshot::restore(&logger, &target, &path).await.is_err());
+        assert_eq!(target.get_schemas(&logger).await.unwrap().len(), 1);
+    }
+}
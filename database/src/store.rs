@@ -1,15 +1,23 @@
 use crate::{
     Collection,
+    CompactionReport,
+    DeadLetter,
     Document,
+    DocumentDelta,
+    DocumentVersion,
     Schema,
+    Transaction,
+    Webhook,
 };
 use failure::Error;
-use futures::future::BoxFuture;
-use slog::Logger;
-use std::{
-    collections::HashMap,
-    sync::Arc,
+use futures::{
+    future::BoxFuture,
+    stream::BoxStream,
+    FutureExt,
+    StreamExt,
 };
+use slog::Logger;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 pub trait Store: Sync + Send + 'static {
@@ -28,6 +36,21 @@ pub trait Store: Sync + Send + 'static {
         schema: &'a Schema,
         collection: &'a Collection,
     ) -> BoxFuture<'a, Result<Vec<Document>, Error>>;
+    /// Yields the documents of a collection a batch at a time, so that a
+    /// large collection is never held twice while it is loaded. A document
+    /// can show up in more than one batch, the one with the highest revision
+    /// is the current one. Stores that can not do better yield all documents
+    /// as a single batch
+    fn stream_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxStream<'a, Result<Vec<Document>, Error>> {
+        self.get_documents(logger, schema, collection)
+            .into_stream()
+            .boxed()
+    }
     fn save_schema<'a>(
         &'a self,
         logger: &'a Logger,
@@ -39,12 +62,88 @@ pub trait Store: Sync + Send + 'static {
         schema: &'a Schema,
         collection: &'a Collection,
     ) -> BoxFuture<'a, Result<(), Error>>;
-    fn save_document<'a>(
+    /// Hands over the documents that changed in a collection since the last
+    /// save, they are written on the next flush
+    fn save_delta<'a>(
         &'a self,
         logger: &'a Logger,
         schema: &'a Schema,
         collection: &'a Collection,
-        document: Arc<Document>,
+        delta: DocumentDelta,
     ) -> BoxFuture<'a, Result<(), Error>>;
     fn flush<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<(), Error>>;
+    fn get_webhooks<'a>(&'a self, logger: &'a Logger)
+        -> BoxFuture<'a, Result<Vec<Webhook>, Error>>;
+    /// Replaces all stored webhooks, unlike documents this is written right
+    /// away since registrations are rare
+    fn save_webhooks<'a>(
+        &'a self,
+        logger: &'a Logger,
+        webhooks: &'a [Webhook],
+    ) -> BoxFuture<'a, Result<(), Error>>;
+    fn save_dead_letter<'a>(
+        &'a self,
+        logger: &'a Logger,
+        dead_letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+    /// Appends versions to the history of a `@versioned` collection, and
+    /// makes sure they are on disk before returning. The versions are in the
+    /// write-ahead log as well and are saved again when it is replayed, so
+    /// only a single copy of each revision should be kept
+    fn save_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+        versions: &'a [DocumentVersion],
+    ) -> BoxFuture<'a, Result<(), Error>>;
+    /// Returns every stored version of a document, ordered by revision
+    fn get_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+        id: Uuid,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>>;
+    /// Returns every stored version of every document in a collection,
+    /// including the documents that have since been deleted
+    fn get_collection_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>>;
+    /// Appends a committed transaction to the write-ahead log. Depending on
+    /// the fsync policy this only returns once the transaction is on disk
+    fn append_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+    /// Returns every transaction in the write-ahead log, oldest first
+    fn read_wal<'a>(&'a self, logger: &'a Logger)
+        -> BoxFuture<'a, Result<Vec<Transaction>, Error>>;
+    /// Starts a new write-ahead log segment, and returns the id of the
+    /// segment that was closed. Everything in that segment and earlier ones
+    /// is in the cache by the time this returns
+    fn rotate_wal<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<u64, Error>>;
+    /// Removes all write-ahead log segments up to and including `segment`,
+    /// should only be called once their content has been flushed
+    fn truncate_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+        segment: u64,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+    /// Tidies up what repeated saves leave behind, like half empty chunks.
+    /// Must not run at the same time as a flush
+    fn compact<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<CompactionReport, Error>>;
+    /// Encrypts a file of a backup the same way the store encrypts its own
+    /// files. Stores that do not encrypt hand the data back as it is
+    fn seal_backup(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        Ok(data)
+    }
+    /// Decrypts a file of a backup sealed by `seal_backup`
+    fn open_backup(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        Ok(data)
+    }
 }
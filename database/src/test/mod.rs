@@ -1,7 +1,12 @@
+mod store_fixture;
 mod test_cache;
 mod test_store;
 
 pub use self::{
+    store_fixture::StoreFixture,
     test_cache::*,
-    test_store::TestStore,
+    test_store::{
+        TestStore,
+        WalPause,
+    },
 };
@@ -0,0 +1,77 @@
+use crate::{
+    Collection,
+    Document,
+    DocumentDelta,
+    Schema,
+    Store,
+};
+use slog::{
+    Discard,
+    Logger,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A schema with a single collection, for the tests of the stores. The
+/// fixture outlives the stores it is saved to, so that a test can open a store
+/// again and read back what the last one wrote
+pub struct StoreFixture {
+    pub logger: Logger,
+    pub schema: Schema,
+    pub collection: Collection,
+}
+
+impl StoreFixture {
+    pub fn new() -> Self {
+        Self {
+            logger: Logger::root(Discard, o!()),
+            schema: Schema::new(Uuid::new_v4(), "shelf", None),
+            collection: Collection::new("Car".to_string(), None),
+        }
+    }
+
+    /// Saves the schema and the collection to a store
+    pub async fn create<S: Store>(&self, store: &S) {
+        store.save_schema(&self.logger, &self.schema).await.unwrap();
+        store
+            .save_collection(&self.logger, &self.schema, &self.collection)
+            .await
+            .unwrap();
+    }
+
+    pub async fn save<S: Store>(
+        &self,
+        store: &S,
+        upserted: Vec<Arc<Document>>,
+        deleted: Vec<Uuid>,
+    ) {
+        let delta = DocumentDelta { upserted, deleted };
+        store
+            .save_delta(&self.logger, &self.schema, &self.collection, delta)
+            .await
+            .unwrap();
+    }
+
+    pub async fn save_and_flush<S: Store>(
+        &self,
+        store: &S,
+        upserted: Vec<Arc<Document>>,
+        deleted: Vec<Uuid>,
+    ) {
+        self.save(store, upserted, deleted).await;
+        store.flush(&self.logger).await.unwrap();
+    }
+
+    pub async fn documents<S: Store>(&self, store: &S) -> Vec<Document> {
+        store
+            .get_documents(&self.logger, &self.schema, &self.collection)
+            .await
+            .unwrap()
+    }
+}
+
+impl Default for StoreFixture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
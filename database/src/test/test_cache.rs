@@ -2,11 +2,15 @@ use crate::{
     Cache,
     CacheCollection,
     CacheSchema,
+    Change,
     Collection,
     Document,
     DocumentResult,
+    MemoryUsage,
     Schema,
+    SnapshotDocuments,
     Store,
+    Transaction,
 };
 use failure::Error;
 use futures::{
@@ -72,10 +76,23 @@ impl Cache for TestCache {
         unimplemented!()
     }
 
+    fn apply_transaction<'a, S: Store>(
+        &'a self,
+        _logger: &'a Logger,
+        _transaction: &'a Transaction,
+        _store: &'a S,
+    ) -> BoxFuture<'a, Result<Transaction, Error>> {
+        unimplemented!()
+    }
+
     fn cache_size(&self) -> BoxFuture<usize> {
         unimplemented!()
     }
 
+    fn memory_usage(&self) -> BoxFuture<MemoryUsage> {
+        unimplemented!()
+    }
+
     fn is_empty(&self) -> BoxFuture<bool> {
         unimplemented!()
     }
@@ -83,6 +100,10 @@ impl Cache for TestCache {
     fn on_schema_updates(&self) -> Receiver<()> {
         unimplemented!()
     }
+
+    fn changes(&self) -> Receiver<Change> {
+        unimplemented!()
+    }
 }
 
 impl CacheSchema for TestCacheSchema {
@@ -114,7 +135,11 @@ impl CacheSchema for TestCacheSchema {
 }
 
 impl CacheCollection for TestCacheCollection {
-    fn set_document(&self, _document: Document) -> BoxFuture<()> {
+    fn set_document(&self, _document: Document) -> BoxFuture<Result<(), Error>> {
+        unimplemented!()
+    }
+
+    fn delete_document(&self, _id: Uuid) -> BoxFuture<Result<Option<Arc<Document>>, Error>> {
         unimplemented!()
     }
 
@@ -126,11 +151,11 @@ impl CacheCollection for TestCacheCollection {
         unimplemented!()
     }
 
-    fn documents<'a>(&'a self) -> BoxFuture<'a, Box<dyn DocumentResult + 'a>> {
+    fn documents<'a>(&'a self) -> BoxFuture<'a, Result<Box<dyn DocumentResult + 'a>, Error>> {
         unimplemented!()
     }
 
-    fn document(&self, _id: Uuid) -> BoxFuture<Option<Arc<Document>>> {
+    fn document(&self, _id: Uuid) -> BoxFuture<Result<Option<Arc<Document>>, Error>> {
         unimplemented!()
     }
 
@@ -138,7 +163,7 @@ impl CacheCollection for TestCacheCollection {
         &self,
         _field_name: &str,
         _field_value: &str,
-    ) -> BoxFuture<Option<Arc<Document>>> {
+    ) -> BoxFuture<Result<Option<Arc<Document>>, Error>> {
         unimplemented!()
     }
 
@@ -146,7 +171,11 @@ impl CacheCollection for TestCacheCollection {
         &'a self,
         _field_name: &'a str,
         _field_value: &'a str,
-    ) -> BoxFuture<'a, Box<dyn DocumentResult + 'a>> {
+    ) -> BoxFuture<'a, Result<Box<dyn DocumentResult + 'a>, Error>> {
+        unimplemented!()
+    }
+
+    fn snapshot_documents(&self) -> BoxFuture<SnapshotDocuments> {
         unimplemented!()
     }
 }
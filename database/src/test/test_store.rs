@@ -1,70 +1,345 @@
 use crate::{
     Collection,
+    CompactionReport,
+    DeadLetter,
     Document,
+    DocumentDelta,
+    DocumentVersion,
     Schema,
     Store,
+    Transaction,
+    Webhook,
 };
 use failure::Error;
 use futures::{
+    channel::oneshot,
     future::BoxFuture,
+    stream::{
+        self,
+        BoxStream,
+    },
     FutureExt,
+    StreamExt,
 };
 use slog::Logger;
 use std::{
     collections::HashMap,
-    sync::Arc,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
 };
 use uuid::Uuid;
 
-#[derive(Clone)]
-pub struct TestStore;
+/// Keeps everything in memory, nothing survives the store being dropped
+#[derive(Clone, Default)]
+pub struct TestStore {
+    schemas: Arc<Mutex<HashMap<Uuid, Schema>>>,
+    collections: Arc<Mutex<HashMap<Uuid, Vec<Collection>>>>,
+    /// Keyed by collection id
+    documents: Arc<Mutex<HashMap<Uuid, HashMap<Uuid, Arc<Document>>>>>,
+    webhooks: Arc<Mutex<Vec<Webhook>>>,
+    /// Keyed by collection id
+    history: Arc<Mutex<HashMap<Uuid, Vec<DocumentVersion>>>>,
+    /// Streamed instead of the documents, keyed by collection id
+    batches: Arc<Mutex<HashMap<Uuid, Vec<Vec<Document>>>>>,
+    wal: Arc<Mutex<Wal>>,
+    failing_wal: bool,
+    failing_history: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Wal {
+    segment: u64,
+    /// With the segment each was appended to
+    transactions: Vec<(u64, Transaction)>,
+    /// Makes the next append wait, once it is written, until it is released
+    pause: Option<(oneshot::Sender<()>, oneshot::Receiver<()>)>,
+}
+
+/// Returned by `TestStore::pause_wal`
+pub struct WalPause {
+    /// Told once the paused transaction is in the write-ahead log
+    pub appended: oneshot::Receiver<()>,
+    /// Lets the paused append return
+    pub release: oneshot::Sender<()>,
+}
+
+impl TestStore {
+    /// A store that fails every append to the write-ahead log
+    pub fn failing_wal() -> Self {
+        Self {
+            failing_wal: true,
+            ..Self::default()
+        }
+    }
+
+    /// Makes every save of history fail until it is turned off again
+    pub fn fail_history(&self, failing: bool) {
+        self.failing_history.store(failing, Ordering::SeqCst);
+    }
+
+    /// The versions saved for a collection, in the order they were saved
+    pub fn saved_history(&self, collection_id: Uuid) -> Vec<DocumentVersion> {
+        self.history
+            .lock()
+            .unwrap()
+            .get(&collection_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Makes the next append to the write-ahead log wait after the
+    /// transaction is written, until it is released
+    pub fn pause_wal(&self) -> WalPause {
+        let (appended_sender, appended) = oneshot::channel();
+        let (release, released) = oneshot::channel();
+        self.wal.lock().unwrap().pause = Some((appended_sender, released));
+        WalPause { appended, release }
+    }
+
+    /// The transactions in the write-ahead log that have not been truncated
+    pub fn wal_transactions(&self) -> Vec<Transaction> {
+        self.wal
+            .lock()
+            .unwrap()
+            .transactions
+            .iter()
+            .map(|(_, i)| i.clone())
+            .collect()
+    }
+
+    /// The documents saved for a collection
+    pub fn saved_documents(&self, collection_id: Uuid) -> Vec<Arc<Document>> {
+        self.documents
+            .lock()
+            .unwrap()
+            .get(&collection_id)
+            .map(|i| i.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Makes `stream_documents` yield the batch as one of its own, the way a
+    /// store yields a chunk
+    pub fn add_batch(&self, collection_id: Uuid, documents: Vec<Document>) {
+        self.batches
+            .lock()
+            .unwrap()
+            .entry(collection_id)
+            .or_default()
+            .push(documents);
+    }
+}
 
 impl Store for TestStore {
     fn get_schemas(&self, _logger: &Logger) -> BoxFuture<Result<HashMap<Uuid, Schema>, Error>> {
-        futures::future::ok(HashMap::new()).boxed()
+        futures::future::ok(self.schemas.lock().unwrap().clone()).boxed()
     }
 
     fn get_collections(
         &self,
         _logger: &Logger,
-        _schema: &Schema,
+        schema: &Schema,
     ) -> BoxFuture<Result<Vec<Collection>, Error>> {
-        futures::future::ok(Vec::new()).boxed()
+        let collections = self.collections.lock().unwrap().get(&schema.id).cloned();
+        futures::future::ok(collections.unwrap_or_default()).boxed()
     }
 
     fn get_documents(
         &self,
         _logger: &Logger,
         _schema: &Schema,
-        _collection: &Collection,
+        collection: &Collection,
     ) -> BoxFuture<Result<Vec<Document>, Error>> {
-        futures::future::ok(Vec::new()).boxed()
+        let documents = match self.documents.lock().unwrap().get(&collection.id) {
+            Some(documents) => documents.values().map(|i| Document::clone(i)).collect(),
+            None => Vec::new(),
+        };
+        futures::future::ok(documents).boxed()
+    }
+
+    fn stream_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxStream<'a, Result<Vec<Document>, Error>> {
+        match self.batches.lock().unwrap().get(&collection.id) {
+            Some(batches) => stream::iter(batches.clone()).map(Ok).boxed(),
+            None => self
+                .get_documents(logger, schema, collection)
+                .into_stream()
+                .boxed(),
+        }
     }
 
-    fn save_schema(&self, _logger: &Logger, _schema: &Schema) -> BoxFuture<Result<(), Error>> {
+    fn save_schema(&self, _logger: &Logger, schema: &Schema) -> BoxFuture<Result<(), Error>> {
+        self.schemas
+            .lock()
+            .unwrap()
+            .insert(schema.id, schema.clone());
         futures::future::ok(()).boxed()
     }
 
     fn save_collection<'a>(
         &'a self,
         _logger: &'a Logger,
-        _schema: &'a Schema,
-        _collection: &'a Collection,
+        schema: &'a Schema,
+        collection: &'a Collection,
     ) -> BoxFuture<Result<(), Error>> {
+        let mut collections = self.collections.lock().unwrap();
+        let list = collections.entry(schema.id).or_default();
+        list.retain(|i| i.id != collection.id);
+        list.push(collection.clone());
         futures::future::ok(()).boxed()
     }
 
-    fn save_document<'a>(
+    fn save_delta<'a>(
         &'a self,
         _logger: &'a Logger,
         _schema: &'a Schema,
-        _collection: &'a Collection,
-        _document: Arc<Document>,
+        collection: &'a Collection,
+        delta: DocumentDelta,
     ) -> BoxFuture<Result<(), Error>> {
+        let mut documents = self.documents.lock().unwrap();
+        let documents = documents.entry(collection.id).or_default();
+        for document in delta.upserted {
+            documents.insert(document.id, document);
+        }
+        for id in delta.deleted {
+            documents.remove(&id);
+        }
         futures::future::ok(()).boxed()
     }
 
     fn flush<'a>(&'a self, _logger: &'a Logger) -> BoxFuture<Result<(), Error>> {
         futures::future::ok(()).boxed()
     }
+
+    fn get_webhooks<'a>(&'a self, _logger: &'a Logger) -> BoxFuture<Result<Vec<Webhook>, Error>> {
+        futures::future::ok(self.webhooks.lock().unwrap().clone()).boxed()
+    }
+
+    fn save_webhooks<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        webhooks: &'a [Webhook],
+    ) -> BoxFuture<Result<(), Error>> {
+        *self.webhooks.lock().unwrap() = webhooks.to_vec();
+        futures::future::ok(()).boxed()
+    }
+
+    fn save_dead_letter<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        _dead_letter: &'a DeadLetter,
+    ) -> BoxFuture<Result<(), Error>> {
+        futures::future::ok(()).boxed()
+    }
+
+    fn save_history<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+        versions: &'a [DocumentVersion],
+    ) -> BoxFuture<Result<(), Error>> {
+        if self.failing_history.load(Ordering::SeqCst) {
+            return futures::future::err(format_err!("The history is not writable")).boxed();
+        }
+        self.history
+            .lock()
+            .unwrap()
+            .entry(collection.id)
+            .or_default()
+            .extend_from_slice(versions);
+        futures::future::ok(()).boxed()
+    }
+
+    fn get_history<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+        id: Uuid,
+    ) -> BoxFuture<Result<Vec<DocumentVersion>, Error>> {
+        let mut versions: Vec<_> = self
+            .history
+            .lock()
+            .unwrap()
+            .get(&collection.id)
+            .into_iter()
+            .flatten()
+            .filter(|i| i.document_id == id)
+            .cloned()
+            .collect();
+        versions.sort_by_key(|i| i.revision);
+        futures::future::ok(versions).boxed()
+    }
+
+    fn get_collection_history<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<Result<Vec<DocumentVersion>, Error>> {
+        let versions = self.history.lock().unwrap().get(&collection.id).cloned();
+        futures::future::ok(versions.unwrap_or_default()).boxed()
+    }
+
+    fn append_wal<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<Result<(), Error>> {
+        async move {
+            if self.failing_wal {
+                bail!("The write-ahead log is not writable");
+            }
+            let pause = {
+                let mut wal = self.wal.lock().unwrap();
+                let segment = wal.segment;
+                wal.transactions.push((segment, transaction.clone()));
+                wal.pause.take()
+            };
+            if let Some((appended, released)) = pause {
+                let _ = appended.send(());
+                let _ = released.await;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn read_wal<'a>(&'a self, _logger: &'a Logger) -> BoxFuture<Result<Vec<Transaction>, Error>> {
+        futures::future::ok(self.wal_transactions()).boxed()
+    }
+
+    fn rotate_wal<'a>(&'a self, _logger: &'a Logger) -> BoxFuture<Result<u64, Error>> {
+        let mut wal = self.wal.lock().unwrap();
+        let closed = wal.segment;
+        wal.segment += 1;
+        futures::future::ok(closed).boxed()
+    }
+
+    fn truncate_wal<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        segment: u64,
+    ) -> BoxFuture<Result<(), Error>> {
+        self.wal
+            .lock()
+            .unwrap()
+            .transactions
+            .retain(|(i, _)| *i > segment);
+        futures::future::ok(()).boxed()
+    }
+
+    fn compact<'a>(&'a self, _logger: &'a Logger) -> BoxFuture<Result<CompactionReport, Error>> {
+        futures::future::ok(CompactionReport::default()).boxed()
+    }
 }
@@ -0,0 +1,107 @@
+use serde_json::{
+    Map,
+    Value,
+};
+use std::{
+    collections::HashMap,
+    mem,
+};
+
+/// What a `BTreeMap` spends on its nodes besides the entries. The nodes hold
+/// up to 11 entries and are seldom full, which comes to about a pointer per
+/// entry
+const BTREE_OVERHEAD_PER_ENTRY: usize = mem::size_of::<usize>();
+
+/// The memory a value takes up, including everything it points to
+pub fn value_size(value: &Value) -> usize {
+    mem::size_of::<Value>() + value_heap_size(value)
+}
+
+/// The memory a value points to, not counting the value itself
+pub fn value_heap_size(value: &Value) -> usize {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+        Value::String(value) => value.capacity(),
+        Value::Array(values) => {
+            values.capacity() * mem::size_of::<Value>()
+                + values.iter().map(value_heap_size).sum::<usize>()
+        }
+        Value::Object(object) => object_heap_size(object),
+    }
+}
+
+fn object_heap_size(object: &Map<String, Value>) -> usize {
+    object
+        .iter()
+        .map(|(key, value)| {
+            mem::size_of::<String>() + key.capacity() + value_size(value) + BTREE_OVERHEAD_PER_ENTRY
+        })
+        .sum()
+}
+
+/// The memory the fields of a document point to
+pub fn fields_heap_size(fields: &HashMap<String, Value>) -> usize {
+    // The table is allocated for the whole capacity, with a control byte for
+    // every slot
+    let table = fields.capacity() * (mem::size_of::<(String, Value)>() + 1);
+    table
+        + fields
+            .iter()
+            .map(|(key, value)| key.capacity() + value_heap_size(value))
+            .sum::<usize>()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::deep_size::{
+        fields_heap_size,
+        value_size,
+    };
+    use serde_json::{
+        json,
+        Value,
+    };
+    use std::{
+        collections::HashMap,
+        mem,
+    };
+
+    #[test]
+    fn value_size_should_count_what_strings_arrays_and_objects_hold() {
+        let small = json!({ "name": "a", "tags": [] });
+        let large = json!({ "name": "a".repeat(1000), "tags": ["b", "c", { "d": "e" }] });
+
+        assert!(value_size(&large) >= value_size(&small) + 1000);
+        assert!(value_size(&json!(null)) < value_size(&json!("a".repeat(10))));
+    }
+
+    #[test]
+    fn value_size_should_add_up_the_allocations() {
+        let string = "a".repeat(10);
+        let mut values = Vec::with_capacity(4);
+        values.push(Value::String(string.clone()));
+        values.push(json!(1));
+
+        assert_eq!(value_size(&json!(1)), mem::size_of::<Value>());
+        assert_eq!(
+            value_size(&Value::String(string.clone())),
+            mem::size_of::<Value>() + string.capacity()
+        );
+        assert_eq!(
+            value_size(&Value::Array(values)),
+            mem::size_of::<Value>() + 4 * mem::size_of::<Value>() + string.capacity()
+        );
+    }
+
+    #[test]
+    fn fields_heap_size_should_count_the_table_and_what_it_holds() {
+        let mut fields = HashMap::with_capacity(1);
+        fields.insert("name".to_string(), json!("value"));
+        let capacity = fields.capacity();
+
+        assert_eq!(
+            fields_heap_size(&fields),
+            capacity * (mem::size_of::<(String, Value)>() + 1) + "name".len() + "value".len()
+        );
+    }
+}
@@ -0,0 +1,15 @@
+use crate::Document;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Keeps the document with the highest revision. A store can hand over the
+/// same document more than once, like when its chunks overlap after a crash
+/// in the middle of a split
+pub fn keep_newest(documents: &mut HashMap<Uuid, Document>, document: Document) {
+    match documents.get(&document.id) {
+        Some(current) if current.revision >= document.revision => {}
+        _ => {
+            documents.insert(document.id, document);
+        }
+    }
+}
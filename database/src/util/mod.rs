@@ -1,7 +1,15 @@
+mod deep_size;
 mod extract_graphql_schema;
+mod keep_newest;
+mod save_versions;
+mod validate_document;
 mod validate_graphql_schema_correctness;
 
 pub use self::{
+    deep_size::*,
     extract_graphql_schema::*,
+    keep_newest::*,
+    save_versions::*,
+    validate_document::*,
     validate_graphql_schema_correctness::*,
 };
@@ -0,0 +1,51 @@
+use crate::{
+    Cache,
+    CacheCollection,
+    CacheSchema,
+    DocumentVersion,
+    Store,
+    TransactionVersion,
+};
+use failure::Error;
+use slog::Logger;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Stores versions in the history of their collections. Versions of
+/// collections that no longer exist are dropped. Stores keep a single copy of
+/// each revision, so saving the same versions again is harmless
+pub async fn save_versions<C: Cache, S: Store>(
+    logger: &Logger,
+    cache: &C,
+    store: &S,
+    versions: &[TransactionVersion],
+) -> Result<(), Error> {
+    let mut by_collection: BTreeMap<(Uuid, Uuid), Vec<DocumentVersion>> = BTreeMap::new();
+    for version in versions {
+        by_collection
+            .entry((version.schema_id, version.collection_id))
+            .or_default()
+            .push(version.version.clone());
+    }
+
+    for ((schema_id, collection_id), versions) in by_collection {
+        let schema = match cache.schema(schema_id).await {
+            Some(schema) => schema,
+            None => continue,
+        };
+        let collection = match schema.collection(collection_id).await {
+            Some(collection) => collection,
+            None => continue,
+        };
+        store
+            .save_history(
+                &logger,
+                &schema.inner_schema().await,
+                &collection.inner_collection().await,
+                &versions,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use crate::{
+    Document,
+    Schema,
+};
+use failure::Error;
+use graphql_parser::schema::Type;
+use serde_json::Value;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Makes sure a document follows the definition of its collection, meaning
+/// that all required fields are there, that there are no unknown fields and
+/// that the values have the right types
+pub fn validate_document(
+    schema: &Schema,
+    collection_name: &str,
+    document: &Document,
+) -> Result<(), Error> {
+    let types = match schema.types() {
+        Some(types) => types,
+        None => bail!("The schema \"{}\" has no definition", schema.name),
+    };
+
+    let collection = match types.collections.iter().find(|i| i.name == collection_name) {
+        Some(collection) => collection,
+        None => bail!("The collection \"{}\" does not exist", collection_name),
+    };
+
+    for key in document.fields.keys() {
+        if !collection.fields.iter().any(|i| &i.name == key) {
+            bail!(
+                "The field \"{}\" does not exist on collection \"{}\"",
+                key,
+                collection_name
+            );
+        }
+    }
+
+    for field in collection.fields.iter().filter(|i| i.name != "id") {
+        match (document.fields.get(&field.name), &field.field_type) {
+            (None, Type::NonNullType(_)) | (Some(Value::Null), Type::NonNullType(_)) => {
+                bail!(
+                    "The field \"{}\" is required on collection \"{}\"",
+                    field.name,
+                    collection_name
+                );
+            }
+            (Some(value), field_type) => {
+                if !value_matches_type(value, field_type) {
+                    bail!(
+                        "The field \"{}\" on collection \"{}\" has the wrong type",
+                        field.name,
+                        collection_name
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn value_matches_type(value: &Value, field_type: &Type) -> bool {
+    match (value, field_type) {
+        (Value::Null, Type::NonNullType(_)) => false,
+        (Value::Null, _) => true,
+        (value, Type::NonNullType(inner)) => value_matches_type(value, inner),
+        (Value::Array(values), Type::ListType(inner)) => {
+            values.iter().all(|i| value_matches_type(i, inner))
+        }
+        (_, Type::ListType(_)) => false,
+        (value, Type::NamedType(name)) => match name.as_str() {
+            "String" | "ID" => value.is_string(),
+            "Int" | "i32" => value.is_i64(),
+            "Float" => value.is_number(),
+            "Boolean" => value.is_boolean(),
+            "Uuid" => value.as_str().map_or(false, |i| Uuid::from_str(i).is_ok()),
+            // TODO: Validate enums and nested types
+            _ => true,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        util::validate_document,
+        Document,
+        Schema,
+    };
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    const SCHEMA: &str = r#"
+        directive @collection on OBJECT
+
+        scalar Uuid
+
+        type Car @collection {
+            id: Uuid!
+            brand: String!
+            model: String
+            seats: Int
+        }
+    "#;
+
+    fn schema() -> Schema {
+        let mut schema = Schema::new(Uuid::nil(), "TEST", None);
+        schema.graphql_schemas.insert(0, SCHEMA.to_string());
+        schema
+    }
+
+    fn document(fields: Vec<(&str, Value)>) -> Document {
+        Document::new(
+            Uuid::new_v4(),
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn it_accepts_a_valid_document() {
+        let doc = document(vec![("brand", "Tesla".into()), ("seats", 5.into())]);
+
+        assert!(validate_document(&schema(), "Car", &doc).is_ok());
+    }
+
+    #[test]
+    fn it_throw_if_a_required_field_is_missing() {
+        let doc = document(vec![("model", "Model S".into())]);
+
+        assert_eq!(
+            format!("{}", validate_document(&schema(), "Car", &doc).unwrap_err()),
+            "The field \"brand\" is required on collection \"Car\""
+        );
+    }
+
+    #[test]
+    fn it_throw_if_a_field_is_unknown() {
+        let doc = document(vec![("brand", "Tesla".into()), ("color", "Red".into())]);
+
+        assert_eq!(
+            format!("{}", validate_document(&schema(), "Car", &doc).unwrap_err()),
+            "The field \"color\" does not exist on collection \"Car\""
+        );
+    }
+
+    #[test]
+    fn it_throw_if_a_field_has_the_wrong_type() {
+        let doc = document(vec![("brand", "Tesla".into()), ("seats", "five".into())]);
+
+        assert_eq!(
+            format!("{}", validate_document(&schema(), "Car", &doc).unwrap_err()),
+            "The field \"seats\" on collection \"Car\" has the wrong type"
+        );
+    }
+}
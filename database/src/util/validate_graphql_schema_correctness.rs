@@ -12,7 +12,8 @@ use slog::Logger;
 
 pub const RESERVED_TYPE_NAMES: &[&str] = &["Query", "Mutation"];
 pub const COLLECTION_DIRECTIVE_NAME: &str = "collection";
-pub const KNOWN_DIRECTIVES: &[&str] = &[COLLECTION_DIRECTIVE_NAME];
+pub const VERSIONED_DIRECTIVE_NAME: &str = "versioned";
+pub const KNOWN_DIRECTIVES: &[&str] = &[COLLECTION_DIRECTIVE_NAME, VERSIONED_DIRECTIVE_NAME];
 
 pub fn validate_graphql_schema_correctness(
     logger: &Logger,
@@ -50,6 +51,12 @@ pub fn validate_graphql_schema_correctness(
                             }
 
                             schema_objects.push(o.clone());
+                        } else if has_versioned_directive(&o.directives) {
+                            crit!(logger, "Only collections can be versioned, \"{}\" is missing {}", o.name, "@collection".magenta(); "position" => format!("{}", o.position));
+                            bail!(
+                                "Only collections can be versioned, \"{}\" is not a collection",
+                                o.name
+                            );
                         } else {
                             warn!(logger, "This schema definitions does not contain any definitions! 🤷‍ Remember to add this line to the top of your schema \"{}\"", "directive @collection on OBJECT".magenta())
                         }
@@ -82,6 +89,13 @@ pub fn has_collection_directive(directives: &[Directive]) -> bool {
         .any(|i| i.name == COLLECTION_DIRECTIVE_NAME)
 }
 
+/// Versioned collections keep every previous version of their documents
+pub fn has_versioned_directive(directives: &[Directive]) -> bool {
+    directives
+        .iter()
+        .any(|i| i.name == VERSIONED_DIRECTIVE_NAME)
+}
+
 fn is_unknown_directives(directive: &Directive) -> bool {
     !KNOWN_DIRECTIVES.contains(&&*directive.name)
 }
@@ -223,4 +237,28 @@ mod test {
             "It should not throw"
         );
     }
+
+    #[test]
+    fn it_throw_if_a_versioned_type_is_not_a_collection() {
+        let logger = NullLoggerBuilder.build().unwrap();
+
+        let schema = r#"
+            directive @versioned on OBJECT
+
+            type Engine @versioned {
+                id: Uuid!
+            }
+        "#;
+
+        let document = parse_schema(&schema).unwrap();
+
+        assert_eq!(
+            format!(
+                "{}",
+                validate_graphql_schema_correctness(&logger, &document).unwrap_err()
+            ),
+            "Only collections can be versioned, \"Engine\" is not a collection",
+            "It should throw when a type that is not a collection is versioned"
+        );
+    }
 }
@@ -0,0 +1,533 @@
+use crate::{
+    chunk_index::{
+        chunk_file_name,
+        chunk_number,
+        partition,
+        ChunkIndex,
+        MERGE_SIZE,
+        ROOT_CHUNK,
+    },
+    codec::{
+        encode_chunk,
+        ChunkCodec,
+    },
+    util::compute_hash_sum,
+};
+use colored::Colorize;
+use failure::Error;
+use futures::{
+    future::BoxFuture,
+    stream::{
+        self,
+        BoxStream,
+    },
+    StreamExt,
+};
+use shelf_database::{
+    keep_newest,
+    CompactionReport,
+    Document,
+};
+use slog::Logger;
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+        HashSet,
+    },
+    sync::Arc,
+};
+use tokio::task;
+use uuid::Uuid;
+
+/// Document changes for a collection, `None` marks a deleted document
+pub type Changes = HashMap<Uuid, Option<Arc<Document>>>;
+
+/// Where the chunks of a single collection are kept, like a folder on disk or
+/// a prefix in a bucket. Chunks are only ever written whole
+pub trait ChunkStorage: Clone + Send + Sync {
+    /// The names of every chunk, anything else kept next to them is left out
+    fn list(&self) -> BoxFuture<Result<Vec<String>, Error>>;
+    /// Returns the content of a chunk as it was handed to `write`
+    fn read<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<u8>, Error>>;
+    /// Writes a chunk, and then removes the chunk it replaces if there is
+    /// one. A crash in between must never lose both
+    fn write<'a>(
+        &'a self,
+        name: &'a str,
+        data: Vec<u8>,
+        replaced: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(), Error>>;
+    /// Removes chunks, one that is already gone is not an error
+    fn remove<'a>(&'a self, names: &'a [String]) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// The hash a chunk is named after, so that a chunk is only written again
+    /// when its content changes
+    fn hash(&self, data: &[u8]) -> u64 {
+        compute_hash_sum(data)
+    }
+}
+
+/// Lays out the documents of a collection in chunks following the chunk tree
+/// of `ChunkIndex`. Shared by every store that keeps chunks, so that they all
+/// split, merge and rearrange chunks the same way
+#[derive(Clone, Copy)]
+pub struct ChunkEngine {
+    /// How new chunks are written, existing chunks are read whatever they
+    /// were written with
+    codec: ChunkCodec,
+    /// How many chunks are read at once while documents are loaded
+    load_concurrency: usize,
+}
+
+impl ChunkEngine {
+    pub fn new(codec: ChunkCodec, load_concurrency: usize) -> Self {
+        Self {
+            codec,
+            load_concurrency: load_concurrency.max(1),
+        }
+    }
+
+    /// Reads and decodes a chunk, the checksum is verified before anything is
+    /// decompressed
+    ///
+    /// # Errors
+    /// Returns an error if the chunk can not be read or is damaged
+    pub async fn read_chunk<S: ChunkStorage>(
+        &self,
+        storage: &S,
+        name: &str,
+    ) -> Result<Vec<Document>, Error> {
+        let data = storage.read(name).await?;
+        task::spawn_blocking(move || ChunkCodec::decode(&data)).await?
+    }
+
+    /// Streams the documents of a collection a chunk at a time. Only a few
+    /// chunks are read at once, so the raw chunks never take up much more
+    /// memory than the documents decoded from them. Chunks can overlap after
+    /// a crash in the middle of a split, so a document can show up twice
+    pub fn stream_documents<'a, S: ChunkStorage + 'a>(
+        &self,
+        logger: &Logger,
+        storage: S,
+    ) -> BoxStream<'a, Result<Vec<Document>, Error>> {
+        let logger = logger.clone();
+        let engine = *self;
+
+        stream::once(async move {
+            let names = storage.list().await;
+            names.map(|names| (storage, names))
+        })
+        .map(move |listed| match listed {
+            Ok((storage, names)) => {
+                let logger = logger.clone();
+                stream::iter(names)
+                    .map(move |name| {
+                        let logger = logger.clone();
+                        let storage = storage.clone();
+                        async move {
+                            trace!(logger, "Reading chunk {}", name.yellow());
+                            let documents = match engine.read_chunk(&storage, &name).await {
+                                Ok(documents) => documents,
+                                Err(e) => {
+                                    crit!(logger, "Found a damaged chunk, the collection can not be loaded without it"; "chunk_name" => &name, "error" => format!("{}", e));
+                                    return Err(e);
+                                }
+                            };
+
+                            if documents.is_empty() {
+                                warn!(logger, "Weird, found a chunk with no content"; "chunk_name" => &name);
+                            }
+                            trace!(logger, "Found {} documents in chunk {}", documents.len().to_string().yellow(), name.yellow());
+
+                            Ok(documents)
+                        }
+                    })
+                    .buffer_unordered(engine.load_concurrency)
+                    .boxed()
+            }
+            Err(e) => stream::once(async { Err(e) }).boxed(),
+        })
+        .flatten()
+        .boxed()
+    }
+
+    /// Reads every document of a collection, keeping the newest revision
+    /// where chunks overlap. A damaged chunk stops the load, going on without
+    /// it would make the documents in it disappear without anyone noticing
+    ///
+    /// # Errors
+    /// Returns an error if a chunk can not be read or is damaged
+    pub async fn get_documents<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: S,
+    ) -> Result<Vec<Document>, Error> {
+        let mut by_id = HashMap::new();
+        let mut batches = self.stream_documents(logger, storage);
+        while let Some(batch) = batches.next().await {
+            for document in batch? {
+                keep_newest(&mut by_id, document);
+            }
+        }
+
+        if by_id.is_empty() {
+            warn!(logger, "No documents found");
+        }
+
+        Ok(by_id.into_iter().map(|(_, i)| i).collect())
+    }
+
+    /// Builds the index from the chunks already in the storage
+    ///
+    /// # Errors
+    /// Returns an error if a chunk can not be read
+    pub async fn load_index<S: ChunkStorage>(&self, storage: &S) -> Result<ChunkIndex, Error> {
+        let mut files = vec![];
+        for name in storage.list().await? {
+            let ids = self
+                .read_chunk(storage, &name)
+                .await?
+                .iter()
+                .map(|i| i.id)
+                .collect();
+            files.push((name, ids));
+        }
+        Ok(ChunkIndex::from_files(files))
+    }
+
+    /// Applies the changes to the chunks they belong in, splitting chunks
+    /// that grow too large and merging the ones that shrink. `index` is the
+    /// one returned by the last call, it is built from the storage if left
+    /// out. Should this fail the index is lost and rebuilt on the next call
+    ///
+    /// # Errors
+    /// Returns an error if a chunk can not be read or written
+    pub async fn save<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: &S,
+        index: Option<ChunkIndex>,
+        changes: Changes,
+    ) -> Result<ChunkIndex, Error> {
+        let names = storage.list().await?;
+        let mut index = match index {
+            Some(index) => index,
+            None => self.load_index(storage).await?,
+        };
+
+        if index.is_misplaced() {
+            warn!(
+                logger,
+                "The chunks do not follow the expected layout, rearranging all of them"
+            );
+            self.rearrange_chunks(logger, storage, &names, changes)
+                .await?;
+            return self.load_index(storage).await;
+        }
+
+        let mut files = by_chunk(names);
+        let mut touched: BTreeMap<usize, Changes> = BTreeMap::new();
+        for (id, change) in changes {
+            touched
+                .entry(index.chunk_for(id))
+                .or_default()
+                .insert(id, change);
+        }
+
+        let chunks: Vec<_> = touched.keys().copied().collect();
+        for (chunk, changes) in touched {
+            self.rewrite_chunk(logger, storage, &mut index, &mut files, chunk, changes)
+                .await?;
+        }
+        for chunk in chunks {
+            self.merge_chunk(logger, storage, &mut index, &mut files, chunk)
+                .await?;
+        }
+
+        Ok(index)
+    }
+
+    /// Merges the small chunks of a collection, rearranges chunks that do not
+    /// follow the chunk tree and converts chunks written with another format
+    /// or compression. Deleted documents are left out whenever their chunk is
+    /// written, so there are no tombstones to drop here
+    ///
+    /// # Errors
+    /// Returns an error if a chunk can not be read or written
+    pub async fn compact<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: &S,
+        index: Option<ChunkIndex>,
+    ) -> Result<(ChunkIndex, CompactionReport), Error> {
+        let names = storage.list().await?;
+        let mut report = CompactionReport {
+            collections: 1,
+            files_before: names.len(),
+            ..CompactionReport::default()
+        };
+
+        let mut index = match index {
+            Some(index) => index,
+            None => self.load_index(storage).await?,
+        };
+
+        if index.is_misplaced() {
+            self.rearrange_chunks(logger, storage, &names, Changes::new())
+                .await?;
+            index = self.load_index(storage).await?;
+        } else {
+            let mut files = by_chunk(names);
+            for chunk in index.chunks() {
+                self.merge_chunk(logger, storage, &mut index, &mut files, chunk)
+                    .await?;
+            }
+            for chunk in index.chunks() {
+                self.convert_chunk(logger, storage, &mut index, &mut files, chunk)
+                    .await?;
+            }
+        }
+
+        report.files_after = storage.list().await?.len();
+        Ok((index, report))
+    }
+
+    /// Applies the changes to a single chunk, splitting it if it grows too
+    /// large
+    async fn rewrite_chunk<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: &S,
+        index: &mut ChunkIndex,
+        files: &mut HashMap<usize, String>,
+        chunk: usize,
+        mut changes: Changes,
+    ) -> Result<(), Error> {
+        let existing = files.get(&chunk).cloned();
+
+        let mut documents = vec![];
+        if let Some(name) = &existing {
+            for document in self.read_chunk(storage, name).await? {
+                match changes.remove(&document.id) {
+                    Some(Some(changed)) => documents.push(changed),
+                    Some(None) => {}
+                    None => documents.push(Arc::new(document)),
+                }
+            }
+        }
+        documents.extend(changes.into_iter().filter_map(|(_, change)| change));
+
+        let mut chunks = partition(chunk, documents);
+        if chunks.len() == 1 {
+            let (chunk, documents) = chunks.remove(0);
+            return self
+                .write_chunk(logger, storage, index, files, chunk, documents)
+                .await;
+        }
+
+        // The new chunks are written before the old one is removed. A crash in
+        // between leaves overlapping chunks, which are rearranged on the next
+        // save using the newest revision of every document
+        info!(
+            logger,
+            "Splitting chunk {} into {} chunks",
+            chunk,
+            chunks.len()
+        );
+        index.replace(chunk, HashSet::new());
+        files.remove(&chunk);
+        for (chunk, documents) in chunks {
+            self.write_chunk(logger, storage, index, files, chunk, documents)
+                .await?;
+        }
+        if let Some(name) = existing {
+            storage.remove(&[name]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges a chunk with its sibling for as long as they are small enough
+    /// together
+    async fn merge_chunk<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: &S,
+        index: &mut ChunkIndex,
+        files: &mut HashMap<usize, String>,
+        mut chunk: usize,
+    ) -> Result<(), Error> {
+        while chunk > ROOT_CHUNK {
+            let sibling = chunk ^ 1;
+            if !index.is_leaf(chunk)
+                || !index.is_leaf(sibling)
+                || index.size(chunk) + index.size(sibling) > MERGE_SIZE
+            {
+                break;
+            }
+
+            let mut documents = vec![];
+            let mut merged = vec![];
+            for part in &[chunk, sibling] {
+                if let Some(name) = files.remove(part) {
+                    for document in self.read_chunk(storage, &name).await? {
+                        documents.push(Arc::new(document));
+                    }
+                    merged.push(name);
+                }
+                index.replace(*part, HashSet::new());
+            }
+
+            let parent = chunk / 2;
+            self.write_chunk(logger, storage, index, files, parent, documents)
+                .await?;
+            if !merged.is_empty() {
+                storage.remove(&merged).await?;
+                debug!(
+                    logger,
+                    "Merged chunks {} and {} into {}", chunk, sibling, parent
+                );
+            }
+
+            chunk = parent;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the documents of a chunk. Nothing is written if the content ends
+    /// up the same, and the chunk is removed if it is empty
+    async fn write_chunk<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: &S,
+        index: &mut ChunkIndex,
+        files: &mut HashMap<usize, String>,
+        chunk: usize,
+        documents: Vec<Arc<Document>>,
+    ) -> Result<(), Error> {
+        index.replace(chunk, documents.iter().map(|i| i.id).collect());
+        let existing = files.remove(&chunk);
+
+        if documents.is_empty() {
+            if let Some(name) = existing {
+                storage.remove(&[name]).await?;
+                debug!(logger, "Removed empty chunk {}", chunk);
+            }
+            return Ok(());
+        }
+
+        let count = documents.len();
+        let data = encode_chunk(self.codec, documents).await?;
+        let name = chunk_file_name(chunk, storage.hash(&data));
+
+        if existing.as_ref() == Some(&name) {
+            debug!(logger, "Chunk {} has not changed", chunk);
+        } else {
+            storage.write(&name, data, existing.as_deref()).await?;
+            trace!(logger, "Wrote chunk {} with {} documents", chunk, count);
+        }
+        files.insert(chunk, name);
+
+        Ok(())
+    }
+
+    /// Writes a chunk again if it was written with another format or
+    /// compression than the configured one
+    async fn convert_chunk<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: &S,
+        index: &mut ChunkIndex,
+        files: &mut HashMap<usize, String>,
+        chunk: usize,
+    ) -> Result<(), Error> {
+        let name = match files.get(&chunk) {
+            Some(name) => name.to_string(),
+            None => return Ok(()),
+        };
+
+        let data = storage.read(&name).await?;
+        if ChunkCodec::of(&data)? == self.codec {
+            return Ok(());
+        }
+        let documents = task::spawn_blocking(move || ChunkCodec::decode(&data)).await??;
+
+        debug!(
+            logger,
+            "Converting chunk {} to the configured format", chunk
+        );
+        let documents = documents.into_iter().map(Arc::new).collect();
+        self.write_chunk(logger, storage, index, files, chunk, documents)
+            .await
+    }
+
+    /// Reads every chunk of a collection and writes them again following the
+    /// chunk tree. Where a document is found in more than one chunk the
+    /// newest revision is kept
+    async fn rearrange_chunks<S: ChunkStorage>(
+        &self,
+        logger: &Logger,
+        storage: &S,
+        names: &[String],
+        changes: Changes,
+    ) -> Result<(), Error> {
+        let mut documents = HashMap::new();
+        for name in names {
+            for document in self.read_chunk(storage, name).await? {
+                keep_newest(&mut documents, document);
+            }
+        }
+        for (id, change) in changes {
+            match change {
+                Some(document) => {
+                    documents.insert(id, Document::clone(&document));
+                }
+                None => {
+                    documents.remove(&id);
+                }
+            }
+        }
+
+        let mut documents: Vec<_> = documents.into_iter().map(|(_, i)| Arc::new(i)).collect();
+        documents.sort_by_key(|i| i.id);
+
+        let mut written = HashSet::new();
+        for (chunk, documents) in partition(ROOT_CHUNK, documents) {
+            if documents.is_empty() {
+                continue;
+            }
+            let data = encode_chunk(self.codec, documents).await?;
+            let name = chunk_file_name(chunk, storage.hash(&data));
+            if !names.contains(&name) {
+                storage.write(&name, data, None).await?;
+            }
+            written.insert(name);
+        }
+
+        let unused: Vec<_> = names
+            .iter()
+            .filter(|i| !written.contains(*i))
+            .cloned()
+            .collect();
+        storage.remove(&unused).await?;
+
+        info!(
+            logger,
+            "Rearranged the chunks into {} chunks",
+            written.len()
+        );
+
+        Ok(())
+    }
+}
+
+/// Maps chunk numbers to chunk names, only valid for chunks that follow the
+/// chunk tree
+fn by_chunk(names: Vec<String>) -> HashMap<usize, String> {
+    names
+        .into_iter()
+        .filter_map(|i| Some((chunk_number(&i)?, i)))
+        .collect()
+}
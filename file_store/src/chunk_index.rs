@@ -0,0 +1,224 @@
+use shelf_database::Document;
+use std::{
+    collections::{
+        BTreeMap,
+        HashSet,
+    },
+    sync::Arc,
+};
+use uuid::Uuid;
+
+/// The most documents a single chunk file holds, a chunk that grows past this
+/// is split in two
+pub const CHUNK_SIZE: usize = 10_000;
+/// Two sibling chunks are merged back together once they hold no more than
+/// this many documents combined
+pub const MERGE_SIZE: usize = CHUNK_SIZE / 2;
+pub const ROOT_CHUNK: usize = 1;
+/// Keeps the chunk numbers well within an usize, a tree this deep holds far
+/// more documents than would ever fit in memory
+const MAX_DEPTH: u32 = 48;
+const CHUNK_EXTENSION: &str = "chunk";
+
+/// Keeps track of the chunks of a collection. Chunks are the nodes of a
+/// binary tree over the bits of the document ids, the root is chunk 1 and
+/// chunk `n` has the children `2n` and `2n + 1`. A document always lives in
+/// the same chunk no matter in which order documents are saved, so a save
+/// only has to rewrite the chunks that were touched
+#[derive(Default)]
+pub struct ChunkIndex {
+    /// The ids in every chunk that holds documents
+    members: BTreeMap<usize, HashSet<Uuid>>,
+    /// Every chunk above one that holds documents
+    internal: HashSet<usize>,
+    /// Set if the files on disk do not follow the tree, like the ones written
+    /// by older versions or left behind by a crash in the middle of a split
+    misplaced: bool,
+}
+
+impl ChunkIndex {
+    /// Builds the index from the names of the chunk files of a collection and
+    /// the ids of the documents in each of them
+    pub fn from_files(files: Vec<(String, Vec<Uuid>)>) -> Self {
+        let mut index = Self::default();
+
+        let mut seen = HashSet::new();
+        for (file_name, ids) in files {
+            let chunk = match chunk_number(&file_name) {
+                Some(chunk) if chunk >= ROOT_CHUNK && depth(chunk) <= MAX_DEPTH => chunk,
+                _ => {
+                    index.misplaced = true;
+                    continue;
+                }
+            };
+            if index.members.contains_key(&chunk) {
+                index.misplaced = true;
+            }
+
+            let members = index.members.entry(chunk).or_default();
+            for id in ids {
+                if !seen.insert(id) {
+                    index.misplaced = true;
+                }
+                members.insert(id);
+            }
+        }
+
+        index.update_internal();
+        if index.members.keys().any(|i| index.internal.contains(i)) {
+            index.misplaced = true;
+        }
+        if !index.misplaced {
+            index.misplaced = index
+                .members
+                .iter()
+                .any(|(chunk, ids)| ids.iter().any(|id| index.chunk_for(*id) != *chunk));
+        }
+
+        index
+    }
+
+    pub fn is_misplaced(&self) -> bool {
+        self.misplaced
+    }
+
+    /// Returns the chunk a document belongs in
+    pub fn chunk_for(&self, id: Uuid) -> usize {
+        let mut chunk = ROOT_CHUNK;
+        while self.internal.contains(&chunk) {
+            chunk = child(chunk, id);
+        }
+        chunk
+    }
+
+    /// Every chunk that holds documents
+    pub fn chunks(&self) -> Vec<usize> {
+        self.members.keys().copied().collect()
+    }
+
+    pub fn size(&self, chunk: usize) -> usize {
+        self.members.get(&chunk).map_or(0, HashSet::len)
+    }
+
+    /// If documents can end up in this chunk, that is if it is not above or
+    /// below a chunk that holds documents
+    pub fn is_leaf(&self, chunk: usize) -> bool {
+        if self.internal.contains(&chunk) {
+            return false;
+        }
+        let mut parent = chunk / 2;
+        while parent >= ROOT_CHUNK {
+            if !self.internal.contains(&parent) {
+                return false;
+            }
+            parent /= 2;
+        }
+        true
+    }
+
+    /// Sets the ids held by a chunk
+    pub fn replace(&mut self, chunk: usize, ids: HashSet<Uuid>) {
+        if ids.is_empty() {
+            self.members.remove(&chunk);
+        } else {
+            self.members.insert(chunk, ids);
+        }
+        self.update_internal();
+    }
+
+    fn update_internal(&mut self) {
+        self.internal.clear();
+        for chunk in self.members.keys() {
+            let mut parent = chunk / 2;
+            while parent >= ROOT_CHUNK && self.internal.insert(parent) {
+                parent /= 2;
+            }
+        }
+    }
+}
+
+/// Splits the documents of a chunk over its children until no chunk holds
+/// more than `CHUNK_SIZE` documents. Chunks that end up empty are included
+pub fn partition(chunk: usize, documents: Vec<Arc<Document>>) -> Vec<(usize, Vec<Arc<Document>>)> {
+    if documents.len() <= CHUNK_SIZE || depth(chunk) >= MAX_DEPTH {
+        return vec![(chunk, documents)];
+    }
+
+    let (left, right): (Vec<_>, Vec<_>) = documents
+        .into_iter()
+        .partition(|i| child(chunk, i.id) == chunk * 2);
+    let mut chunks = partition(chunk * 2, left);
+    chunks.append(&mut partition(chunk * 2 + 1, right));
+    chunks
+}
+
+/// Chunk files are named `{chunk}_{hash}.chunk`, or `{chunk}_{hash}.gz` if
+/// they were written before the format was selectable
+pub fn chunk_file_name(chunk: usize, hash: u64) -> String {
+    format!("{}_{}.{}", chunk, hash, CHUNK_EXTENSION)
+}
+
+pub fn chunk_number(file_name: &str) -> Option<usize> {
+    file_name.split('_').next()?.parse().ok()
+}
+
+/// The child of `chunk` that a document belongs in, decided by the bit of
+/// the id at the depth of the chunk
+fn child(chunk: usize, id: Uuid) -> usize {
+    let bit = (id.as_u128() >> (127 - depth(chunk))) & 1;
+    if bit == 0 {
+        chunk * 2
+    } else {
+        chunk * 2 + 1
+    }
+}
+
+fn depth(chunk: usize) -> u32 {
+    let mut depth = 0;
+    let mut parent = chunk / 2;
+    while parent >= ROOT_CHUNK {
+        depth += 1;
+        parent /= 2;
+    }
+    depth
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunk_index::{
+        partition,
+        ChunkIndex,
+        CHUNK_SIZE,
+        ROOT_CHUNK,
+    };
+    use shelf_database::Document;
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn documents_should_stay_in_their_chunk_after_a_split() {
+        let documents: Vec<_> = (0..=CHUNK_SIZE)
+            .map(|_| Arc::new(Document::new(Uuid::new_v4(), HashMap::new())))
+            .collect();
+
+        let chunks = partition(ROOT_CHUNK, documents);
+        assert_eq!(chunks.len(), 2, "The root should be split in two");
+
+        let mut index = ChunkIndex::default();
+        for (chunk, documents) in &chunks {
+            index.replace(*chunk, documents.iter().map(|i| i.id).collect());
+        }
+
+        for (chunk, documents) in &chunks {
+            for document in documents {
+                assert_eq!(index.chunk_for(document.id), *chunk);
+            }
+        }
+        assert!(!index.is_leaf(ROOT_CHUNK));
+        assert!(index.is_leaf(2));
+        assert!(index.is_leaf(3));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::{
+    encryption::{
+        open,
+        Encryption,
+    },
+    util::compute_hash_sum,
+};
+use failure::Error;
+use flate2::{
+    read::GzDecoder,
+    write::GzEncoder,
+    Compression,
+};
+use shelf_config::{
+    ChunkCompression,
+    ChunkFormat,
+    Config,
+};
+use shelf_database::Document;
+use std::{
+    convert::TryInto,
+    io::{
+        Read,
+        Write,
+    },
+    path::Path,
+    sync::Arc,
+};
+use tokio::{
+    fs::read,
+    task,
+};
+
+/// Chunk files written before the format was selectable, always json and gzip
+const MAGIC_V1: &[u8; 8] = b"SHELFCK1";
+const HEADER_LEN_V1: usize = 16;
+const MAGIC: &[u8; 8] = b"SHELFCK2";
+/// The magic, one byte each for the format and the compression, and the
+/// checksum of the payload
+const HEADER_LEN: usize = 18;
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Checksum mismatch, expected {:016x} but the content sums to {:016x}",
+    expected, actual
+)]
+pub struct ChecksumMismatch {
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Turns the documents of a chunk into file content and back. Every file
+/// starts with a header naming how it was written, so files written with
+/// other settings, or by older versions, can always be read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkCodec {
+    pub format: ChunkFormat,
+    pub compression: ChunkCompression,
+}
+
+impl ChunkCodec {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            format: config.chunk_format,
+            compression: config.chunk_compression,
+        }
+    }
+
+    /// Serializes and compresses the documents, and puts the header in front
+    ///
+    /// # Errors
+    /// Might return an error if the documents can not be serialized
+    pub fn encode(self, documents: &[Arc<Document>]) -> Result<Vec<u8>, Error> {
+        let serialized = match self.format {
+            ChunkFormat::Json => serde_json::to_vec(documents)?,
+            ChunkFormat::MessagePack => rmp_serde::to_vec_named(documents)?,
+            ChunkFormat::Cbor => serde_cbor::to_vec(documents)?,
+        };
+        let payload = compress(self.compression, &serialized)?;
+
+        let mut data = Vec::with_capacity(HEADER_LEN + payload.len());
+        data.extend_from_slice(MAGIC);
+        data.push(format_id(self.format));
+        data.push(compression_id(self.compression));
+        data.extend_from_slice(&compute_hash_sum(&payload).to_be_bytes());
+        data.extend_from_slice(&payload);
+
+        Ok(data)
+    }
+
+    /// Verifies the checksum and returns the documents
+    ///
+    /// # Errors
+    /// Returns an error if the data is damaged
+    pub fn decode(data: &[u8]) -> Result<Vec<Document>, Error> {
+        let (codec, payload) = Self::unpack(data)?;
+        let serialized = decompress(codec.compression, payload)?;
+
+        Ok(match codec.format {
+            ChunkFormat::Json => serde_json::from_slice(&serialized)?,
+            ChunkFormat::MessagePack => rmp_serde::from_read_ref(&serialized)?,
+            ChunkFormat::Cbor => serde_cbor::from_slice(&serialized)?,
+        })
+    }
+
+    /// Returns how a file was written
+    ///
+    /// # Errors
+    /// Returns an error if the data is damaged
+    pub fn of(data: &[u8]) -> Result<Self, Error> {
+        Ok(Self::unpack(data)?.0)
+    }
+
+    fn unpack(data: &[u8]) -> Result<(Self, &[u8]), Error> {
+        if data.starts_with(MAGIC) {
+            if data.len() < HEADER_LEN {
+                bail!("The chunk header is truncated");
+            }
+            let codec = Self {
+                format: format_from_id(data[8])?,
+                compression: compression_from_id(data[9])?,
+            };
+            let payload = verify(&data[10..HEADER_LEN], &data[HEADER_LEN..])?;
+            Ok((codec, payload))
+        } else if data.starts_with(MAGIC_V1) {
+            if data.len() < HEADER_LEN_V1 {
+                bail!("The chunk header is truncated");
+            }
+            let payload = verify(&data[8..HEADER_LEN_V1], &data[HEADER_LEN_V1..])?;
+            Ok((Self::legacy(), payload))
+        } else {
+            // Written before chunks had a header, there is no checksum to verify
+            Ok((Self::legacy(), data))
+        }
+    }
+
+    fn legacy() -> Self {
+        Self {
+            format: ChunkFormat::Json,
+            compression: ChunkCompression::Gzip,
+        }
+    }
+}
+
+/// Reads, decrypts and decodes a chunk file, the checksum is verified before
+/// anything is decompressed
+pub async fn read_chunk_file(
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<Vec<Document>, Error> {
+    let data = read(path).await?;
+    let encryption = encryption.cloned();
+    task::spawn_blocking(move || ChunkCodec::decode(&open(encryption.as_ref(), data)?)).await?
+}
+
+/// Encodes the documents of a chunk off the async runtime
+///
+/// # Errors
+/// Might return an error if the documents can not be serialized
+pub async fn encode_chunk(
+    codec: ChunkCodec,
+    documents: Vec<Arc<Document>>,
+) -> Result<Vec<u8>, Error> {
+    task::spawn_blocking(move || codec.encode(&documents)).await?
+}
+
+fn verify<'a>(checksum: &[u8], payload: &'a [u8]) -> Result<&'a [u8], Error> {
+    let expected = u64::from_be_bytes(checksum.try_into()?);
+    let actual = compute_hash_sum(payload);
+    if expected != actual {
+        return Err(ChecksumMismatch { expected, actual }.into());
+    }
+    Ok(payload)
+}
+
+fn compress(compression: ChunkCompression, data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(match compression {
+        ChunkCompression::None => data.to_vec(),
+        ChunkCompression::Gzip => {
+            let mut e = GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(data)?;
+            e.finish()?
+        }
+        ChunkCompression::Zstd => zstd::encode_all(data, 0)?,
+        ChunkCompression::Lz4 => {
+            let mut e = lz4::EncoderBuilder::new().build(Vec::new())?;
+            e.write_all(data)?;
+            let (compressed, result) = e.finish();
+            result?;
+            compressed
+        }
+    })
+}
+
+fn decompress(compression: ChunkCompression, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decompressed = vec![];
+    match compression {
+        ChunkCompression::None => decompressed.extend_from_slice(data),
+        ChunkCompression::Gzip => {
+            GzDecoder::new(data).read_to_end(&mut decompressed)?;
+        }
+        ChunkCompression::Zstd => decompressed = zstd::decode_all(data)?,
+        ChunkCompression::Lz4 => {
+            lz4::Decoder::new(data)?.read_to_end(&mut decompressed)?;
+        }
+    }
+    Ok(decompressed)
+}
+
+// The ids are stored in every file, so they must never change
+
+fn format_id(format: ChunkFormat) -> u8 {
+    match format {
+        ChunkFormat::Json => 1,
+        ChunkFormat::MessagePack => 2,
+        ChunkFormat::Cbor => 3,
+    }
+}
+
+fn format_from_id(id: u8) -> Result<ChunkFormat, Error> {
+    Ok(match id {
+        1 => ChunkFormat::Json,
+        2 => ChunkFormat::MessagePack,
+        3 => ChunkFormat::Cbor,
+        _ => bail!("Unknown chunk format {}", id),
+    })
+}
+
+fn compression_id(compression: ChunkCompression) -> u8 {
+    match compression {
+        ChunkCompression::None => 0,
+        ChunkCompression::Gzip => 1,
+        ChunkCompression::Zstd => 2,
+        ChunkCompression::Lz4 => 3,
+    }
+}
+
+fn compression_from_id(id: u8) -> Result<ChunkCompression, Error> {
+    Ok(match id {
+        0 => ChunkCompression::None,
+        1 => ChunkCompression::Gzip,
+        2 => ChunkCompression::Zstd,
+        3 => ChunkCompression::Lz4,
+        _ => bail!("Unknown chunk compression {}", id),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::codec::ChunkCodec;
+    use flate2::{
+        write::GzEncoder,
+        Compression,
+    };
+    use serde_json::Value;
+    use shelf_config::{
+        ChunkCompression,
+        ChunkFormat,
+    };
+    use shelf_database::Document;
+    use std::{
+        collections::HashMap,
+        io::Write,
+        sync::Arc,
+    };
+    use uuid::Uuid;
+
+    fn documents() -> Vec<Arc<Document>> {
+        let mut fields = HashMap::new();
+        fields.insert("brand".to_string(), Value::from("Tesla"));
+        fields.insert("seats".to_string(), Value::from(5));
+        vec![Arc::new(Document::new(Uuid::new_v4(), fields))]
+    }
+
+    #[test]
+    fn every_codec_should_read_what_it_wrote() {
+        let documents = documents();
+        for format in &[
+            ChunkFormat::Json,
+            ChunkFormat::MessagePack,
+            ChunkFormat::Cbor,
+        ] {
+            for compression in &[
+                ChunkCompression::None,
+                ChunkCompression::Gzip,
+                ChunkCompression::Zstd,
+                ChunkCompression::Lz4,
+            ] {
+                let codec = ChunkCodec {
+                    format: *format,
+                    compression: *compression,
+                };
+                let data = codec.encode(&documents).unwrap();
+
+                assert_eq!(ChunkCodec::of(&data).unwrap(), codec);
+                let read = ChunkCodec::decode(&data).unwrap();
+                assert_eq!(read[0].id, documents[0].id);
+                assert_eq!(read[0].fields, documents[0].fields);
+            }
+        }
+    }
+
+    #[test]
+    fn files_without_a_header_should_be_read_as_gzip_json() {
+        let documents = documents();
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(serde_json::to_string(&documents).unwrap().as_bytes())
+            .unwrap();
+
+        let read = ChunkCodec::decode(&e.finish().unwrap()).unwrap();
+        assert_eq!(read[0].id, documents[0].id);
+    }
+
+    #[test]
+    fn a_flipped_bit_should_fail_the_checksum() {
+        let mut data = ChunkCodec::default().encode(&documents()).unwrap();
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(ChunkCodec::decode(&data).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use failure::Error;
+use fs2::FileExt;
+use serde_derive::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        Read,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::Path,
+    process,
+};
+
+pub const LOCK_FILE: &str = "shelf.lock";
+
+/// Written to the lock file, so that whoever is locked out knows who to look
+/// for
+#[derive(Serialize, Deserialize)]
+struct Owner {
+    pid: u32,
+    hostname: String,
+}
+
+/// An advisory lock on the data folder, held for as long as this is kept
+/// around. The operating system releases it should the process die, so a
+/// crash never leaves the folder locked. The file itself is left behind, it
+/// is the lock on it that counts
+pub struct DataFolderLock {
+    file: File,
+}
+
+impl DataFolderLock {
+    /// Takes the lock, without waiting for it
+    ///
+    /// # Errors
+    /// Returns an error if another process holds the lock
+    pub fn acquire(data_folder: &Path) -> Result<Self, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(data_folder.join(LOCK_FILE))?;
+
+        if file.try_lock_exclusive().is_err() {
+            let mut contents = String::new();
+            let owner = file
+                .read_to_string(&mut contents)
+                .ok()
+                .and_then(|_| serde_json::from_str::<Owner>(&contents).ok());
+            match owner {
+                Some(owner) => bail!(
+                    "The data folder {} is already in use by process {} on {}",
+                    data_folder.display(),
+                    owner.pid,
+                    owner.hostname
+                ),
+                None => bail!(
+                    "The data folder {} is already in use by another process",
+                    data_folder.display()
+                ),
+            }
+        }
+
+        let owner = Owner {
+            pid: process::id(),
+            hostname: sys_info::hostname().unwrap_or_else(|_| "an unknown host".to_string()),
+        };
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&serde_json::to_vec(&owner)?)?;
+        file.sync_all()?;
+
+        Ok(Self { file })
+    }
+}
+
+impl Drop for DataFolderLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock as well, this just makes it
+        // explicit
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data_folder_lock::DataFolderLock;
+    use tempfile::TempDir;
+
+    #[test]
+    fn lock_should_only_be_held_by_one_at_a_time() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+
+        let lock = DataFolderLock::acquire(&path).unwrap();
+        let err = DataFolderLock::acquire(&path).err().unwrap();
+        assert!(
+            format!("{}", err).contains(&std::process::id().to_string()),
+            "The error should name the process holding the lock"
+        );
+
+        drop(lock);
+        DataFolderLock::acquire(&path).unwrap();
+    }
+}
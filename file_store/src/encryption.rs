@@ -0,0 +1,360 @@
+use crate::util::compute_hash_sum;
+use aes_gcm::{
+    aead::{
+        generic_array::GenericArray,
+        Aead,
+        NewAead,
+    },
+    Aes256Gcm,
+};
+use chacha20poly1305::ChaCha20Poly1305;
+use failure::Error;
+use hmac::{
+    Hmac,
+    Mac,
+};
+use rand::RngCore;
+use sha2::Sha256;
+use shelf_config::{
+    Config,
+    EncryptionCipher,
+};
+use std::{
+    env,
+    fs,
+};
+
+const MAGIC: &[u8; 8] = b"SHELFEN1";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+/// The magic, one byte for the cipher and the nonce
+const HEADER_LEN: usize = 21;
+/// Sets the key chunk names are hashed with apart from the key the files
+/// are encrypted with
+const HASH_CONTEXT: &[u8] = b"shelf chunk names";
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "The data is not encrypted, but an encryption key is configured. Run shelf rotate-key without the key in the config to encrypt the data folder"
+)]
+pub struct NotEncrypted;
+
+/// Encrypts and authenticates the content of files. Every sealed file starts
+/// with a header naming the cipher, so files sealed with another cipher can
+/// still be opened as long as the key is the same
+#[derive(Clone)]
+pub struct Encryption {
+    cipher: EncryptionCipher,
+    key: [u8; KEY_LEN],
+}
+
+impl Encryption {
+    /// Loads the key referenced by the config, returns `None` if there is
+    /// none
+    ///
+    /// # Errors
+    /// Returns an error if the key can not be read or is not a valid key
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Error> {
+        Self::load(
+            config.encryption_cipher,
+            config.encryption_key_file.as_deref(),
+            config.encryption_key_env.as_deref(),
+        )
+    }
+
+    /// Loads a key from a file or from an environment variable
+    ///
+    /// # Errors
+    /// Returns an error if the key can not be read or is not a valid key
+    pub fn load(
+        cipher: EncryptionCipher,
+        key_file: Option<&str>,
+        key_env: Option<&str>,
+    ) -> Result<Option<Self>, Error> {
+        let encoded = match (key_file, key_env) {
+            (Some(_), Some(_)) => {
+                bail!("Only one of the key file and the key environment variable can be given")
+            }
+            (Some(path), None) => fs::read_to_string(path).map_err(|e| {
+                format_err!("Failed to read the encryption key file {}: {}", path, e)
+            })?,
+            (None, Some(name)) => env::var(name).map_err(|_| {
+                format_err!(
+                    "The environment variable {} holding the encryption key is not set",
+                    name
+                )
+            })?,
+            (None, None) => return Ok(None),
+        };
+
+        let decoded = base64::decode(encoded.trim())?;
+        if decoded.len() != KEY_LEN {
+            bail!(
+                "The encryption key has to be {} bytes encoded as base64, found {} bytes",
+                KEY_LEN,
+                decoded.len()
+            );
+        }
+        let mut key = [0; KEY_LEN];
+        key.copy_from_slice(&decoded);
+
+        Ok(Some(Self { cipher, key }))
+    }
+
+    /// Encrypts the data with a fresh random nonce and puts the header in
+    /// front
+    ///
+    /// # Errors
+    /// Should not fail, the ciphers only refuse data far larger than a chunk
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = GenericArray::from_slice(&nonce);
+        let key = GenericArray::clone_from_slice(&self.key);
+
+        let sealed = match self.cipher {
+            EncryptionCipher::Aes256Gcm => Aes256Gcm::new(key).encrypt(nonce, data),
+            EncryptionCipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key).encrypt(nonce, data),
+        }
+        .map_err(|_| format_err!("Failed to encrypt data"))?;
+
+        let mut result = Vec::with_capacity(HEADER_LEN + sealed.len());
+        result.extend_from_slice(MAGIC);
+        result.push(cipher_id(self.cipher));
+        result.extend_from_slice(nonce);
+        result.extend_from_slice(&sealed);
+
+        Ok(result)
+    }
+
+    /// Verifies and decrypts sealed data
+    ///
+    /// # Errors
+    /// Returns an error if the data was sealed with another key or has been
+    /// changed since it was sealed
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if !is_sealed(data) {
+            bail!("The data is not encrypted");
+        }
+        if data.len() < HEADER_LEN {
+            bail!("The encryption header is truncated");
+        }
+        let nonce = GenericArray::from_slice(&data[MAGIC.len() + 1..HEADER_LEN]);
+        let key = GenericArray::clone_from_slice(&self.key);
+        let sealed = &data[HEADER_LEN..];
+
+        let opened = match cipher_from_id(data[MAGIC.len()])? {
+            EncryptionCipher::Aes256Gcm => Aes256Gcm::new(key).decrypt(nonce, sealed),
+            EncryptionCipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key).decrypt(nonce, sealed),
+        };
+        opened.map_err(|_| {
+            format_err!(
+                "Failed to decrypt, the data was encrypted with another key or has been damaged"
+            )
+        })
+    }
+
+    /// A keyed hash of the data. Chunks are named after the hash of their
+    /// content, a plain hash would let anyone holding a guess of the content
+    /// check it against the names
+    pub fn hash(&self, data: &[u8]) -> u64 {
+        let hash_key = hmac(&self.key, HASH_CONTEXT);
+        let mut code = [0; 8];
+        code.copy_from_slice(&hmac(&hash_key, data)[..8]);
+        u64::from_be_bytes(code)
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; KEY_LEN] {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC takes keys of any length");
+    mac.input(data);
+    let mut code = [0; KEY_LEN];
+    code.copy_from_slice(&mac.result().code());
+    code
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts the data if there is a key
+///
+/// # Errors
+/// Should not fail, see [`Encryption::seal`]
+pub fn seal(encryption: Option<&Encryption>, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match encryption {
+        Some(encryption) => encryption.seal(&data),
+        None => Ok(data),
+    }
+}
+
+/// The hash a chunk is named after, keyed when there is a key
+pub fn chunk_hash(encryption: Option<&Encryption>, data: &[u8]) -> u64 {
+    match encryption {
+        Some(encryption) => encryption.hash(data),
+        None => compute_hash_sum(data),
+    }
+}
+
+/// Decrypts the data if there is a key. Once there is a key unencrypted data
+/// is refused, otherwise anyone able to write to the data folder could slip
+/// in data of their own
+///
+/// # Errors
+/// Returns an error if the data can not be decrypted, is not encrypted
+/// while there is a key, or is encrypted while there is none
+pub fn open(encryption: Option<&Encryption>, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match encryption {
+        Some(encryption) if !is_sealed(&data) => Err(NotEncrypted.into()),
+        _ => open_any(encryption, data),
+    }
+}
+
+/// Decrypts the data if it is encrypted and returns unencrypted data as it
+/// is. Only for rotate-key and the upgrades of a data folder, which have to
+/// read data written before there was a key
+///
+/// # Errors
+/// Returns an error if the data is encrypted and can not be decrypted
+pub fn open_any(encryption: Option<&Encryption>, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if !is_sealed(&data) {
+        return Ok(data);
+    }
+    match encryption {
+        Some(encryption) => encryption.open(&data),
+        None => bail!("The data is encrypted, but no encryption key is configured"),
+    }
+}
+
+/// Seals a line of a log file. Sealed lines are base64 encoded so that they
+/// never hold a line break
+///
+/// # Errors
+/// Should not fail, see [`Encryption::seal`]
+pub fn seal_line(encryption: Option<&Encryption>, line: String) -> Result<String, Error> {
+    match encryption {
+        Some(encryption) => Ok(base64::encode(&encryption.seal(line.as_bytes())?)),
+        None => Ok(line),
+    }
+}
+
+/// Opens a line of a log file, lines written without encryption are json.
+/// Like `open` they are refused once there is a key
+///
+/// # Errors
+/// Returns an error if the line can not be decrypted, or is not encrypted
+/// while there is a key
+pub fn open_line(encryption: Option<&Encryption>, line: &str) -> Result<String, Error> {
+    if encryption.is_some() && line.starts_with('{') {
+        return Err(NotEncrypted.into());
+    }
+    open_line_any(encryption, line)
+}
+
+/// Opens a line of a log file whether it is encrypted or not, see `open_any`
+///
+/// # Errors
+/// Returns an error if the line is encrypted and can not be decrypted
+pub fn open_line_any(encryption: Option<&Encryption>, line: &str) -> Result<String, Error> {
+    if line.starts_with('{') {
+        return Ok(line.to_string());
+    }
+    let data = open_any(encryption, base64::decode(line)?)?;
+    Ok(String::from_utf8(data)?)
+}
+
+fn cipher_id(cipher: EncryptionCipher) -> u8 {
+    match cipher {
+        EncryptionCipher::Aes256Gcm => 1,
+        EncryptionCipher::ChaCha20Poly1305 => 2,
+    }
+}
+
+fn cipher_from_id(id: u8) -> Result<EncryptionCipher, Error> {
+    Ok(match id {
+        1 => EncryptionCipher::Aes256Gcm,
+        2 => EncryptionCipher::ChaCha20Poly1305,
+        _ => bail!("Unknown cipher {}", id),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        encryption::{
+            open,
+            open_any,
+            open_line,
+            open_line_any,
+            seal_line,
+            Encryption,
+        },
+        util::compute_hash_sum,
+    };
+    use shelf_config::EncryptionCipher;
+
+    #[test]
+    fn sealed_data_should_only_open_with_the_same_key() {
+        for cipher in &[
+            EncryptionCipher::Aes256Gcm,
+            EncryptionCipher::ChaCha20Poly1305,
+        ] {
+            let encryption = Encryption {
+                cipher: *cipher,
+                key: [7; 32],
+            };
+            let other = Encryption {
+                cipher: *cipher,
+                key: [8; 32],
+            };
+            let sealed = encryption.seal(b"[{\"id\":1}]").unwrap();
+
+            assert_ne!(&sealed[..], &b"[{\"id\":1}]"[..]);
+            assert_eq!(
+                open(Some(&encryption), sealed.clone()).unwrap(),
+                b"[{\"id\":1}]"
+            );
+            assert!(open(None, sealed.clone()).is_err());
+            assert!(other.open(&sealed).is_err());
+
+            let line = seal_line(Some(&encryption), "{\"id\":1}".to_string()).unwrap();
+            assert!(!line.contains('\n'));
+            assert_eq!(open_line(Some(&encryption), &line).unwrap(), "{\"id\":1}");
+        }
+
+        assert_eq!(open(None, b"[]".to_vec()).unwrap(), b"[]");
+    }
+
+    #[test]
+    fn unencrypted_data_should_be_refused_once_there_is_a_key() {
+        let encryption = Encryption {
+            cipher: EncryptionCipher::Aes256Gcm,
+            key: [7; 32],
+        };
+
+        assert!(open(Some(&encryption), b"[]".to_vec()).is_err());
+        assert!(open_line(Some(&encryption), "{\"id\":1}").is_err());
+        assert_eq!(open_any(Some(&encryption), b"[]".to_vec()).unwrap(), b"[]");
+        assert_eq!(
+            open_line_any(Some(&encryption), "{\"id\":1}").unwrap(),
+            "{\"id\":1}"
+        );
+    }
+
+    #[test]
+    fn chunk_hashes_should_depend_on_the_key() {
+        let encryption = Encryption {
+            cipher: EncryptionCipher::Aes256Gcm,
+            key: [7; 32],
+        };
+        let other = Encryption {
+            cipher: EncryptionCipher::Aes256Gcm,
+            key: [8; 32],
+        };
+        let data = b"[{\"id\":1}]";
+
+        assert_eq!(encryption.hash(data), encryption.hash(data));
+        assert_ne!(encryption.hash(data), other.hash(data));
+        assert_ne!(encryption.hash(data), compute_hash_sum(data));
+    }
+}
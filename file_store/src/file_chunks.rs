@@ -0,0 +1,132 @@
+use crate::{
+    chunk_engine::ChunkStorage,
+    encryption::{
+        chunk_hash,
+        open,
+        seal,
+        Encryption,
+    },
+    util::{
+        is_collection_file,
+        sync_dir,
+        sync_parent,
+        with_suffix,
+        write_atomic,
+        OLD_SUFFIX,
+    },
+};
+use failure::Error;
+use futures::{
+    future::BoxFuture,
+    FutureExt,
+    StreamExt,
+};
+use std::{
+    io::ErrorKind,
+    path::PathBuf,
+};
+use tokio::{
+    fs::{
+        read,
+        read_dir,
+        remove_file,
+        rename,
+    },
+    task,
+};
+
+/// The chunk files in the docs folder of a collection, encrypted if there is
+/// a key
+#[derive(Clone)]
+pub struct FileChunks<'a> {
+    path: PathBuf,
+    encryption: Option<&'a Encryption>,
+}
+
+impl<'a> FileChunks<'a> {
+    pub fn new(path: PathBuf, encryption: Option<&'a Encryption>) -> Self {
+        Self { path, encryption }
+    }
+}
+
+impl<'e> ChunkStorage for FileChunks<'e> {
+    fn list(&self) -> BoxFuture<Result<Vec<String>, Error>> {
+        async move {
+            if !self.path.is_dir() {
+                return Ok(vec![]);
+            }
+
+            // Anything else is left over from an unfinished write
+            let mut names = vec![];
+            for entry in read_dir(&self.path).await?.collect::<Vec<_>>().await {
+                let name = entry?.file_name().to_string_lossy().to_string();
+                if is_collection_file(&name) {
+                    names.push(name);
+                }
+            }
+            Ok(names)
+        }
+        .boxed()
+    }
+
+    fn read<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            let data = read(self.path.join(name)).await?;
+            let encryption = self.encryption.cloned();
+            task::spawn_blocking(move || open(encryption.as_ref(), data)).await?
+        }
+        .boxed()
+    }
+
+    fn write<'a>(
+        &'a self,
+        name: &'a str,
+        data: Vec<u8>,
+        replaced: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let encryption = self.encryption.cloned();
+            let data = task::spawn_blocking(move || seal(encryption.as_ref(), data)).await??;
+
+            let replaced = match replaced {
+                Some(replaced) => replaced,
+                None => return write_atomic(&self.path.join(name), &data).await,
+            };
+
+            // The new chunk gets a different name, so the old one is moved
+            // aside first. Should we crash before the new chunk is on disk,
+            // recovery puts the old one back
+            let old_path = with_suffix(&self.path.join(replaced), OLD_SUFFIX);
+            if let Err(e) = rename(self.path.join(replaced), &old_path).await {
+                bail!("Failed to rename old chunk file {}: {}", replaced, e);
+            }
+            sync_parent(&old_path).await?;
+
+            write_atomic(&self.path.join(name), &data).await?;
+            remove_file(&old_path).await.ok();
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, names: &'a [String]) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            if names.is_empty() {
+                return Ok(());
+            }
+
+            for name in names {
+                match remove_file(self.path.join(name)).await {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            sync_dir(&self.path).await
+        }
+        .boxed()
+    }
+
+    fn hash(&self, data: &[u8]) -> u64 {
+        chunk_hash(self.encryption, data)
+    }
+}
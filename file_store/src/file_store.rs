@@ -1,33 +1,70 @@
-use crate::util::{
-    compute_hash_sum,
-    extract_file_name,
-    is_collection_file,
-    write_compressed_file,
+use crate::{
+    chunk_engine::{
+        Changes,
+        ChunkEngine,
+    },
+    chunk_index::ChunkIndex,
+    codec::ChunkCodec,
+    data_folder_lock::DataFolderLock,
+    encryption::{
+        open,
+        open_line,
+        seal,
+        seal_line,
+        Encryption,
+        NotEncrypted,
+    },
+    file_chunks::FileChunks,
+    layout::{
+        docs_folder,
+        docs_folder_id,
+        history_file,
+        history_folder,
+        schema_folder,
+        schema_folder_id,
+        NameMap,
+        NAMES_FILE,
+    },
+    recovery::recover,
+    upgrade::prepare_data_folder,
+    util::{
+        sync_parent,
+        write_atomic,
+        write_private,
+    },
+    wal::Wal,
 };
 use colored::Colorize;
 use failure::Error;
-use flate2::read::GzDecoder;
 use futures::{
-    future::{
-        join_all,
-        BoxFuture,
-    },
+    future::BoxFuture,
     lock::Mutex,
-    stream::StreamExt,
+    stream::{
+        BoxStream,
+        StreamExt,
+    },
     Future,
     FutureExt,
 };
 use shelf_config::Config;
 use shelf_database::{
     Collection,
+    CompactionReport,
+    DeadLetter,
     Document,
+    DocumentDelta,
+    DocumentVersion,
     Schema,
     Store,
+    Transaction,
+    Webhook,
 };
 use slog::Logger;
 use std::{
-    collections::HashMap,
-    io::Read,
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
     mem,
     path::Path,
     pin::Pin,
@@ -37,10 +74,9 @@ use std::{
 use tokio::{
     fs::{
         create_dir,
+        create_dir_all,
+        read,
         read_dir,
-        remove_file,
-        rename,
-        File,
         OpenOptions,
     },
     prelude::*,
@@ -50,35 +86,137 @@ use uuid::Uuid;
 
 pub struct FileStore {
     base_path: String,
-    collections: Mutex<HashMap<String, Vec<Collection>>>,
-    documents: Mutex<HashMap<String, HashMap<String, Vec<Arc<Document>>>>>,
+    collections: Mutex<HashMap<Uuid, Vec<Collection>>>,
+    /// Documents handed over since the last flush, keyed by schema and
+    /// collection id
+    pending: Mutex<HashMap<(Uuid, Uuid), Changes>>,
+    chunks: Mutex<HashMap<(Uuid, Uuid), ChunkIndex>>,
+    /// The names of the schemas and collections, written next to the
+    /// schemas since the folders are named by id
+    names: Mutex<NameMap>,
+    /// Held while chunk files are written, so that a flush and a compaction
+    /// never work on the same files, and while documents are read back in
+    io_lock: Mutex<()>,
+    /// Reads and writes the chunk files
+    engine: ChunkEngine,
+    /// Every file is encrypted with this if set
+    encryption: Option<Encryption>,
+    wal: Arc<Wal>,
+    /// Keeps other processes out of the data folder until the store is
+    /// dropped
+    _lock: DataFolderLock,
 }
 
 impl FileStore {
     /// Created a new file store
     ///
     /// # Errors
-    /// Might return an error if it can not create the needed data folder
+    /// Might return an error if it can not create the needed data folder, or
+    /// if another process is using it
     pub async fn new(logger: &Logger, config: &Config) -> Result<Self, Error> {
         let b_path = Path::new(&config.data_folder);
         if !b_path.is_dir() {
             std::fs::create_dir(b_path)?;
         }
 
+        let lock = match DataFolderLock::acquire(b_path) {
+            Ok(lock) => lock,
+            Err(err) => {
+                crit!(logger, "Two servers can not share a data folder, stop the other one or use another data folder"; "error" => format!("{}", err));
+                return Err(err);
+            }
+        };
+
         info!(logger, "Setting up file store"; "data_folder" => Path::new(&config.data_folder).canonicalize().unwrap().to_str().unwrap().to_owned());
 
+        let recovered = recover(logger, b_path).await?;
+        if recovered > 0 {
+            warn!(
+                logger,
+                "Recovered {} files left behind by an unfinished write", recovered
+            );
+        }
+
+        let encryption = Encryption::from_config(config)?;
+        if encryption.is_some() {
+            info!(logger, "\u{1f512} Encrypting files at rest"; "cipher" => format!("{:?}", config.encryption_cipher));
+        }
+
+        let codec = ChunkCodec::from_config(config);
+        prepare_data_folder(
+            logger,
+            b_path,
+            config.auto_upgrade_data,
+            codec,
+            encryption.as_ref(),
+        )
+        .await?;
+
+        let names_path = b_path.join(NAMES_FILE);
+        let names = if names_path.is_file() {
+            serde_json::from_slice(&open(encryption.as_ref(), read(&names_path).await?)?)?
+        } else {
+            NameMap::new()
+        };
+
+        let wal = Arc::new(
+            Wal::open(
+                logger,
+                b_path.join("wal"),
+                config.wal_fsync,
+                encryption.clone(),
+            )
+            .await?,
+        );
+        Wal::start_sync_loop(logger, &wal, config.wal_fsync_interval);
+
         Ok(Self {
             base_path: config.data_folder.to_owned(),
             collections: Mutex::new(HashMap::new()),
-            documents: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            chunks: Mutex::new(HashMap::new()),
+            names: Mutex::new(names),
+            io_lock: Mutex::new(()),
+            engine: ChunkEngine::new(codec, config.load_concurrency),
+            encryption,
+            wal,
+            _lock: lock,
         })
     }
 
+    /// Writes a whole file, encrypted if there is a key
+    async fn write_file(&self, path: &Path, data: Vec<u8>) -> Result<(), Error> {
+        let encryption = self.encryption.clone();
+        let data = task::spawn_blocking(move || seal(encryption.as_ref(), data)).await??;
+        write_atomic(path, &data).await
+    }
+
+    /// Same as `write_file`, but only the owner of the data folder may read
+    /// the file
+    async fn write_private_file(&self, path: &Path, data: Vec<u8>) -> Result<(), Error> {
+        let encryption = self.encryption.clone();
+        let data = task::spawn_blocking(move || seal(encryption.as_ref(), data)).await??;
+        write_private(path, &data).await
+    }
+
+    /// Reads a whole file, decrypting it if it is encrypted
+    async fn read_file(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        open(self.encryption.as_ref(), read(path).await?)
+    }
+
+    /// Writes the mapping of ids to names, called with the lock on the names
+    /// held so that two writes never race
+    async fn write_names(&self, names: &NameMap) -> Result<(), Error> {
+        let data = serde_json::to_vec_pretty(names)?;
+        self.write_file(&Path::new(&self.base_path).join(NAMES_FILE), data)
+            .await
+    }
+
     async fn do_save_collections(&self, logger: &Logger) -> Result<(), Error> {
         let schemas = mem::replace(&mut *self.collections.lock().await, HashMap::new());
-        for (schema_name, collections) in schemas {
-            debug!(logger, "Saving collections for schema {}", schema_name);
-            let base_path = Path::new(&self.base_path).join(schema_name);
+        for (schema_id, collections) in schemas {
+            debug!(logger, "Saving collections for schema {}", schema_id);
+            let base_path = Path::new(&self.base_path).join(schema_folder(schema_id));
 
             if !base_path.is_dir() {
                 create_dir(base_path.clone()).await?;
@@ -87,15 +225,15 @@ impl FileStore {
             let path = base_path.join("collections.json");
 
             debug!(logger, "Writing file");
-            let mut file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(path)
-                .await?;
-
-            let data = serde_json::to_string_pretty(&collections).unwrap();
-
-            file.write_all(&data.as_bytes()).await?;
+            let data = serde_json::to_vec_pretty(&collections)?;
+            self.write_file(&path, data).await?;
+
+            let mut names = self.names.lock().await;
+            names.entry(schema_id).or_default().collections = collections
+                .iter()
+                .map(|i| (i.id, i.name.to_string()))
+                .collect();
+            self.write_names(&names).await?;
         }
         debug!(logger, "Saved collections");
 
@@ -104,83 +242,105 @@ impl FileStore {
 
     async fn do_save_documents(&self, logger: &Logger) -> Result<(), Error> {
         let logger = logger.clone();
-        let documents = mem::replace(&mut *self.documents.lock().await, HashMap::new());
-        for (schema_name, collections) in documents {
-            for (collection_name, documents) in collections {
-                info!(
-                    logger,
-                    "Saving documents for collection {} in schema {}",
-                    collection_name.yellow(),
-                    &schema_name.yellow()
-                );
-                let base_path = Path::new(&self.base_path)
-                    .join(&schema_name)
-                    .join(format!("{}_docs", collection_name));
-                if !base_path.is_dir() {
-                    create_dir(base_path.clone()).await?;
-                }
+        let pending = mem::replace(&mut *self.pending.lock().await, HashMap::new());
+        for ((schema_id, collection_id), changes) in pending {
+            let (schema_name, collection_name) = {
+                let names = self.names.lock().await;
+                let schema = names.get(&schema_id);
+                (
+                    schema.map_or_else(|| schema_id.to_string(), |i| i.name.to_string()),
+                    schema
+                        .and_then(|i| i.collections.get(&collection_id))
+                        .map_or_else(|| collection_id.to_string(), ToString::to_string),
+                )
+            };
+            info!(
+                logger,
+                "Saving {} changed documents for collection {} in schema {}",
+                changes.len(),
+                collection_name.yellow(),
+                &schema_name.yellow()
+            );
+            let base_path = Path::new(&self.base_path)
+                .join(schema_folder(schema_id))
+                .join(docs_folder(collection_id));
+            if !base_path.is_dir() {
+                create_dir(base_path.clone()).await?;
+            }
 
-                let chunks = documents.chunks(10_000);
-
-                let dir: Vec<_> = read_dir(base_path.clone()).await?.collect().await;
-
-                for (index, chunk) in chunks.enumerate() {
-                    if let Some(dir) = dir.iter().find(|i| {
-                        i.as_ref()
-                            .unwrap()
-                            .file_name()
-                            .to_str()
-                            .unwrap()
-                            .starts_with(&format!("{}_", index))
-                    }) {
-                        let (file_name, hash) = extract_file_name(&dir)?;
-                        if !is_collection_file(&file_name) {
-                            continue;
-                        }
-                        let data = serde_json::to_string(&chunk).unwrap();
-                        let sum = compute_hash_sum(&data);
-
-                        if sum == hash {
-                            debug!(
-                                logger,
-                                "Chunk {} has not changed for collection {} in schema {}",
-                                index,
-                                collection_name,
-                                &schema_name
-                            );
-                        } else {
-                            let new_path = format!("{}~old", file_name);
-                            if rename(base_path.join(&file_name), base_path.join(&new_path))
-                                .await
-                                .is_ok()
-                            {
-                                debug!(
-                                    logger,
-                                    "Renamed old collection file in case all goes wrong"
-                                );
-                            } else {
-                                error!(logger, "Failed to rename old collection file"; "file_name" => &file_name);
-                                bail!("Failed to rename old collection file");
-                            }
-
-                            let path = base_path.join(&format!("{}_{}.gz", index, sum));
-                            write_compressed_file(&data, &path).await?;
-
-                            if remove_file(base_path.join(&new_path)).await.is_ok() {
-                                debug!(logger, "Removed old collection file");
-                            }
-                        }
-                    } else {
-                        let data = serde_json::to_string(&chunk).unwrap();
-                        let path =
-                            base_path.join(&format!("{}_{}.gz", index, compute_hash_sum(&data)));
-                        write_compressed_file(&data, &path).await?;
-                    }
+            // If a chunk fails the index is left out, it is rebuilt from the
+            // files on the next save
+            let key = (schema_id, collection_id);
+            let index = self.chunks.lock().await.remove(&key);
+            let logger = logger.new(o!("collection_name" => collection_name));
+            let index = self
+                .engine
+                .save(
+                    &logger,
+                    &self.chunk_files(schema_id, collection_id),
+                    index,
+                    changes,
+                )
+                .await?;
+
+            self.chunks.lock().await.insert(key, index);
+        }
+
+        Ok(())
+    }
+
+    /// The chunk files of a collection
+    fn chunk_files(&self, schema_id: Uuid, collection_id: Uuid) -> FileChunks {
+        FileChunks::new(
+            Path::new(&self.base_path)
+                .join(schema_folder(schema_id))
+                .join(docs_folder(collection_id)),
+            self.encryption.as_ref(),
+        )
+    }
+
+    async fn compact_collection(
+        &self,
+        logger: &Logger,
+        schema_id: Uuid,
+        collection_id: Uuid,
+    ) -> Result<CompactionReport, Error> {
+        let key = (schema_id, collection_id);
+        let index = self.chunks.lock().await.remove(&key);
+        let (index, report) = self
+            .engine
+            .compact(logger, &self.chunk_files(schema_id, collection_id), index)
+            .await?;
+        self.chunks.lock().await.insert(key, index);
+
+        Ok(report)
+    }
+
+    /// Reads the versions in the history file of a document
+    async fn read_history_file(
+        &self,
+        logger: &Logger,
+        path: &Path,
+    ) -> Result<Vec<DocumentVersion>, Error> {
+        let mut file = OpenOptions::new().read(true).open(path).await?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).await?;
+
+        let mut versions = vec![];
+        for line in contents.lines().filter(|i| !i.is_empty()) {
+            let version = open_line(self.encryption.as_ref(), line)
+                .and_then(|i| Ok(serde_json::from_str::<DocumentVersion>(&i)?));
+            match version {
+                Ok(version) => versions.push(version),
+                Err(e) if e.downcast_ref::<NotEncrypted>().is_some() => return Err(e),
+                Err(e) => {
+                    // A crash in the middle of an append leaves a torn last line
+                    warn!(logger, "Skipping unreadable line in history file"; "error" => format!("{}", e));
                 }
             }
         }
 
-        Ok(())
+        Ok(versions)
     }
 }
 
@@ -197,13 +357,7 @@ impl Store for FileStore {
 
             if path.is_file() {
                 debug!(logger, "Fetching schemas from file");
-                let mut file = OpenOptions::new()
-                    .read(true)
-                    .open(path)
-                    .await?;
-
-                let mut contents = vec![];
-                file.read_to_end(&mut contents).await?;
+                let contents = self.read_file(&path).await?;
 
                 match serde_json::from_slice::<HashMap<Uuid, Schema>>(&contents) {
                     Ok(result) => Ok(result),
@@ -228,17 +382,15 @@ impl Store for FileStore {
             o!("schema_name" => schema.name.to_string(), "schema_id" => schema.id.to_string()),
         );
         let path = Path::new(&self.base_path)
-            .join(&schema.name)
+            .join(schema_folder(schema.id))
             .join("collections.json");
         let base_path = self.base_path.to_string();
+        let encryption = self.encryption.clone();
 
         async move {
             if path.is_file() {
                 debug!(logger, "Fetching collections from file");
-                let mut file = OpenOptions::new().read(true).open(path).await?;
-
-                let mut contents = vec![];
-                file.read_to_end(&mut contents).await?;
+                let contents = open(encryption.as_ref(), read(&path).await?)?;
 
                 let result = serde_json::from_slice::<Vec<Collection>>(&contents)?;
 
@@ -248,8 +400,7 @@ impl Store for FileStore {
                 if !Path::new(&base_path).is_dir() {
                     create_dir(&base_path).await?;
                 }
-                let mut file = File::create(path).await?;
-                file.write_all(b"[]").await?;
+                write_atomic(&path, &seal(encryption.as_ref(), b"[]".to_vec())?).await?;
 
                 Ok(vec![])
             }
@@ -257,69 +408,33 @@ impl Store for FileStore {
         .boxed()
     }
 
-    fn get_documents(
-        &self,
-        logger: &Logger,
-        schema: &Schema,
-        collection: &Collection,
-    ) -> Pin<Box<dyn Future<Output = Result<Vec<Document>, Error>> + Send>> {
-        let logger = logger.new(o!("schema_name" => schema.name.to_string(), "schema_id" => schema.id.to_string(), "collection_name" => collection.name.to_string(), "collection_id" => collection.id.to_string()));
-        let path = Path::new(&self.base_path)
-            .join(&schema.name)
-            .join(&format!("{}_docs", collection.name));
-
+    fn get_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<Document>, Error>> {
         async move {
-            let mut documents = vec![];
-
-            if path.is_dir() {
-                let files: Vec<_> = path.read_dir().unwrap().collect();
-
-                let contents = join_all(files.into_iter().map(|file| async {
-                    if let Ok(dir) = file {
-                        let name = dir.file_name().to_str().unwrap().to_string();
-                        trace!(logger, "Reading file {}", name.yellow());
-                        let mut file = OpenOptions::new()
-                            .read(true)
-                            .open(path.join(name.clone()))
-                            .await?;
-
-                        let mut contents = vec![];
-                        file.read_to_end(&mut contents).await?;
-
-                        let res = task::spawn_blocking(move || {
-                            let mut gz = GzDecoder::new(&contents[..]);
-                            let mut decoded = String::new();
-                            gz.read_to_string(&mut decoded).unwrap();
-
-                            let result = serde_json::from_str::<Vec<Document>>(&decoded)?;
-
-                            Result::<Vec<Document>, Error>::Ok(result)
-                        }).await??;
-
-                        if res.is_empty() {
-                            warn!(logger, "Weird, found file with no content"; "file_name" => &name);
-                        }
-                        trace!(logger, "Found {} documents in file {}", res.len().to_string().yellow(), name.yellow());
-
-                        Ok(res)
-                    } else {
-                        bail!("Could not read file");
-                    }
-                }).collect::<Vec<_>>()).await;
-
-                for content in contents {
-                    if let Ok(mut data) = content {
-                        documents.append(&mut data);
-                    }
-                }
-            }
-
-            if documents.is_empty() {
-                warn!(logger, "No documents found");
-            }
+            let logger = logger.new(o!("collection_name" => collection.name.to_string()));
+            // Evicted collections are read back in while the cache runs, this
+            // keeps them from seeing a flush or compaction halfway through
+            let _guard = self.io_lock.lock().await;
+            self.engine
+                .get_documents(&logger, self.chunk_files(schema.id, collection.id))
+                .await
+        }
+        .boxed()
+    }
 
-            Ok(documents)
-        }.boxed()
+    fn stream_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxStream<'a, Result<Vec<Document>, Error>> {
+        let logger = logger.new(o!("schema_name" => schema.name.to_string(), "schema_id" => schema.id.to_string(), "collection_name" => collection.name.to_string(), "collection_id" => collection.id.to_string()));
+        self.engine
+            .stream_documents(&logger, self.chunk_files(schema.id, collection.id))
     }
 
     fn save_schema<'a>(
@@ -345,23 +460,7 @@ impl Store for FileStore {
             }
 
             let path = base_path.join("schemas.json");
-
-            if rename(path.clone(), base_path.join("schemas.json~old"))
-                .await
-                .is_ok()
-            {
-                debug!(logger, "Renamed old schema file in case all goes wrong");
-            }
-
-            let mut file = OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(path)
-                .await?;
-
-            debug!(logger, "Opened handle to new schema file");
-
-            let data = serde_json::to_string_pretty(&schemas)?;
+            let data = serde_json::to_vec_pretty(&schemas)?;
 
             trace!(
                 logger,
@@ -369,18 +468,19 @@ impl Store for FileStore {
                 serde_json::to_string(&schemas).unwrap()
             );
 
-            file.write_all(&data.as_bytes()).await?;
+            self.write_file(&path, data).await?;
 
             debug!(logger, "Successfully saved schema {}", &schema_name);
 
-            if remove_file(base_path.join("schemas.json~old"))
+            let mut names = self.names.lock().await;
+            names.entry(schema_id).or_default().name = schema_name.to_string();
+            self.write_names(&names).await?;
+            drop(names);
+
+            if create_dir(base_path.join(schema_folder(schema_id)))
                 .await
                 .is_ok()
             {
-                debug!(logger, "Removed old schema file");
-            }
-
-            if create_dir(base_path.join(&schema_name)).await.is_ok() {
                 info!(logger, "Created new home dir for all collections");
             }
 
@@ -402,36 +502,34 @@ impl Store for FileStore {
         async move {
             let mut collections = self.collections.lock().await;
 
-            if let Some(list) = collections.get_mut(&schema.name) {
+            if let Some(list) = collections.get_mut(&schema.id) {
                 list.push(collection.clone());
             } else {
-                collections.insert(schema.name.to_string(), vec![collection.clone()]);
+                collections.insert(schema.id, vec![collection.clone()]);
             }
             Ok(())
         }
         .boxed()
     }
 
-    fn save_document<'a>(
+    fn save_delta<'a>(
         &'a self,
         _logger: &'a Logger,
         schema: &'a Schema,
         collection: &'a Collection,
-        document: Arc<Document>,
-    ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
+        delta: DocumentDelta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
         async move {
-            let mut documents = self.documents.lock().await;
+            let mut pending = self.pending.lock().await;
+            let changes = pending
+                .entry((schema.id, collection.id))
+                .or_insert_with(HashMap::new);
 
-            if let Some(schemas) = documents.get_mut(&schema.name) {
-                if let Some(collections) = schemas.get_mut(&collection.name) {
-                    collections.push(document);
-                } else {
-                    schemas.insert(collection.name.to_string(), vec![document]);
-                }
-            } else {
-                let mut schemas = HashMap::new();
-                schemas.insert(collection.name.to_string(), vec![document]);
-                documents.insert(schema.name.to_string(), schemas);
+            for document in delta.upserted {
+                changes.insert(document.id, Some(document));
+            }
+            for id in delta.deleted {
+                changes.insert(id, None);
             }
             Ok(())
         }
@@ -444,6 +542,7 @@ impl Store for FileStore {
     ) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>> {
         async move {
             let start_time = Instant::now();
+            let _guard = self.io_lock.lock().await;
             self.do_save_collections(&logger).await?;
             self.do_save_documents(&logger).await?;
             info!(logger, "\u{1f4bf} Saved data to disk"; "save_time" => format!("{:#?}", Instant::now().duration_since(start_time)));
@@ -451,4 +550,548 @@ impl Store for FileStore {
         }
         .boxed()
     }
+
+    fn compact<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<CompactionReport, Error>> {
+        async move {
+            let start_time = Instant::now();
+            let _guard = self.io_lock.lock().await;
+            let mut report = CompactionReport::default();
+
+            // The wal and the quarantine are skipped, the files in the
+            // quarantine are damaged and must be left as they are
+            for schema_entry in read_dir(&self.base_path).await?.collect::<Vec<_>>().await {
+                let schema_path = schema_entry?.path();
+                let schema_id = schema_path.file_name().and_then(|i| schema_folder_id(&i.to_string_lossy()));
+                let schema_id = match schema_id {
+                    Some(id) if schema_path.is_dir() => id,
+                    _ => continue,
+                };
+
+                for entry in read_dir(&schema_path).await?.collect::<Vec<_>>().await {
+                    let path = entry?.path();
+                    let collection_id = path.file_name().and_then(|i| docs_folder_id(&i.to_string_lossy()));
+                    let collection_id = match collection_id {
+                        Some(id) if path.is_dir() => id,
+                        _ => continue,
+                    };
+
+                    let collection_report = self
+                        .compact_collection(&logger, schema_id, collection_id)
+                        .await?;
+                    if collection_report.files_after != collection_report.files_before {
+                        debug!(logger, "Compacted collection {}", collection_id.to_string().yellow(); "schema_id" => schema_id.to_string(), "files_before" => collection_report.files_before, "files_after" => collection_report.files_after);
+                    }
+                    report.add(&collection_report);
+                }
+            }
+
+            info!(logger, "🧹 Compacted chunk files"; "files_before" => report.files_before, "files_after" => report.files_after, "compaction_time" => format!("{:#?}", Instant::now().duration_since(start_time)));
+            Ok(report)
+        }
+        .boxed()
+    }
+
+    fn get_webhooks<'a>(
+        &'a self,
+        logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<Vec<Webhook>, Error>> {
+        async move {
+            let path = Path::new(&self.base_path).join("webhooks.json");
+
+            if !path.is_file() {
+                debug!(logger, "No webhooks file detected");
+                return Ok(vec![]);
+            }
+
+            debug!(logger, "Fetching webhooks from file");
+            let contents = self.read_file(&path).await?;
+
+            match serde_json::from_slice::<Vec<Webhook>>(&contents) {
+                Ok(result) => Ok(result),
+                Err(e) => {
+                    crit!(logger, "Failed to parse webhooks file, perhaps the file has been corrupted?"; "error" => format!("{}", e));
+                    Err(e.into())
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn save_webhooks<'a>(
+        &'a self,
+        logger: &'a Logger,
+        webhooks: &'a [Webhook],
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let base_path = Path::new(&self.base_path);
+            let path = base_path.join("webhooks.json");
+
+            // The file holds the secrets deliveries are signed with
+            let data = serde_json::to_vec_pretty(&webhooks)?;
+            self.write_private_file(&path, data).await?;
+
+            debug!(logger, "Saved {} webhooks", webhooks.len());
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_dead_letter<'a>(
+        &'a self,
+        logger: &'a Logger,
+        dead_letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let path = Path::new(&self.base_path).join("dead_letters.log");
+
+            let mut file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .await?;
+
+            let mut data = seal_line(
+                self.encryption.as_ref(),
+                serde_json::to_string(&dead_letter)?,
+            )?;
+            data.push('\n');
+            file.write_all(&data.as_bytes()).await?;
+
+            debug!(logger, "Wrote dead letter to log"; "webhook_id" => dead_letter.webhook_id.to_string(), "delivery_id" => dead_letter.delivery_id.to_string());
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+        versions: &'a [DocumentVersion],
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let base_path = Path::new(&self.base_path)
+                .join(schema_folder(schema.id))
+                .join(history_folder(collection.id));
+            if !base_path.is_dir() {
+                create_dir_all(&base_path).await?;
+            }
+
+            let mut by_document: BTreeMap<Uuid, String> = BTreeMap::new();
+            for version in versions {
+                let data = by_document.entry(version.document_id).or_default();
+                data.push_str(&seal_line(
+                    self.encryption.as_ref(),
+                    serde_json::to_string(&version)?,
+                )?);
+                data.push('\n');
+            }
+
+            for (document_id, data) in by_document {
+                let path = base_path.join(history_file(document_id));
+                let created = !path.is_file();
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&path)
+                    .await?;
+                file.write_all(&data.as_bytes()).await?;
+                file.sync_all().await?;
+                if created {
+                    sync_parent(&path).await?;
+                }
+            }
+
+            trace!(logger, "Wrote {} versions to history", versions.len(); "collection_name" => &collection.name);
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+        id: Uuid,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>> {
+        async move {
+            let path = Path::new(&self.base_path)
+                .join(schema_folder(schema.id))
+                .join(history_folder(collection.id))
+                .join(history_file(id));
+
+            if !path.is_file() {
+                debug!(logger, "No history file detected"; "collection_name" => &collection.name, "document_id" => id.to_string());
+                return Ok(vec![]);
+            }
+
+            let mut versions = self.read_history_file(logger, &path).await?;
+            // Replaying the write-ahead log saves versions a second time
+            versions.sort_by_key(|i| i.revision);
+            versions.dedup_by_key(|i| i.revision);
+
+            Ok(versions)
+        }
+        .boxed()
+    }
+
+    fn get_collection_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>> {
+        async move {
+            let path = Path::new(&self.base_path)
+                .join(schema_folder(schema.id))
+                .join(history_folder(collection.id));
+            if !path.is_dir() {
+                return Ok(vec![]);
+            }
+
+            let mut versions = vec![];
+            for entry in read_dir(&path).await?.collect::<Vec<_>>().await {
+                let path = entry?.path();
+                if path.is_file() {
+                    versions.append(&mut self.read_history_file(logger, &path).await?);
+                }
+            }
+            versions.sort_by_key(|i| (i.document_id, i.revision));
+            versions.dedup_by_key(|i| (i.document_id, i.revision));
+
+            Ok(versions)
+        }
+        .boxed()
+    }
+
+    fn append_wal<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        self.wal.append(transaction).boxed()
+    }
+
+    fn read_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<Vec<Transaction>, Error>> {
+        self.wal.read(logger).boxed()
+    }
+
+    fn rotate_wal<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<u64, Error>> {
+        async move {
+            let closed = self.wal.rotate().await?;
+            debug!(logger, "Rotated write-ahead log"; "closed_segment" => closed);
+            Ok(closed)
+        }
+        .boxed()
+    }
+
+    fn truncate_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+        segment: u64,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        self.wal.truncate(logger, segment).boxed()
+    }
+
+    fn seal_backup(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        seal(self.encryption.as_ref(), data)
+    }
+
+    fn open_backup(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        open(self.encryption.as_ref(), data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        chunk_index::{
+            chunk_file_name,
+            chunk_number,
+            CHUNK_SIZE,
+            MERGE_SIZE,
+        },
+        codec::{
+            encode_chunk,
+            ChunkCodec,
+        },
+        file_store::FileStore,
+        fsck::QUARANTINE_FOLDER,
+        layout::{
+            docs_folder,
+            schema_folder,
+        },
+        util::{
+            compute_hash_sum,
+            is_collection_file,
+        },
+    };
+    use futures::StreamExt;
+    use shelf_config::Config;
+    use shelf_database::{
+        test::StoreFixture,
+        Document,
+        Store,
+    };
+    use std::{
+        collections::HashMap,
+        fs,
+        ops::Deref,
+        path::{
+            Path,
+            PathBuf,
+        },
+        sync::Arc,
+    };
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    /// The store files are kept until the test ends, so that they can be
+    /// opened again
+    struct Setup {
+        fixture: StoreFixture,
+        config: Config,
+        _dir: TempDir,
+    }
+
+    impl Setup {
+        async fn new() -> (Self, FileStore) {
+            let dir = TempDir::new().unwrap();
+            let setup = Self {
+                fixture: StoreFixture::new(),
+                config: Config {
+                    data_folder: dir.path().join("data").to_string_lossy().to_string(),
+                    ..Config::default()
+                },
+                _dir: dir,
+            };
+
+            let store = FileStore::new(&setup.logger, &setup.config).await.unwrap();
+            setup.create(&store).await;
+            (setup, store)
+        }
+
+        async fn reopen(&self, store: FileStore) -> FileStore {
+            drop(store);
+            FileStore::new(&self.logger, &self.config).await.unwrap()
+        }
+
+        fn docs_path(&self) -> PathBuf {
+            Path::new(&self.config.data_folder)
+                .join(schema_folder(self.schema.id))
+                .join(docs_folder(self.collection.id))
+        }
+
+        /// The chunk numbers of the chunk files on disk, in order
+        fn chunks(&self) -> Vec<usize> {
+            let mut chunks: Vec<_> = fs::read_dir(self.docs_path())
+                .unwrap()
+                .map(|i| i.unwrap().file_name().to_string_lossy().to_string())
+                .filter(|i| is_collection_file(i))
+                .map(|i| chunk_number(&i).unwrap())
+                .collect();
+            chunks.sort_unstable();
+            chunks
+        }
+
+        /// Writes a chunk file by hand, like a flush that crashed before it
+        /// could merge would leave it
+        async fn write_chunk(&self, chunk: usize, documents: Vec<Arc<Document>>) {
+            let codec = ChunkCodec::from_config(&self.config);
+            let data = encode_chunk(codec, documents).await.unwrap();
+            let file_name = chunk_file_name(chunk, compute_hash_sum(&data));
+            fs::write(self.docs_path().join(file_name), data).unwrap();
+        }
+    }
+
+    impl Deref for Setup {
+        type Target = StoreFixture;
+
+        fn deref(&self) -> &StoreFixture {
+            &self.fixture
+        }
+    }
+
+    fn documents(count: usize) -> Vec<Arc<Document>> {
+        (0..count)
+            .map(|_| Arc::new(Document::new(Uuid::new_v4(), HashMap::new())))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn flush_should_split_a_chunk_that_grows_past_the_chunk_size() {
+        let (setup, store) = Setup::new().await;
+
+        setup
+            .save_and_flush(&store, documents(CHUNK_SIZE), vec![])
+            .await;
+        assert_eq!(setup.chunks(), vec![1]);
+
+        setup.save_and_flush(&store, documents(1), vec![]).await;
+        assert_eq!(setup.chunks(), vec![2, 3]);
+        assert_eq!(setup.documents(&store).await.len(), CHUNK_SIZE + 1);
+    }
+
+    #[tokio::test]
+    async fn stream_documents_should_yield_a_batch_per_chunk() {
+        let (setup, store) = Setup::new().await;
+        setup
+            .save_and_flush(&store, documents(CHUNK_SIZE + 1), vec![])
+            .await;
+        assert_eq!(setup.chunks(), vec![2, 3]);
+
+        let batches: Vec<_> = store
+            .stream_documents(&setup.logger, &setup.schema, &setup.collection)
+            .map(|i| i.unwrap().len())
+            .collect()
+            .await;
+
+        assert_eq!(batches.len(), 2);
+        assert!(batches.iter().all(|i| *i <= CHUNK_SIZE));
+        assert_eq!(batches.iter().sum::<usize>(), CHUNK_SIZE + 1);
+    }
+
+    #[tokio::test]
+    async fn flush_should_merge_chunks_that_shrink_below_the_merge_size() {
+        let (setup, store) = Setup::new().await;
+        let documents = documents(CHUNK_SIZE + 1);
+        setup
+            .save_and_flush(&store, documents.clone(), vec![])
+            .await;
+        assert_eq!(setup.chunks(), vec![2, 3]);
+
+        // Still too many to fit in one chunk
+        let deleted = CHUNK_SIZE + 1 - MERGE_SIZE - 1;
+        let ids: Vec<_> = documents.iter().map(|i| i.id).collect();
+        setup
+            .save_and_flush(&store, vec![], ids[..deleted].to_vec())
+            .await;
+        assert_eq!(setup.chunks(), vec![2, 3]);
+
+        setup
+            .save_and_flush(&store, vec![], ids[deleted..=deleted].to_vec())
+            .await;
+        assert_eq!(setup.chunks(), vec![1]);
+        assert_eq!(setup.documents(&store).await.len(), MERGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn flush_should_rearrange_chunk_files_that_are_misplaced() {
+        let (setup, store) = Setup::new().await;
+        setup.save_and_flush(&store, documents(10), vec![]).await;
+
+        // Left behind by an older version, with a chunk number that does not
+        // match the documents in it
+        let path = setup.docs_path();
+        for entry in fs::read_dir(&path).unwrap() {
+            let file_name = entry.unwrap().file_name().to_string_lossy().to_string();
+            if !is_collection_file(&file_name) {
+                continue;
+            }
+            let misplaced = format!("5{}", &file_name[1..]);
+            fs::rename(path.join(&file_name), path.join(misplaced)).unwrap();
+        }
+        assert_eq!(setup.chunks(), vec![5]);
+
+        let store = setup.reopen(store).await;
+        assert_eq!(setup.documents(&store).await.len(), 10);
+
+        setup.save_and_flush(&store, documents(1), vec![]).await;
+        assert_eq!(setup.chunks(), vec![1]);
+        assert_eq!(setup.documents(&store).await.len(), 11);
+    }
+
+    /// Documents in the left and right half of the chunk tree, that is in
+    /// chunk 2 and chunk 3
+    fn halves(count: u128) -> (Vec<Arc<Document>>, Vec<Arc<Document>>) {
+        let document = |id| Arc::new(Document::new(Uuid::from_u128(id), HashMap::new()));
+        (
+            (1..=count).map(document).collect(),
+            (1..=count).map(|i| document(i | (1 << 127))).collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn compact_should_merge_small_sibling_chunks() {
+        let (setup, store) = Setup::new().await;
+        setup.save_and_flush(&store, documents(1), vec![]).await;
+        fs::remove_dir_all(setup.docs_path()).unwrap();
+        fs::create_dir(setup.docs_path()).unwrap();
+
+        let (left, right) = halves(3);
+        setup.write_chunk(2, left).await;
+        setup.write_chunk(3, right).await;
+        let store = setup.reopen(store).await;
+
+        let report = store.compact(&setup.logger).await.unwrap();
+
+        assert_eq!(report.collections, 1);
+        assert_eq!(report.files_before, 2);
+        assert_eq!(report.files_after, 1);
+        assert_eq!(setup.chunks(), vec![1]);
+        assert_eq!(setup.documents(&store).await.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn compact_should_keep_the_newest_revision_of_overlapping_chunks() {
+        let (setup, store) = Setup::new().await;
+        setup.save_and_flush(&store, documents(1), vec![]).await;
+        fs::remove_dir_all(setup.docs_path()).unwrap();
+        fs::create_dir(setup.docs_path()).unwrap();
+
+        // Left behind by a crash in the middle of a split
+        let (left, _) = halves(2);
+        let mut newer = Document::clone(&left[0]);
+        newer.revision = 2;
+        setup.write_chunk(1, left.clone()).await;
+        setup
+            .write_chunk(2, vec![Arc::new(newer), Arc::clone(&left[1])])
+            .await;
+        let store = setup.reopen(store).await;
+
+        store.compact(&setup.logger).await.unwrap();
+
+        assert_eq!(setup.chunks(), vec![1]);
+        let documents = setup.documents(&store).await;
+        assert_eq!(documents.len(), 2);
+        let revision = documents
+            .iter()
+            .find(|i| i.id == left[0].id)
+            .unwrap()
+            .revision;
+        assert_eq!(revision, 2);
+    }
+
+    #[tokio::test]
+    async fn compact_should_leave_the_quarantine_alone() {
+        let (setup, store) = Setup::new().await;
+        setup.save_and_flush(&store, documents(1), vec![]).await;
+
+        let relative = setup
+            .docs_path()
+            .strip_prefix(&setup.config.data_folder)
+            .unwrap()
+            .to_path_buf();
+        let quarantine = Path::new(&setup.config.data_folder)
+            .join(QUARANTINE_FOLDER)
+            .join(relative);
+        fs::create_dir_all(&quarantine).unwrap();
+        fs::write(quarantine.join(chunk_file_name(2, 1)), "damaged").unwrap();
+
+        let report = store.compact(&setup.logger).await.unwrap();
+
+        assert_eq!(report.collections, 1);
+        assert_eq!(
+            fs::read(quarantine.join(chunk_file_name(2, 1))).unwrap(),
+            b"damaged"
+        );
+    }
 }
@@ -0,0 +1,63 @@
+use crate::util::write_atomic;
+use failure::Error;
+use serde_derive::{
+    Deserialize,
+    Serialize,
+};
+use std::path::Path;
+use tokio::fs::read;
+
+/// The version of the data folder layout written by this version of shelf.
+/// Bump it together with a new step in `upgrade`
+///
+/// 1. No manifest, chunks may still be gzipped json named `{chunk}_{hash}.gz`
+/// 2. Every chunk is a `{chunk}_{hash}.chunk` file with a header
+/// 3. Schema and collection folders are named by id instead of by name
+/// 4. The history of a collection is a folder with a log per document
+pub const FORMAT_VERSION: u32 = 4;
+
+const MANIFEST_FILE: &str = "format.json";
+
+/// Tells which layout a data folder is in. It is never encrypted, so the
+/// version can be checked before any key is loaded
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatManifest {
+    pub version: u32,
+    /// The version of shelf that last wrote the manifest, for troubleshooting
+    pub written_by: String,
+}
+
+impl FormatManifest {
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            written_by: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Returns `None` if the data folder has no manifest
+    ///
+    /// # Errors
+    /// Returns an error if the manifest can not be read or parsed
+    pub async fn read(data_folder: &Path) -> Result<Option<Self>, Error> {
+        let path = data_folder.join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let data = read(&path).await?;
+        let manifest = serde_json::from_slice(&data)
+            .map_err(|e| format_err!("The format manifest {} is damaged: {}", path.display(), e))?;
+        Ok(Some(manifest))
+    }
+
+    /// # Errors
+    /// Returns an error if the manifest can not be written
+    pub async fn write(&self, data_folder: &Path) -> Result<(), Error> {
+        write_atomic(
+            &data_folder.join(MANIFEST_FILE),
+            &serde_json::to_vec_pretty(self)?,
+        )
+        .await
+    }
+}
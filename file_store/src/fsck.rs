@@ -0,0 +1,265 @@
+use crate::{
+    chunk_index::chunk_number,
+    codec::read_chunk_file,
+    data_folder_lock::DataFolderLock,
+    encryption::{
+        open,
+        Encryption,
+        NotEncrypted,
+    },
+    layout::{
+        NameMap,
+        NAMES_FILE,
+    },
+    util::{
+        is_collection_file,
+        sync_parent,
+    },
+};
+use failure::Error;
+use futures::stream::StreamExt;
+use serde::de::DeserializeOwned;
+use shelf_database::{
+    Collection,
+    Schema,
+    Webhook,
+};
+use slog::Logger;
+use std::{
+    collections::HashMap,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use tokio::fs::{
+    create_dir_all,
+    read,
+    read_dir,
+    rename,
+};
+use uuid::Uuid;
+
+/// Damaged files are moved here, keeping their path within the data folder
+pub const QUARANTINE_FOLDER: &str = "quarantine";
+
+#[derive(Debug)]
+pub struct DamagedFile {
+    pub path: PathBuf,
+    pub reason: String,
+    pub quarantined: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    pub checked: usize,
+    pub damaged: Vec<DamagedFile>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.damaged.is_empty()
+    }
+
+    /// Unencrypted files are not damaged, just not rotated yet. Quarantining
+    /// them would throw away the data, so the check stops instead
+    fn check(
+        &mut self,
+        logger: &Logger,
+        path: &Path,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.checked += 1;
+        match result {
+            Ok(()) => trace!(logger, "File is intact"; "path" => path.display().to_string()),
+            Err(e) if e.downcast_ref::<NotEncrypted>().is_some() => {
+                bail!("Failed to check {}: {}", path.display(), e)
+            }
+            Err(e) => {
+                error!(logger, "Found damaged file"; "path" => path.display().to_string(), "error" => format!("{}", e));
+                self.damaged.push(DamagedFile {
+                    path: path.to_owned(),
+                    reason: format!("{}", e),
+                    quarantined: false,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Verifies every schema, collection and chunk file in a data folder. The
+/// store must not be running while this is done. With `quarantine` set the
+/// damaged files are moved out of the way, so the database can start with
+/// whatever is left
+///
+/// # Errors
+/// Returns an error if the data folder can not be read or is in use, or if a
+/// file is not encrypted while there is a key. Damaged files are only
+/// reported
+pub async fn fsck(
+    logger: &Logger,
+    data_folder: &Path,
+    quarantine: bool,
+    encryption: Option<&Encryption>,
+) -> Result<FsckReport, Error> {
+    if !data_folder.is_dir() {
+        bail!("The data folder {} does not exist", data_folder.display());
+    }
+    let _lock = DataFolderLock::acquire(data_folder)?;
+
+    let mut report = FsckReport::default();
+
+    let schemas_path = data_folder.join("schemas.json");
+    if schemas_path.is_file() {
+        let result = check_json::<HashMap<Uuid, Schema>>(&schemas_path, encryption).await;
+        report.check(logger, &schemas_path, result)?;
+    }
+    let webhooks_path = data_folder.join("webhooks.json");
+    if webhooks_path.is_file() {
+        let result = check_json::<Vec<Webhook>>(&webhooks_path, encryption).await;
+        report.check(logger, &webhooks_path, result)?;
+    }
+    let names_path = data_folder.join(NAMES_FILE);
+    if names_path.is_file() {
+        let result = check_json::<NameMap>(&names_path, encryption).await;
+        report.check(logger, &names_path, result)?;
+    }
+
+    for schema_folder in folders(data_folder).await? {
+        let name = schema_folder
+            .file_name()
+            .map(|i| i.to_string_lossy().to_string());
+        if name.as_deref() == Some(QUARANTINE_FOLDER) || name.as_deref() == Some("wal") {
+            continue;
+        }
+
+        let collections_path = schema_folder.join("collections.json");
+        if collections_path.is_file() {
+            let result = check_json::<Vec<Collection>>(&collections_path, encryption).await;
+            report.check(logger, &collections_path, result)?;
+        }
+
+        for docs_folder in folders(&schema_folder).await? {
+            check_chunks(logger, &docs_folder, encryption, &mut report).await?;
+        }
+    }
+
+    if quarantine {
+        for damaged in &mut report.damaged {
+            let relative = damaged.path.strip_prefix(data_folder)?;
+            let target = data_folder.join(QUARANTINE_FOLDER).join(relative);
+            if let Some(parent) = target.parent() {
+                create_dir_all(parent).await?;
+            }
+            rename(&damaged.path, &target).await?;
+            sync_parent(&damaged.path).await?;
+            warn!(logger, "Moved damaged file to quarantine"; "path" => damaged.path.display().to_string(), "target" => target.display().to_string());
+            damaged.quarantined = true;
+        }
+    }
+
+    Ok(report)
+}
+
+async fn check_chunks(
+    logger: &Logger,
+    path: &Path,
+    encryption: Option<&Encryption>,
+    report: &mut FsckReport,
+) -> Result<(), Error> {
+    let entries: Vec<_> = read_dir(path).await?.collect().await;
+    let mut chunks = HashMap::new();
+
+    for entry in entries {
+        let file_name = entry?.file_name().to_string_lossy().to_string();
+        if !is_collection_file(&file_name) {
+            continue;
+        }
+
+        let file_path = path.join(&file_name);
+        let result =
+            match chunk_number(&file_name).and_then(|i| chunks.insert(i, file_name.clone())) {
+                Some(other) => Err(format_err!("Holds the same chunk as {}", other)),
+                None => check_chunk(&file_path, encryption).await,
+            };
+        report.check(logger, &file_path, result)?;
+    }
+
+    Ok(())
+}
+
+async fn check_chunk(path: &Path, encryption: Option<&Encryption>) -> Result<(), Error> {
+    read_chunk_file(path, encryption).await?;
+    Ok(())
+}
+
+async fn check_json<T: DeserializeOwned>(
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<(), Error> {
+    serde_json::from_slice::<T>(&open(encryption, read(path).await?)?)?;
+    Ok(())
+}
+
+async fn folders(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries: Vec<_> = read_dir(path).await?.collect().await;
+
+    let mut folders = vec![];
+    for entry in entries {
+        let entry_path = entry?.path();
+        if entry_path.is_dir() {
+            folders.push(entry_path);
+        }
+    }
+    folders.sort();
+
+    Ok(folders)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        codec::ChunkCodec,
+        fsck::{
+            fsck,
+            QUARANTINE_FOLDER,
+        },
+    };
+    use sloggers::{
+        null::NullLoggerBuilder,
+        Build,
+    };
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn fsck_should_quarantine_chunks_with_a_bad_checksum() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+        let docs = path.join("schema").join("users_docs");
+        fs::create_dir_all(&docs).unwrap();
+
+        let data = ChunkCodec::default().encode(&[]).unwrap();
+        fs::write(docs.join("0_1.chunk"), &data).unwrap();
+        fs::write(docs.join("1_2.chunk"), &data).unwrap();
+        let mut damaged = fs::read(docs.join("1_2.chunk")).unwrap();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+        fs::write(docs.join("1_2.chunk"), damaged).unwrap();
+
+        let report = fsck(&logger, &path, true, None).await.unwrap();
+
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.damaged.len(), 1);
+        assert_eq!(report.damaged[0].path, docs.join("1_2.chunk"));
+        assert!(!docs.join("1_2.chunk").exists());
+        assert!(path
+            .join(QUARANTINE_FOLDER)
+            .join("schema")
+            .join("users_docs")
+            .join("1_2.chunk")
+            .exists());
+    }
+}
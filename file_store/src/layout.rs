@@ -0,0 +1,71 @@
+use serde_derive::{
+    Deserialize,
+    Serialize,
+};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Maps the ids of schemas and collections to their names, since the folders
+/// are named by id
+pub const NAMES_FILE: &str = "names.json";
+
+/// The names of a schema and its collections, kept so that the folders of a
+/// data folder can be told apart without reading every `collections.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaNames {
+    pub name: String,
+    pub collections: BTreeMap<Uuid, String>,
+}
+
+pub type NameMap = BTreeMap<Uuid, SchemaNames>;
+
+/// Folders are named by id, so renaming a schema never moves its data and
+/// names that only differ in case never share a folder
+pub fn schema_folder(schema_id: Uuid) -> String {
+    schema_id.to_string()
+}
+
+/// Returns the id of the schema a folder belongs to. The wal and the
+/// quarantine are the only other folders in a data folder, and they are not
+/// named by id
+pub fn schema_folder_id(folder_name: &str) -> Option<Uuid> {
+    Uuid::parse_str(folder_name).ok()
+}
+
+pub fn docs_folder(collection_id: Uuid) -> String {
+    format!("{}_docs", collection_id)
+}
+
+/// The history of a collection is split into a file per document, so that
+/// reading the history of one document never reads the rest
+pub fn history_folder(collection_id: Uuid) -> String {
+    format!("{}_history", collection_id)
+}
+
+pub fn history_file(document_id: Uuid) -> String {
+    format!("{}.log", document_id)
+}
+
+/// The single history log a collection had before format 4
+pub fn history_log(collection_id: Uuid) -> String {
+    format!("{}_history.log", collection_id)
+}
+
+/// Returns the id of the collection a `{collection_id}_history.log` file
+/// belongs to
+pub fn history_log_id(file_name: &str) -> Option<Uuid> {
+    if !file_name.ends_with("_history.log") {
+        return None;
+    }
+    Uuid::parse_str(&file_name[..file_name.len() - "_history.log".len()]).ok()
+}
+
+/// Returns the id of the collection a `{collection_id}_docs` folder belongs
+/// to
+pub fn docs_folder_id(folder_name: &str) -> Option<Uuid> {
+    if !folder_name.ends_with("_docs") {
+        return None;
+    }
+    Uuid::parse_str(&folder_name[..folder_name.len() - "_docs".len()]).ok()
+}
@@ -9,7 +9,57 @@ extern crate slog;
 #[macro_use]
 extern crate failure;
 
+mod chunk_engine;
+mod chunk_index;
+mod codec;
+mod data_folder_lock;
+mod encryption;
+mod file_chunks;
 mod file_store;
+mod format_manifest;
+mod fsck;
+mod layout;
+mod recovery;
+mod rotate_key;
+mod upgrade;
 mod util;
+mod wal;
 
-pub use self::file_store::FileStore;
+pub use self::{
+    chunk_engine::{
+        Changes,
+        ChunkEngine,
+        ChunkStorage,
+    },
+    chunk_index::{
+        chunk_file_name,
+        ChunkIndex,
+    },
+    codec::{
+        encode_chunk,
+        ChunkCodec,
+    },
+    encryption::Encryption,
+    file_store::FileStore,
+    format_manifest::FORMAT_VERSION,
+    fsck::{
+        fsck,
+        DamagedFile,
+        FsckReport,
+        QUARANTINE_FOLDER,
+    },
+    layout::{
+        docs_folder,
+        docs_folder_id,
+        history_file,
+        history_folder,
+        schema_folder,
+        schema_folder_id,
+    },
+    rotate_key::rotate_key,
+    upgrade::upgrade_data_folder,
+    util::{
+        compute_hash_sum,
+        is_collection_file,
+    },
+};
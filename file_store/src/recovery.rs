@@ -0,0 +1,118 @@
+use crate::{
+    chunk_index::chunk_number,
+    util::{
+        is_collection_file,
+        OLD_SUFFIX,
+        TEMP_SUFFIX,
+    },
+};
+use failure::Error;
+use slog::Logger;
+use std::{
+    fs,
+    path::Path,
+};
+use tokio::task;
+
+/// Cleans up after a crash in the middle of a write. Temporary files are
+/// removed, and `~old` files are removed if their replacement made it to
+/// disk or put back if it did not
+pub async fn recover(logger: &Logger, path: &Path) -> Result<usize, Error> {
+    let logger = logger.clone();
+    let path = path.to_owned();
+    task::spawn_blocking(move || recover_folder(&logger, &path)).await?
+}
+
+fn recover_folder(logger: &Logger, path: &Path) -> Result<usize, Error> {
+    let mut names = vec![];
+    let mut folders = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            folders.push(entry.path());
+        } else {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    let mut recovered = 0;
+    for name in names.iter().filter(|i| i.ends_with(TEMP_SUFFIX)) {
+        fs::remove_file(path.join(name))?;
+        warn!(logger, "Removed unfinished file"; "file" => path.join(name).display().to_string());
+        recovered += 1;
+    }
+
+    for name in names.iter().filter(|i| i.ends_with(OLD_SUFFIX)) {
+        let original = &name[..name.len() - OLD_SUFFIX.len()];
+
+        // Chunk files get a new name when their content changes, so any file
+        // for the same chunk counts as a replacement
+        let replaced = if is_collection_file(original) {
+            names
+                .iter()
+                .any(|i| is_collection_file(i) && chunk_number(i) == chunk_number(original))
+        } else {
+            names.iter().any(|i| i == original)
+        };
+
+        if replaced {
+            fs::remove_file(path.join(name))?;
+            info!(logger, "Removed old file, its replacement is intact"; "file" => path.join(name).display().to_string());
+        } else {
+            fs::rename(path.join(name), path.join(original))?;
+            warn!(logger, "Restored old file, its replacement never made it to disk"; "file" => path.join(original).display().to_string());
+        }
+        recovered += 1;
+    }
+
+    if recovered > 0 {
+        fs::File::open(path)?.sync_all()?;
+    }
+
+    for folder in folders {
+        recovered += recover_folder(logger, &folder)?;
+    }
+
+    Ok(recovered)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::recovery::recover;
+    use sloggers::{
+        null::NullLoggerBuilder,
+        Build,
+    };
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn recover_should_keep_exactly_one_version_of_every_file() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+        let docs = path.join("schema").join("users_docs");
+        fs::create_dir_all(&docs).unwrap();
+
+        // Crashed before the new chunk was written
+        fs::write(docs.join("0_1.gz~old"), "old").unwrap();
+        // Crashed after the new chunk was written
+        fs::write(docs.join("1_2.gz~old"), "old").unwrap();
+        fs::write(docs.join("1_3.gz"), "new").unwrap();
+        // Crashed in the middle of writing
+        fs::write(docs.join("2_4.gz~tmp"), "half").unwrap();
+        fs::write(path.join("schemas.json~tmp"), "half").unwrap();
+
+        let recovered = recover(&logger, &path).await.unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(&docs)
+            .unwrap()
+            .map(|i| i.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+
+        assert_eq!(recovered, 4);
+        assert_eq!(left, vec!["0_1.gz", "1_3.gz"]);
+        assert!(!path.join("schemas.json~tmp").exists());
+    }
+}
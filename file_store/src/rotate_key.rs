@@ -0,0 +1,192 @@
+use crate::{
+    chunk_index::{
+        chunk_file_name,
+        chunk_number,
+    },
+    data_folder_lock::DataFolderLock,
+    encryption::{
+        is_sealed,
+        open_any,
+        open_line_any,
+        seal_line,
+        Encryption,
+    },
+    fsck::QUARANTINE_FOLDER,
+    layout::NAMES_FILE,
+    recovery::recover,
+    util::{
+        is_collection_file,
+        sync_parent,
+        write_atomic,
+        write_private,
+    },
+};
+use failure::Error;
+use slog::Logger;
+use std::{
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use tokio::fs::{
+    read,
+    remove_file,
+};
+
+/// Files that are written as a whole
+const WHOLE_FILES: [&str; 4] = [
+    "schemas.json",
+    "webhooks.json",
+    "collections.json",
+    NAMES_FILE,
+];
+/// Files that are appended to a line at a time
+const LINE_EXTENSIONS: [&str; 2] = ["wal", "log"];
+
+/// Encrypts every file in a data folder with a new key, decrypting them with
+/// the current key first if they are encrypted. Chunk files are renamed after
+/// their hash under the new key. Files already encrypted with the new key
+/// are left as they are, so a rotation that did not finish can simply be run
+/// again. The store must not be running while this is done
+///
+/// # Errors
+/// Returns an error if a file can not be decrypted, files rotated before
+/// that keep the new key
+pub async fn rotate_key(
+    logger: &Logger,
+    data_folder: &Path,
+    current: Option<&Encryption>,
+    new: &Encryption,
+) -> Result<usize, Error> {
+    if !data_folder.is_dir() {
+        bail!("The data folder {} does not exist", data_folder.display());
+    }
+    let _lock = DataFolderLock::acquire(data_folder)?;
+    recover(logger, data_folder).await?;
+
+    let mut rotated = 0;
+    for path in files(data_folder)? {
+        let name = path
+            .file_name()
+            .map(|i| i.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let extension = path
+            .extension()
+            .map(|i| i.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let data = read(&path).await?;
+        let result = if is_collection_file(&name) {
+            reseal_chunk(current, new, &path, data)
+        } else if WHOLE_FILES.contains(&name.as_str()) {
+            reseal(current, new, data).map(|i| i.map(|data| (path.clone(), data)))
+        } else if LINE_EXTENSIONS.contains(&extension.as_str()) {
+            reseal_lines(current, new, data).map(|i| i.map(|data| (path.clone(), data)))
+        } else {
+            continue;
+        };
+
+        match result {
+            Ok(Some((target, data))) => {
+                if name == "webhooks.json" {
+                    write_private(&target, &data).await?;
+                } else {
+                    write_atomic(&target, &data).await?;
+                }
+                // Should we crash before the old chunk is gone, it is still
+                // under the current key and is rotated again on the next run
+                if target != path {
+                    remove_file(&path).await?;
+                    sync_parent(&path).await?;
+                }
+                trace!(logger, "Encrypted file with the new key"; "path" => target.display().to_string());
+                rotated += 1;
+            }
+            Ok(None) => {
+                trace!(logger, "File already encrypted with the new key"; "path" => path.display().to_string());
+            }
+            Err(e) => bail!("Failed to rotate the key of {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(rotated)
+}
+
+/// Returns `None` if the data already is encrypted with the new key
+fn reseal(
+    current: Option<&Encryption>,
+    new: &Encryption,
+    data: Vec<u8>,
+) -> Result<Option<Vec<u8>>, Error> {
+    if is_sealed(&data) && new.open(&data).is_ok() {
+        return Ok(None);
+    }
+    Ok(Some(new.seal(&open_any(current, data)?)?))
+}
+
+/// Like `reseal`, and also returns where the chunk goes under the new key
+fn reseal_chunk(
+    current: Option<&Encryption>,
+    new: &Encryption,
+    path: &Path,
+    data: Vec<u8>,
+) -> Result<Option<(PathBuf, Vec<u8>)>, Error> {
+    if is_sealed(&data) && new.open(&data).is_ok() {
+        return Ok(None);
+    }
+    let data = open_any(current, data)?;
+    let name = path
+        .file_name()
+        .and_then(|i| chunk_number(&i.to_string_lossy()))
+        .map(|chunk| chunk_file_name(chunk, new.hash(&data)));
+    let target = match name {
+        Some(name) => path.with_file_name(name),
+        None => path.to_path_buf(),
+    };
+    Ok(Some((target, new.seal(&data)?)))
+}
+
+fn reseal_lines(
+    current: Option<&Encryption>,
+    new: &Encryption,
+    data: Vec<u8>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut changed = false;
+    let mut result = String::new();
+
+    for line in String::from_utf8(data)?.lines().filter(|i| !i.is_empty()) {
+        if !line.starts_with('{') && open_line_any(Some(new), line).is_ok() {
+            result.push_str(line);
+        } else {
+            result.push_str(&seal_line(Some(new), open_line_any(current, line)?)?);
+            changed = true;
+        }
+        result.push('\n');
+    }
+
+    Ok(if changed {
+        Some(result.into_bytes())
+    } else {
+        None
+    })
+}
+
+/// Every file in the data folder, except the quarantined ones
+fn files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if entry.file_name() != QUARANTINE_FOLDER {
+                files.append(&mut self::files(&entry.path())?);
+            }
+        } else {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
@@ -0,0 +1,634 @@
+use crate::{
+    chunk_index::{
+        chunk_file_name,
+        chunk_number,
+    },
+    codec::{
+        encode_chunk,
+        ChunkCodec,
+    },
+    data_folder_lock::{
+        DataFolderLock,
+        LOCK_FILE,
+    },
+    encryption::{
+        chunk_hash,
+        open_any,
+        open_line_any,
+        seal,
+        Encryption,
+    },
+    format_manifest::{
+        FormatManifest,
+        FORMAT_VERSION,
+    },
+    fsck::QUARANTINE_FOLDER,
+    layout::{
+        docs_folder,
+        history_file,
+        history_folder,
+        history_log,
+        history_log_id,
+        schema_folder,
+        NameMap,
+        SchemaNames,
+        NAMES_FILE,
+    },
+    recovery::recover,
+    util::{
+        is_collection_file,
+        sync_dir,
+        sync_parent,
+        write_atomic,
+    },
+};
+use failure::Error;
+use shelf_config::Config;
+use shelf_database::{
+    Collection,
+    Document,
+    DocumentVersion,
+    Schema,
+};
+use slog::Logger;
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    fs::{
+        create_dir_all,
+        read,
+        remove_file,
+        rename,
+    },
+    task,
+};
+use uuid::Uuid;
+
+/// Upgrades a data folder written by an older version of shelf to the
+/// current format, after copying it to a backup next to the data folder.
+/// Returns the format it was upgraded from, or `None` if it already was up to
+/// date. The store must not be running while this is done
+///
+/// # Errors
+/// Returns an error if the data folder is in use, was written by a newer
+/// version of shelf, or if a step of the upgrade fails. The manifest is
+/// written after every step, so a failed upgrade can be run again
+pub async fn upgrade_data_folder(logger: &Logger, config: &Config) -> Result<Option<u32>, Error> {
+    let data_folder = Path::new(&config.data_folder);
+    if !data_folder.is_dir() {
+        bail!("The data folder {} does not exist", data_folder.display());
+    }
+    let _lock = DataFolderLock::acquire(data_folder)?;
+    recover(logger, data_folder).await?;
+    let encryption = Encryption::from_config(config)?;
+
+    match outdated_version(data_folder).await? {
+        Some(version) => {
+            upgrade(
+                logger,
+                data_folder,
+                version,
+                ChunkCodec::from_config(config),
+                encryption.as_ref(),
+            )
+            .await?;
+            Ok(Some(version))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Makes sure the data folder is in the current format before the store
+/// opens it, upgrading it if that is allowed
+pub(crate) async fn prepare_data_folder(
+    logger: &Logger,
+    data_folder: &Path,
+    auto_upgrade: bool,
+    codec: ChunkCodec,
+    encryption: Option<&Encryption>,
+) -> Result<(), Error> {
+    if let Some(version) = outdated_version(data_folder).await? {
+        if !auto_upgrade {
+            bail!(
+                "The data folder is in format {} but this version of shelf needs format {}, run shelf upgrade-data to upgrade it",
+                version,
+                FORMAT_VERSION
+            );
+        }
+        upgrade(logger, data_folder, version, codec, encryption).await?;
+    }
+    Ok(())
+}
+
+/// Returns the format of the data folder if it needs to be upgraded. A new
+/// data folder gets a manifest with the current format right away
+async fn outdated_version(data_folder: &Path) -> Result<Option<u32>, Error> {
+    let version = match FormatManifest::read(data_folder).await? {
+        Some(manifest) => manifest.version,
+        // Data folders written before there was a manifest always have a
+        // list of schemas
+        None if data_folder.join("schemas.json").is_file() => 1,
+        None => {
+            FormatManifest::new(FORMAT_VERSION)
+                .write(data_folder)
+                .await?;
+            return Ok(None);
+        }
+    };
+
+    if version > FORMAT_VERSION {
+        bail!(
+            "The data folder is in format {} which was written by a newer version of shelf, this version only reads up to format {}",
+            version,
+            FORMAT_VERSION
+        );
+    }
+
+    Ok(if version < FORMAT_VERSION {
+        Some(version)
+    } else {
+        None
+    })
+}
+
+async fn upgrade(
+    logger: &Logger,
+    data_folder: &Path,
+    from: u32,
+    codec: ChunkCodec,
+    encryption: Option<&Encryption>,
+) -> Result<(), Error> {
+    let folder = data_folder.to_path_buf();
+    let backup = task::spawn_blocking(move || backup(&folder, from)).await??;
+    warn!(logger, "\u{2b06}\u{fe0f} Upgrading the data folder"; "from" => from, "to" => FORMAT_VERSION, "backup" => backup.display().to_string());
+
+    for version in from..FORMAT_VERSION {
+        match version {
+            1 => convert_legacy_chunks(logger, data_folder, codec, encryption).await?,
+            2 => key_folders_by_id(logger, data_folder, encryption).await?,
+            3 => split_history_logs(logger, data_folder, encryption).await?,
+            _ => bail!("There is no upgrade from format {}", version),
+        }
+        FormatManifest::new(version + 1).write(data_folder).await?;
+        info!(logger, "Upgraded the data folder to format {}", version + 1);
+    }
+
+    Ok(())
+}
+
+/// Version 1 to 2, rewrites the chunks that were gzipped json without a
+/// header with the configured codec
+async fn convert_legacy_chunks(
+    logger: &Logger,
+    data_folder: &Path,
+    codec: ChunkCodec,
+    encryption: Option<&Encryption>,
+) -> Result<(), Error> {
+    for path in legacy_chunks(data_folder)? {
+        let file_name = path
+            .file_name()
+            .map(|i| i.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let chunk = match chunk_number(&file_name) {
+            Some(chunk) => chunk,
+            None => {
+                warn!(logger, "Skipping chunk file with an unknown name"; "path" => path.display().to_string());
+                continue;
+            }
+        };
+
+        let documents = read_old_chunk(&path, encryption).await.map_err(|e| {
+            format_err!(
+                "Failed to read {}, run shelf fsck to quarantine it: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let data = encode_chunk(codec, documents.into_iter().map(Arc::new).collect()).await?;
+        let new_path = path.with_file_name(chunk_file_name(chunk, chunk_hash(encryption, &data)));
+        let encryption = encryption.cloned();
+        let data = task::spawn_blocking(move || seal(encryption.as_ref(), data)).await??;
+
+        // Should we crash before the old file is gone the manifest is not
+        // bumped yet, so the step runs again and writes the same file
+        write_atomic(&new_path, &data).await?;
+        remove_file(&path).await?;
+        sync_parent(&path).await?;
+        trace!(logger, "Converted legacy chunk"; "from" => path.display().to_string(), "to" => new_path.display().to_string());
+    }
+
+    Ok(())
+}
+
+/// Reads a chunk file written by an older version, which might have been
+/// written before there was a key
+async fn read_old_chunk(
+    path: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<Vec<Document>, Error> {
+    let data = open_any(encryption, read(path).await?)?;
+    task::spawn_blocking(move || ChunkCodec::decode(&data)).await?
+}
+
+/// Version 2 to 3, renames the folders of schemas and collections from their
+/// names to their ids and writes down the names. Renames that already
+/// happened are skipped, so the step can be run again after a crash
+async fn key_folders_by_id(
+    logger: &Logger,
+    data_folder: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<(), Error> {
+    let schemas_path = data_folder.join("schemas.json");
+    let schemas: HashMap<Uuid, Schema> = if schemas_path.is_file() {
+        serde_json::from_slice(&open_any(encryption, read(&schemas_path).await?)?)?
+    } else {
+        HashMap::new()
+    };
+
+    let mut names = NameMap::new();
+    for (schema_id, schema) in schemas {
+        let old_folder = data_folder.join(&schema.name);
+        let new_folder = data_folder.join(schema_folder(schema_id));
+        let folder = if old_folder.is_dir() && !new_folder.exists() {
+            &old_folder
+        } else {
+            &new_folder
+        };
+
+        let collections_path = folder.join("collections.json");
+        let collections: Vec<Collection> = if collections_path.is_file() {
+            serde_json::from_slice(&open_any(encryption, read(&collections_path).await?)?)?
+        } else {
+            vec![]
+        };
+
+        for collection in &collections {
+            let renames = [
+                (
+                    format!("{}_docs", collection.name),
+                    docs_folder(collection.id),
+                ),
+                (
+                    format!("{}_history.log", collection.name),
+                    history_log(collection.id),
+                ),
+            ];
+            for (from, to) in &renames {
+                if folder.join(from).exists() && !folder.join(to).exists() {
+                    rename(folder.join(from), folder.join(to)).await?;
+                    trace!(logger, "Renamed collection file"; "from" => from, "to" => to);
+                }
+            }
+        }
+        if folder.is_dir() {
+            sync_dir(folder).await?;
+        }
+        if folder == &old_folder {
+            rename(&old_folder, &new_folder).await?;
+            sync_parent(&new_folder).await?;
+            debug!(logger, "Renamed schema folder"; "from" => &schema.name, "to" => new_folder.display().to_string());
+        }
+
+        names.insert(
+            schema_id,
+            SchemaNames {
+                name: schema.name,
+                collections: collections.into_iter().map(|i| (i.id, i.name)).collect(),
+            },
+        );
+    }
+
+    let data = serde_json::to_vec_pretty(&names)?;
+    let encryption = encryption.cloned();
+    let data = task::spawn_blocking(move || seal(encryption.as_ref(), data)).await??;
+    write_atomic(&data_folder.join(NAMES_FILE), &data).await
+}
+
+/// Version 3 to 4, splits the history log of every collection into a file
+/// per document. The lines are moved as they are, so encrypted lines stay
+/// encrypted. A log is only removed once every file split from it is on disk,
+/// and the files are written as a whole, so the step can be run again after a
+/// crash
+async fn split_history_logs(
+    logger: &Logger,
+    data_folder: &Path,
+    encryption: Option<&Encryption>,
+) -> Result<(), Error> {
+    for path in history_logs(data_folder)? {
+        let collection_id = match path
+            .file_name()
+            .and_then(|i| history_log_id(&i.to_string_lossy()))
+        {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let data = read(&path).await?;
+        let mut by_document: BTreeMap<Uuid, String> = BTreeMap::new();
+        for line in String::from_utf8_lossy(&data)
+            .lines()
+            .filter(|i| !i.is_empty())
+        {
+            let version = open_line_any(encryption, line)
+                .and_then(|i| Ok(serde_json::from_str::<DocumentVersion>(&i)?));
+            match version {
+                Ok(version) => {
+                    let lines = by_document.entry(version.document_id).or_default();
+                    lines.push_str(line);
+                    lines.push('\n');
+                }
+                Err(e) => {
+                    // A crash in the middle of an append leaves a torn last line
+                    warn!(logger, "Skipping unreadable line in history file"; "path" => path.display().to_string(), "error" => format!("{}", e));
+                }
+            }
+        }
+
+        let folder = path.with_file_name(history_folder(collection_id));
+        create_dir_all(&folder).await?;
+        let documents = by_document.len();
+        for (document_id, lines) in by_document {
+            write_atomic(&folder.join(history_file(document_id)), lines.as_bytes()).await?;
+        }
+        remove_file(&path).await?;
+        sync_parent(&path).await?;
+        trace!(logger, "Split history log"; "path" => path.display().to_string(), "documents" => documents);
+    }
+
+    Ok(())
+}
+
+fn history_logs(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut logs = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_dir() {
+            if name != QUARANTINE_FOLDER && name != "wal" {
+                logs.append(&mut history_logs(&entry.path())?);
+            }
+        } else if history_log_id(&name).is_some() {
+            logs.push(entry.path());
+        }
+    }
+    logs.sort();
+
+    Ok(logs)
+}
+
+fn legacy_chunks(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut chunks = vec![];
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_dir() {
+            if name != QUARANTINE_FOLDER && name != "wal" {
+                chunks.append(&mut legacy_chunks(&entry.path())?);
+            }
+        } else if is_collection_file(&name) && name.ends_with(".gz") {
+            chunks.push(entry.path());
+        }
+    }
+    chunks.sort();
+
+    Ok(chunks)
+}
+
+/// Copies the data folder to `{data_folder}.backup-format-{version}-{time}`
+fn backup(data_folder: &Path, version: u32) -> Result<PathBuf, Error> {
+    let data_folder = data_folder.canonicalize()?;
+    let name = data_folder
+        .file_name()
+        .map(|i| i.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let backup = data_folder.with_file_name(format!("{}.backup-format-{}-{}", name, version, time));
+
+    copy_folder(&data_folder, &backup)?;
+
+    Ok(backup)
+}
+
+fn copy_folder(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_folder(&entry.path(), &target)?;
+        } else if entry.file_name() != LOCK_FILE {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    fs::File::open(to)?.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        codec::read_chunk_file,
+        format_manifest::{
+            FormatManifest,
+            FORMAT_VERSION,
+        },
+        layout::{
+            history_file,
+            history_folder,
+            history_log,
+            NameMap,
+            NAMES_FILE,
+        },
+        upgrade::upgrade_data_folder,
+    };
+    use flate2::{
+        write::GzEncoder,
+        Compression,
+    };
+    use shelf_config::Config;
+    use shelf_database::{
+        Collection,
+        Document,
+        DocumentVersion,
+        Schema,
+    };
+    use sloggers::{
+        null::NullLoggerBuilder,
+        Build,
+    };
+    use std::{
+        collections::HashMap,
+        fs,
+        io::Write,
+        path::{
+            Path,
+            PathBuf,
+        },
+    };
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    /// The backups are written next to the data folder, which is kept in a
+    /// temporary folder of its own so they are removed along with it
+    fn backups(path: &Path) -> Vec<PathBuf> {
+        let prefix = format!("{}.backup-format-", path.display());
+        fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|i| i.unwrap().path())
+            .filter(|i| i.to_string_lossy().starts_with(&prefix))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn upgrade_should_convert_legacy_chunks_and_keep_a_backup() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("data");
+        let docs = path.join("schema").join("users_docs");
+        fs::create_dir_all(&docs).unwrap();
+        fs::write(path.join("schemas.json"), "{}").unwrap();
+
+        let id = Uuid::new_v4();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        write!(encoder, "[{{\"id\":\"{}\",\"fields\":{{}}}}]", id).unwrap();
+        fs::write(docs.join("1_5.gz"), encoder.finish().unwrap()).unwrap();
+
+        let config = Config {
+            data_folder: path.to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let upgraded = upgrade_data_folder(&logger, &config).await.unwrap();
+
+        let files: Vec<_> = fs::read_dir(&docs)
+            .unwrap()
+            .map(|i| i.unwrap().path())
+            .collect();
+        assert_eq!(upgraded, Some(1));
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_string_lossy().ends_with(".chunk"));
+        assert_eq!(read_chunk_file(&files[0], None).await.unwrap()[0].id, id);
+
+        let manifest = FormatManifest::read(&path).await.unwrap().unwrap();
+        assert_eq!(manifest.version, FORMAT_VERSION);
+        assert_eq!(upgrade_data_folder(&logger, &config).await.unwrap(), None);
+
+        let backups = backups(&path);
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].join("schema/users_docs/1_5.gz").is_file());
+    }
+
+    #[tokio::test]
+    async fn upgrade_should_name_folders_by_id() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("data");
+        let schema = Schema::new(Uuid::new_v4(), "shop", None);
+        let collection = Collection::new("users".to_string(), None);
+        fs::create_dir_all(path.join("shop/users_docs")).unwrap();
+        FormatManifest::new(2).write(&path).await.unwrap();
+
+        let mut schemas = HashMap::new();
+        schemas.insert(schema.id, schema.clone());
+        fs::write(
+            path.join("schemas.json"),
+            serde_json::to_vec(&schemas).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            path.join("shop/collections.json"),
+            serde_json::to_vec(&vec![collection.clone()]).unwrap(),
+        )
+        .unwrap();
+        fs::write(path.join("shop/users_docs/0_1.chunk"), "chunk").unwrap();
+        fs::write(path.join("shop/users_history.log"), "").unwrap();
+
+        let config = Config {
+            data_folder: path.to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            upgrade_data_folder(&logger, &config).await.unwrap(),
+            Some(2)
+        );
+
+        let schema_path = path.join(schema.id.to_string());
+        assert!(!path.join("shop").exists());
+        assert!(schema_path
+            .join(format!("{}_docs", collection.id))
+            .join("0_1.chunk")
+            .is_file());
+        assert!(schema_path.join(history_folder(collection.id)).is_dir());
+        assert!(!schema_path.join(history_log(collection.id)).exists());
+
+        let names: NameMap =
+            serde_json::from_slice(&fs::read(path.join(NAMES_FILE)).unwrap()).unwrap();
+        assert_eq!(names[&schema.id].name, "shop");
+        assert_eq!(names[&schema.id].collections[&collection.id], "users");
+
+        assert_eq!(backups(&path).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn upgrade_should_split_history_by_document() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("data");
+        let schema_path = path.join(Uuid::new_v4().to_string());
+        let collection_id = Uuid::new_v4();
+        fs::create_dir_all(&schema_path).unwrap();
+        FormatManifest::new(3).write(&path).await.unwrap();
+
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let mut log = String::new();
+        for (id, revision) in &[(first, 1), (second, 1), (first, 2)] {
+            let document = Document::new(*id, HashMap::new());
+            let version = DocumentVersion {
+                document_id: *id,
+                revision: *revision,
+                timestamp: document.updated_at,
+                document: Some(document),
+            };
+            log.push_str(&serde_json::to_string(&version).unwrap());
+            log.push('\n');
+        }
+        // Torn by a crash in the middle of an append
+        log.push_str("{\"documentId\":");
+        fs::write(schema_path.join(history_log(collection_id)), log).unwrap();
+
+        let config = Config {
+            data_folder: path.to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            upgrade_data_folder(&logger, &config).await.unwrap(),
+            Some(3)
+        );
+
+        let folder = schema_path.join(history_folder(collection_id));
+        let lines = |id| {
+            fs::read_to_string(folder.join(history_file(id)))
+                .unwrap()
+                .lines()
+                .count()
+        };
+        assert_eq!(lines(first), 2);
+        assert_eq!(lines(second), 1);
+        assert!(!schema_path.join(history_log(collection_id)).exists());
+    }
+}
@@ -1,13 +1,10 @@
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{
-        Hash,
-        Hasher,
-    },
-};
+use std::hash::Hasher;
+use twox_hash::XxHash64;
 
-pub fn compute_hash_sum(data: &str) -> u64 {
-    let mut s = DefaultHasher::new();
-    data.hash(&mut s);
+/// A checksum that stays the same across Rust releases and platforms, so it
+/// can be stored on disk
+pub fn compute_hash_sum(data: &[u8]) -> u64 {
+    let mut s = XxHash64::with_seed(0);
+    s.write(data);
     s.finish()
 }
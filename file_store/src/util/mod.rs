@@ -1,11 +1,19 @@
 mod compute_hash_sum;
 mod extract_file_name;
 mod is_collection_file;
-mod write_compressed_file;
+mod write_atomic;
 
 pub use self::{
     compute_hash_sum::compute_hash_sum,
     extract_file_name::extract_file_name,
     is_collection_file::is_collection_file,
-    write_compressed_file::write_compressed_file,
+    write_atomic::{
+        sync_dir,
+        sync_parent,
+        with_suffix,
+        write_atomic,
+        write_private,
+        OLD_SUFFIX,
+        TEMP_SUFFIX,
+    },
 };
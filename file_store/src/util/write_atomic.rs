@@ -0,0 +1,85 @@
+use failure::Error;
+#[cfg(unix)]
+use std::fs::Permissions;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use tokio::{
+    fs::{
+        rename,
+        File,
+        OpenOptions,
+    },
+    io::AsyncWriteExt,
+};
+
+/// Suffix of files that are still being written
+pub const TEMP_SUFFIX: &str = "~tmp";
+/// Suffix of files that are kept around until their replacement is on disk
+pub const OLD_SUFFIX: &str = "~old";
+
+/// Writes the data to a temporary file which is synced and then renamed over
+/// `path`. A crash leaves either the old or the new content, never a mix
+pub async fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Error> {
+    write_replacing(path, data, false).await
+}
+
+/// Same as `write_atomic`, but only the owner may read the file. Used for
+/// files holding secrets
+pub async fn write_private(path: &Path, data: &[u8]) -> Result<(), Error> {
+    write_replacing(path, data, true).await
+}
+
+async fn write_replacing(path: &Path, data: &[u8], private: bool) -> Result<(), Error> {
+    let temp_path = with_suffix(path, TEMP_SUFFIX);
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp_path)
+        .await?;
+    // The permissions are set before anything is written, and the rename
+    // carries them over to `path`
+    if private {
+        make_private(&file).await?;
+    }
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    rename(&temp_path, path).await?;
+    sync_parent(path).await
+}
+
+#[cfg(unix)]
+async fn make_private(file: &File) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(Permissions::from_mode(0o600)).await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn make_private(_file: &File) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Makes renames and removals in the folder of `path` durable
+pub async fn sync_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) => sync_dir(parent).await,
+        None => Ok(()),
+    }
+}
+
+pub async fn sync_dir(path: &Path) -> Result<(), Error> {
+    File::open(path).await?.sync_all().await?;
+    Ok(())
+}
+
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
@@ -0,0 +1,312 @@
+use crate::encryption::{
+    open_line,
+    seal_line,
+    Encryption,
+    NotEncrypted,
+};
+use failure::Error;
+use futures::stream::StreamExt;
+use shelf_config::WalFsync;
+use shelf_database::Transaction;
+use slog::Logger;
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs::{
+        create_dir_all,
+        read_dir,
+        remove_file,
+        File,
+        OpenOptions,
+    },
+    prelude::*,
+    sync::Mutex,
+    time::delay_for,
+};
+
+const SEGMENT_EXTENSION: &str = "wal";
+
+/// A position in the log, the segment id and the number of bytes written to it
+type Position = (u64, u64);
+
+struct Segment {
+    id: u64,
+    file: File,
+    written: u64,
+}
+
+/// The write-ahead log. Every committed transaction is appended as one json
+/// line. The log is split into numbered segments so that it can be truncated
+/// without losing writes that arrive while the data is being flushed
+pub struct Wal {
+    path: PathBuf,
+    fsync: WalFsync,
+    encryption: Option<Encryption>,
+    segment: Mutex<Segment>,
+    synced: Mutex<Position>,
+}
+
+impl Wal {
+    /// Opens the log, a new segment is always started so that existing
+    /// segments are never appended to after a restart
+    pub async fn open(
+        logger: &Logger,
+        path: PathBuf,
+        fsync: WalFsync,
+        encryption: Option<Encryption>,
+    ) -> Result<Self, Error> {
+        if !path.is_dir() {
+            create_dir_all(&path).await?;
+        }
+
+        let id = Self::segment_ids(&path)
+            .await?
+            .last()
+            .map_or(1, |last| last + 1);
+        let file = Self::create_segment(&path, id).await?;
+
+        debug!(logger, "Opened write-ahead log"; "segment" => id, "fsync" => format!("{:?}", fsync));
+
+        Ok(Self {
+            path,
+            fsync,
+            encryption,
+            segment: Mutex::new(Segment {
+                id,
+                file,
+                written: 0,
+            }),
+            synced: Mutex::new((id, 0)),
+        })
+    }
+
+    /// Syncs the log in the background, only does something for the interval
+    /// fsync policy. The loop ends once the log is dropped
+    pub fn start_sync_loop(logger: &Logger, wal: &Arc<Self>, interval: Duration) {
+        if wal.fsync != WalFsync::Interval {
+            return;
+        }
+
+        let logger = logger.clone();
+        let wal = Arc::downgrade(wal);
+        let interval = interval.max(Duration::from_millis(1));
+
+        tokio::spawn(async move {
+            loop {
+                delay_for(interval).await;
+                match wal.upgrade() {
+                    Some(wal) => {
+                        if let Err(err) = wal.sync().await {
+                            error!(logger, "Failed to sync write-ahead log"; "error" => format!("{}", err));
+                        }
+                    }
+                    None => break,
+                }
+            }
+        });
+    }
+
+    pub async fn append(&self, transaction: &Transaction) -> Result<(), Error> {
+        let mut line = seal_line(
+            self.encryption.as_ref(),
+            serde_json::to_string(transaction)?,
+        )?
+        .into_bytes();
+        line.push(b'\n');
+
+        let position = {
+            let mut segment = self.segment.lock().await;
+            segment.file.write_all(&line).await?;
+            if self.fsync == WalFsync::Always {
+                segment.file.sync_data().await?;
+            }
+            segment.written += line.len() as u64;
+            (segment.id, segment.written)
+        };
+
+        if self.fsync == WalFsync::Batched {
+            self.sync_to(position).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns all transactions in the log, oldest first
+    pub async fn read(&self, logger: &Logger) -> Result<Vec<Transaction>, Error> {
+        let mut transactions = vec![];
+
+        for id in Self::segment_ids(&self.path).await? {
+            let mut contents = String::new();
+            File::open(Self::segment_path(&self.path, id))
+                .await?
+                .read_to_string(&mut contents)
+                .await?;
+
+            for line in contents.lines().filter(|i| !i.is_empty()) {
+                let transaction = open_line(self.encryption.as_ref(), line)
+                    .and_then(|i| Ok(serde_json::from_str::<Transaction>(&i)?));
+                match transaction {
+                    Ok(transaction) => transactions.push(transaction),
+                    Err(e) if e.downcast_ref::<NotEncrypted>().is_some() => return Err(e),
+                    Err(e) => {
+                        // A crash in the middle of an append leaves a torn last line
+                        warn!(logger, "Skipping unreadable write-ahead log entry"; "segment" => id, "error" => format!("{}", e));
+                    }
+                }
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    /// Starts a new segment and returns the id of the one that was closed
+    pub async fn rotate(&self) -> Result<u64, Error> {
+        let mut segment = self.segment.lock().await;
+        segment.file.sync_data().await?;
+
+        let closed = segment.id;
+        let file = Self::create_segment(&self.path, closed + 1).await?;
+        *segment = Segment {
+            id: closed + 1,
+            file,
+            written: 0,
+        };
+
+        Ok(closed)
+    }
+
+    /// Removes all segments up to and including `up_to`
+    pub async fn truncate(&self, logger: &Logger, up_to: u64) -> Result<(), Error> {
+        for id in Self::segment_ids(&self.path)
+            .await?
+            .into_iter()
+            .filter(|id| *id <= up_to)
+        {
+            remove_file(Self::segment_path(&self.path, id)).await?;
+            trace!(logger, "Removed write-ahead log segment"; "segment" => id);
+        }
+
+        Ok(())
+    }
+
+    /// Syncs everything written so far
+    async fn sync(&self) -> Result<(), Error> {
+        let position = {
+            let segment = self.segment.lock().await;
+            (segment.id, segment.written)
+        };
+        self.sync_to(position).await
+    }
+
+    /// Makes sure everything up to `position` is on disk. Appends waiting here
+    /// at the same time are all covered by a single sync
+    async fn sync_to(&self, position: Position) -> Result<(), Error> {
+        let mut synced = self.synced.lock().await;
+        if *synced >= position {
+            return Ok(());
+        }
+
+        let (current, mut file) = {
+            let segment = self.segment.lock().await;
+            (
+                (segment.id, segment.written),
+                segment.file.try_clone().await?,
+            )
+        };
+        file.sync_data().await?;
+        *synced = current;
+
+        Ok(())
+    }
+
+    async fn create_segment(path: &Path, id: u64) -> Result<File, Error> {
+        Ok(OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(Self::segment_path(path, id))
+            .await?)
+    }
+
+    fn segment_path(path: &Path, id: u64) -> PathBuf {
+        path.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+    }
+
+    async fn segment_ids(path: &Path) -> Result<Vec<u64>, Error> {
+        let entries: Vec<_> = read_dir(path).await?.collect().await;
+
+        let mut ids = vec![];
+        for entry in entries {
+            let entry_path = entry?.path();
+            if entry_path.extension().and_then(|i| i.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            if let Some(id) = entry_path
+                .file_stem()
+                .and_then(|i| i.to_str())
+                .and_then(|i| i.parse::<u64>().ok())
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::wal::Wal;
+    use shelf_config::WalFsync;
+    use shelf_database::{
+        Document,
+        Transaction,
+    };
+    use sloggers::{
+        null::NullLoggerBuilder,
+        Build,
+    };
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    fn transaction() -> Transaction {
+        let mut transaction = Transaction::new();
+        transaction.set(
+            Uuid::nil(),
+            Uuid::nil(),
+            Document::new(Uuid::new_v4(), HashMap::new()),
+            None,
+        );
+        transaction
+    }
+
+    #[tokio::test]
+    async fn truncate_should_only_remove_rotated_segments() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let dir = TempDir::new().unwrap();
+        let wal = Wal::open(&logger, dir.path().join("wal"), WalFsync::Batched, None)
+            .await
+            .unwrap();
+
+        let first = transaction();
+        let second = transaction();
+        wal.append(&first).await.unwrap();
+        let closed = wal.rotate().await.unwrap();
+        wal.append(&second).await.unwrap();
+
+        assert_eq!(wal.read(&logger).await.unwrap().len(), 2);
+
+        wal.truncate(&logger, closed).await.unwrap();
+        let left = wal.read(&logger).await.unwrap();
+
+        assert_eq!(left.len(), 1, "Only the new segment should be left");
+        assert_eq!(left[0].id, second.id);
+    }
+}
@@ -0,0 +1,134 @@
+#![feature(test)]
+
+extern crate test;
+
+use futures::{
+    executor::block_on,
+    StreamExt,
+};
+use shelf_database::{
+    CacheCollection,
+    ChangeFeed,
+    Collection,
+    Document,
+};
+use shelf_memory_cache::memory_cache_collection::MemoryCacheCollection;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    thread,
+    thread::JoinHandle,
+};
+use test::Bencher;
+use uuid::Uuid;
+
+const DOCUMENTS: usize = 10_000;
+const THREADS: usize = 4;
+
+/// Runs the work over and over on a few threads until it is dropped
+struct Load {
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Load {
+    fn start(work: impl Fn() + Send + Sync + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let work = Arc::new(work);
+        let threads = (0..THREADS)
+            .map(|_| {
+                let stop = Arc::clone(&stop);
+                let work = Arc::clone(&work);
+                thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        work();
+                    }
+                })
+            })
+            .collect();
+
+        Self { stop, threads }
+    }
+}
+
+impl Drop for Load {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+    }
+}
+
+fn collection() -> MemoryCacheCollection {
+    let data = (0..DOCUMENTS)
+        .map(|_| Document::new(Uuid::new_v4(), HashMap::new()))
+        .collect::<Vec<_>>();
+
+    MemoryCacheCollection::new(
+        Uuid::nil(),
+        Collection::new("TEST".to_string(), None),
+        data,
+        ChangeFeed::new(16),
+    )
+}
+
+fn read_all(collection: &MemoryCacheCollection) -> usize {
+    block_on(async {
+        let result = collection.documents().await.unwrap();
+        let documents: Vec<_> = result.stream().collect().await;
+        documents.len()
+    })
+}
+
+fn write_one(collection: &MemoryCacheCollection) {
+    block_on(collection.set_document(Document::new(Uuid::new_v4(), HashMap::new()))).unwrap()
+}
+
+#[bench]
+fn read_without_load(bencher: &mut Bencher) {
+    let collection = collection();
+
+    bencher.iter(|| read_all(&collection))
+}
+
+#[bench]
+fn read_while_writing(bencher: &mut Bencher) {
+    let collection = collection();
+    let writer = collection.clone();
+    let _load = Load::start(move || write_one(&writer));
+
+    bencher.iter(|| read_all(&collection))
+}
+
+#[bench]
+fn write_without_load(bencher: &mut Bencher) {
+    let collection = collection();
+
+    bencher.iter(|| write_one(&collection))
+}
+
+#[bench]
+fn write_while_reading(bencher: &mut Bencher) {
+    let collection = collection();
+    let reader = collection.clone();
+    let _load = Load::start(move || {
+        read_all(&reader);
+    });
+
+    bencher.iter(|| write_one(&collection))
+}
+
+#[bench]
+fn write_while_a_result_is_held(bencher: &mut Bencher) {
+    let collection = collection();
+    let _result = block_on(collection.documents()).unwrap();
+
+    bencher.iter(|| write_one(&collection))
+}
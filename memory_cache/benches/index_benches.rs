@@ -8,6 +8,7 @@ use futures::{
 };
 use shelf_database::{
     CacheCollection,
+    ChangeFeed,
     Collection,
     Document,
 };
@@ -26,19 +27,13 @@ use uuid::Uuid;
 
 #[bench]
 fn btree_clone_performance(bencher: &mut Bencher) {
-    let data = vec![
-        Document {
-            id: Uuid::nil(),
-            fields: HashMap::new()
-        };
-        100_000
-    ]
-    .into_iter()
-    .map(|mut i| {
-        i.id = Uuid::new_v4();
-        (i.id, i)
-    })
-    .collect::<Vec<_>>();
+    let data = vec![Document::new(Uuid::nil(), HashMap::new()); 100_000]
+        .into_iter()
+        .map(|mut i| {
+            i.id = Uuid::new_v4();
+            (i.id, i)
+        })
+        .collect::<Vec<_>>();
 
     let tree = BTreeMap::from_iter(data);
 
@@ -47,19 +42,13 @@ fn btree_clone_performance(bencher: &mut Bencher) {
 
 #[bench]
 fn btree_iter_performance(bencher: &mut Bencher) {
-    let data = vec![
-        Document {
-            id: Uuid::nil(),
-            fields: HashMap::new()
-        };
-        100_000
-    ]
-    .into_iter()
-    .map(|mut i| {
-        i.id = Uuid::new_v4();
-        (i.id, i)
-    })
-    .collect::<Vec<_>>();
+    let data = vec![Document::new(Uuid::nil(), HashMap::new()); 100_000]
+        .into_iter()
+        .map(|mut i| {
+            i.id = Uuid::new_v4();
+            (i.id, i)
+        })
+        .collect::<Vec<_>>();
 
     let tree = BTreeMap::from_iter(data);
 
@@ -82,19 +71,19 @@ fn std_rwlock_read_lock(bencher: &mut Bencher) {
 
 #[bench]
 fn memory_cache_collection_documents_performance_same_data(bencher: &mut Bencher) {
-    let data = vec![
-        Document {
-            id: Uuid::nil(),
-            fields: HashMap::new()
-        };
-        100_000
-    ];
+    let data = vec![Document::new(Uuid::nil(), HashMap::new()); 100_000];
 
-    let collection = MemoryCacheCollection::new(Collection::new("TEST".to_string(), None), data);
+    let collection = MemoryCacheCollection::new(
+        Uuid::nil(),
+        Collection::new("TEST".to_string(), None),
+        data,
+        ChangeFeed::new(16),
+    );
 
     bencher.iter(|| {
         block_on(
             block_on(collection.documents())
+                .unwrap()
                 .stream()
                 .take(100)
                 .collect::<Vec<_>>(),
@@ -104,25 +93,25 @@ fn memory_cache_collection_documents_performance_same_data(bencher: &mut Bencher
 
 #[bench]
 fn memory_cache_collection_documents_performance_random_data(bencher: &mut Bencher) {
-    let data = vec![
-        Document {
-            id: Uuid::nil(),
-            fields: HashMap::new()
-        };
-        100_000
-    ]
-    .into_iter()
-    .map(|mut i| {
-        i.id = Uuid::new_v4();
-        i
-    })
-    .collect();
-
-    let collection = MemoryCacheCollection::new(Collection::new("TEST".to_string(), None), data);
+    let data = vec![Document::new(Uuid::nil(), HashMap::new()); 100_000]
+        .into_iter()
+        .map(|mut i| {
+            i.id = Uuid::new_v4();
+            i
+        })
+        .collect();
+
+    let collection = MemoryCacheCollection::new(
+        Uuid::nil(),
+        Collection::new("TEST".to_string(), None),
+        data,
+        ChangeFeed::new(16),
+    );
 
     bencher.iter(|| {
         block_on(
             block_on(collection.documents())
+                .unwrap()
                 .stream()
                 .take(100)
                 .collect::<Vec<_>>(),
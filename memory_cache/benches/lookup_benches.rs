@@ -0,0 +1,113 @@
+#![feature(test)]
+
+extern crate test;
+
+use futures::executor::block_on;
+use shelf_database::{
+    Cache,
+    CacheSchema,
+    Schema,
+};
+use shelf_memory_cache::MemoryCache;
+use slog::{
+    o,
+    Discard,
+    Logger,
+};
+use test::Bencher;
+use uuid::Uuid;
+
+/// A GraphQL schema with the given number of collections
+fn graphql_schema(collections: usize) -> String {
+    let mut graphql = "directive @collection on OBJECT\nscalar Uuid\n".to_string();
+    for i in 0..collections {
+        graphql.push_str(&format!(
+            "type Collection{} @collection {{ id: Uuid! }}\n",
+            i
+        ));
+    }
+    graphql
+}
+
+/// A cache with the given number of schemas, every schema has one collection
+fn cache_with_schemas(count: usize) -> (MemoryCache, Vec<Schema>) {
+    let logger = Logger::root(Discard, o!());
+    let cache = block_on(MemoryCache::new(&logger, None)).unwrap();
+    let graphql = graphql_schema(1);
+
+    let schemas: Vec<_> = (0..count)
+        .map(|i| Schema::new(Uuid::new_v4(), &format!("Schema{}", i), None))
+        .collect();
+    for schema in &schemas {
+        block_on(cache.insert_schema(&logger, schema.clone(), &graphql)).unwrap();
+    }
+
+    (cache, schemas)
+}
+
+/// A cache with one schema that has the given number of collections
+fn cache_with_collections(count: usize) -> (MemoryCache, Uuid) {
+    let logger = Logger::root(Discard, o!());
+    let cache = block_on(MemoryCache::new(&logger, None)).unwrap();
+    let id = Uuid::new_v4();
+    block_on(cache.insert_schema(
+        &logger,
+        Schema::new(id, "Schema", None),
+        &graphql_schema(count),
+    ))
+    .unwrap();
+
+    (cache, id)
+}
+
+fn bench_schema_by_id(bencher: &mut Bencher, count: usize) {
+    let (cache, schemas) = cache_with_schemas(count);
+    let id = schemas.last().unwrap().id;
+
+    bencher.iter(|| block_on(cache.schema(id)).unwrap())
+}
+
+fn bench_schema_by_name(bencher: &mut Bencher, count: usize) {
+    let (cache, schemas) = cache_with_schemas(count);
+    let name = schemas.last().unwrap().name.clone();
+
+    bencher.iter(|| block_on(cache.schema_by_name(&name)).unwrap())
+}
+
+fn bench_collection_by_name(bencher: &mut Bencher, count: usize) {
+    let (cache, id) = cache_with_collections(count);
+    let schema = block_on(cache.schema(id)).unwrap();
+    let name = format!("Collection{}", count - 1);
+
+    bencher.iter(|| block_on(schema.collection_by_name(&name)).unwrap())
+}
+
+#[bench]
+fn schema_by_id_10_schemas(bencher: &mut Bencher) {
+    bench_schema_by_id(bencher, 10)
+}
+
+#[bench]
+fn schema_by_id_1000_schemas(bencher: &mut Bencher) {
+    bench_schema_by_id(bencher, 1000)
+}
+
+#[bench]
+fn schema_by_name_10_schemas(bencher: &mut Bencher) {
+    bench_schema_by_name(bencher, 10)
+}
+
+#[bench]
+fn schema_by_name_1000_schemas(bencher: &mut Bencher) {
+    bench_schema_by_name(bencher, 1000)
+}
+
+#[bench]
+fn collection_by_name_10_collections(bencher: &mut Bencher) {
+    bench_collection_by_name(bencher, 10)
+}
+
+#[bench]
+fn collection_by_name_1000_collections(bencher: &mut Bencher) {
+    bench_collection_by_name(bencher, 1000)
+}
@@ -0,0 +1,143 @@
+use crate::memory_cache_collection::{
+    entry_size,
+    DocumentIndex,
+};
+use shelf_database::{
+    ChangeKind,
+    Document,
+};
+use std::{
+    collections::HashSet,
+    mem,
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+use tokio::sync::{
+    MutexGuard,
+    RwLock,
+};
+use uuid::Uuid;
+
+/// Holds the write lock of a collection. All document writes go through
+/// this, so that single writes and transactions modify the collection the
+/// same way. Writes are made to a copy of the index, readers keep seeing the
+/// documents as they were until `publish` is called
+pub struct CollectionWriter<'a> {
+    schema_id: Uuid,
+    collection_id: Uuid,
+    _write_lock: MutexGuard<'a, ()>,
+    published: &'a RwLock<DocumentIndex>,
+    id_index: DocumentIndex,
+    published_size: &'a AtomicUsize,
+    /// The size of the documents in `id_index`, kept up to date by every
+    /// write
+    size: usize,
+    written: HashSet<Uuid>,
+    dirty: Arc<Mutex<HashSet<Uuid>>>,
+    changes: Vec<ChangeKind>,
+}
+
+impl<'a> CollectionWriter<'a> {
+    pub fn new(
+        schema_id: Uuid,
+        collection_id: Uuid,
+        write_lock: MutexGuard<'a, ()>,
+        published: &'a RwLock<DocumentIndex>,
+        id_index: DocumentIndex,
+        published_size: &'a AtomicUsize,
+        dirty: Arc<Mutex<HashSet<Uuid>>>,
+    ) -> Self {
+        Self {
+            schema_id,
+            collection_id,
+            _write_lock: write_lock,
+            published,
+            id_index,
+            published_size,
+            size: published_size.load(Ordering::SeqCst),
+            written: HashSet::new(),
+            dirty,
+            changes: vec![],
+        }
+    }
+
+    /// Returns the current revision of a document, `None` if it does not
+    /// exist
+    pub fn revision(&self, id: Uuid) -> Option<u64> {
+        self.id_index.get(&id).map(|i| i.revision)
+    }
+
+    /// Inserts or replaces a document, returns the replaced document if there
+    /// was one. The revision and timestamps of the document are stamped here
+    pub fn set(&mut self, mut document: Document) -> Option<Arc<Document>> {
+        document.revise(self.id_index.get(&document.id).map(|i| &**i));
+        self.restore(document)
+    }
+
+    /// Inserts or replaces a document as is, keeping its revision and
+    /// timestamps. Used when replaying writes that were already stamped
+    pub fn restore(&mut self, document: Document) -> Option<Arc<Document>> {
+        let doc = Arc::new(document);
+        self.written.insert(doc.id);
+        self.size += entry_size(&doc);
+
+        match self.id_index.insert(doc.id, Arc::clone(&doc)) {
+            Some(before) => {
+                self.size -= entry_size(&before);
+                self.changes.push(ChangeKind::DocumentUpdated {
+                    schema_id: self.schema_id,
+                    collection_id: self.collection_id,
+                    before: Arc::clone(&before),
+                    after: doc,
+                });
+                Some(before)
+            }
+            None => {
+                self.changes.push(ChangeKind::DocumentInserted {
+                    schema_id: self.schema_id,
+                    collection_id: self.collection_id,
+                    after: doc,
+                });
+                None
+            }
+        }
+    }
+
+    /// Removes a document, returns the removed document if it existed
+    pub fn delete(&mut self, id: Uuid) -> Option<Arc<Document>> {
+        let before = self.id_index.remove(&id)?;
+        self.written.insert(id);
+        self.size -= entry_size(&before);
+
+        self.changes.push(ChangeKind::DocumentDeleted {
+            schema_id: self.schema_id,
+            collection_id: self.collection_id,
+            before: Arc::clone(&before),
+        });
+        Some(before)
+    }
+
+    /// Makes the writes visible to readers and marks the written documents
+    /// for the next save. Dropping the writer without publishing discards
+    /// the writes
+    pub async fn publish(&mut self) {
+        *self.published.write().await = self.id_index.clone();
+        self.published_size.store(self.size, Ordering::SeqCst);
+        self.dirty
+            .lock()
+            .expect("Lock was poisoned")
+            .extend(self.written.drain());
+    }
+
+    /// Returns the changes made since the last call, in the order they were
+    /// made. These should be published while the lock is still held
+    pub fn take_changes(&mut self) -> Vec<ChangeKind> {
+        mem::replace(&mut self.changes, vec![])
+    }
+}
@@ -7,9 +7,12 @@ extern crate slog;
 #[macro_use]
 extern crate failure;
 
+mod collection_writer;
 mod memory_cache;
 pub mod memory_cache_collection;
 mod memory_cache_schema;
 mod memory_document_result;
+mod name_index;
+mod residency;
 
 pub use self::memory_cache::MemoryCache;
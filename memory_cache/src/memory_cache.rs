@@ -1,6 +1,8 @@
 use crate::{
     memory_cache_collection::MemoryCacheCollection,
     memory_cache_schema::MemoryCacheSchema,
+    name_index::NameIndex,
+    residency::Residency,
 };
 use failure::Error;
 use futures::{
@@ -12,17 +14,33 @@ use futures::{
 };
 use pretty_bytes::converter::convert;
 use shelf_database::{
+    keep_newest,
+    save_versions,
     Cache,
     CacheCollection,
     CacheSchema,
+    Change,
+    ChangeFeed,
+    MemoryUsage,
+    RevisionConflict,
     Schema,
     Store,
+    Transaction,
+    TransactionWrite,
 };
 use slog::Logger;
 use std::{
-    collections::HashMap,
+    collections::{
+        BTreeMap,
+        HashMap,
+        HashSet,
+    },
     mem,
-    time::Instant,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
 };
 use tokio::sync::{
     broadcast::{
@@ -34,14 +52,30 @@ use tokio::sync::{
 };
 use uuid::Uuid;
 
+/// How many changes a subscriber can fall behind before it starts missing them
+const CHANGE_FEED_CAPACITY: usize = 1024;
+/// How often progress is logged while documents are loaded
+const LOAD_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct MemoryCache {
-    schemas: RwLock<Vec<MemoryCacheSchema>>,
+    schemas: RwLock<Schemas>,
+    schema_names: NameIndex,
     on_schema_updates_sender: Sender<()>,
+    changes: ChangeFeed,
+    residency: Arc<Residency>,
+    load_progress_interval: Duration,
 }
 
 impl MemoryCache {
-    pub async fn new(logger: &Logger) -> Result<Self, Error> {
+    /// With a memory budget, in bytes, the least recently used collections
+    /// are evicted once the documents take up more than that. Their ids stay
+    /// in memory and the documents are read back in from the store when the
+    /// collection is used again
+    pub async fn new(logger: &Logger, memory_budget: Option<usize>) -> Result<Self, Error> {
         info!(logger, "Starting memory cache");
+        if let Some(budget) = memory_budget {
+            info!(logger, "Evicting cold collections above the memory budget"; "budget" => convert(budget as f64));
+        }
 
         let info = sys_info::mem_info().unwrap();
         info!(
@@ -53,15 +87,189 @@ impl MemoryCache {
         let (sender, _) = channel(1);
 
         Ok(Self {
-            schemas: RwLock::new(Vec::new()),
+            schemas: RwLock::new(Schemas::default()),
+            schema_names: NameIndex::default(),
             on_schema_updates_sender: sender,
+            changes: ChangeFeed::new(CHANGE_FEED_CAPACITY),
+            residency: Arc::new(Residency::new(logger, memory_budget)),
+            load_progress_interval: LOAD_PROGRESS_INTERVAL,
         })
     }
 
+    /// Evicts the least recently used collections until the documents fit in
+    /// the memory budget, and returns the size of the cache afterwards.
+    /// Collections with writes that have not been saved yet are skipped, so
+    /// this should run right after a save
+    async fn enforce_budget(&self, logger: &Logger) -> usize {
+        let budget = match self.residency.budget() {
+            Some(budget) => budget,
+            None => return 0,
+        };
+
+        let mut size = 0;
+        let mut candidates = vec![];
+        for schema in self.schemas.read().await.list.iter() {
+            let collections: Vec<_> = schema.collections().collect().await;
+            for collection in collections {
+                let collection_size = collection.get_size().await;
+                size += collection_size;
+                if let Some(last_used) = collection.last_used() {
+                    candidates.push((last_used, collection_size, collection));
+                }
+            }
+        }
+        if size <= budget {
+            return size;
+        }
+
+        candidates.sort_by_key(|(last_used, _, _)| *last_used);
+        let mut evicted = 0;
+        for (_, collection_size, collection) in candidates {
+            if size <= budget {
+                break;
+            }
+            if collection.evict().await {
+                size = size - collection_size + collection.get_size().await;
+                evicted += 1;
+            }
+        }
+
+        info!(logger, "Evicted the least recently used collections to stay within the memory budget"; "collections" => evicted, "cache_size" => convert(size as f64), "budget" => convert(budget as f64));
+        if size > budget {
+            warn!(logger, "The cache is still above its memory budget, the remaining collections have writes that are not saved yet"; "cache_size" => convert(size as f64));
+        }
+        size
+    }
+
+    /// Hands everything that changed since the last save over to the store
+    async fn save_dirty<S: Store>(
+        &self,
+        logger: &Logger,
+        store: &S,
+        taken: &mut Taken,
+    ) -> Result<(), Error> {
+        let schemas = self.schemas.read().await.list.clone();
+
+        for schema in schemas {
+            let inner_schema = schema.inner_schema().await;
+            let collections: Vec<_> = schema.collections().collect().await;
+
+            let mut schema_dirty = schema.take_dirty();
+            if schema_dirty {
+                taken.schemas.push(schema.clone());
+            }
+            let mut collections_dirty = vec![];
+            for collection in &collections {
+                let dirty = collection.take_dirty();
+                schema_dirty |= dirty;
+                collections_dirty.push(dirty);
+            }
+
+            let mut deltas = vec![];
+            for (collection, dirty) in collections.into_iter().zip(collections_dirty) {
+                let (ids, delta) = collection.take_delta().await;
+                taken.documents += ids.len();
+                deltas.push((collection.inner_collection().await, delta));
+                taken.collections.push((collection, dirty, ids));
+            }
+
+            // The store keeps the collections of a schema in one place, so they
+            // are all written whenever one of them has changed
+            if schema_dirty {
+                store.save_schema(&logger, &inner_schema).await?;
+                for (collection, _) in &deltas {
+                    store
+                        .save_collection(&logger, &inner_schema, collection)
+                        .await?;
+                }
+            }
+
+            for (collection, delta) in deltas {
+                if !delta.is_empty() {
+                    store
+                        .save_delta(&logger, &inner_schema, &collection, delta)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces any schema with the same id
     async fn do_insert_schema(&self, schema: MemoryCacheSchema) {
+        let inner = schema.inner_schema().await;
         let mut lock = self.schemas.write().await;
-        lock.push(schema);
+        let position = lock.by_id.get(&inner.id).cloned();
+        match position {
+            Some(i) => {
+                let previous = mem::replace(&mut lock.list[i], schema);
+                drop(lock);
+                let previous_name = previous.inner_schema().await.name;
+                self.schema_names
+                    .rename(&previous_name, &inner.name, inner.id);
+            }
+            None => {
+                let i = lock.list.len();
+                lock.by_id.insert(inner.id, i);
+                lock.list.push(schema);
+                self.schema_names.insert(&inner.name, inner.id);
+            }
+        }
     }
+
+    /// Applies a write-ahead log entry as is, and stores the versions it
+    /// holds in case they were not stored before. Nothing is published since
+    /// this only happens while loading
+    async fn replay<S: Store>(
+        &self,
+        logger: &Logger,
+        store: &S,
+        transaction: &Transaction,
+    ) -> Result<(), Error> {
+        for write in transaction.writes() {
+            let collection = match self.schema(write.schema_id()).await {
+                Some(schema) => schema.collection(write.collection_id()).await,
+                None => None,
+            };
+            let collection = match collection {
+                Some(collection) => collection,
+                None => {
+                    warn!(logger, "Skipping write-ahead log entry for a collection that no longer exists"; "transaction_id" => transaction.id.to_string(), "collection_id" => write.collection_id().to_string());
+                    continue;
+                }
+            };
+
+            let mut writer = collection.writer().await?;
+            match write {
+                TransactionWrite::Set { document, .. } => {
+                    writer.restore(document.clone());
+                }
+                TransactionWrite::Delete { id, .. } => {
+                    writer.delete(*id);
+                }
+            }
+            writer.publish().await;
+            writer.take_changes();
+        }
+        save_versions(logger, self, store, transaction.versions()).await
+    }
+}
+
+/// The schemas in the order they were added, with their positions by id
+#[derive(Default)]
+struct Schemas {
+    list: Vec<MemoryCacheSchema>,
+    by_id: HashMap<Uuid, usize>,
+}
+
+/// What a save took from the dirty tracking, kept so that it can be put back
+/// if the save fails
+#[derive(Default)]
+struct Taken {
+    schemas: Vec<MemoryCacheSchema>,
+    collections: Vec<(MemoryCacheCollection, bool, Vec<Uuid>)>,
+    documents: usize,
 }
 
 impl Cache for MemoryCache
@@ -79,24 +287,63 @@ where
             let start_time = Instant::now();
             info!(logger, "Fetching schemas from store");
             let schemas = store.get_schemas(&logger).await?;
-            info!(logger, "Info found {} schemas", schemas.len());
+            let schema_count = schemas.len();
+            info!(logger, "Info found {} schemas", schema_count);
 
-            for (_id, schema) in schemas {
-                info!(logger, "Fetching collection for schema {}", schema.name);
+            let mut loaded = 0;
+            let mut resident_size = 0;
+            let mut last_progress = Instant::now();
+            for (i, (_id, schema)) in schemas.into_iter().enumerate() {
+                info!(logger, "Fetching collection for schema {} ({}/{})", schema.name, i + 1, schema_count);
                 let collections = store.get_collections(&logger, &schema).await?;
                 info!(logger, "Info found {} collections for schema {}", collections.len(), schema.name);
 
+                // The schema goes in first, so that its collections can be
+                // evicted while the rest are loaded
+                let mem_schema = MemoryCacheSchema::new(schema.clone(), self.changes.clone(), Arc::clone(&self.residency), self.schema_names.clone());
+                self.do_insert_schema(mem_schema.clone()).await;
 
-                let mut mapped_collections = HashMap::new();
                 for collection in collections {
-                    let documents = store.get_documents(&logger, &schema, &collection).await?;
-                    mapped_collections.insert(collection.id, MemoryCacheCollection::new(collection, documents));
+                    // The documents are taken in batch by batch, so the store
+                    // never has to hold a whole collection on its own
+                    let mut documents = HashMap::new();
+                    let mut batches = store.stream_documents(&logger, &schema, &collection);
+                    while let Some(batch) = batches.next().await {
+                        for document in batch? {
+                            keep_newest(&mut documents, document);
+                        }
+                        if last_progress.elapsed() >= self.load_progress_interval {
+                            info!(logger, "Still loading documents"; "collection_name" => &collection.name, "documents" => documents.len(), "total_documents" => loaded + documents.len());
+                            last_progress = Instant::now();
+                        }
+                    }
+                    drop(batches);
+
+                    debug!(logger, "Loaded collection {}", collection.name; "documents" => documents.len());
+                    loaded += documents.len();
+                    let collection = MemoryCacheCollection::new(schema.id, collection, documents.into_iter().map(|(_, i)| i), self.changes.clone());
+
+                    if let Some(budget) = self.residency.budget() {
+                        resident_size += collection.get_size().await;
+                        mem_schema.add_loaded_collection(collection).await;
+                        if resident_size > budget {
+                            resident_size = self.enforce_budget(&logger).await;
+                        }
+                    } else {
+                        mem_schema.add_loaded_collection(collection).await;
+                    }
                 }
+            }
 
-                self.do_insert_schema(MemoryCacheSchema::new(schema, mapped_collections)).await;
+            let transactions = store.read_wal(&logger).await?;
+            if !transactions.is_empty() {
+                info!(logger, "Replaying {} transactions from the write-ahead log", transactions.len());
+                for transaction in &transactions {
+                    self.replay(&logger, store, transaction).await?;
+                }
             }
 
-            info!(logger, "All schemas fetched and added to cache! 😎"; "load_time" => format!("{}ms", Instant::now().duration_since(start_time).as_millis()));
+            info!(logger, "All schemas fetched and added to cache! 😎"; "documents" => loaded, "load_time" => format!("{}ms", Instant::now().duration_since(start_time).as_millis()));
             Ok(())
         }.boxed()
     }
@@ -107,75 +354,60 @@ where
         store: &'a S,
     ) -> BoxFuture<Result<(), Error>> {
         async move {
-            self.schemas().for_each_concurrent(None, |schema: Self::CacheSchema| {
-                let schema = schema;
-                async move {
-                    let inner_schema = schema.inner_schema().await;
-                    if let Err(err) = store.save_schema(&logger, &inner_schema).await {
-                        error!(logger, "Failed to save schema"; "error" => format!("{}", err));
+            let start_time = Instant::now();
+            let mut taken = Taken::default();
+
+            let result = self.save_dirty(&logger, store, &mut taken).await;
+            let result = match result {
+                Ok(()) => store.flush(&logger).await,
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(()) => {
+                    debug!(logger, "Saved changes"; "documents" => taken.documents, "save_time" => format!("{}ms", Instant::now().duration_since(start_time).as_millis()));
+                    // Everything that was written is in the store now, so
+                    // the collections that were not written to since can go
+                    self.enforce_budget(&logger).await;
+                    Ok(())
+                }
+                Err(err) => {
+                    // Everything taken is marked dirty again so the next save retries it
+                    for schema in taken.schemas {
+                        schema.mark_dirty();
                     }
-                    schema.collections().for_each_concurrent(None, move |collection| {
-                        let inner_schema = inner_schema.clone();
-                        async move {
-                            let inner_collection = collection.inner_collection().await;
-                            if let Err(err) = store.save_collection(&logger, &inner_schema, &inner_collection).await {
-                                error!(logger, "Failed to save collection"; "error" => format!("{}", err));
-                            }
-
-                            collection.documents().await.stream().for_each_concurrent(None, |document| async {
-                                if let Err(err) = store.save_document(&logger, &inner_schema, &inner_collection, document).await {
-                                    error!(logger, "Failed to save document"; "error" => format!("{}", err));
-                                }
-                            }).await;
-                        }
-                    }).await;
+                    for (collection, dirty, ids) in taken.collections {
+                        collection.mark_dirty(dirty, ids);
+                    }
+                    Err(err)
                 }
-            }).await;
-
-            store.flush(&logger).await?;
-            Ok(())
+            }
         }
         .boxed()
     }
 
     fn schemas(&self) -> BoxStream<Self::CacheSchema> {
         stream::once(self.schemas.read())
-            .map(|i| stream::iter(i.clone().into_iter()))
+            .map(|i| stream::iter(i.list.clone().into_iter()))
             .flatten()
             .then(|i| async move { Self::CacheSchema::clone(&i) })
             .boxed()
     }
 
     fn schema(&self, id: Uuid) -> BoxFuture<Option<Self::CacheSchema>> {
-        self.schemas()
-            .filter_map(move |i| {
-                async move {
-                    if i.inner_schema().await.id == id {
-                        return Some(i);
-                    }
-                    return None;
-                }
-                .boxed()
-            })
-            .into_future()
-            .map(|(next, _)| next)
-            .boxed()
+        async move {
+            let schemas = self.schemas.read().await;
+            schemas.by_id.get(&id).map(|i| schemas.list[*i].clone())
+        }
+        .boxed()
     }
 
     fn schema_by_name<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Option<Self::CacheSchema>> {
-        self.schemas()
-            .filter_map(move |i| {
-                async move {
-                    if i.inner_schema().await.name == name {
-                        return Some(i);
-                    }
-                    return None;
-                }
-                .boxed()
-            })
-            .into_future()
-            .map(|(next, _)| next)
-            .boxed()
+        async move {
+            let id = self.schema_names.get(name)?;
+            self.schema(id).await
+        }
+        .boxed()
     }
 
     fn insert_schema<'a>(
@@ -185,20 +417,135 @@ where
         new_graphql_schema: &'a str,
     ) -> BoxFuture<'a, Result<(), Error>> {
         async move {
-            let mem_schema = MemoryCacheSchema::new(schema, HashMap::new());
+            let mem_schema = MemoryCacheSchema::new(
+                schema,
+                self.changes.clone(),
+                Arc::clone(&self.residency),
+                self.schema_names.clone(),
+            );
             mem_schema.migrate(&logger, new_graphql_schema).await?;
+            mem_schema.mark_dirty();
             self.do_insert_schema(mem_schema).await;
+
+            // Nobody listening is not an error
+            let _ = self.on_schema_updates_sender.send(());
             Ok(())
         }
         .boxed()
     }
 
+    fn apply_transaction<'a, S: Store>(
+        &'a self,
+        logger: &'a Logger,
+        transaction: &'a Transaction,
+        store: &'a S,
+    ) -> BoxFuture<'a, Result<Transaction, Error>> {
+        async move {
+            let mut collections = BTreeMap::new();
+            let mut versioned = HashSet::new();
+            for write in transaction.writes() {
+                if collections.contains_key(&write.collection_id()) {
+                    continue;
+                }
+                let schema = match self.schema(write.schema_id()).await {
+                    Some(schema) => schema,
+                    None => bail!("The schema {} does not exist", write.schema_id()),
+                };
+                let collection = match schema.collection(write.collection_id()).await {
+                    Some(collection) => collection,
+                    None => bail!("The collection {} does not exist", write.collection_id()),
+                };
+                if schema
+                    .inner_schema()
+                    .await
+                    .is_versioned(&collection.inner_collection().await.name)
+                {
+                    versioned.insert(write.collection_id());
+                }
+                collections.insert(write.collection_id(), collection);
+            }
+
+            // The locks are always taken in collection id order, that way two
+            // transactions can never end up waiting for each other
+            let mut writers = HashMap::new();
+            for (id, collection) in &collections {
+                writers.insert(*id, collection.writer().await?);
+            }
+
+            // Everything is checked before anything is written, so that a
+            // failing transaction leaves no trace
+            let mut revisions = HashMap::new();
+            for write in transaction.writes() {
+                let key = (write.collection_id(), write.document_id());
+                let revision = match revisions.get(&key) {
+                    Some(revision) => *revision,
+                    None => writers[&write.collection_id()].revision(write.document_id()),
+                };
+                RevisionConflict::check(
+                    write.document_id(),
+                    write.expected_revision(),
+                    revision,
+                )?;
+                match write {
+                    TransactionWrite::Set { .. } => {
+                        revisions.insert(key, Some(revision.map_or(1, |i| i + 1)));
+                    }
+                    TransactionWrite::Delete { id, .. } => {
+                        if revision.is_none() {
+                            bail!("The document {} does not exist", id);
+                        }
+                        revisions.insert(key, None);
+                    }
+                }
+            }
+
+            let mut applied = vec![];
+            for write in transaction.writes() {
+                let writer = writers
+                    .get_mut(&write.collection_id())
+                    .expect("All collections in the transaction are locked");
+                match write {
+                    TransactionWrite::Set { document, .. } => {
+                        writer.set(document.clone());
+                    }
+                    TransactionWrite::Delete { id, .. } => {
+                        writer.delete(*id);
+                    }
+                }
+                applied.append(&mut writer.take_changes());
+            }
+
+            // Nothing is visible yet, should the append fail the writers are
+            // dropped and the writes with them
+            let outcome = transaction.outcome(&applied, &versioned);
+            if let Err(err) = store.append_wal(logger, &outcome).await {
+                error!(logger, "Failed to write transaction to the write-ahead log, it was not applied"; "transaction_id" => transaction.id.to_string(), "error" => format!("{}", err));
+                return Err(err);
+            }
+
+            for writer in writers.values_mut() {
+                writer.publish().await;
+            }
+            for change in &applied {
+                self.changes.publish(change.clone());
+            }
+
+            debug!(logger, "Applied transaction"; "transaction_id" => transaction.id.to_string(), "writes" => transaction.writes().len());
+            Ok(outcome)
+        }
+        .boxed()
+    }
+
+    fn attach_store<S: Store>(&self, store: Arc<S>) {
+        self.residency.set_store(store);
+    }
+
     fn cache_size(&self) -> BoxFuture<usize> {
         async move {
             let mut size = 0;
             size += mem::size_of_val(&self);
 
-            for schema in self.schemas.read().await.iter() {
+            for schema in self.schemas.read().await.list.iter() {
                 size += schema.get_size().await;
             }
 
@@ -207,11 +554,420 @@ where
         .boxed()
     }
 
+    fn memory_usage(&self) -> BoxFuture<MemoryUsage> {
+        async move {
+            let mut schemas = vec![];
+            for schema in self.schemas.read().await.list.iter() {
+                schemas.push(schema.memory_usage().await);
+            }
+            MemoryUsage::new(schemas)
+        }
+        .boxed()
+    }
+
     fn is_empty(&self) -> BoxFuture<bool> {
-        async move { self.schemas.read().await.is_empty() }.boxed()
+        async move { self.schemas.read().await.list.is_empty() }.boxed()
     }
 
     fn on_schema_updates(&self) -> Receiver<()> {
         self.on_schema_updates_sender.subscribe()
     }
+
+    fn changes(&self) -> Receiver<Change> {
+        self.changes.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::MemoryCache;
+    use shelf_config::Config;
+    use shelf_database::{
+        test::TestStore,
+        Cache,
+        CacheCollection,
+        CacheSchema,
+        Collection,
+        Database,
+        Document,
+        Schema,
+        Store,
+        Transaction,
+    };
+    use slog::{
+        Discard,
+        Drain,
+        Logger,
+        Never,
+        OwnedKVList,
+        Record,
+    };
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    };
+    use tokio::time::delay_for;
+    use uuid::Uuid;
+
+    /// Keeps the message of every record logged
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for Recorder {
+        type Err = Never;
+        type Ok = ();
+
+        fn log(&self, record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    /// A store holding one schema with one collection
+    async fn store_with_collection(logger: &Logger) -> (TestStore, Schema, Collection) {
+        let store = TestStore::default();
+        let schema = Schema::new(Uuid::new_v4(), "Test", None);
+        let collection = Collection::new("Car".to_string(), None);
+        store.save_schema(logger, &schema).await.unwrap();
+        store
+            .save_collection(logger, &schema, &collection)
+            .await
+            .unwrap();
+        (store, schema, collection)
+    }
+
+    const TEST_GRAPHQL_SCHEMA: &str = r#"
+        directive @collection on OBJECT
+
+        scalar Uuid
+
+        type Car @collection {
+            id: Uuid!
+        }
+    "#;
+
+    const VERSIONED_GRAPHQL_SCHEMA: &str = r#"
+        directive @collection on OBJECT
+        directive @versioned on OBJECT
+
+        scalar Uuid
+
+        type Car @collection @versioned {
+            id: Uuid!
+        }
+    "#;
+
+    /// A saved database with a schema holding a `@versioned` collection,
+    /// returns the ids of the schema and the collection
+    async fn versioned_database(
+        logger: &Logger,
+        store: &TestStore,
+    ) -> (Database<MemoryCache, TestStore>, Uuid, Uuid) {
+        let config = Config {
+            save_interval: Duration::from_secs(3600),
+            compaction_interval: Duration::from_secs(3600),
+            ..Config::default()
+        };
+        let db = Database::new(
+            logger,
+            &config,
+            store.clone(),
+            MemoryCache::new(logger, None).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        let schema = Schema::new(Uuid::new_v4(), "Versioned", None);
+        let schema_id = schema.id;
+        db.insert_schema(logger, schema, VERSIONED_GRAPHQL_SCHEMA)
+            .await
+            .unwrap();
+        db.save(logger).await.unwrap();
+
+        let collection_id = db
+            .schema(schema_id)
+            .await
+            .unwrap()
+            .collection_by_name("Car")
+            .await
+            .unwrap()
+            .inner_collection()
+            .await
+            .id;
+        (db, schema_id, collection_id)
+    }
+
+    #[tokio::test]
+    async fn transactions_should_not_be_applied_if_the_wal_can_not_be_written() {
+        let logger = Logger::root(Discard, o!());
+        let cache = MemoryCache::new(&logger, None).await.unwrap();
+        cache
+            .insert_schema(
+                &logger,
+                Schema::new(Uuid::nil(), "Test", None),
+                TEST_GRAPHQL_SCHEMA,
+            )
+            .await
+            .unwrap();
+        let collection = cache
+            .schema(Uuid::nil())
+            .await
+            .unwrap()
+            .collection_by_name("Car")
+            .await
+            .unwrap();
+        let collection_id = collection.inner_collection().await.id;
+        let mut changes = cache.changes();
+
+        let mut transaction = Transaction::new();
+        transaction.set(
+            Uuid::nil(),
+            collection_id,
+            Document::new(Uuid::new_v4(), HashMap::new()),
+            None,
+        );
+
+        assert!(cache
+            .apply_transaction(&logger, &transaction, &TestStore::failing_wal())
+            .await
+            .is_err());
+        assert_eq!(collection.documents().await.unwrap().total(), 0);
+        assert!(changes.try_recv().is_err(), "Nothing should be published");
+
+        cache
+            .apply_transaction(&logger, &transaction, &TestStore::default())
+            .await
+            .unwrap();
+        assert_eq!(collection.documents().await.unwrap().total(), 1);
+        assert!(changes.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn snapshots_should_hold_whole_schemas_while_schemas_are_inserted() {
+        let logger = Logger::root(Discard, o!());
+        let config = Config {
+            save_interval: Duration::from_secs(3600),
+            compaction_interval: Duration::from_secs(3600),
+            ..Config::default()
+        };
+        let db = Database::new(
+            &logger,
+            &config,
+            TestStore::default(),
+            MemoryCache::new(&logger, None).await.unwrap(),
+        )
+        .await
+        .unwrap();
+
+        for i in 0..20 {
+            let schema = Schema::new(Uuid::new_v4(), &format!("Test{}", i), None);
+            let (snapshot, inserted) = futures::join!(
+                db.snapshot(),
+                db.insert_schema(&logger, schema, TEST_GRAPHQL_SCHEMA)
+            );
+            inserted.unwrap();
+
+            for schema in &snapshot.schemas {
+                assert!(
+                    !schema.collections.is_empty(),
+                    "The schema {} was captured without its collections",
+                    schema.schema.name
+                );
+            }
+        }
+        assert_eq!(db.snapshot().await.schemas.len(), 21);
+    }
+
+    #[tokio::test]
+    async fn history_that_fails_to_save_should_be_saved_before_the_wal_is_dropped() {
+        let logger = Logger::root(Discard, o!());
+        let store = TestStore::default();
+        let (db, schema_id, collection_id) = versioned_database(&logger, &store).await;
+
+        store.fail_history(true);
+        let mut transaction = Transaction::new();
+        transaction.set(
+            schema_id,
+            collection_id,
+            Document::new(Uuid::new_v4(), HashMap::new()),
+            None,
+        );
+        db.commit(&logger, transaction)
+            .await
+            .expect("The transaction is committed even if its history is not saved");
+        assert!(store.saved_history(collection_id).is_empty());
+
+        assert!(
+            db.save(&logger).await.is_err(),
+            "The save should fail while the history can not be saved"
+        );
+        assert_eq!(store.wal_transactions().len(), 1, "The log should be kept");
+
+        store.fail_history(false);
+        db.save(&logger).await.unwrap();
+        assert_eq!(store.saved_history(collection_id).len(), 1);
+        assert!(store.wal_transactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replaying_the_wal_should_save_the_history_it_holds() {
+        let logger = Logger::root(Discard, o!());
+        let store = TestStore::default();
+        let (db, schema_id, collection_id) = versioned_database(&logger, &store).await;
+
+        store.fail_history(true);
+        let mut transaction = Transaction::new();
+        transaction.set(
+            schema_id,
+            collection_id,
+            Document::new(Uuid::new_v4(), HashMap::new()),
+            None,
+        );
+        db.commit(&logger, transaction).await.unwrap();
+        store.fail_history(false);
+
+        // Loading from the store is what happens after a crash
+        let cache = MemoryCache::new(&logger, None).await.unwrap();
+        cache.load(&logger, &store).await.unwrap();
+        assert_eq!(store.saved_history(collection_id).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_checkpoint_should_not_drop_a_commit_that_is_being_applied() {
+        let logger = Logger::root(Discard, o!());
+        let config = Config {
+            save_interval: Duration::from_secs(3600),
+            compaction_interval: Duration::from_secs(3600),
+            ..Config::default()
+        };
+        let store = TestStore::default();
+        let db = Database::new(
+            &logger,
+            &config,
+            store.clone(),
+            MemoryCache::new(&logger, None).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        let schema = db.schema_by_name("shelf").await.unwrap();
+        let schema_id = schema.inner_schema().await.id;
+        let collection_id = schema
+            .collection_by_name("Car")
+            .await
+            .unwrap()
+            .inner_collection()
+            .await
+            .id;
+
+        let id = Uuid::new_v4();
+        let mut fields = HashMap::new();
+        fields.insert("brand".to_string(), "Volvo".into());
+        fields.insert("model".to_string(), "V70".into());
+        let mut transaction = Transaction::new();
+        transaction.set(schema_id, collection_id, Document::new(id, fields), None);
+        let pause = store.pause_wal();
+        let commit = {
+            let db = db.clone();
+            let logger = logger.clone();
+            tokio::spawn(async move { db.commit(&logger, transaction).await })
+        };
+        pause.appended.await.unwrap();
+
+        // The commit is in the log but not yet applied when the save starts
+        let save = {
+            let db = db.clone();
+            let logger = logger.clone();
+            tokio::spawn(async move { db.save(&logger).await })
+        };
+        delay_for(Duration::from_millis(20)).await;
+        pause.release.send(()).unwrap();
+        commit.await.unwrap().unwrap();
+        save.await.unwrap().unwrap();
+
+        let saved = store
+            .saved_documents(collection_id)
+            .iter()
+            .any(|i| i.id == id);
+        let logged = !store.wal_transactions().is_empty();
+        assert!(
+            saved || logged,
+            "The commit is neither saved nor in the write-ahead log"
+        );
+    }
+
+    #[tokio::test]
+    async fn load_should_keep_the_newest_revision_of_documents_in_several_batches() {
+        let logger = Logger::root(Discard, o!());
+        let (store, schema, collection) = store_with_collection(&logger).await;
+        let document = Document::new(Uuid::new_v4(), HashMap::new());
+        let mut newer = document.clone();
+        newer.revision = 2;
+        store.add_batch(
+            collection.id,
+            vec![
+                document.clone(),
+                Document::new(Uuid::new_v4(), HashMap::new()),
+            ],
+        );
+        store.add_batch(collection.id, vec![newer]);
+        store.add_batch(collection.id, vec![document.clone()]);
+
+        let cache = MemoryCache::new(&logger, None).await.unwrap();
+        cache.load(&logger, &store).await.unwrap();
+
+        let loaded = cache
+            .schema(schema.id)
+            .await
+            .unwrap()
+            .collection(collection.id)
+            .await
+            .unwrap();
+        assert_eq!(loaded.documents().await.unwrap().total(), 2);
+        assert_eq!(
+            loaded
+                .document(document.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .revision,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn load_should_log_progress_while_a_large_collection_loads() {
+        let records = Arc::new(Mutex::new(vec![]));
+        let logger = Logger::root(Recorder(Arc::clone(&records)).fuse(), o!());
+        let (store, schema, collection) = store_with_collection(&logger).await;
+        for _ in 0..10 {
+            store.add_batch(
+                collection.id,
+                (0..1_000)
+                    .map(|_| Document::new(Uuid::new_v4(), HashMap::new()))
+                    .collect(),
+            );
+        }
+
+        let mut cache = MemoryCache::new(&logger, None).await.unwrap();
+        cache.load_progress_interval = Duration::from_secs(0);
+        cache.load(&logger, &store).await.unwrap();
+
+        let progress = records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|i| *i == "Still loading documents")
+            .count();
+        assert_eq!(progress, 10, "Progress should be logged after every batch");
+        let loaded = cache
+            .schema(schema.id)
+            .await
+            .unwrap()
+            .collection(collection.id)
+            .await
+            .unwrap();
+        assert_eq!(loaded.documents().await.unwrap().total(), 10_000);
+    }
 }
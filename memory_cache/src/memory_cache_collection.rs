@@ -1,95 +1,360 @@
-use crate::memory_document_result::MemoryDocumentResult;
+use crate::{
+    collection_writer::CollectionWriter,
+    memory_document_result::MemoryDocumentResult,
+    name_index::NameIndex,
+    residency::{
+        CollectionResidency,
+        Residency,
+    },
+};
+use failure::Error;
 use futures::{
     future::BoxFuture,
     FutureExt,
     StreamExt,
 };
+use im::OrdMap;
 use shelf_database::{
     CacheCollection,
+    ChangeFeed,
+    ChangeKind,
     Collection,
+    CollectionMemoryUsage,
     Document,
+    DocumentDelta,
     DocumentResult,
+    Schema,
+    SnapshotDocuments,
 };
 use std::{
-    collections::BTreeMap,
+    collections::{
+        BTreeMap,
+        HashSet,
+    },
     mem,
-    sync::Arc,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+use tokio::sync::{
+    Mutex as AsyncMutex,
+    RwLock,
 };
-use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// A persistent map, so cloning it is cheap and a clone is never affected
+/// by later writes. Readers work on such a snapshot instead of holding a lock
+pub type DocumentIndex = OrdMap<Uuid, Arc<Document>>;
+
+/// The memory a document takes up in the index, along with its id
+pub(crate) fn entry_size(document: &Document) -> usize {
+    mem::size_of::<Uuid>() + mem::size_of::<usize>() + document.get_size()
+}
+
+/// The memory the id and revision of an evicted document take up
+const EVICTED_ENTRY_SIZE: usize = mem::size_of::<Uuid>() + mem::size_of::<u64>();
+
 #[derive(Clone)]
 pub struct MemoryCacheCollection {
+    schema_id: Uuid,
     collection: Arc<RwLock<Collection>>,
-    documents: Arc<RwLock<Vec<Arc<Document>>>>,
-    id_index: Arc<RwLock<BTreeMap<Uuid, Arc<Document>>>>,
+    /// Only held for as long as it takes to make a snapshot, or to publish
+    /// what a writer wrote
+    id_index: Arc<RwLock<DocumentIndex>>,
+    /// Held by the writer of the collection, there is only one at a time
+    write_lock: Arc<AsyncMutex<()>>,
+    /// The memory the documents, or their ids once evicted, take up. Kept
+    /// up to date as they change so that it never has to be counted
+    size: Arc<AtomicUsize>,
+    changes: ChangeFeed,
+    /// Documents written since the last save
+    dirty_documents: Arc<Mutex<HashSet<Uuid>>>,
+    /// Set when the collection itself has changed since the last save
+    dirty: Arc<AtomicBool>,
+    /// Set once the collection belongs to a schema in the cache, without it
+    /// the documents are never evicted
+    residency: Option<CollectionResidency>,
+    /// The names of the collections in the schema, kept up to date when this
+    /// collection is renamed
+    names: Option<NameIndex>,
 }
 
 impl MemoryCacheCollection {
-    pub fn new(collection: Collection, documents: Vec<Document>) -> Self {
-        let docs: Vec<_> = documents.into_iter().map(Arc::new).collect();
-
-        let id_index: BTreeMap<_, _> = docs.clone().into_iter().map(|i| (i.id, i)).collect();
+    pub fn new(
+        schema_id: Uuid,
+        collection: Collection,
+        documents: impl IntoIterator<Item = Document>,
+        changes: ChangeFeed,
+    ) -> Self {
+        let id_index: DocumentIndex = documents.into_iter().map(|i| (i.id, Arc::new(i))).collect();
+        let size = id_index.values().map(|i| entry_size(i)).sum();
 
         Self {
+            schema_id,
             collection: Arc::new(RwLock::new(collection)),
-            documents: Arc::new(RwLock::new(docs)),
             id_index: Arc::new(RwLock::new(id_index)),
+            write_lock: Arc::new(AsyncMutex::new(())),
+            size: Arc::new(AtomicUsize::new(size)),
+            changes,
+            dirty_documents: Arc::new(Mutex::new(HashSet::new())),
+            dirty: Arc::new(AtomicBool::new(false)),
+            residency: None,
+            names: None,
         }
     }
 
+    /// Lets the documents be evicted when the cache runs out of memory, they
+    /// are read back in from the store the next time they are used
+    pub(crate) fn with_residency(
+        mut self,
+        schema: Arc<RwLock<Schema>>,
+        residency: Arc<Residency>,
+    ) -> Self {
+        self.residency = Some(CollectionResidency::new(schema, residency));
+        self
+    }
+
+    pub(crate) fn with_name_index(mut self, names: NameIndex) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    /// Never walks the documents, and does not read evicted ones back in
     pub(crate) async fn get_size(&self) -> usize {
-        let mut size =
-            self.id_index.read().await.len() * (mem::size_of::<Uuid>() + mem::size_of::<usize>());
+        self.size.load(Ordering::SeqCst) + self.collection.read().await.get_size()
+    }
 
-        size = self
-            .documents()
-            .await
-            .stream()
-            .fold(size, |acc, val| async move { acc + val.get_size() })
-            .await;
+    pub(crate) async fn memory_usage(&self) -> CollectionMemoryUsage {
+        let collection = self.collection.read().await.clone();
+        let evicted = self
+            .residency
+            .as_ref()
+            .map_or(0, CollectionResidency::evicted_count);
+        let documents = self.id_index.read().await.len() + evicted;
 
-        size += self.collection.read().await.get_size();
+        CollectionMemoryUsage {
+            id: collection.id,
+            name: collection.name,
+            documents,
+            size: self.get_size().await,
+            evicted: self.residency.as_ref().map_or(false, |i| !i.is_resident()),
+        }
+    }
 
-        size
+    /// When the collection was last used, `None` if its documents are not in
+    /// memory or can not be evicted
+    pub(crate) fn last_used(&self) -> Option<u64> {
+        self.residency
+            .as_ref()
+            .filter(|i| i.is_resident())
+            .map(CollectionResidency::last_used)
     }
-}
 
-impl CacheCollection for MemoryCacheCollection {
-    // this is crazy slow...
-    fn set_document(&self, document: Document) -> BoxFuture<()> {
-        async move {
-            let mut index = self.id_index.write().await;
+    /// Drops the documents from memory, keeping only their ids and
+    /// revisions as the id index. There are no other indexes to keep, fields
+    /// are always looked up by reading every document. Collections with
+    /// writes that have not been saved yet are left alone, since the store
+    /// does not have them. Returns if the documents were evicted
+    pub(crate) async fn evict(&self) -> bool {
+        let residency = match &self.residency {
+            Some(residency) => residency,
+            None => return false,
+        };
+        let _write_lock = self.write_lock.lock().await;
+        let mut index = self.id_index.write().await;
+
+        let clean = self
+            .dirty_documents
+            .lock()
+            .expect("Lock was poisoned")
+            .is_empty();
+        if !residency.is_resident() || !clean {
+            return false;
+        }
 
-            match index.get(&document.id) {
-                Some(_val) => {
-                    // let mut lock = self.documents.write().await;
-                    // lock[*index] = Arc::new(document);
+        // Snapshots that readers still hold keep the documents alive until
+        // they are done
+        residency.mark_evicted(index.values().map(|i| (i.id, i.revision)).collect());
+        self.size
+            .store(index.len() * EVICTED_ENTRY_SIZE, Ordering::SeqCst);
+        *index = DocumentIndex::new();
+        true
+    }
+
+    /// Reads the documents back in from the store, once no snapshot pins
+    /// them there anymore. The collection stays evicted if that fails
+    async fn fault_in(&self, residency: &CollectionResidency) -> Result<(), Error> {
+        let _guard = loop {
+            let guard = residency.fault_lock().lock().await;
+            if residency.is_resident() {
+                return Ok(());
+            }
+            match residency.wait_for_pins() {
+                Some(unpinned) => {
+                    drop(guard);
+                    // Dropping the sender means the pins are gone as well
+                    let _ = unpinned.await;
                 }
-                None => {
-                    let doc = Arc::new(document);
-                    index.insert(doc.id, doc.clone());
+                None => break guard,
+            }
+        };
+
+        let collection = self.collection.read().await.clone();
+        let loaded = match residency.load(&collection).await {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                error!(residency.logger(), "Failed to read evicted collection back in"; "collection_name" => &collection.name, "error" => format!("{}", e));
+                return Err(e);
+            }
+        };
+
+        // Nothing is written while the collection is evicted, so the store
+        // must hold exactly what was evicted
+        let ids: BTreeMap<_, _> = loaded.iter().map(|i| (i.id, i.revision)).collect();
+        if ids.len() != loaded.len() || ids != residency.evicted_ids() {
+            error!(residency.logger(), "The store does not hold the documents the collection was evicted with"; "collection_name" => &collection.name);
+            bail!(
+                "The store does not hold the documents the collection {} was evicted with",
+                collection.name
+            );
+        }
+
+        let mut index = self.id_index.write().await;
+        let count = loaded.len();
+        self.size
+            .store(loaded.iter().map(entry_size).sum(), Ordering::SeqCst);
+        *index = loaded.into_iter().map(|i| (i.id, Arc::new(i))).collect();
+        residency.mark_resident();
+        debug!(residency.logger(), "Read evicted collection back in"; "collection_name" => &collection.name, "documents" => count);
 
-                    let mut lock = self.documents.write().await;
-                    lock.push(doc);
+        Ok(())
+    }
+
+    /// Takes a snapshot of the documents, reading them back in first if they
+    /// were evicted. Writes made after this are not seen by the snapshot, and
+    /// holding on to it never blocks them
+    async fn snapshot(&self) -> Result<DocumentIndex, Error> {
+        let residency = match &self.residency {
+            Some(residency) => residency,
+            None => return Ok(self.id_index.read().await.clone()),
+        };
+        residency.touch();
+
+        loop {
+            let index = self.id_index.read().await;
+            if residency.is_resident() {
+                return Ok(index.clone());
+            }
+            drop(index);
+            self.fault_in(residency).await?;
+        }
+    }
+
+    /// Takes the write lock of this collection, reading the documents back
+    /// in first if they were evicted. Readers are not blocked until the
+    /// writer publishes
+    pub(crate) async fn writer(&self) -> Result<CollectionWriter<'_>, Error> {
+        let write_lock = loop {
+            let write_lock = self.write_lock.lock().await;
+            match &self.residency {
+                Some(residency) if !residency.is_resident() => {
+                    drop(write_lock);
+                    self.fault_in(residency).await?;
                 }
+                Some(residency) => {
+                    residency.touch();
+                    break write_lock;
+                }
+                None => break write_lock,
+            }
+        };
+        let collection_id = self.collection.read().await.id;
+        let id_index = self.id_index.read().await.clone();
+
+        Ok(CollectionWriter::new(
+            self.schema_id,
+            collection_id,
+            write_lock,
+            &self.id_index,
+            id_index,
+            &self.size,
+            Arc::clone(&self.dirty_documents),
+        ))
+    }
+
+    /// Returns if the collection itself has changed since the last call
+    pub(crate) fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::SeqCst)
+    }
+
+    /// Collects the documents written since the last call. The ids are
+    /// returned as well so they can be marked dirty again if the save fails
+    pub(crate) async fn take_delta(&self) -> (Vec<Uuid>, DocumentDelta) {
+        let ids: Vec<_> = mem::replace(
+            &mut *self.dirty_documents.lock().expect("Lock was poisoned"),
+            HashSet::new(),
+        )
+        .into_iter()
+        .collect();
+
+        let mut delta = DocumentDelta::default();
+        let index = self.id_index.read().await;
+        for id in &ids {
+            match index.get(id) {
+                Some(document) => delta.upserted.push(Arc::clone(document)),
+                None => delta.deleted.push(*id),
+            }
+        }
+
+        (ids, delta)
+    }
+
+    /// Marks things as changed again after a failed save
+    pub(crate) fn mark_dirty(&self, collection: bool, ids: Vec<Uuid>) {
+        if collection {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        self.dirty_documents
+            .lock()
+            .expect("Lock was poisoned")
+            .extend(ids);
+    }
+}
+
+impl CacheCollection for MemoryCacheCollection {
+    fn set_document(&self, document: Document) -> BoxFuture<Result<(), Error>> {
+        async move {
+            let mut writer = self.writer().await?;
+            writer.set(document);
+            writer.publish().await;
+
+            for change in writer.take_changes() {
+                self.changes.publish(change);
             }
+            Ok(())
         }
         .boxed()
+    }
+
+    fn delete_document(&self, id: Uuid) -> BoxFuture<Result<Option<Arc<Document>>, Error>> {
+        async move {
+            let mut writer = self.writer().await?;
+            let before = writer.delete(id);
+            writer.publish().await;
+
+            for change in writer.take_changes() {
+                self.changes.publish(change);
+            }
 
-        //        match self.id_index.binary_search_by_key(&document.id, |i|
-        // i.0) {            Ok(key) => {
-        //                let index = self.id_index[key].1;
-        //                self.documents[index] = RwLock::new(document);
-        //            },
-        //            Err(key) => {
-        //                // The document does not exist, we just need to insert
-        // it                self.id_index.insert(key, (document.id,
-        // self.documents.len()));
-        // self.documents.push(RwLock::new(document));
-        //
-        //            },
-        //        }
+            Ok(before)
+        }
+        .boxed()
     }
 
     fn inner_collection(&self) -> BoxFuture<Collection> {
@@ -99,26 +364,37 @@ impl CacheCollection for MemoryCacheCollection {
     fn set_collection(&self, collection: Collection) -> BoxFuture<()> {
         async move {
             let mut lock = self.collection.write().await;
-            *lock = collection;
+            if let Some(names) = &self.names {
+                names.rename(&lock.name, &collection.name, collection.id);
+            }
+            *lock = collection.clone();
+            self.dirty.store(true, Ordering::SeqCst);
+
+            self.changes.publish(ChangeKind::CollectionUpdated {
+                schema_id: self.schema_id,
+                collection,
+            });
         }
         .boxed()
     }
 
-    fn documents<'a>(&'a self) -> BoxFuture<'a, Box<dyn DocumentResult + 'a>> {
+    fn documents<'a>(&'a self) -> BoxFuture<'a, Result<Box<dyn DocumentResult + 'a>, Error>> {
         async move {
-            let lock = self.id_index.read().await;
-            Box::new(MemoryDocumentResult::new(lock)) as Box<dyn DocumentResult>
+            let snapshot = self.snapshot().await?;
+            Ok(Box::new(MemoryDocumentResult::new(snapshot)) as Box<dyn DocumentResult>)
         }
         .boxed()
     }
 
-    fn document(&self, id: Uuid) -> BoxFuture<Option<Arc<Document>>> {
+    fn document(&self, id: Uuid) -> BoxFuture<Result<Option<Arc<Document>>, Error>> {
         async move {
-            let index = self.id_index.read().await;
-            match index.get(&id) {
-                None => None,
-                Some(val) => Some(Arc::clone(&val)),
+            if let Some(residency) = &self.residency {
+                if !residency.might_hold(id) {
+                    return Ok(None);
+                }
             }
+            let index = self.snapshot().await?;
+            Ok(index.get(&id).map(Arc::clone))
         }
         .boxed()
     }
@@ -127,11 +403,11 @@ impl CacheCollection for MemoryCacheCollection {
         &'a self,
         field_name: &'a str,
         field_value: &'a str,
-    ) -> BoxFuture<'a, Option<Arc<Document>>> {
+    ) -> BoxFuture<'a, Result<Option<Arc<Document>>, Error>> {
         async move {
-            let docs = self.find_by_field(field_name, field_value).await;
+            let docs = self.find_by_field(field_name, field_value).await?;
             let stream = docs.stream();
-            stream.into_future().map(|(next, _)| next).await
+            Ok(stream.into_future().map(|(next, _)| next).await)
         }
         .boxed()
     }
@@ -141,10 +417,35 @@ impl CacheCollection for MemoryCacheCollection {
         &'a self,
         _field_name: &'a str,
         _field_value: &'a str,
-    ) -> BoxFuture<'a, Box<dyn DocumentResult + 'a>> {
+    ) -> BoxFuture<'a, Result<Box<dyn DocumentResult + 'a>, Error>> {
+        async move {
+            let snapshot = self.snapshot().await?;
+            Ok(Box::new(MemoryDocumentResult::new(snapshot)) as Box<dyn DocumentResult>)
+        }
+        .boxed()
+    }
+
+    fn snapshot_documents(&self) -> BoxFuture<SnapshotDocuments> {
         async move {
-            let lock = self.id_index.read().await;
-            Box::new(MemoryDocumentResult::new(lock)) as Box<dyn DocumentResult>
+            let residency = match &self.residency {
+                Some(residency) => residency,
+                None => {
+                    let index = self.id_index.read().await;
+                    return SnapshotDocuments::Resident(index.values().cloned().collect());
+                }
+            };
+
+            loop {
+                let index = self.id_index.read().await;
+                if residency.is_resident() {
+                    return SnapshotDocuments::Resident(index.values().cloned().collect());
+                }
+                drop(index);
+                // `None` if it was read back in after it was checked
+                if let Some(pin) = residency.pin().await {
+                    return SnapshotDocuments::stored(residency.evicted_count(), Box::new(pin));
+                }
+            }
         }
         .boxed()
     }
@@ -152,15 +453,380 @@ impl CacheCollection for MemoryCacheCollection {
 
 #[cfg(test)]
 mod test {
-    use crate::memory_cache_collection::MemoryCacheCollection;
+    use crate::{
+        memory_cache_collection::{
+            entry_size,
+            MemoryCacheCollection,
+            EVICTED_ENTRY_SIZE,
+        },
+        residency::{
+            DocumentSource,
+            Residency,
+        },
+    };
+    use failure::Error;
+    use futures::{
+        future::BoxFuture,
+        FutureExt,
+        StreamExt,
+    };
     use shelf_database::{
         CacheCollection,
+        ChangeFeed,
+        ChangeKind,
         Collection,
+        Document,
+        Schema,
+        SnapshotDocuments,
+    };
+    use slog::{
+        Discard,
+        Logger,
     };
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+    };
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    struct TestSource(Vec<Document>);
+
+    impl DocumentSource for TestSource {
+        fn load_documents<'a>(
+            &'a self,
+            _logger: &'a Logger,
+            _schema: &'a Schema,
+            _collection: &'a Collection,
+        ) -> BoxFuture<'a, Result<Vec<Document>, Error>> {
+            async move { Ok(self.0.clone()) }.boxed()
+        }
+    }
+
+    struct FailingSource;
+
+    impl DocumentSource for FailingSource {
+        fn load_documents<'a>(
+            &'a self,
+            _logger: &'a Logger,
+            _schema: &'a Schema,
+            _collection: &'a Collection,
+        ) -> BoxFuture<'a, Result<Vec<Document>, Error>> {
+            async move { Err(format_err!("The store is gone")) }.boxed()
+        }
+    }
+
+    /// A collection holding `document` that reads its documents from `source`
+    fn evictable(document: &Document, source: Arc<dyn DocumentSource>) -> MemoryCacheCollection {
+        let residency = Arc::new(Residency::new(&Logger::root(Discard, o!()), Some(0)));
+        residency.set_source(source);
+        MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            vec![document.clone()],
+            ChangeFeed::new(16),
+        )
+        .with_residency(
+            Arc::new(RwLock::new(Schema::new(Uuid::nil(), "TEST", None))),
+            residency,
+        )
+    }
 
     #[tokio::test]
     async fn inner_collection_should_return_the_inner_collection() {
-        let cache = MemoryCacheCollection::new(Collection::new("TEST".to_string(), None), vec![]);
+        let cache = MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            vec![],
+            ChangeFeed::new(16),
+        );
         assert_eq!(cache.inner_collection().await.name, "TEST");
     }
+
+    #[tokio::test]
+    async fn document_changes_should_be_published_in_order() {
+        let changes = ChangeFeed::new(16);
+        let mut receiver = changes.subscribe();
+        let cache = MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            vec![],
+            changes,
+        );
+
+        let id = Uuid::new_v4();
+        let document = Document::new(id, HashMap::new());
+        cache.set_document(document.clone()).await.unwrap();
+        cache.set_document(document).await.unwrap();
+        cache.delete_document(id).await.unwrap();
+
+        let inserted = receiver.recv().await.unwrap();
+        let updated = receiver.recv().await.unwrap();
+        let deleted = receiver.recv().await.unwrap();
+
+        assert!(matches!(inserted.kind, ChangeKind::DocumentInserted { .. }));
+        assert!(matches!(updated.kind, ChangeKind::DocumentUpdated { .. }));
+        assert!(matches!(deleted.kind, ChangeKind::DocumentDeleted { .. }));
+        assert!(inserted.sequence < updated.sequence && updated.sequence < deleted.sequence);
+        assert!(
+            cache.document(id).await.unwrap().is_none(),
+            "The document was not deleted"
+        );
+    }
+
+    #[tokio::test]
+    async fn every_write_should_bump_the_revision() {
+        let cache = MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            vec![],
+            ChangeFeed::new(16),
+        );
+
+        let id = Uuid::new_v4();
+        cache
+            .set_document(Document::new(id, HashMap::new()))
+            .await
+            .unwrap();
+        let first = cache.document(id).await.unwrap().unwrap();
+        cache
+            .set_document(Document::new(id, HashMap::new()))
+            .await
+            .unwrap();
+        let second = cache.document(id).await.unwrap().unwrap();
+
+        assert_eq!(first.revision, 1);
+        assert_eq!(second.revision, 2);
+        assert_eq!(first.created_at, second.created_at);
+    }
+
+    #[tokio::test]
+    async fn take_delta_should_only_contain_documents_written_since_last_call() {
+        let kept = Document::new(Uuid::new_v4(), HashMap::new());
+        let cache = MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            vec![kept.clone()],
+            ChangeFeed::new(16),
+        );
+
+        let added = Uuid::new_v4();
+        cache
+            .set_document(Document::new(added, HashMap::new()))
+            .await
+            .unwrap();
+        cache.delete_document(kept.id).await.unwrap();
+
+        let (_, delta) = cache.take_delta().await;
+        assert_eq!(delta.upserted.len(), 1);
+        assert_eq!(delta.upserted[0].id, added);
+        assert_eq!(delta.deleted, vec![kept.id]);
+
+        let (_, delta) = cache.take_delta().await;
+        assert!(delta.is_empty(), "Nothing has been written since");
+    }
+
+    #[tokio::test]
+    async fn writes_should_not_wait_for_a_result_that_is_still_read() {
+        let kept = Document::new(Uuid::new_v4(), HashMap::new());
+        let cache = MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            vec![kept.clone()],
+            ChangeFeed::new(16),
+        );
+
+        let result = cache.documents().await.unwrap();
+        cache
+            .set_document(Document::new(Uuid::new_v4(), HashMap::new()))
+            .await
+            .unwrap();
+        cache.delete_document(kept.id).await.unwrap();
+
+        let read: Vec<_> = result.stream().collect().await;
+        assert_eq!(read.len(), 1, "The result should not see later writes");
+        assert_eq!(read[0].id, kept.id);
+        assert_eq!(cache.documents().await.unwrap().total(), 1);
+    }
+
+    #[tokio::test]
+    async fn evicted_documents_should_be_read_back_in_when_used() {
+        let document = Document::new(Uuid::new_v4(), HashMap::new());
+        let cache = evictable(&document, Arc::new(TestSource(vec![document.clone()])));
+
+        assert!(cache.evict().await);
+        assert!(cache.id_index.read().await.is_empty());
+        assert!(
+            cache.document(Uuid::new_v4()).await.unwrap().is_none(),
+            "Unknown ids should not read the collection back in"
+        );
+        assert!(
+            cache.last_used().is_none(),
+            "The collection is still evicted"
+        );
+
+        assert_eq!(
+            cache.document(document.id).await.unwrap().unwrap().id,
+            document.id
+        );
+        assert!(cache.last_used().is_some());
+
+        cache
+            .set_document(Document::new(Uuid::new_v4(), HashMap::new()))
+            .await
+            .unwrap();
+        assert!(
+            !cache.evict().await,
+            "Unsaved writes should never be evicted"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_read_should_leave_the_collection_evicted() {
+        let document = Document::new(Uuid::new_v4(), HashMap::new());
+        let cache = evictable(&document, Arc::new(FailingSource));
+
+        assert!(cache.evict().await);
+        assert!(cache.document(document.id).await.is_err());
+        assert!(cache
+            .set_document(Document::new(Uuid::new_v4(), HashMap::new()))
+            .await
+            .is_err());
+        assert!(
+            cache.last_used().is_none(),
+            "The collection should still be evicted"
+        );
+        assert_eq!(cache.memory_usage().await.documents, 1);
+    }
+
+    #[tokio::test]
+    async fn documents_that_changed_in_the_store_should_not_be_read_back_in() {
+        let document = Document::new(Uuid::new_v4(), HashMap::new());
+        let mut changed = document.clone();
+        changed.revision += 1;
+        let cache = evictable(&document, Arc::new(TestSource(vec![changed])));
+
+        assert!(cache.evict().await);
+        assert!(cache.documents().await.is_err());
+        assert!(cache.last_used().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_snapshot_should_keep_an_evicted_collection_in_the_store() {
+        let document = Document::new(Uuid::new_v4(), HashMap::new());
+        let cache = evictable(&document, Arc::new(TestSource(vec![document.clone()])));
+
+        assert!(cache.evict().await);
+        let documents = cache.snapshot_documents().await;
+        assert!(matches!(
+            documents,
+            SnapshotDocuments::Stored { count: 1, .. }
+        ));
+        assert!(
+            cache.document(document.id).now_or_never().is_none(),
+            "The collection should not be read back in while it is pinned"
+        );
+
+        drop(documents);
+        assert_eq!(
+            cache.document(document.id).await.unwrap().unwrap().id,
+            document.id
+        );
+        assert!(matches!(
+            cache.snapshot_documents().await,
+            SnapshotDocuments::Resident(documents) if documents.len() == 1
+        ));
+    }
+
+    /// Counts the size the way it was counted before it was kept up to date
+    async fn counted_size(cache: &MemoryCacheCollection) -> usize {
+        let documents: usize = cache
+            .id_index
+            .read()
+            .await
+            .values()
+            .map(|i| entry_size(i))
+            .sum();
+        documents + cache.inner_collection().await.get_size()
+    }
+
+    #[tokio::test]
+    async fn the_size_should_be_kept_up_to_date() {
+        let document = Document::new(Uuid::new_v4(), HashMap::new());
+        let cache = evictable(&document, Arc::new(TestSource(vec![document.clone()])));
+        assert_eq!(cache.get_size().await, counted_size(&cache).await);
+
+        assert!(cache.evict().await);
+        assert_eq!(
+            cache.get_size().await,
+            EVICTED_ENTRY_SIZE + cache.inner_collection().await.get_size()
+        );
+        cache.document(document.id).await.unwrap();
+        assert_eq!(cache.get_size().await, counted_size(&cache).await);
+
+        let mut fields = HashMap::new();
+        fields.insert("name".to_string(), "Some long enough value".into());
+        let added = Uuid::new_v4();
+        cache
+            .set_document(Document::new(added, fields.clone()))
+            .await
+            .unwrap();
+        assert_eq!(cache.get_size().await, counted_size(&cache).await);
+        cache
+            .set_document(Document::new(document.id, fields))
+            .await
+            .unwrap();
+        assert_eq!(cache.get_size().await, counted_size(&cache).await);
+        cache.delete_document(added).await.unwrap();
+        assert_eq!(cache.get_size().await, counted_size(&cache).await);
+    }
+
+    #[tokio::test]
+    async fn the_published_size_should_match_a_recount_after_a_mix_of_writes() {
+        let document = |id: u128, value: &str| {
+            let mut fields = HashMap::new();
+            fields.insert("value".to_string(), value.into());
+            Document::new(Uuid::from_u128(id), fields)
+        };
+        let cache = MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            vec![document(1, "one"), document(2, "two")],
+            ChangeFeed::new(16),
+        );
+
+        {
+            let mut writer = cache.writer().await.unwrap();
+            writer.set(document(3, "three"));
+            writer.set(document(1, &"a much longer value".repeat(10)));
+            writer.set(document(1, "shorter again"));
+            writer.delete(Uuid::from_u128(2));
+            writer.set(document(4, "inserted and deleted"));
+            writer.delete(Uuid::from_u128(4));
+            writer.delete(Uuid::from_u128(5));
+            writer.publish().await;
+        }
+        assert_eq!(cache.get_size().await, counted_size(&cache).await);
+
+        // Writes that are never published should not count
+        {
+            let mut writer = cache.writer().await.unwrap();
+            writer.set(document(6, &"discarded".repeat(100)));
+            writer.delete(Uuid::from_u128(3));
+        }
+        assert_eq!(cache.get_size().await, counted_size(&cache).await);
+
+        let recounted = MemoryCacheCollection::new(
+            Uuid::nil(),
+            Collection::new("TEST".to_string(), None),
+            cache
+                .id_index
+                .read()
+                .await
+                .values()
+                .map(|i| Document::clone(i)),
+            ChangeFeed::new(16),
+        );
+        assert_eq!(cache.get_size().await, recounted.get_size().await);
+    }
 }
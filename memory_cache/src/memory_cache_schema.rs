@@ -1,4 +1,8 @@
-use crate::memory_cache_collection::MemoryCacheCollection;
+use crate::{
+    memory_cache_collection::MemoryCacheCollection,
+    name_index::NameIndex,
+    residency::Residency,
+};
 use failure::Error;
 use futures::{
     future::BoxFuture,
@@ -10,12 +14,21 @@ use futures::{
 use shelf_database::{
     CacheCollection,
     CacheSchema,
+    ChangeFeed,
+    ChangeKind,
     Collection,
     Schema,
+    SchemaMemoryUsage,
 };
 use std::{
     collections::HashMap,
-    sync::Arc,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
 };
 use tokio::sync::RwLock;
 use uuid::Uuid;
@@ -24,16 +37,78 @@ use uuid::Uuid;
 pub struct MemoryCacheSchema {
     schema: Arc<RwLock<Schema>>,
     collections: Arc<RwLock<HashMap<Uuid, MemoryCacheCollection>>>,
+    changes: ChangeFeed,
+    /// Set when the schema or its list of collections has changed since the
+    /// last save
+    dirty: Arc<AtomicBool>,
+    residency: Arc<Residency>,
+    /// The names of every schema in the cache, kept up to date when this
+    /// schema is renamed
+    schema_names: NameIndex,
+    collection_names: NameIndex,
 }
 
 impl MemoryCacheSchema {
-    pub fn new(schema: Schema, collections: HashMap<Uuid, MemoryCacheCollection>) -> Self {
+    pub fn new(
+        schema: Schema,
+        changes: ChangeFeed,
+        residency: Arc<Residency>,
+        schema_names: NameIndex,
+    ) -> Self {
         Self {
             schema: Arc::new(RwLock::new(schema)),
-            collections: Arc::new(RwLock::new(collections)),
+            collections: Arc::new(RwLock::new(HashMap::new())),
+            changes,
+            dirty: Arc::new(AtomicBool::new(false)),
+            residency,
+            schema_names,
+            collection_names: NameIndex::default(),
+        }
+    }
+
+    pub(crate) fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns if the schema has changed since the last call
+    pub(crate) fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::SeqCst)
+    }
+
+    /// Adds a collection read from the store, unlike `insert_collection`
+    /// nothing is marked as changed
+    pub(crate) async fn add_loaded_collection(&self, collection: MemoryCacheCollection) {
+        self.put_collection(collection).await;
+    }
+
+    /// Replaces any collection with the same id
+    async fn put_collection(&self, collection: MemoryCacheCollection) {
+        let inner = collection.inner_collection().await;
+        let collection = collection
+            .with_residency(Arc::clone(&self.schema), Arc::clone(&self.residency))
+            .with_name_index(self.collection_names.clone());
+
+        let previous = self.collections.write().await.insert(inner.id, collection);
+        match previous {
+            Some(previous) => {
+                let previous_name = previous.inner_collection().await.name;
+                self.collection_names
+                    .rename(&previous_name, &inner.name, inner.id);
+            }
+            None => self.collection_names.insert(&inner.name, inner.id),
         }
     }
 
+    pub(crate) async fn memory_usage(&self) -> SchemaMemoryUsage {
+        let schema = self.schema.read().await.clone();
+        let collections = self
+            .collections()
+            .then(|i| async move { i.memory_usage().await })
+            .collect()
+            .await;
+        SchemaMemoryUsage::new(schema.id, schema.name, collections)
+    }
+
     pub async fn get_size(&self) -> usize {
         self.collections()
             .fold(0, |acc, val| async move { acc + val.get_size().await })
@@ -51,7 +126,12 @@ impl CacheSchema for MemoryCacheSchema {
     fn set_schema(&self, schema: Schema) -> BoxFuture<()> {
         async move {
             let mut lock = self.schema.write().await;
-            *lock = schema
+            self.schema_names
+                .rename(&lock.name, &schema.name, schema.id);
+            *lock = schema.clone();
+            self.mark_dirty();
+
+            self.changes.publish(ChangeKind::SchemaUpdated { schema });
         }
         .boxed()
     }
@@ -74,11 +154,20 @@ impl CacheSchema for MemoryCacheSchema {
             }
 
             // DO THE INSERT
-            let mut lock = self.collections.write().await;
-            lock.insert(
-                collection.id,
-                MemoryCacheCollection::new(collection, vec![]),
-            );
+            let schema_id = self.schema.read().await.id;
+            self.put_collection(MemoryCacheCollection::new(
+                schema_id,
+                collection.clone(),
+                vec![],
+                self.changes.clone(),
+            ))
+            .await;
+            self.mark_dirty();
+
+            self.changes.publish(ChangeKind::CollectionUpdated {
+                schema_id,
+                collection,
+            });
 
             Ok(())
         }
@@ -86,19 +175,7 @@ impl CacheSchema for MemoryCacheSchema {
     }
 
     fn collection(&self, id: Uuid) -> BoxFuture<Option<Self::CacheCollection>> {
-        async move {
-            let stream = self.collections();
-            let mut mapped = stream
-                .filter_map(move |i| async move {
-                    if i.inner_collection().await.id.eq(&id) {
-                        return Some(i);
-                    }
-                    return None;
-                })
-                .boxed();
-            mapped.next().await
-        }
-        .boxed()
+        async move { self.collections.read().await.get(&id).cloned() }.boxed()
     }
 
     fn collection_by_name<'a>(
@@ -106,19 +183,8 @@ impl CacheSchema for MemoryCacheSchema {
         name: &'a str,
     ) -> BoxFuture<'a, Option<Self::CacheCollection>> {
         async move {
-            let stream = self.collections();
-            let mut mapped = stream
-                .filter_map(move |i| {
-                    let name = name.to_string();
-                    async move {
-                        if i.inner_collection().await.name == name {
-                            return Some(i);
-                        }
-                        return None;
-                    }
-                })
-                .boxed();
-            mapped.next().await
+            let id = self.collection_names.get(name)?;
+            self.collection(id).await
         }
         .boxed()
     }
@@ -126,22 +192,104 @@ impl CacheSchema for MemoryCacheSchema {
 
 #[cfg(test)]
 mod test {
-    use crate::memory_cache_schema::MemoryCacheSchema;
+    use crate::{
+        memory_cache_schema::MemoryCacheSchema,
+        name_index::NameIndex,
+        residency::Residency,
+    };
     use shelf_database::{
+        CacheCollection,
         CacheSchema,
+        ChangeFeed,
+        Collection,
         Schema,
     };
-    use std::collections::HashMap;
+    use slog::{
+        Discard,
+        Logger,
+    };
+    use std::sync::Arc;
     use uuid::Uuid;
 
     #[tokio::test]
     async fn inner_schema_should_return_the_inner_schema() {
         let id = Uuid::new_v4();
-        let mem_schema = MemoryCacheSchema::new(Schema::new(id, "TEST", None), HashMap::new());
+        let logger = Logger::root(Discard, o!());
+        let mem_schema = MemoryCacheSchema::new(
+            Schema::new(id, "TEST", None),
+            ChangeFeed::new(16),
+            Arc::new(Residency::new(&logger, None)),
+            NameIndex::default(),
+        );
         assert_eq!(
             mem_schema.inner_schema().await.id,
             id,
             "The schemas are not the same"
         );
     }
+
+    #[tokio::test]
+    async fn collection_by_name_should_follow_renames() {
+        let logger = Logger::root(Discard, o!());
+        let mem_schema = MemoryCacheSchema::new(
+            Schema::new(Uuid::new_v4(), "TEST", None),
+            ChangeFeed::new(16),
+            Arc::new(Residency::new(&logger, None)),
+            NameIndex::default(),
+        );
+        let collection = Collection::new("Car".to_string(), None);
+        mem_schema
+            .insert_collection(collection.clone())
+            .await
+            .unwrap();
+
+        let renamed = Collection {
+            name: "Vehicle".to_string(),
+            ..collection.clone()
+        };
+        mem_schema
+            .collection(collection.id)
+            .await
+            .unwrap()
+            .set_collection(renamed)
+            .await;
+
+        assert!(mem_schema.collection_by_name("Car").await.is_none());
+        assert_eq!(
+            mem_schema
+                .collection_by_name("Vehicle")
+                .await
+                .unwrap()
+                .inner_collection()
+                .await
+                .id,
+            collection.id
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_usage_should_list_collections_of_the_same_size_by_name() {
+        let logger = Logger::root(Discard, o!());
+        let mem_schema = MemoryCacheSchema::new(
+            Schema::new(Uuid::new_v4(), "TEST", None),
+            ChangeFeed::new(16),
+            Arc::new(Residency::new(&logger, None)),
+            NameIndex::default(),
+        );
+        for name in &["Car", "Boat", "Plane"] {
+            mem_schema
+                .insert_collection(Collection::new(name.to_string(), None))
+                .await
+                .unwrap();
+        }
+
+        let names: Vec<_> = mem_schema
+            .memory_usage()
+            .await
+            .collections
+            .into_iter()
+            .map(|i| i.name)
+            .collect();
+        assert_eq!(names, vec!["Boat", "Car", "Plane"]);
+    }
 }
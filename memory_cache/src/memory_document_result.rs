@@ -1,3 +1,4 @@
+use crate::memory_cache_collection::DocumentIndex;
 use futures::{
     stream,
     stream::BoxStream,
@@ -7,32 +8,26 @@ use shelf_database::{
     Document,
     DocumentResult,
 };
-use std::{
-    collections::BTreeMap,
-    sync::Arc,
-};
-use tokio::sync::RwLockReadGuard;
-use uuid::Uuid;
+use std::sync::Arc;
 
-pub struct MemoryDocumentResult<'a> {
-    lock: RwLockReadGuard<'a, BTreeMap<Uuid, Arc<Document>>>,
+/// Streams a snapshot of a collection, so writers are never kept waiting for
+/// a slow reader
+pub struct MemoryDocumentResult {
+    snapshot: DocumentIndex,
 }
 
-impl<'a> MemoryDocumentResult<'a> {
-    pub fn new(lock: RwLockReadGuard<'a, BTreeMap<Uuid, Arc<Document>>>) -> Self {
-        Self { lock }
+impl MemoryDocumentResult {
+    pub fn new(snapshot: DocumentIndex) -> Self {
+        Self { snapshot }
     }
 }
 
-impl<'a> DocumentResult for MemoryDocumentResult<'a> {
+impl DocumentResult for MemoryDocumentResult {
     fn total(&self) -> usize {
-        self.lock.len()
+        self.snapshot.len()
     }
 
     fn stream(&self) -> BoxStream<Arc<Document>> {
-        let iter = self.lock.iter();
-        stream::iter(iter)
-            .map(|(_key, val)| Arc::clone(&val))
-            .boxed()
+        stream::iter(self.snapshot.values()).map(Arc::clone).boxed()
     }
 }
@@ -0,0 +1,78 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        RwLock,
+    },
+};
+use uuid::Uuid;
+
+/// Finds schemas or collections by name. It is shared between a container and
+/// everything in it, so that a rename updates it right away. Data written
+/// before names were checked can hold the same name twice, so every id with a
+/// name is kept in the order they got it
+#[derive(Clone, Default)]
+pub struct NameIndex(Arc<RwLock<HashMap<String, Vec<Uuid>>>>);
+
+impl NameIndex {
+    /// Returns whatever had the name first
+    pub fn get(&self, name: &str) -> Option<Uuid> {
+        self.0
+            .read()
+            .expect("Lock was poisoned")
+            .get(name)
+            .and_then(|i| i.first())
+            .cloned()
+    }
+
+    /// If the name is taken already the id is found once the others with the
+    /// name are renamed
+    pub fn insert(&self, name: &str, id: Uuid) {
+        let mut names = self.0.write().expect("Lock was poisoned");
+        add(&mut names, name, id);
+    }
+
+    pub fn rename(&self, from: &str, to: &str, id: Uuid) {
+        if from == to {
+            return;
+        }
+        let mut names = self.0.write().expect("Lock was poisoned");
+        if let Some(ids) = names.get_mut(from) {
+            ids.retain(|i| *i != id);
+            if ids.is_empty() {
+                names.remove(from);
+            }
+        }
+        add(&mut names, to, id);
+    }
+}
+
+fn add(names: &mut HashMap<String, Vec<Uuid>>, name: &str, id: Uuid) {
+    let ids = names.entry(name.to_string()).or_default();
+    if !ids.contains(&id) {
+        ids.push(id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::name_index::NameIndex;
+    use uuid::Uuid;
+
+    #[test]
+    fn a_shadowed_name_should_be_found_once_the_first_holder_is_renamed() {
+        let names = NameIndex::default();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        names.insert("Car", first);
+        names.insert("Car", second);
+        assert_eq!(names.get("Car"), Some(first));
+
+        names.rename("Car", "Vehicle", first);
+        assert_eq!(names.get("Car"), Some(second));
+        assert_eq!(names.get("Vehicle"), Some(first));
+
+        names.rename("Car", "Boat", second);
+        assert_eq!(names.get("Car"), None);
+    }
+}
@@ -0,0 +1,240 @@
+use failure::Error;
+use futures::{
+    channel::oneshot,
+    future::BoxFuture,
+};
+use shelf_database::{
+    Collection,
+    Document,
+    Schema,
+    Store,
+};
+use slog::Logger;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        RwLock as StdRwLock,
+    },
+};
+use tokio::sync::{
+    Mutex as AsyncMutex,
+    RwLock,
+};
+use uuid::Uuid;
+
+/// Where the documents of an evicted collection are read back from
+pub trait DocumentSource: Send + Sync {
+    fn load_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<Document>, Error>>;
+}
+
+struct StoreSource<S: Store>(Arc<S>);
+
+impl<S: Store> DocumentSource for StoreSource<S> {
+    fn load_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<Document>, Error>> {
+        self.0.get_documents(logger, schema, collection)
+    }
+}
+
+/// Shared by every collection of a cache. Keeps track of when collections
+/// were last used, so that the least recently used ones can be evicted once
+/// the documents take up more memory than the budget
+pub struct Residency {
+    logger: Logger,
+    budget: Option<usize>,
+    clock: AtomicU64,
+    source: StdRwLock<Option<Arc<dyn DocumentSource>>>,
+}
+
+impl Residency {
+    /// Without a budget nothing is ever evicted
+    pub fn new(logger: &Logger, budget: Option<usize>) -> Self {
+        Self {
+            logger: logger.clone(),
+            budget,
+            clock: AtomicU64::new(0),
+            source: StdRwLock::new(None),
+        }
+    }
+
+    pub fn budget(&self) -> Option<usize> {
+        self.budget
+    }
+
+    pub fn set_store<S: Store>(&self, store: Arc<S>) {
+        self.set_source(Arc::new(StoreSource(store)));
+    }
+
+    pub fn set_source(&self, source: Arc<dyn DocumentSource>) {
+        *self.source.write().expect("Lock was poisoned") = Some(source);
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn load(&self, schema: &Schema, collection: &Collection) -> Result<Vec<Document>, Error> {
+        let source = self.source.read().expect("Lock was poisoned").clone();
+        match source {
+            Some(source) => {
+                source
+                    .load_documents(&self.logger, schema, collection)
+                    .await
+            }
+            None => bail!("The cache has no store to read evicted documents from"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pins {
+    count: usize,
+    /// Told once the last pin is dropped
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// Keeps an evicted collection from being read back in, and so from being
+/// written to, for as long as it is held
+pub struct EvictionPin(Arc<Mutex<Pins>>);
+
+impl Drop for EvictionPin {
+    fn drop(&mut self) {
+        let mut pins = self.0.lock().expect("Lock was poisoned");
+        pins.count -= 1;
+        if pins.count == 0 {
+            for waiter in pins.waiters.drain(..) {
+                // The waiter might have given up already
+                let _ = waiter.send(());
+            }
+        }
+    }
+}
+
+/// What a single collection needs to be evicted and read back in
+#[derive(Clone)]
+pub struct CollectionResidency {
+    schema: Arc<RwLock<Schema>>,
+    residency: Arc<Residency>,
+    /// Only changed while the write lock on the documents is held
+    resident: Arc<AtomicBool>,
+    last_used: Arc<AtomicU64>,
+    /// The ids and revisions of the documents while they are evicted. This
+    /// is the id index of an evicted collection, so that lookups of ids that
+    /// do not exist never have to read anything, and so that what is read
+    /// back in can be checked against it
+    evicted: Arc<Mutex<BTreeMap<Uuid, u64>>>,
+    /// Makes sure only one access reads the documents back in
+    fault_lock: Arc<AsyncMutex<()>>,
+    pins: Arc<Mutex<Pins>>,
+}
+
+impl CollectionResidency {
+    pub fn new(schema: Arc<RwLock<Schema>>, residency: Arc<Residency>) -> Self {
+        let last_used = residency.tick();
+        Self {
+            schema,
+            residency,
+            resident: Arc::new(AtomicBool::new(true)),
+            last_used: Arc::new(AtomicU64::new(last_used)),
+            evicted: Arc::new(Mutex::new(BTreeMap::new())),
+            fault_lock: Arc::new(AsyncMutex::new(())),
+            pins: Arc::new(Mutex::new(Pins::default())),
+        }
+    }
+
+    pub fn logger(&self) -> &Logger {
+        &self.residency.logger
+    }
+
+    pub fn is_resident(&self) -> bool {
+        self.resident.load(Ordering::SeqCst)
+    }
+
+    pub fn touch(&self) {
+        self.last_used
+            .store(self.residency.tick(), Ordering::Relaxed);
+    }
+
+    pub fn last_used(&self) -> u64 {
+        self.last_used.load(Ordering::Relaxed)
+    }
+
+    /// Tells if an evicted collection might hold a document
+    pub fn might_hold(&self, id: Uuid) -> bool {
+        self.is_resident()
+            || self
+                .evicted
+                .lock()
+                .expect("Lock was poisoned")
+                .contains_key(&id)
+    }
+
+    pub fn evicted_count(&self) -> usize {
+        self.evicted.lock().expect("Lock was poisoned").len()
+    }
+
+    pub fn evicted_ids(&self) -> BTreeMap<Uuid, u64> {
+        self.evicted.lock().expect("Lock was poisoned").clone()
+    }
+
+    /// Must be called with the write lock on the documents held
+    pub fn mark_evicted(&self, ids: BTreeMap<Uuid, u64>) {
+        *self.evicted.lock().expect("Lock was poisoned") = ids;
+        self.resident.store(false, Ordering::SeqCst);
+    }
+
+    /// Must be called with the write lock on the documents held
+    pub fn mark_resident(&self) {
+        self.evicted.lock().expect("Lock was poisoned").clear();
+        self.resident.store(true, Ordering::SeqCst);
+    }
+
+    pub fn fault_lock(&self) -> &AsyncMutex<()> {
+        &self.fault_lock
+    }
+
+    /// Keeps the collection in the store until the pin is dropped. Returns
+    /// `None` if the documents are in memory
+    pub async fn pin(&self) -> Option<EvictionPin> {
+        let _guard = self.fault_lock.lock().await;
+        if self.is_resident() {
+            return None;
+        }
+        self.pins.lock().expect("Lock was poisoned").count += 1;
+        Some(EvictionPin(Arc::clone(&self.pins)))
+    }
+
+    /// Returns a receiver that is told once the collection is no longer
+    /// pinned, or `None` if it is not pinned. Must be called with the fault
+    /// lock held, so that no pin is taken in between
+    pub fn wait_for_pins(&self) -> Option<oneshot::Receiver<()>> {
+        let mut pins = self.pins.lock().expect("Lock was poisoned");
+        if pins.count == 0 {
+            return None;
+        }
+        let (sender, receiver) = oneshot::channel();
+        pins.waiters.push(sender);
+        Some(receiver)
+    }
+
+    pub async fn load(&self, collection: &Collection) -> Result<Vec<Document>, Error> {
+        let schema = self.schema.read().await.clone();
+        self.residency.load(&schema, collection).await
+    }
+}
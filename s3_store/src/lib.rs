@@ -0,0 +1,23 @@
+#![deny(clippy::pedantic)]
+#![allow(clippy::module_inception)]
+#![allow(clippy::multiple_crate_versions)]
+#![deny(dead_code)]
+#![deny(unused_imports)]
+
+#[macro_use]
+extern crate slog;
+#[macro_use]
+extern crate failure;
+
+mod memory_bucket;
+mod object_chunks;
+mod object_storage;
+mod s3_bucket;
+mod s3_store;
+
+pub use self::{
+    memory_bucket::MemoryBucket,
+    object_storage::ObjectStorage,
+    s3_bucket::S3Bucket,
+    s3_store::S3Store,
+};
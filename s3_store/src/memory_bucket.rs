@@ -0,0 +1,69 @@
+use crate::object_storage::ObjectStorage;
+use failure::Error;
+use futures::{
+    future::BoxFuture,
+    lock::Mutex,
+    FutureExt,
+};
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+};
+
+/// Keeps the objects in memory. Meant for tests, nothing survives a restart
+#[derive(Default)]
+pub struct MemoryBucket {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+    puts: AtomicUsize,
+}
+
+impl MemoryBucket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many objects have been written, to check what a save uploads
+    pub fn put_count(&self) -> usize {
+        self.puts.load(Ordering::SeqCst)
+    }
+}
+
+impl ObjectStorage for MemoryBucket {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>> {
+        async move { Ok(self.objects.lock().await.get(key).cloned()) }.boxed()
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.objects.lock().await.insert(key.to_string(), data);
+            self.puts.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.objects.lock().await.remove(key);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, Error>> {
+        async move {
+            Ok(self
+                .objects
+                .lock()
+                .await
+                .keys()
+                .filter(|i| i.starts_with(prefix))
+                .cloned()
+                .collect())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,88 @@
+use crate::object_storage::ObjectStorage;
+use failure::Error;
+use futures::{
+    future::BoxFuture,
+    FutureExt,
+};
+use shelf_file_store::{
+    is_collection_file,
+    ChunkStorage,
+};
+
+/// The chunk objects under the docs prefix of a collection
+pub struct ObjectChunks<'a, O: ObjectStorage> {
+    storage: &'a O,
+    prefix: String,
+}
+
+impl<'a, O: ObjectStorage> ObjectChunks<'a, O> {
+    pub fn new(storage: &'a O, prefix: String) -> Self {
+        Self { storage, prefix }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+}
+
+// Derived it would need `O: Clone`, only the reference is cloned
+impl<'a, O: ObjectStorage> Clone for ObjectChunks<'a, O> {
+    fn clone(&self) -> Self {
+        Self::new(self.storage, self.prefix.to_string())
+    }
+}
+
+impl<'s, O: ObjectStorage> ChunkStorage for ObjectChunks<'s, O> {
+    fn list(&self) -> BoxFuture<Result<Vec<String>, Error>> {
+        async move {
+            Ok(self
+                .storage
+                .list(&self.prefix)
+                .await?
+                .into_iter()
+                .map(|i| i[self.prefix.len()..].to_string())
+                .filter(|i| is_collection_file(i))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn read<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<u8>, Error>> {
+        async move {
+            match self.storage.get(&self.key(name)).await? {
+                Some(data) => Ok(data),
+                None => bail!("The chunk object {} is missing", self.key(name)),
+            }
+        }
+        .boxed()
+    }
+
+    fn write<'a>(
+        &'a self,
+        name: &'a str,
+        data: Vec<u8>,
+        replaced: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            // Objects are replaced as a whole, there is no need for the
+            // renaming the file store does. Should we crash before the old
+            // object is removed, both are rearranged on the next save
+            self.storage.put(&self.key(name), data).await?;
+            if let Some(replaced) = replaced {
+                self.storage.delete(&self.key(replaced)).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a self, names: &'a [String]) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            for name in names {
+                self.storage.delete(&self.key(name)).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,37 @@
+use failure::Error;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+
+/// A flat key value store of objects, like an S3 bucket. Keys use `/` to
+/// group objects the same way as folders do on disk
+pub trait ObjectStorage: Sync + Send + 'static {
+    /// Returns `None` if there is no object with the key
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>>;
+    /// Creates or replaces an object, it is readable in full once this
+    /// returns
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), Error>>;
+    /// Removing an object that does not exist is not an error
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>>;
+    /// Returns the keys of every object starting with `prefix`, sorted
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, Error>>;
+}
+
+/// Lets several stores share one storage, like a store that is opened again
+/// on the same bucket
+impl<O: ObjectStorage> ObjectStorage for Arc<O> {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>> {
+        O::get(self, key)
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), Error>> {
+        O::put(self, key, data)
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        O::delete(self, key)
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, Error>> {
+        O::list(self, prefix)
+    }
+}
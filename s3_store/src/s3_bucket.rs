@@ -0,0 +1,152 @@
+use crate::object_storage::ObjectStorage;
+use failure::Error;
+use futures::{
+    future::BoxFuture,
+    FutureExt,
+    TryStreamExt,
+};
+use rusoto_core::{
+    HttpClient,
+    Region,
+    RusotoError,
+};
+use rusoto_credential::StaticProvider;
+use rusoto_s3::{
+    DeleteObjectRequest,
+    GetObjectError,
+    GetObjectRequest,
+    ListObjectsV2Request,
+    PutObjectRequest,
+    S3Client,
+    S3,
+};
+use shelf_config::Config;
+
+/// A bucket in S3, or in any service speaking the same protocol
+pub struct S3Bucket {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3Bucket {
+    /// Sets up a client for the configured bucket, nothing is sent until the
+    /// bucket is used
+    ///
+    /// # Errors
+    /// Returns an error if no bucket is configured or the region is unknown
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        if config.s3_bucket.is_empty() {
+            bail!("No bucket configured, s3Bucket has to be set to use the S3 store");
+        }
+
+        let region = match &config.s3_endpoint {
+            Some(endpoint) => Region::Custom {
+                name: config.s3_region.to_string(),
+                endpoint: endpoint.to_string(),
+            },
+            None => config.s3_region.parse()?,
+        };
+
+        let client = match (&config.s3_access_key_id, &config.s3_secret_access_key) {
+            (Some(key), Some(secret)) => S3Client::new_with(
+                HttpClient::new()?,
+                StaticProvider::new_minimal(key.to_string(), secret.to_string()),
+                region,
+            ),
+            _ => S3Client::new(region),
+        };
+
+        Ok(Self {
+            client,
+            bucket: config.s3_bucket.to_string(),
+        })
+    }
+}
+
+impl ObjectStorage for S3Bucket {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, Error>> {
+        async move {
+            let request = GetObjectRequest {
+                bucket: self.bucket.to_string(),
+                key: key.to_string(),
+                ..GetObjectRequest::default()
+            };
+
+            let output = match self.client.get_object(request).await {
+                Ok(output) => output,
+                Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+
+            match output.body {
+                Some(body) => Ok(Some(body.map_ok(|i| i.to_vec()).try_concat().await?)),
+                None => Ok(Some(vec![])),
+            }
+        }
+        .boxed()
+    }
+
+    fn put<'a>(&'a self, key: &'a str, data: Vec<u8>) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let request = PutObjectRequest {
+                bucket: self.bucket.to_string(),
+                key: key.to_string(),
+                body: Some(data.into()),
+                ..PutObjectRequest::default()
+            };
+            self.client.put_object(request).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let request = DeleteObjectRequest {
+                bucket: self.bucket.to_string(),
+                key: key.to_string(),
+                ..DeleteObjectRequest::default()
+            };
+            self.client.delete_object(request).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, Error>> {
+        async move {
+            let mut keys = vec![];
+            let mut continuation_token = None;
+
+            // A single response holds at most a thousand keys
+            loop {
+                let request = ListObjectsV2Request {
+                    bucket: self.bucket.to_string(),
+                    prefix: Some(prefix.to_string()),
+                    continuation_token,
+                    ..ListObjectsV2Request::default()
+                };
+                let output = self.client.list_objects_v2(request).await?;
+
+                keys.extend(
+                    output
+                        .contents
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|i| i.key),
+                );
+
+                match output.next_continuation_token {
+                    Some(token) if output.is_truncated == Some(true) => {
+                        continuation_token = Some(token);
+                    }
+                    _ => break,
+                }
+            }
+
+            keys.sort();
+            Ok(keys)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,873 @@
+use crate::{
+    object_chunks::ObjectChunks,
+    object_storage::ObjectStorage,
+    s3_bucket::S3Bucket,
+};
+use colored::Colorize;
+use failure::Error;
+use futures::{
+    future::BoxFuture,
+    lock::Mutex,
+    stream::BoxStream,
+    FutureExt,
+};
+use serde::{
+    de::DeserializeOwned,
+    Serialize,
+};
+use shelf_config::Config;
+use shelf_database::{
+    Collection,
+    CompactionReport,
+    DeadLetter,
+    Document,
+    DocumentDelta,
+    DocumentVersion,
+    Schema,
+    Store,
+    Transaction,
+    Webhook,
+};
+use shelf_file_store::{
+    docs_folder,
+    docs_folder_id,
+    history_folder,
+    schema_folder,
+    schema_folder_id,
+    Changes,
+    ChunkCodec,
+    ChunkEngine,
+    ChunkIndex,
+};
+use slog::Logger;
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
+    mem,
+    time::Instant,
+};
+use uuid::Uuid;
+
+/// Where the next write-ahead log entry goes
+struct WalPosition {
+    segment: u64,
+    next: u64,
+}
+
+/// Keeps the data in an S3 compatible bucket, laid out the same way as the
+/// file store lays out the data folder, with the objects keyed by the ids of
+/// their schema and collection. Chunk objects are named after the hash of
+/// their content, so a chunk that has not changed is never uploaded again
+pub struct S3Store<O: ObjectStorage = S3Bucket> {
+    storage: O,
+    prefix: String,
+    collections: Mutex<HashMap<Uuid, Vec<Collection>>>,
+    /// Documents handed over since the last flush, keyed by schema and
+    /// collection id. The name of the collection is kept for the logs
+    pending: Mutex<HashMap<(Uuid, Uuid), (String, Changes)>>,
+    chunks: Mutex<HashMap<(Uuid, Uuid), ChunkIndex>>,
+    /// Held while chunk objects are written, so that a flush and a compaction
+    /// never work on the same chunks, and while documents are read back in
+    io_lock: Mutex<()>,
+    /// Reads and writes the chunk objects
+    engine: ChunkEngine,
+    wal: Mutex<WalPosition>,
+}
+
+impl S3Store {
+    /// Creates a store using the bucket in the config
+    ///
+    /// # Errors
+    /// Returns an error if the bucket is not configured or can not be listed
+    pub async fn new(logger: &Logger, config: &Config) -> Result<Self, Error> {
+        info!(logger, "Setting up S3 store"; "bucket" => &config.s3_bucket, "prefix" => &config.s3_prefix);
+        Self::with_storage(logger, config, S3Bucket::new(config)?).await
+    }
+}
+
+impl<O: ObjectStorage> S3Store<O> {
+    /// Creates a store on top of any object storage
+    ///
+    /// # Errors
+    /// Returns an error if the storage can not be listed
+    pub async fn with_storage(logger: &Logger, config: &Config, storage: O) -> Result<Self, Error> {
+        let prefix = config.s3_prefix.to_string();
+
+        // Just like the file store a new segment is always started, so
+        // existing segments are never added to after a restart
+        let wal_prefix = format!("{}wal/", prefix);
+        let segment = storage
+            .list(&wal_prefix)
+            .await?
+            .iter()
+            .filter_map(|i| wal_segment(&wal_prefix, i))
+            .max()
+            .map_or(1, |last| last + 1);
+        debug!(logger, "Opened write-ahead log"; "segment" => segment);
+
+        Ok(Self {
+            storage,
+            prefix,
+            collections: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            chunks: Mutex::new(HashMap::new()),
+            io_lock: Mutex::new(()),
+            engine: ChunkEngine::new(ChunkCodec::from_config(config), config.load_concurrency),
+            wal: Mutex::new(WalPosition { segment, next: 0 }),
+        })
+    }
+
+    pub fn storage(&self) -> &O {
+        &self.storage
+    }
+
+    fn key(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path)
+    }
+
+    fn collections_key(&self, schema_id: Uuid) -> String {
+        self.key(&format!("{}/collections.json", schema_folder(schema_id)))
+    }
+
+    /// The chunk objects of a collection
+    fn chunk_objects(&self, schema_id: Uuid, collection_id: Uuid) -> ObjectChunks<O> {
+        let prefix = self.key(&format!(
+            "{}/{}/",
+            schema_folder(schema_id),
+            docs_folder(collection_id)
+        ));
+        ObjectChunks::new(&self.storage, prefix)
+    }
+
+    /// The history of a collection is split by document, so that reading the
+    /// history of one document only lists the objects of that document
+    fn history_key(
+        &self,
+        schema_id: Uuid,
+        collection_id: Uuid,
+        document_id: Option<Uuid>,
+    ) -> String {
+        let mut key = self.key(&format!(
+            "{}/{}/",
+            schema_folder(schema_id),
+            history_folder(collection_id)
+        ));
+        if let Some(document_id) = document_id {
+            key.push_str(&format!("{}/", document_id));
+        }
+        key
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, Error> {
+        match self.storage.get(key).await? {
+            Some(data) => match serde_json::from_slice(&data) {
+                Ok(value) => Ok(Some(value)),
+                Err(e) => bail!(
+                    "Failed to parse {}, perhaps the object has been corrupted? {}",
+                    key,
+                    e
+                ),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn put_json<T: Serialize>(&self, key: &str, value: &T) -> Result<(), Error> {
+        self.storage
+            .put(key, serde_json::to_vec_pretty(value)?)
+            .await
+    }
+
+    /// Reads the versions in every history object under `prefix`
+    async fn read_history(
+        &self,
+        logger: &Logger,
+        prefix: &str,
+    ) -> Result<Vec<DocumentVersion>, Error> {
+        let mut versions = vec![];
+        for key in self.storage.list(prefix).await? {
+            let data = match self.storage.get(&key).await? {
+                Some(data) => data,
+                None => continue,
+            };
+            for line in String::from_utf8_lossy(&data)
+                .lines()
+                .filter(|i| !i.is_empty())
+            {
+                match serde_json::from_str::<DocumentVersion>(line) {
+                    Ok(version) => versions.push(version),
+                    Err(e) => {
+                        warn!(logger, "Skipping unreadable line in history object"; "key" => &key, "error" => format!("{}", e));
+                    }
+                }
+            }
+        }
+
+        // A save that is retried after its upload went through writes the
+        // same versions again
+        versions.sort_by_key(|i| (i.document_id, i.revision));
+        versions.dedup_by_key(|i| (i.document_id, i.revision));
+        Ok(versions)
+    }
+
+    async fn do_save_collections(&self, logger: &Logger) -> Result<(), Error> {
+        let schemas = mem::replace(&mut *self.collections.lock().await, HashMap::new());
+        for (schema_id, collections) in schemas {
+            debug!(logger, "Saving collections for schema {}", schema_id);
+            self.put_json(&self.collections_key(schema_id), &collections)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn do_save_documents(&self, logger: &Logger) -> Result<(), Error> {
+        let pending = mem::replace(&mut *self.pending.lock().await, HashMap::new());
+        for ((schema_id, collection_id), (collection_name, changes)) in pending {
+            info!(
+                logger,
+                "Saving {} changed documents for collection {}",
+                changes.len(),
+                collection_name.yellow();
+                "schema_id" => schema_id.to_string()
+            );
+
+            // If a chunk fails the index is left out, it is rebuilt from the
+            // objects on the next save
+            let key = (schema_id, collection_id);
+            let index = self.chunks.lock().await.remove(&key);
+            let logger = logger.new(o!("collection_name" => collection_name));
+            let index = self
+                .engine
+                .save(
+                    &logger,
+                    &self.chunk_objects(schema_id, collection_id),
+                    index,
+                    changes,
+                )
+                .await?;
+            self.chunks.lock().await.insert(key, index);
+        }
+
+        Ok(())
+    }
+
+    async fn compact_collection(
+        &self,
+        logger: &Logger,
+        schema_id: Uuid,
+        collection_id: Uuid,
+    ) -> Result<CompactionReport, Error> {
+        let key = (schema_id, collection_id);
+        let index = self.chunks.lock().await.remove(&key);
+        let (index, report) = self
+            .engine
+            .compact(logger, &self.chunk_objects(schema_id, collection_id), index)
+            .await?;
+        self.chunks.lock().await.insert(key, index);
+
+        Ok(report)
+    }
+}
+
+/// Write-ahead log entries are named `wal/{segment}/{entry}.json`
+fn wal_segment(wal_prefix: &str, key: &str) -> Option<u64> {
+    key.get(wal_prefix.len()..)?.split('/').next()?.parse().ok()
+}
+
+impl<O: ObjectStorage> Store for S3Store<O> {
+    fn get_schemas<'a>(
+        &'a self,
+        logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<HashMap<Uuid, Schema>, Error>> {
+        async move {
+            match self.get_json(&self.key("schemas.json")).await? {
+                Some(schemas) => Ok(schemas),
+                None => {
+                    warn!(logger, "No schemas object detected");
+                    Ok(HashMap::new())
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn get_collections<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+    ) -> BoxFuture<'a, Result<Vec<Collection>, Error>> {
+        async move {
+            match self.get_json(&self.collections_key(schema.id)).await? {
+                Some(collections) => Ok(collections),
+                None => {
+                    warn!(logger, "No collections object detected"; "schema_name" => &schema.name);
+                    Ok(vec![])
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn get_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<Document>, Error>> {
+        async move {
+            let logger = logger.new(o!("collection_name" => collection.name.to_string()));
+            // Evicted collections are read back in while the cache runs, this
+            // keeps them from seeing a flush or compaction halfway through
+            let _guard = self.io_lock.lock().await;
+            self.engine
+                .get_documents(&logger, self.chunk_objects(schema.id, collection.id))
+                .await
+        }
+        .boxed()
+    }
+
+    fn stream_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxStream<'a, Result<Vec<Document>, Error>> {
+        let logger = logger.new(o!("schema_name" => schema.name.to_string(), "schema_id" => schema.id.to_string(), "collection_name" => collection.name.to_string(), "collection_id" => collection.id.to_string()));
+        self.engine
+            .stream_documents(&logger, self.chunk_objects(schema.id, collection.id))
+    }
+
+    fn save_schema<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let mut schemas = self.get_schemas(logger).await?;
+            schemas.insert(schema.id, schema.clone());
+            self.put_json(&self.key("schemas.json"), &schemas).await?;
+
+            debug!(logger, "Saved schema \"{}\"", schema.name);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_collection<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.collections
+                .lock()
+                .await
+                .entry(schema.id)
+                .or_default()
+                .push(collection.clone());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_delta<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+        delta: DocumentDelta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let mut pending = self.pending.lock().await;
+            let (_, changes) = pending
+                .entry((schema.id, collection.id))
+                .or_insert_with(|| (collection.name.to_string(), Changes::new()));
+
+            for document in delta.upserted {
+                changes.insert(document.id, Some(document));
+            }
+            for id in delta.deleted {
+                changes.insert(id, None);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let start_time = Instant::now();
+            let _guard = self.io_lock.lock().await;
+            self.do_save_collections(logger).await?;
+            self.do_save_documents(logger).await?;
+            info!(logger, "\u{1f4bf} Saved data to S3"; "save_time" => format!("{:#?}", Instant::now().duration_since(start_time)));
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn compact<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<CompactionReport, Error>> {
+        async move {
+            let start_time = Instant::now();
+            let _guard = self.io_lock.lock().await;
+            let mut report = CompactionReport::default();
+
+            let mut collections = BTreeSet::new();
+            for key in self.storage.list(&self.prefix).await? {
+                let parts: Vec<_> = key[self.prefix.len()..].split('/').collect();
+                if let [schema, docs, _] = parts.as_slice() {
+                    if let (Some(schema_id), Some(collection_id)) =
+                        (schema_folder_id(schema), docs_folder_id(docs))
+                    {
+                        collections.insert((schema_id, collection_id));
+                    }
+                }
+            }
+
+            for (schema_id, collection_id) in collections {
+                let collection_report = self
+                    .compact_collection(logger, schema_id, collection_id)
+                    .await?;
+                if collection_report.files_after != collection_report.files_before {
+                    debug!(logger, "Compacted collection"; "schema_id" => schema_id.to_string(), "collection_id" => collection_id.to_string(), "files_before" => collection_report.files_before, "files_after" => collection_report.files_after);
+                }
+                report.add(&collection_report);
+            }
+
+            info!(logger, "\u{1f9f9} Compacted chunk objects"; "files_before" => report.files_before, "files_after" => report.files_after, "compaction_time" => format!("{:#?}", Instant::now().duration_since(start_time)));
+            Ok(report)
+        }
+        .boxed()
+    }
+
+    fn get_webhooks<'a>(
+        &'a self,
+        logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<Vec<Webhook>, Error>> {
+        async move {
+            match self.get_json(&self.key("webhooks.json")).await? {
+                Some(webhooks) => Ok(webhooks),
+                None => {
+                    debug!(logger, "No webhooks object detected");
+                    Ok(vec![])
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn save_webhooks<'a>(
+        &'a self,
+        logger: &'a Logger,
+        webhooks: &'a [Webhook],
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.put_json(&self.key("webhooks.json"), &webhooks).await?;
+            debug!(logger, "Saved {} webhooks", webhooks.len());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_dead_letter<'a>(
+        &'a self,
+        logger: &'a Logger,
+        dead_letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let key = self.key(&format!("dead_letters/{}.json", dead_letter.delivery_id));
+            self.put_json(&key, dead_letter).await?;
+
+            debug!(logger, "Wrote dead letter to bucket"; "webhook_id" => dead_letter.webhook_id.to_string(), "delivery_id" => dead_letter.delivery_id.to_string());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+        versions: &'a [DocumentVersion],
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            // Objects can not be appended to, so every save gets an object of
+            // its own for each document in it
+            let mut by_document: BTreeMap<Uuid, String> = BTreeMap::new();
+            for version in versions {
+                let data = by_document.entry(version.document_id).or_default();
+                data.push_str(&serde_json::to_string(&version)?);
+                data.push('\n');
+            }
+
+            let object_name = Uuid::new_v4();
+            for (document_id, data) in by_document {
+                let key = format!(
+                    "{}{}.log",
+                    self.history_key(schema.id, collection.id, Some(document_id)),
+                    object_name
+                );
+                self.storage.put(&key, data.into_bytes()).await?;
+            }
+
+            trace!(logger, "Wrote {} versions to history", versions.len(); "collection_name" => &collection.name);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+        id: Uuid,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>> {
+        async move {
+            let prefix = self.history_key(schema.id, collection.id, Some(id));
+            self.read_history(logger, &prefix).await
+        }
+        .boxed()
+    }
+
+    fn get_collection_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>> {
+        async move {
+            let prefix = self.history_key(schema.id, collection.id, None);
+            self.read_history(logger, &prefix).await
+        }
+        .boxed()
+    }
+
+    fn append_wal<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let data = serde_json::to_vec(transaction)?;
+
+            // The lock is held during the upload so that entries are numbered
+            // in the order they are acknowledged
+            let mut position = self.wal.lock().await;
+            let key = self.key(&format!(
+                "wal/{:020}/{:020}.json",
+                position.segment, position.next
+            ));
+            self.storage.put(&key, data).await?;
+            position.next += 1;
+
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn read_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<Vec<Transaction>, Error>> {
+        async move {
+            let mut transactions = vec![];
+            for key in self.storage.list(&self.key("wal/")).await? {
+                let data = match self.storage.get(&key).await? {
+                    Some(data) => data,
+                    None => continue,
+                };
+                match serde_json::from_slice::<Transaction>(&data) {
+                    Ok(transaction) => transactions.push(transaction),
+                    Err(e) => {
+                        warn!(logger, "Skipping unreadable write-ahead log entry"; "key" => &key, "error" => format!("{}", e));
+                    }
+                }
+            }
+
+            Ok(transactions)
+        }
+        .boxed()
+    }
+
+    fn rotate_wal<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<u64, Error>> {
+        async move {
+            let mut position = self.wal.lock().await;
+            let closed = position.segment;
+            *position = WalPosition {
+                segment: closed + 1,
+                next: 0,
+            };
+
+            debug!(logger, "Rotated write-ahead log"; "closed_segment" => closed);
+            Ok(closed)
+        }
+        .boxed()
+    }
+
+    fn truncate_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+        segment: u64,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let wal_prefix = self.key("wal/");
+            for key in self.storage.list(&wal_prefix).await? {
+                match wal_segment(&wal_prefix, &key) {
+                    Some(id) if id <= segment => {
+                        self.storage.delete(&key).await?;
+                    }
+                    _ => {}
+                }
+            }
+
+            trace!(logger, "Removed write-ahead log segments"; "segment" => segment);
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        MemoryBucket,
+        S3Store,
+    };
+    use chrono::Utc;
+    use shelf_config::Config;
+    use shelf_database::{
+        test::StoreFixture,
+        Document,
+        DocumentVersion,
+        Store,
+        Transaction,
+    };
+    use shelf_file_store::{
+        chunk_file_name,
+        compute_hash_sum,
+        encode_chunk,
+        ChunkCodec,
+        ChunkStorage,
+    };
+    use std::{
+        collections::HashMap,
+        ops::Deref,
+        sync::Arc,
+    };
+    use uuid::Uuid;
+
+    /// A bucket that outlives the stores opened on it
+    struct Setup {
+        fixture: StoreFixture,
+        config: Config,
+        bucket: Arc<MemoryBucket>,
+    }
+
+    impl Setup {
+        async fn new() -> (Self, S3Store<Arc<MemoryBucket>>) {
+            let setup = Self {
+                fixture: StoreFixture::new(),
+                config: Config::default(),
+                bucket: Arc::new(MemoryBucket::new()),
+            };
+            let store = setup.open().await;
+            setup.create(&store).await;
+            store.flush(&setup.logger).await.unwrap();
+            (setup, store)
+        }
+
+        async fn open(&self) -> S3Store<Arc<MemoryBucket>> {
+            S3Store::with_storage(&self.logger, &self.config, Arc::clone(&self.bucket))
+                .await
+                .unwrap()
+        }
+
+        async fn chunks(&self, store: &S3Store<Arc<MemoryBucket>>) -> Vec<usize> {
+            let mut chunks: Vec<usize> = store
+                .chunk_objects(self.schema.id, self.collection.id)
+                .list()
+                .await
+                .unwrap()
+                .iter()
+                .map(|i| i.split('_').next().unwrap().parse().unwrap())
+                .collect();
+            chunks.sort_unstable();
+            chunks
+        }
+
+        /// Writes a chunk object by hand, like a flush that crashed before it
+        /// could merge would leave it
+        async fn write_chunk(
+            &self,
+            store: &S3Store<Arc<MemoryBucket>>,
+            chunk: usize,
+            documents: Vec<Arc<Document>>,
+        ) {
+            let codec = ChunkCodec::from_config(&self.config);
+            let data = encode_chunk(codec, documents).await.unwrap();
+            let name = chunk_file_name(chunk, compute_hash_sum(&data));
+            store
+                .chunk_objects(self.schema.id, self.collection.id)
+                .write(&name, data, None)
+                .await
+                .unwrap();
+        }
+    }
+
+    impl Deref for Setup {
+        type Target = StoreFixture;
+
+        fn deref(&self) -> &StoreFixture {
+            &self.fixture
+        }
+    }
+
+    /// Documents in the left and right half of the chunk tree, that is in
+    /// chunk 2 and chunk 3
+    fn halves(count: u128) -> (Vec<Arc<Document>>, Vec<Arc<Document>>) {
+        let document = |id| Arc::new(Document::new(Uuid::from_u128(id), HashMap::new()));
+        (
+            (1..=count).map(document).collect(),
+            (1..=count).map(|i| document(i | (1 << 127))).collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn unchanged_chunks_should_not_be_uploaded_again() {
+        let (setup, store) = Setup::new().await;
+        let document = Arc::new(Document::new(Uuid::new_v4(), HashMap::new()));
+
+        setup
+            .save_and_flush(&store, vec![Arc::clone(&document)], vec![])
+            .await;
+        let uploaded = setup.bucket.put_count();
+
+        setup
+            .save_and_flush(&store, vec![Arc::clone(&document)], vec![])
+            .await;
+
+        assert_eq!(setup.bucket.put_count(), uploaded);
+        let documents = setup.documents(&store).await;
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].id, document.id);
+    }
+
+    #[tokio::test]
+    async fn get_documents_should_keep_the_newest_revision_of_overlapping_chunks() {
+        let (setup, store) = Setup::new().await;
+
+        // Left behind by a crash in the middle of a split
+        let (left, _) = halves(2);
+        let mut newer = Document::clone(&left[0]);
+        newer.revision = 2;
+        setup.write_chunk(&store, 1, left.clone()).await;
+        setup
+            .write_chunk(&store, 2, vec![Arc::new(newer), Arc::clone(&left[1])])
+            .await;
+        let store = setup.open().await;
+
+        let documents = setup.documents(&store).await;
+
+        assert_eq!(documents.len(), 2);
+        let first = documents.iter().find(|i| i.id == left[0].id).unwrap();
+        assert_eq!(first.revision, 2);
+    }
+
+    #[tokio::test]
+    async fn compact_should_merge_small_sibling_chunks() {
+        let (setup, store) = Setup::new().await;
+        let (left, right) = halves(3);
+        setup.write_chunk(&store, 2, left).await;
+        setup.write_chunk(&store, 3, right).await;
+        let store = setup.open().await;
+
+        let report = store.compact(&setup.logger).await.unwrap();
+
+        assert_eq!(report.collections, 1);
+        assert_eq!(report.files_before, 2);
+        assert_eq!(report.files_after, 1);
+        assert_eq!(setup.chunks(&store).await, vec![1]);
+        assert_eq!(setup.documents(&store).await.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn history_should_be_read_per_document() {
+        let (setup, store) = Setup::new().await;
+        let document = Document::new(Uuid::new_v4(), HashMap::new());
+        let other = Document::new(Uuid::new_v4(), HashMap::new());
+        let version = |document: &Document, revision| DocumentVersion {
+            document_id: document.id,
+            revision,
+            timestamp: Utc::now(),
+            document: Some(document.clone()),
+        };
+
+        let versions = vec![version(&document, 1), version(&other, 1)];
+        store
+            .save_history(&setup.logger, &setup.schema, &setup.collection, &versions)
+            .await
+            .unwrap();
+        // Saved again, like a retried save would
+        let versions = vec![version(&document, 2), version(&document, 1)];
+        store
+            .save_history(&setup.logger, &setup.schema, &setup.collection, &versions)
+            .await
+            .unwrap();
+
+        let history = store
+            .get_history(&setup.logger, &setup.schema, &setup.collection, document.id)
+            .await
+            .unwrap();
+        let revisions: Vec<_> = history.iter().map(|i| i.revision).collect();
+        assert_eq!(revisions, vec![1, 2]);
+        assert!(history.iter().all(|i| i.document_id == document.id));
+
+        let history = store
+            .get_collection_history(&setup.logger, &setup.schema, &setup.collection)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn wal_should_survive_a_restart_and_be_truncated_by_segment() {
+        let (setup, store) = Setup::new().await;
+        let first = Transaction::new();
+        let second = Transaction::new();
+        store.append_wal(&setup.logger, &first).await.unwrap();
+        store.rotate_wal(&setup.logger).await.unwrap();
+        store.append_wal(&setup.logger, &second).await.unwrap();
+        drop(store);
+
+        let store = setup.open().await;
+        let ids: Vec<_> = store
+            .read_wal(&setup.logger)
+            .await
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(ids, vec![first.id, second.id]);
+
+        // A reopened store continues in a new segment
+        let closed = store.rotate_wal(&setup.logger).await.unwrap();
+        let third = Transaction::new();
+        store.append_wal(&setup.logger, &third).await.unwrap();
+        store.truncate_wal(&setup.logger, closed).await.unwrap();
+        let ids: Vec<_> = store
+            .read_wal(&setup.logger)
+            .await
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(ids, vec![third.id]);
+    }
+}
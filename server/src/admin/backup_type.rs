@@ -0,0 +1,30 @@
+use chrono::{
+    DateTime,
+    Utc,
+};
+use shelf_database::Snapshot;
+use std::convert::TryFrom;
+
+#[derive(GraphQLObject)]
+#[graphql(name = "Backup")]
+pub struct BackupType {
+    /// Where the backup was written, on the machine running the server
+    pub path: String,
+    /// The point in time the backup captures
+    pub created_at: DateTime<Utc>,
+    pub schemas: i32,
+    pub collections: i32,
+    pub documents: i32,
+}
+
+impl BackupType {
+    pub fn new(path: String, snapshot: &Snapshot) -> Self {
+        Self {
+            path,
+            created_at: snapshot.created_at,
+            schemas: i32::try_from(snapshot.schemas.len()).unwrap_or(i32::max_value()),
+            collections: i32::try_from(snapshot.collection_count()).unwrap_or(i32::max_value()),
+            documents: i32::try_from(snapshot.document_count()).unwrap_or(i32::max_value()),
+        }
+    }
+}
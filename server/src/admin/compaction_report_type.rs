@@ -0,0 +1,23 @@
+use shelf_database::CompactionReport;
+use std::convert::TryFrom;
+
+#[derive(GraphQLObject)]
+#[graphql(name = "CompactionReport")]
+pub struct CompactionReportType {
+    /// The number of collections that were compacted
+    pub collections: i32,
+    /// The number of chunk files before the compaction
+    pub files_before: i32,
+    /// The number of chunk files after the compaction
+    pub files_after: i32,
+}
+
+impl From<CompactionReport> for CompactionReportType {
+    fn from(value: CompactionReport) -> Self {
+        Self {
+            collections: i32::try_from(value.collections).unwrap_or(i32::max_value()),
+            files_before: i32::try_from(value.files_before).unwrap_or(i32::max_value()),
+            files_after: i32::try_from(value.files_after).unwrap_or(i32::max_value()),
+        }
+    }
+}
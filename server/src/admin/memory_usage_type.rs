@@ -0,0 +1,81 @@
+use shelf_database::{
+    CollectionMemoryUsage,
+    MemoryUsage,
+    SchemaMemoryUsage,
+};
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+#[derive(GraphQLObject)]
+#[graphql(name = "MemoryUsage")]
+pub struct MemoryUsageType {
+    /// The estimated size of the cache in bytes. A float since it can be
+    /// larger than a GraphQL int
+    pub size: f64,
+    /// The largest schemas first
+    pub schemas: Vec<SchemaMemoryUsageType>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(name = "SchemaMemoryUsage")]
+pub struct SchemaMemoryUsageType {
+    pub id: Uuid,
+    pub name: String,
+    /// In bytes
+    pub size: f64,
+    /// The largest collections first
+    pub collections: Vec<CollectionMemoryUsageType>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(name = "CollectionMemoryUsage")]
+pub struct CollectionMemoryUsageType {
+    pub id: Uuid,
+    pub name: String,
+    pub documents: i32,
+    /// In bytes
+    pub size: f64,
+    /// Evicted collections only keep the ids of their documents in memory,
+    /// the documents are read back in when the collection is used
+    pub evicted: bool,
+}
+
+impl From<MemoryUsage> for MemoryUsageType {
+    fn from(value: MemoryUsage) -> Self {
+        Self {
+            size: value.size as f64,
+            schemas: value
+                .schemas
+                .into_iter()
+                .map(SchemaMemoryUsageType::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<SchemaMemoryUsage> for SchemaMemoryUsageType {
+    fn from(value: SchemaMemoryUsage) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            size: value.size as f64,
+            collections: value
+                .collections
+                .into_iter()
+                .map(CollectionMemoryUsageType::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<CollectionMemoryUsage> for CollectionMemoryUsageType {
+    fn from(value: CollectionMemoryUsage) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            documents: i32::try_from(value.documents).unwrap_or(i32::max_value()),
+            size: value.size as f64,
+            evicted: value.evicted,
+        }
+    }
+}
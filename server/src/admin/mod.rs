@@ -1,8 +1,14 @@
+mod backup_type;
+mod compaction_report_type;
+mod memory_usage_type;
 mod mutation;
 mod query;
 mod schema;
 mod schema_input;
 mod schema_type;
+mod webhook_event_type;
+mod webhook_input;
+mod webhook_type;
 
 pub use self::{
     mutation::Mutation,
@@ -1,17 +1,33 @@
 use crate::{
     admin::{
+        backup_type::BackupType,
+        compaction_report_type::CompactionReportType,
         schema_input::SchemaInput,
         schema_type::SchemaType,
+        webhook_input::WebhookInput,
+        webhook_type::WebhookType,
     },
     context::Context,
 };
+use hyper::Uri;
 use juniper::FieldResult;
 use shelf_database::{
     Cache,
+    CacheCollection,
+    CacheSchema,
     Schema,
     Store,
+    Webhook,
 };
-use std::marker::PhantomData;
+use std::{
+    marker::PhantomData,
+    path::{
+        Component,
+        Path,
+        PathBuf,
+    },
+};
+use uuid::Uuid;
 
 pub struct Mutation<C: Cache, S: Store> {
     phantom_cache: PhantomData<C>,
@@ -36,7 +52,6 @@ impl<C: Cache, S: Store> Mutation<C, S> {
 
         context
             .db
-            .cache()
             .insert_schema(&context.logger, schema, "")
             .await?;
 
@@ -59,4 +74,151 @@ impl<C: Cache, S: Store> Mutation<C, S> {
     ) -> FieldResult<bool> {
         Ok(true)
     }
+
+    #[graphql(
+        description = "Registers a webhook that gets notified when documents are inserted, updated or deleted"
+    )]
+    async fn register_webhook(
+        context: &Context<C, S>,
+        input: WebhookInput,
+    ) -> FieldResult<WebhookType> {
+        let uri = input.url.parse::<Uri>()?;
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            _ => return Err("The webhook url has to be a http or https url".into()),
+        }
+
+        let schema = match &input.schema_name {
+            Some(schema_name) => Some(
+                context
+                    .db
+                    .schema_by_name(schema_name)
+                    .await
+                    .ok_or_else(|| format!("The schema \"{}\" does not exist", schema_name))?,
+            ),
+            None => None,
+        };
+
+        let collection_id = match (&schema, &input.collection_name) {
+            (Some(schema), Some(collection_name)) => Some(
+                schema
+                    .collection_by_name(collection_name)
+                    .await
+                    .ok_or_else(|| {
+                        format!("The collection \"{}\" does not exist", collection_name)
+                    })?
+                    .inner_collection()
+                    .await
+                    .id,
+            ),
+            (None, Some(_)) => {
+                return Err("A collection can only be selected together with a schema".into())
+            }
+            _ => None,
+        };
+
+        let schema_id = match &schema {
+            Some(schema) => Some(schema.inner_schema().await.id),
+            None => None,
+        };
+
+        let webhook = Webhook::new(
+            input.url,
+            input.secret,
+            schema_id,
+            collection_id,
+            input
+                .events
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        );
+
+        context
+            .db
+            .register_webhook(&context.logger, webhook.clone())
+            .await?;
+
+        info!(context.logger, "Registered webhook"; "webhook_id" => webhook.id.to_string());
+
+        Ok(WebhookType::from(webhook))
+    }
+
+    #[graphql(description = "Removes a webhook, returns false if it did not exist")]
+    async fn remove_webhook(context: &Context<C, S>, id: Uuid) -> FieldResult<bool> {
+        Ok(context.db.remove_webhook(&context.logger, id).await?)
+    }
+
+    #[graphql(
+        description = "Compacts the stored data right away instead of waiting for the next scheduled compaction"
+    )]
+    async fn compact(context: &Context<C, S>) -> FieldResult<CompactionReportType> {
+        let report = context.db.compact(&context.logger).await?;
+        Ok(CompactionReportType::from(report))
+    }
+
+    #[graphql(
+        description = "Writes a consistent copy of all data to the backup folder of the server while it keeps running. Names ending with .tar, .tar.gz or .tgz give an archive, anything else a folder"
+    )]
+    async fn backup(context: &Context<C, S>, name: String) -> FieldResult<BackupType> {
+        // Without a token anyone could fill up the disk of the server
+        if context.config.admin_token.is_none() {
+            return Err("Backups can only be requested when an admin token is configured".into());
+        }
+        if !context.admin {
+            return Err("Backups can only be requested with the admin token, as `Authorization: Bearer <token>`".into());
+        }
+        let folder = match &context.config.backup_folder {
+            Some(folder) => Path::new(folder),
+            None => {
+                return Err(
+                    "Backups can only be requested when a backup folder is configured".into(),
+                )
+            }
+        };
+
+        let path = backup_path(folder, &name)?;
+        let snapshot = context.db.backup(&context.logger, &path).await?;
+        Ok(BackupType::new(
+            path.to_string_lossy().to_string(),
+            &snapshot,
+        ))
+    }
+}
+
+/// The path of a backup in the backup folder. The name has to be a plain file
+/// or folder name, so that nothing is written outside the folder
+fn backup_path(folder: &Path, name: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(folder.join(name)),
+        _ => Err(format!(
+            "\"{}\" is not a valid backup name, it can not contain a path",
+            name
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::admin::mutation::backup_path;
+    use std::path::Path;
+
+    #[test]
+    fn backups_should_stay_in_the_backup_folder() {
+        let folder = Path::new("/backups");
+
+        assert_eq!(
+            backup_path(folder, "monday.tar.gz").unwrap(),
+            folder.join("monday.tar.gz")
+        );
+        for name in &["", ".", "..", "../etc", "/etc/passwd", "a/b", "a/../.."] {
+            assert!(
+                backup_path(folder, name).is_err(),
+                "{} should be rejected",
+                name
+            );
+        }
+    }
 }
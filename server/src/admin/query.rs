@@ -1,5 +1,9 @@
 use crate::{
-    admin::schema_type::SchemaType,
+    admin::{
+        memory_usage_type::MemoryUsageType,
+        schema_type::SchemaType,
+        webhook_type::WebhookType,
+    },
     context::Context,
 };
 use futures::StreamExt;
@@ -41,4 +45,22 @@ impl<C: Cache, S: Store> Query<C, S> {
             .collect()
             .await)
     }
+
+    #[graphql(
+        description = "Returns how much memory the cache takes up, broken down by schema and collection"
+    )]
+    async fn memory_usage(context: &Context<C, S>) -> FieldResult<MemoryUsageType> {
+        Ok(MemoryUsageType::from(context.db.memory_usage().await))
+    }
+
+    #[graphql(description = "Returns all registered webhooks")]
+    async fn webhooks(context: &Context<C, S>) -> FieldResult<Vec<WebhookType>> {
+        Ok(context
+            .db
+            .webhooks()
+            .await
+            .into_iter()
+            .map(WebhookType::from)
+            .collect())
+    }
 }
@@ -0,0 +1,29 @@
+use shelf_database::WebhookEvent;
+
+#[derive(GraphQLEnum, Clone, Copy)]
+#[graphql(name = "WebhookEvent")]
+pub enum WebhookEventType {
+    DocumentInserted,
+    DocumentUpdated,
+    DocumentDeleted,
+}
+
+impl From<WebhookEvent> for WebhookEventType {
+    fn from(value: WebhookEvent) -> Self {
+        match value {
+            WebhookEvent::DocumentInserted => WebhookEventType::DocumentInserted,
+            WebhookEvent::DocumentUpdated => WebhookEventType::DocumentUpdated,
+            WebhookEvent::DocumentDeleted => WebhookEventType::DocumentDeleted,
+        }
+    }
+}
+
+impl From<WebhookEventType> for WebhookEvent {
+    fn from(value: WebhookEventType) -> Self {
+        match value {
+            WebhookEventType::DocumentInserted => WebhookEvent::DocumentInserted,
+            WebhookEventType::DocumentUpdated => WebhookEvent::DocumentUpdated,
+            WebhookEventType::DocumentDeleted => WebhookEvent::DocumentDeleted,
+        }
+    }
+}
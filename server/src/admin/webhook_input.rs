@@ -0,0 +1,10 @@
+use crate::admin::webhook_event_type::WebhookEventType;
+
+#[derive(GraphQLInputObject)]
+pub struct WebhookInput {
+    pub url: String,
+    pub secret: String,
+    pub schema_name: Option<String>,
+    pub collection_name: Option<String>,
+    pub events: Option<Vec<WebhookEventType>>,
+}
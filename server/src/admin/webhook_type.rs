@@ -0,0 +1,62 @@
+use crate::admin::webhook_event_type::WebhookEventType;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use juniper::FieldResult;
+use shelf_database::Webhook;
+use uuid::Uuid;
+
+/// The secret is left out on purpose, it can only be set, never read
+pub struct WebhookType {
+    id: Uuid,
+    url: String,
+    schema_id: Option<Uuid>,
+    collection_id: Option<Uuid>,
+    events: Vec<WebhookEventType>,
+    created_at: DateTime<Utc>,
+}
+
+#[juniper::graphql_object(name = "Webhook")]
+impl WebhookType {
+    fn id(&self) -> FieldResult<&Uuid> {
+        Ok(&self.id)
+    }
+
+    fn url(&self) -> FieldResult<&String> {
+        Ok(&self.url)
+    }
+
+    fn schema_id(&self) -> FieldResult<&Option<Uuid>> {
+        Ok(&self.schema_id)
+    }
+
+    fn collection_id(&self) -> FieldResult<&Option<Uuid>> {
+        Ok(&self.collection_id)
+    }
+
+    fn events(&self) -> FieldResult<&Vec<WebhookEventType>> {
+        Ok(&self.events)
+    }
+
+    fn created_at(&self) -> FieldResult<&DateTime<Utc>> {
+        Ok(&self.created_at)
+    }
+}
+
+impl From<Webhook> for WebhookType {
+    fn from(value: Webhook) -> Self {
+        Self {
+            id: value.id,
+            url: value.url,
+            schema_id: value.schema_id,
+            collection_id: value.collection_id,
+            events: value
+                .events
+                .into_iter()
+                .map(WebhookEventType::from)
+                .collect(),
+            created_at: value.created_at,
+        }
+    }
+}
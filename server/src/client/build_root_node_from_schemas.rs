@@ -28,9 +28,9 @@ pub async fn build_root_node_from_schemas<'a, C: Cache, S: Store>(
             let name = inner_schema.name.to_string();
             let node = Arc::new(RootNode::new_with_info(
                 Query::new(i.clone()),
-                Mutation::new(),
+                Mutation::new(i.clone()),
+                DbSchema::clone(&inner_schema),
                 DbSchema::clone(&inner_schema),
-                (),
             ));
             (name, node)
         })
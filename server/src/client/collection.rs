@@ -1,4 +1,8 @@
 use crate::context::Context;
+use chrono::{
+    DateTime,
+    Utc,
+};
 use graphql_parser::schema::Type as GType;
 use juniper::{
     meta::{
@@ -77,6 +81,28 @@ impl<C: Cache, S: Store> GraphQLType for Collection<C, S> {
             }
         }
 
+        fields.push(Field {
+            name: "revision".to_string(),
+            description: Some("\"The revision of the document, it is bumped every time the document is written. Pass it as expectedRevision to updates and deletes to avoid overwriting changes made by someone else. A float since it can be larger than a GraphQL int\"".to_string()),
+            arguments: None,
+            field_type: registry.get_type::<f64>(&()),
+            deprecation_status: DeprecationStatus::Current,
+        });
+        fields.push(Field {
+            name: "createdAt".to_string(),
+            description: Some("\"When the document was first inserted\"".to_string()),
+            arguments: None,
+            field_type: registry.get_type::<DateTime<Utc>>(&()),
+            deprecation_status: DeprecationStatus::Current,
+        });
+        fields.push(Field {
+            name: "updatedAt".to_string(),
+            description: Some("\"When the document was last written\"".to_string()),
+            arguments: None,
+            field_type: registry.get_type::<DateTime<Utc>>(&()),
+            deprecation_status: DeprecationStatus::Current,
+        });
+
         let meta_object = registry
             .build_object_type::<Collection<C, S>>(&info, &fields)
             .into_meta();
@@ -95,8 +121,12 @@ impl<C: Cache, S: Store> GraphQLType for Collection<C, S> {
         _args: &Arguments,
         executor: &Executor<Self::Context>,
     ) -> ExecutionResult {
-        if field_name == "id" {
-            return executor.resolve_with_ctx(&(), &self.document.id);
+        match field_name {
+            "id" => return executor.resolve_with_ctx(&(), &self.document.id),
+            "revision" => return executor.resolve_with_ctx(&(), &(self.document.revision as f64)),
+            "createdAt" => return executor.resolve_with_ctx(&(), &self.document.created_at),
+            "updatedAt" => return executor.resolve_with_ctx(&(), &self.document.updated_at),
+            _ => {}
         }
 
         if let Some(types) = info.1.types() {
@@ -5,6 +5,7 @@ use crate::{
     },
     context::Context,
 };
+use failure::Error;
 use futures::{
     future::BoxFuture,
     FutureExt,
@@ -36,9 +37,9 @@ pub struct Connection<C: Cache, S: Store> {
 }
 
 impl<C: Cache, S: Store> Connection<C, S> {
-    pub async fn new<Ca: CacheCollection>(cache: Ca) -> Connection<C, S> {
+    pub async fn new<Ca: CacheCollection>(cache: Ca) -> Result<Connection<C, S>, Error> {
         let edges: Vec<Edge<C, S>> = {
-            let docs = cache.documents().await;
+            let docs = cache.documents().await?;
             let stream = docs.stream();
             stream.take(100).then(Edge::new).collect().await
         };
@@ -46,7 +47,7 @@ impl<C: Cache, S: Store> Connection<C, S> {
         // let edges: Vec<Edge<C, S>> = ;
         // let total = docs.total();
 
-        Self {
+        Ok(Self {
             edges,
             page_info: PageInfo {
                 has_next_page: false,
@@ -56,7 +57,7 @@ impl<C: Cache, S: Store> Connection<C, S> {
             },
             // TODO: We need to get total count without exhausting the stream
             total_count: 0 as i32,
-        }
+        })
     }
 }
 
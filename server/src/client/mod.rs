@@ -3,12 +3,12 @@ mod collection;
 mod connection;
 mod edge;
 mod mutation;
+mod mutation_field;
 mod node;
 mod page_info;
 mod query;
 mod query_field;
 mod schema;
-// mod mutation_fields;
 
 pub use self::{
     build_root_node_from_schemas::build_root_node_from_schemas,
@@ -1,27 +1,361 @@
-use crate::context::Context;
+use crate::{
+    client::{
+        collection::Collection,
+        mutation_field::MutationField,
+        query::Query,
+    },
+    context::Context,
+};
+use failure::Error;
+use futures::FutureExt;
+use graphql_parser::schema::Type as GType;
+use juniper::{
+    meta::MetaType,
+    Arguments,
+    BoxFuture,
+    DefaultScalarValue,
+    ExecutionResult,
+    Executor,
+    FieldError,
+    GraphQLType,
+    GraphQLTypeAsync,
+    Registry,
+};
+use serde_json::Value;
 use shelf_database::{
+    validate_document,
     Cache,
+    CacheCollection,
+    CacheSchema,
+    Document,
+    RevisionConflict,
+    Schema as DbSchema,
     Store,
+    TransactionWrite,
 };
-use std::marker::PhantomData;
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::Arc,
+};
+use uuid::Uuid;
+
+type CollectionOf<C> = <<C as Cache>::CacheSchema as CacheSchema>::CacheCollection;
 
 pub struct Mutation<C: Cache, S: Store> {
     phantom_cache: PhantomData<C>,
     phantom_store: PhantomData<S>,
+    schema: C::CacheSchema,
 }
 
 impl<C: Cache, S: Store> Mutation<C, S> {
-    pub fn new() -> Self {
+    pub fn new(schema: C::CacheSchema) -> Self {
         Self {
             phantom_cache: PhantomData,
             phantom_store: PhantomData,
+            schema,
+        }
+    }
+
+    async fn resolve_create(
+        &self,
+        info: &DbSchema,
+        context: &Context<C, S>,
+        arguments: &Arguments<'_>,
+        executor: &Executor<'_, Context<C, S>>,
+        coll_name: &str,
+    ) -> ExecutionResult {
+        let (coll, collection_id) = self.unwrap_collection(coll_name).await?;
+        let id = arguments.get::<Uuid>("id").unwrap_or_else(Uuid::new_v4);
+
+        if Self::current_document(context, &coll, collection_id, id)
+            .await?
+            .is_some()
+        {
+            return Err(FieldError::new(
+                "Document already exists",
+                graphql_value!({ "conflict": "A document with this id already exists" }),
+            ));
+        }
+
+        let mut fields = HashMap::new();
+        Self::read_arguments(info, coll_name, arguments, &mut fields);
+        let mut document = Document::new(id, fields);
+        validate_document(info, coll_name, &document)?;
+
+        context
+            .transaction
+            .lock()
+            .await
+            .set(info.id, collection_id, document.clone(), None);
+
+        // Stamped the way the commit stamps it, so that the response carries
+        // the revision the document is stored with
+        document.revise(None);
+
+        executor.resolve_with_ctx(
+            &(coll_name.to_string(), info.clone()),
+            &Collection::new(Arc::new(document)),
+        )
+    }
+
+    async fn resolve_update(
+        &self,
+        info: &DbSchema,
+        context: &Context<C, S>,
+        arguments: &Arguments<'_>,
+        executor: &Executor<'_, Context<C, S>>,
+        coll_name: &str,
+    ) -> ExecutionResult {
+        let id = Self::required_id(arguments)?;
+        let expected_revision = Self::expected_revision(arguments)?;
+        let (coll, collection_id) = self.unwrap_collection(coll_name).await?;
+
+        let current = match Self::current_document(context, &coll, collection_id, id).await? {
+            Some(doc) => doc,
+            None => return Err(Self::missing_document()),
+        };
+        // The revision is checked here to fail early, it is checked again when
+        // the transaction is committed since someone might write in between
+        RevisionConflict::check(id, expected_revision, Some(current.revision))
+            .map_err(Self::conflict)?;
+        let mut document = current.clone();
+        Self::read_arguments(info, coll_name, arguments, &mut document.fields);
+        validate_document(info, coll_name, &document)?;
+
+        context.transaction.lock().await.set(
+            info.id,
+            collection_id,
+            document.clone(),
+            expected_revision,
+        );
+
+        document.revise(Some(&current));
+
+        executor.resolve_with_ctx(
+            &(coll_name.to_string(), info.clone()),
+            &Collection::new(Arc::new(document)),
+        )
+    }
+
+    async fn resolve_delete(
+        &self,
+        info: &DbSchema,
+        context: &Context<C, S>,
+        arguments: &Arguments<'_>,
+        executor: &Executor<'_, Context<C, S>>,
+        coll_name: &str,
+    ) -> ExecutionResult {
+        let id = Self::required_id(arguments)?;
+        let expected_revision = Self::expected_revision(arguments)?;
+        let (coll, collection_id) = self.unwrap_collection(coll_name).await?;
+
+        let document = match Self::current_document(context, &coll, collection_id, id).await? {
+            Some(doc) => doc,
+            None => return Err(Self::missing_document()),
+        };
+        RevisionConflict::check(id, expected_revision, Some(document.revision))
+            .map_err(Self::conflict)?;
+
+        context
+            .transaction
+            .lock()
+            .await
+            .delete(info.id, collection_id, id, expected_revision);
+
+        executor.resolve_with_ctx(&(), &true)
+    }
+
+    async fn unwrap_collection(
+        &self,
+        coll_name: &str,
+    ) -> Result<(CollectionOf<C>, Uuid), FieldError> {
+        match self.schema.collection_by_name(coll_name).await {
+            Some(coll) => {
+                let collection_id = coll.inner_collection().await.id;
+                Ok((coll, collection_id))
+            }
+            None => Err(FieldError::new(
+                "Missing collection",
+                graphql_value!({ "missing_collection": "This should not happen, collection was missing, perhaps deleted?" }),
+            )),
+        }
+    }
+
+    /// Returns the document as this request sees it, with the writes staged
+    /// earlier in the same request applied on top of the cache. A staged
+    /// document carries the revision it will get once committed, the same
+    /// revision the commit checks later writes to it against
+    async fn current_document(
+        context: &Context<C, S>,
+        coll: &CollectionOf<C>,
+        collection_id: Uuid,
+        id: Uuid,
+    ) -> Result<Option<Document>, Error> {
+        let staged = context
+            .transaction
+            .lock()
+            .await
+            .staged(collection_id, id)
+            .cloned();
+
+        match staged {
+            Some(TransactionWrite::Set { mut document, .. }) => {
+                document.revision += 1;
+                Ok(Some(document))
+            }
+            Some(TransactionWrite::Delete { .. }) => Ok(None),
+            None => Ok(coll.document(id).await?.map(|doc| Document::clone(&doc))),
+        }
+    }
+
+    fn required_id(arguments: &Arguments<'_>) -> Result<Uuid, FieldError> {
+        arguments.get::<Uuid>("id").ok_or_else(|| {
+            FieldError::new(
+                "Id has to be provided",
+                graphql_value!({ "missing_argument": "Argument was missing" }),
+            )
+        })
+    }
+
+    /// Revisions are floats since they can be larger than a GraphQL int, so
+    /// anything but a whole number of zero or more is turned away. So are
+    /// numbers of 2^53 and above, since a float can not tell them apart
+    fn expected_revision(arguments: &Arguments<'_>) -> Result<Option<u64>, FieldError> {
+        match arguments.get::<f64>("expectedRevision") {
+            None => Ok(None),
+            Some(revision) => revision_from_float(revision).map(Some).ok_or_else(|| {
+                FieldError::new(
+                    "Invalid expected revision",
+                    graphql_value!({ "invalid_argument": "expectedRevision has to be a whole number from 0 to 2^53 - 1" }),
+                )
+            }),
+        }
+    }
+
+    fn conflict(err: RevisionConflict) -> FieldError {
+        FieldError::new(
+            err,
+            graphql_value!({ "conflict": "The document has been changed by someone else" }),
+        )
+    }
+
+    fn missing_document() -> FieldError {
+        FieldError::new(
+            "Document not found",
+            graphql_value!({ "missing_document": "No document with this id exists" }),
+        )
+    }
+
+    /// Copies the provided arguments into the fields of a document
+    fn read_arguments(
+        info: &DbSchema,
+        coll_name: &str,
+        arguments: &Arguments<'_>,
+        fields: &mut HashMap<String, Value>,
+    ) {
+        let types = match info.types() {
+            Some(types) => types,
+            None => return,
+        };
+
+        if let Some(coll) = types.collections.iter().find(|i| i.name == coll_name) {
+            for field in coll.fields.iter().filter(|i| i.name != "id") {
+                let value = match Self::type_name(&field.field_type) {
+                    "Uuid" => arguments
+                        .get::<Uuid>(&field.name)
+                        .map(|i| Value::String(i.to_string())),
+                    "Int" | "i32" => arguments.get::<i32>(&field.name).map(Value::from),
+                    "Float" => arguments.get::<f64>(&field.name).map(Value::from),
+                    "Boolean" => arguments.get::<bool>(&field.name).map(Value::Bool),
+                    _ => arguments.get::<String>(&field.name).map(Value::String),
+                };
+
+                if let Some(value) = value {
+                    fields.insert(field.name.to_string(), value);
+                }
+            }
+        }
+    }
+
+    fn type_name(field_type: &GType) -> &str {
+        match field_type {
+            GType::NamedType(name) => name,
+            GType::NonNullType(inner) | GType::ListType(inner) => Self::type_name(inner),
         }
     }
 }
 
-#[juniper::graphql_object(Context = Context<C, S>)]
-impl<C: Cache, S: Store> Mutation<C, S> {
-    fn id() -> bool {
-        true
+/// The largest revision that can not be mistaken for another one once it is
+/// a float, 2^53 - 1
+const MAX_EXACT_REVISION: f64 = 9_007_199_254_740_991.0;
+
+fn revision_from_float(revision: f64) -> Option<u64> {
+    if (0.0..=MAX_EXACT_REVISION).contains(&revision) && revision.fract() <= 0.0 {
+        Some(revision as u64)
+    } else {
+        None
+    }
+}
+
+impl<C: Cache, S: Store> GraphQLType for Mutation<C, S> {
+    type Context = Context<C, S>;
+    type TypeInfo = DbSchema;
+
+    fn name(_info: &Self::TypeInfo) -> Option<&'static str> {
+        Some("Mutation")
+    }
+
+    fn meta<'r>(
+        info: &Self::TypeInfo,
+        registry: &mut Registry<'r, DefaultScalarValue>,
+    ) -> MetaType<'r, DefaultScalarValue>
+    where
+        DefaultScalarValue: 'r,
+    {
+        let collections = Query::<C, S>::map_collection_to_name_and_fields(info);
+        let fields = MutationField::fields::<C, S>(&info, registry, &collections);
+        registry
+            .build_object_type::<Mutation<C, S>>(&info, &fields)
+            .into_meta()
+    }
+}
+
+impl<C: Cache, S: Store> GraphQLTypeAsync<DefaultScalarValue> for Mutation<C, S> {
+    fn resolve_field_async<'a>(
+        &'a self,
+        info: &'a Self::TypeInfo,
+        field_name: &'a str,
+        arguments: &'a Arguments<DefaultScalarValue>,
+        executor: &'a Executor<Self::Context, DefaultScalarValue>,
+    ) -> BoxFuture<'a, ExecutionResult<DefaultScalarValue>> {
+        async move {
+            let context = executor.context();
+            let collections = Query::<C, S>::map_collection_to_name_and_fields(info);
+
+            let result = match MutationField::from_str(field_name, &collections) {
+                Ok(MutationField::Create { collection_name }) => {
+                    self.resolve_create(info, context, arguments, executor, &collection_name)
+                        .await
+                }
+                Ok(MutationField::Update { collection_name }) => {
+                    self.resolve_update(info, context, arguments, executor, &collection_name)
+                        .await
+                }
+                Ok(MutationField::Delete { collection_name }) => {
+                    self.resolve_delete(info, context, arguments, executor, &collection_name)
+                        .await
+                }
+                Err(err) => Err(err.into()),
+            };
+
+            // One failing mutation aborts every write staged by this request
+            if result.is_err() {
+                context.rollback();
+            }
+
+            result
+        }
+        .boxed()
     }
 }
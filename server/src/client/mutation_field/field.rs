@@ -0,0 +1,12 @@
+/// The mutations a user can do on every collection. All mutations in one
+/// request are executed as one transaction
+pub enum Field {
+    /// Inserts a new document, the id is generated unless it is provided
+    Create { collection_name: String },
+
+    /// Changes the provided fields of an existing document
+    Update { collection_name: String },
+
+    /// Removes a document
+    Delete { collection_name: String },
+}
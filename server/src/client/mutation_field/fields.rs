@@ -0,0 +1,44 @@
+use super::MutationField;
+use juniper::{
+    meta::Field,
+    DefaultScalarValue,
+    Registry,
+};
+use shelf_database::{
+    Cache,
+    Schema as DbSchema,
+    Store,
+};
+
+impl MutationField {
+    pub fn fields<'r, C: Cache, S: Store>(
+        info: &DbSchema,
+        registry: &mut Registry<'r, DefaultScalarValue>,
+        collections: &[(String, Vec<String>)],
+    ) -> Vec<Field<'r, DefaultScalarValue>> {
+        let mut fields = vec![];
+
+        for (collection_name, _) in collections {
+            fields.push(
+                MutationField::Create {
+                    collection_name: collection_name.to_string(),
+                }
+                .into_field::<C, S>(info, registry),
+            );
+            fields.push(
+                MutationField::Update {
+                    collection_name: collection_name.to_string(),
+                }
+                .into_field::<C, S>(info, registry),
+            );
+            fields.push(
+                MutationField::Delete {
+                    collection_name: collection_name.to_string(),
+                }
+                .into_field::<C, S>(info, registry),
+            );
+        }
+
+        fields
+    }
+}
@@ -0,0 +1,29 @@
+use super::MutationField;
+use failure::Error;
+use inflector::cases::classcase::to_class_case;
+
+impl MutationField {
+    pub fn from_str(
+        field_name: &str,
+        collections: &[(String, Vec<String>)],
+    ) -> Result<MutationField, Error> {
+        for (name, _fields) in collections {
+            let class_name = to_class_case(name);
+            if field_name == format!("create{}", class_name) {
+                return Ok(MutationField::Create {
+                    collection_name: name.to_string(),
+                });
+            } else if field_name == format!("update{}", class_name) {
+                return Ok(MutationField::Update {
+                    collection_name: name.to_string(),
+                });
+            } else if field_name == format!("delete{}", class_name) {
+                return Ok(MutationField::Delete {
+                    collection_name: name.to_string(),
+                });
+            }
+        }
+
+        bail!("Unknown field")
+    }
+}
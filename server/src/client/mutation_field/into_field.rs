@@ -0,0 +1,152 @@
+use super::MutationField;
+use crate::client::collection::Collection;
+use graphql_parser::schema::Type as GType;
+use inflector::cases::classcase::to_class_case;
+use juniper::{
+    meta::{
+        Argument,
+        DeprecationStatus,
+        Field,
+    },
+    DefaultScalarValue,
+    Registry,
+    Type,
+};
+use shelf_database::{
+    Cache,
+    Schema as DbSchema,
+    Store,
+};
+use uuid::Uuid;
+
+impl MutationField {
+    pub fn into_field<'r, C: Cache, S: Store>(
+        self,
+        info: &DbSchema,
+        registry: &mut Registry<'r, DefaultScalarValue>,
+    ) -> Field<'r, DefaultScalarValue> {
+        match self {
+            MutationField::Create { collection_name } => {
+                let mut arguments = vec![
+                    Argument {
+                        name: "id".to_string(),
+                        description: Some("\"The id of the new document, a random id is generated if this is left out\"".to_string()),
+                        arg_type: registry.get_type::<Option<Uuid>>(&()),
+                        default_value: None
+                    }
+                ];
+                arguments.append(&mut field_arguments(info, &collection_name, registry, false));
+
+                Field {
+                    name: format!("create{}", to_class_case(&collection_name)),
+                    description: Some(format!("\"Inserts a new document into the {} collection. All mutations in a request are committed together, if one fails none of them are applied\"", collection_name)),
+                    arguments: Some(arguments),
+                    field_type: registry.get_type::<Collection<C, S>>(&(collection_name, info.clone())),
+                    deprecation_status: DeprecationStatus::Current
+                }
+            },
+            MutationField::Update { collection_name } => {
+                let mut arguments = vec![
+                    Argument {
+                        name: "id".to_string(),
+                        description: Some("\"The id of the document you wish to update\"".to_string()),
+                        arg_type: registry.get_type::<Uuid>(&()),
+                        default_value: None
+                    }
+                ];
+                arguments.push(expected_revision_argument(registry));
+                arguments.append(&mut field_arguments(info, &collection_name, registry, true));
+
+                Field {
+                    name: format!("update{}", to_class_case(&collection_name)),
+                    description: Some(format!("\"Updates a document in the {} collection, fields that are left out keep their current value. All mutations in a request are committed together, if one fails none of them are applied\"", collection_name)),
+                    arguments: Some(arguments),
+                    field_type: registry.get_type::<Collection<C, S>>(&(collection_name, info.clone())),
+                    deprecation_status: DeprecationStatus::Current
+                }
+            },
+            MutationField::Delete { collection_name } => {
+                Field {
+                    name: format!("delete{}", to_class_case(&collection_name)),
+                    description: Some(format!("\"Deletes a document from the {} collection. All mutations in a request are committed together, if one fails none of them are applied\"", collection_name)),
+                    arguments: Some(vec![
+                        Argument {
+                            name: "id".to_string(),
+                            description: Some("\"The id of the document you wish to delete\"".to_string()),
+                            arg_type: registry.get_type::<Uuid>(&()),
+                            default_value: None
+                        },
+                        expected_revision_argument(registry),
+                    ]),
+                    field_type: registry.get_type::<bool>(&()),
+                    deprecation_status: DeprecationStatus::Current
+                }
+            },
+        }
+    }
+}
+
+fn expected_revision_argument<'r>(
+    registry: &mut Registry<'r, DefaultScalarValue>,
+) -> Argument<'r, DefaultScalarValue> {
+    Argument {
+        name: "expectedRevision".to_string(),
+        description: Some("\"If provided, the mutation fails with a conflict unless the document is still at this revision. Has to be a whole number from 0 to 2^53 - 1\"".to_string()),
+        arg_type: registry.get_type::<Option<f64>>(&()),
+        default_value: None,
+    }
+}
+
+/// Turns every field of a collection, except the id, into an argument
+fn field_arguments<'r>(
+    info: &DbSchema,
+    collection_name: &str,
+    registry: &mut Registry<'r, DefaultScalarValue>,
+    optional: bool,
+) -> Vec<Argument<'r, DefaultScalarValue>> {
+    let mut arguments = vec![];
+
+    if let Some(types) = info.types() {
+        if let Some(coll) = types.collections.iter().find(|i| i.name == collection_name) {
+            for field in coll.fields.iter().filter(|i| i.name != "id") {
+                arguments.push(Argument {
+                    name: field.name.to_owned(),
+                    description: field.description.as_ref().map(|f| format!("\"{}\"", f)),
+                    arg_type: get_argument_type(registry, &field.field_type, optional),
+                    default_value: None,
+                });
+            }
+        }
+    }
+
+    arguments
+}
+
+fn get_argument_type<'r>(
+    registry: &mut Registry<'r, DefaultScalarValue>,
+    field: &GType,
+    optional: bool,
+) -> Type<'r> {
+    match field {
+        GType::NonNullType(nt) if !optional => match &**nt {
+            GType::NamedType(t) => match t.as_str() {
+                "Uuid" => registry.get_type::<Uuid>(&()),
+                "Int" | "i32" => registry.get_type::<i32>(&()),
+                "Float" => registry.get_type::<f64>(&()),
+                "Boolean" => registry.get_type::<bool>(&()),
+                _ => registry.get_type::<String>(&()),
+            },
+            GType::ListType(_) => panic!("Can't handle list types yet"),
+            GType::NonNullType(_) => panic!("Cant be doubly wrapped in non null"),
+        },
+        GType::NonNullType(nt) => get_argument_type(registry, nt, true),
+        GType::NamedType(t) => match t.as_str() {
+            "Uuid" => registry.get_type::<Option<Uuid>>(&()),
+            "Int" | "i32" => registry.get_type::<Option<i32>>(&()),
+            "Float" => registry.get_type::<Option<f64>>(&()),
+            "Boolean" => registry.get_type::<Option<bool>>(&()),
+            _ => registry.get_type::<Option<String>>(&()),
+        },
+        GType::ListType(_) => panic!("Can't handle list types yet"),
+    }
+}
@@ -0,0 +1,6 @@
+mod field;
+mod fields;
+mod from_str;
+mod into_field;
+
+pub use self::field::Field as MutationField;
@@ -1,9 +0,0 @@
-
-
-pub enum MutationFields {
-    Clear
-}
-
-impl MutationFields {
-    
-}
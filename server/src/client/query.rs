@@ -7,6 +7,10 @@ use crate::{
     },
     context::Context,
 };
+use chrono::{
+    DateTime,
+    Utc,
+};
 use failure::_core::marker::PhantomData;
 use futures::FutureExt;
 use juniper::{
@@ -28,7 +32,11 @@ use shelf_database::{
     Schema as DbSchema,
     Store,
 };
-use std::future::Future;
+use std::{
+    future::Future,
+    sync::Arc,
+};
+use uuid::Uuid;
 
 pub struct Query<C: Cache, S: Store> {
     phantom_cache: PhantomData<C>,
@@ -48,7 +56,7 @@ impl<C: Cache, S: Store> Query<C, S> {
     async fn resolve_collection<'a>(
         &self,
         info: &DbSchema,
-        _context: &Context<C, S>,
+        context: &Context<C, S>,
         arguments: &Arguments<'a>,
         executor: &Executor<'a, Context<C, S>>,
         coll_name: &str,
@@ -56,7 +64,22 @@ impl<C: Cache, S: Store> Query<C, S> {
         // executor.resolve(collection, &Collection::new())
         match arguments.get("id") {
             Some(id) => match self.schema.collection_by_name(coll_name).await {
-                Some(coll) => match coll.document(id).await {
+                Some(coll) if arguments.get::<DateTime<Utc>>("asOf").is_some() => {
+                    let as_of = arguments.get::<DateTime<Utc>>("asOf").unwrap();
+                    let collection_id = coll.inner_collection().await.id;
+                    match context
+                        .db
+                        .document_as_of(&context.logger, info.id, collection_id, id, as_of)
+                        .await?
+                    {
+                        Some(doc) => executor.resolve_with_ctx(
+                            &(coll_name.to_string(), info.clone()),
+                            &Collection::new(Arc::new(doc)),
+                        ),
+                        None => executor.resolve_with_ctx(&(), &Option::<String>::None),
+                    }
+                }
+                Some(coll) => match coll.document(id).await? {
                     Some(doc) => executor.resolve_with_ctx(
                         &(coll_name.to_string(), info.clone()),
                         &Collection::new(doc),
@@ -85,7 +108,7 @@ impl<C: Cache, S: Store> Query<C, S> {
     ) -> ExecutionResult {
         Self::unwrap_collection(info, context, coll_name, |coll| async move {
             let name = format!("{}Connection", coll_name);
-            let connection = Connection::new(coll.clone()).await;
+            let connection = Connection::new(coll.clone()).await?;
             executor
                 .resolve_with_ctx_async(
                     &(name.to_string(), coll_name.to_string(), info.clone()),
@@ -96,6 +119,41 @@ impl<C: Cache, S: Store> Query<C, S> {
         .await
     }
 
+    async fn resolve_history(
+        &self,
+        info: &DbSchema,
+        context: &Context<C, S>,
+        arguments: &Arguments<'_>,
+        executor: &Executor<'_, Context<C, S>>,
+        coll_name: &str,
+    ) -> ExecutionResult {
+        let id = match arguments.get::<Uuid>("id") {
+            Some(id) => id,
+            None => {
+                return Err(FieldError::new(
+                    "Id has to be provided",
+                    graphql_value!({ "missing_argument": "Argument was missing" }),
+                ))
+            }
+        };
+
+        Self::unwrap_collection(info, context, coll_name, |coll| async move {
+            let collection_id = coll.inner_collection().await.id;
+            let history = context
+                .db
+                .history(&context.logger, info.id, collection_id, id)
+                .await?;
+            let versions: Vec<_> = history
+                .into_iter()
+                .filter_map(|i| i.document)
+                .map(|doc| Collection::new(Arc::new(doc)))
+                .collect();
+
+            executor.resolve_with_ctx(&(coll_name.to_string(), info.clone()), &versions)
+        })
+        .await
+    }
+
     async fn unwrap_collection<'a, CB: FnOnce(<<C as shelf_database::Cache>::CacheSchema as shelf_database::CacheSchema>::CacheCollection) -> FR, FR: Future<Output=ExecutionResult> + 'a>(info: &'a DbSchema, context: &'a Context<C, S>, coll_name: &'a str, callback: CB) -> ExecutionResult{
         match context.db.schema(info.id).await {
             Some(schema) => match schema.collection_by_name(coll_name).await {
@@ -118,7 +176,7 @@ impl<C: Cache, S: Store> Query<C, S> {
         }
     }
 
-    fn map_collection_to_name_and_fields(info: &DbSchema) -> Vec<(String, Vec<String>)> {
+    pub(crate) fn map_collection_to_name_and_fields(info: &DbSchema) -> Vec<(String, Vec<String>)> {
         match info.types() {
             Some(data) => data
                 .collections
@@ -181,6 +239,10 @@ impl<C: Cache, S: Store> GraphQLTypeAsync<DefaultScalarValue> for Query<C, S> {
                     self.resolve_collections(info, context, arguments, executor, &collection_name)
                         .await
                 }
+                QueryField::History { collection_name } => {
+                    self.resolve_history(info, context, arguments, executor, &collection_name)
+                        .await
+                }
                 QueryField::FirstDocumentByField {
                     collection_name: _,
                     field_name: _,
@@ -19,6 +19,11 @@ pub enum Field {
     Documents {
         collection_name: String,
     },
+    /// Every stored version of a document, only available on `@versioned`
+    /// collections
+    History {
+        collection_name: String,
+    },
     FirstDocumentByField {
         collection_name: String,
         field_name: String,
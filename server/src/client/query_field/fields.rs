@@ -36,6 +36,14 @@ impl QueryField {
                 }
                 .into_field::<C, S>(info, registry),
             );
+            if info.is_versioned(collection_name) {
+                fields.push(
+                    QueryField::History {
+                        collection_name: collection_name.to_string(),
+                    }
+                    .into_field::<C, S>(info, registry),
+                );
+            }
 
             for field in collection_fields
                 .iter()
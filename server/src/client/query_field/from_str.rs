@@ -27,6 +27,13 @@ impl QueryField {
                     Ok(QueryField::Documents {
                         collection_name: name.to_string(),
                     })
+                } else if let Some((name, _)) = collections
+                    .iter()
+                    .find(|(name, _fields)| field_name == format!("{}History", to_camel_case(name)))
+                {
+                    Ok(QueryField::History {
+                        collection_name: name.to_string(),
+                    })
                 } else {
                     bail!("Unknown field")
                 }
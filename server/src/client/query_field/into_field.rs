@@ -89,6 +89,12 @@ impl QueryField {
                             description: Some("\"The id of the document you wish to retrieve, has to be a valid Uuid\"".to_string()),
                             arg_type: registry.get_type::<String>(&()),
                             default_value: None
+                        },
+                        Argument {
+                            name: "asOf".to_string(),
+                            description: Some("\"Returns the document as it was at this time. Only collections marked with @versioned keep the history needed to look back further than the last write\"".to_string()),
+                            arg_type: registry.get_type::<Option<DateTime<Utc>>>(&()),
+                            default_value: None
                         }
                     ]),
                     field_type: registry.get_type::<Option<Collection<C, S>>>(&(collection_name.to_string(), info.clone())),
@@ -123,6 +129,22 @@ impl QueryField {
                     deprecation_status: DeprecationStatus::Current
                 }
             },
+            QueryField::History { collection_name } => {
+                Field {
+                    name: format!("{}History", to_camel_case(&collection_name)),
+                    description: Some(format!("\"Returns every stored version of a document in the {} collection, oldest first. Deletes are not included\"", collection_name)),
+                    arguments: Some(vec![
+                        Argument {
+                            name: "id".to_string(),
+                            description: Some("\"The id of the document you wish to see the history of\"".to_string()),
+                            arg_type: registry.get_type::<Uuid>(&()),
+                            default_value: None
+                        }
+                    ]),
+                    field_type: registry.get_type::<Vec<Collection<C, S>>>(&(collection_name, info.clone())),
+                    deprecation_status: DeprecationStatus::Current
+                }
+            },
             QueryField::FirstDocumentByField { collection_name, field_name } => {
                 Field {
                     name: format!("first{}By{}", to_class_case(&collection_name), to_class_case(&field_name)),
@@ -16,6 +16,7 @@ mod tests {
         },
         context::Context,
     };
+    use chrono::Utc;
     use juniper::{
         http::{
             GraphQLRequest,
@@ -36,6 +37,7 @@ mod tests {
         Database,
         Document,
         Schema as DbSchema,
+        Transaction,
     };
     use shelf_memory_cache::MemoryCache;
     use slog::Logger;
@@ -46,15 +48,18 @@ mod tests {
     use std::{
         collections::HashMap,
         sync::Arc,
+        time::Duration,
     };
+    use tokio::time::delay_for;
     use uuid::Uuid;
 
     const TEST_GRAPHQL_SCHEMA: &str = r#"
         directive @collection on OBJECT
+        directive @versioned on OBJECT
 
         scalar Uuid
 
-        type Car @collection {
+        type Car @collection @versioned {
             id: Uuid!
             brand: String!
             model: String!
@@ -148,6 +153,249 @@ mod tests {
         assert_eq!(brand, "Tesla", "Got wrong brand");
     }
 
+    #[tokio::test]
+    async fn mutations_should_only_be_visible_after_commit() {
+        let (root_node, context) = node_and_context().await;
+        let request = GraphQLRequest::<DefaultScalarValue>::new(
+            r#"mutation {
+                first: createCar(brand: "Volvo", model: "V70") {id}
+                second: createCar(brand: "Saab", model: "900") {id}
+            }"#
+            .to_string(),
+            None,
+            None,
+        );
+
+        let response = request.execute_async(&root_node, &context).await;
+        let data = unwrap_data_tag(response);
+        let first =
+            serde_json::from_value::<Uuid>(data.get("first").unwrap().get("id").unwrap().clone())
+                .unwrap();
+        let second =
+            serde_json::from_value::<Uuid>(data.get("second").unwrap().get("id").unwrap().clone())
+                .unwrap();
+
+        let collection = context
+            .db
+            .cache()
+            .schema(Uuid::nil())
+            .await
+            .unwrap()
+            .collection_by_name("Car")
+            .await
+            .unwrap();
+
+        assert!(
+            collection.document(first).await.unwrap().is_none(),
+            "The document was visible before commit"
+        );
+
+        context.commit().await.unwrap();
+
+        assert!(
+            collection.document(first).await.unwrap().is_some(),
+            "First car was not committed"
+        );
+        assert!(
+            collection.document(second).await.unwrap().is_some(),
+            "Second car was not committed"
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_mutation_should_roll_back_the_request() {
+        let (root_node, context) = node_and_context().await;
+        let request = GraphQLRequest::<DefaultScalarValue>::new(
+            format!(
+                r#"mutation {{
+                    createCar(id: "{}", brand: "Volvo", model: "V70") {{id}}
+                    deleteCar(id: "{}")
+                }}"#,
+                Uuid::from_u128(1),
+                Uuid::from_u128(2)
+            ),
+            None,
+            None,
+        );
+
+        let response =
+            serde_json::to_value(request.execute_async(&root_node, &context).await).unwrap();
+        assert!(
+            response.get("errors").is_some(),
+            "Deleting a missing car should fail"
+        );
+
+        context.commit().await.unwrap();
+
+        let collection = context
+            .db
+            .cache()
+            .schema(Uuid::nil())
+            .await
+            .unwrap()
+            .collection_by_name("Car")
+            .await
+            .unwrap();
+
+        assert!(
+            collection
+                .document(Uuid::from_u128(1))
+                .await
+                .unwrap()
+                .is_none(),
+            "The created car should have been rolled back"
+        );
+    }
+
+    #[tokio::test]
+    async fn mutations_should_respond_with_the_committed_revision() {
+        let (root_node, context) = node_and_context().await;
+        let id = Uuid::from_u128(4);
+        let revision = |data: &Map<String, Value>, field: &str| {
+            data.get(field).unwrap().get("revision").unwrap().as_f64()
+        };
+
+        let request = GraphQLRequest::<DefaultScalarValue>::new(
+            format!(
+                r#"mutation {{
+                    createCar(id: "{}", brand: "Volvo", model: "V70") {{revision}}
+                }}"#,
+                id
+            ),
+            None,
+            None,
+        );
+        let data = unwrap_data_tag(request.execute_async(&root_node, &context).await);
+        assert_eq!(revision(&data, "createCar"), Some(1.0));
+        context.commit().await.unwrap();
+
+        let request = GraphQLRequest::<DefaultScalarValue>::new(
+            format!(
+                r#"mutation {{
+                    first: updateCar(id: "{0}", model: "V90", expectedRevision: 1) {{revision}}
+                    second: updateCar(id: "{0}", model: "V60") {{revision}}
+                }}"#,
+                id
+            ),
+            None,
+            None,
+        );
+        let data = unwrap_data_tag(request.execute_async(&root_node, &context).await);
+        assert_eq!(revision(&data, "first"), Some(2.0));
+        assert_eq!(revision(&data, "second"), Some(3.0));
+        context.commit().await.unwrap();
+
+        let committed = context
+            .db
+            .cache()
+            .schema(Uuid::nil())
+            .await
+            .unwrap()
+            .collection_by_name("Car")
+            .await
+            .unwrap()
+            .document(id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(committed.revision, 3);
+    }
+
+    #[tokio::test]
+    async fn expected_revisions_should_be_whole_numbers_a_float_can_hold() {
+        let (root_node, context) = node_and_context().await;
+
+        for expected_revision in &["1.5", "-1", "9007199254740993"] {
+            let request = GraphQLRequest::<DefaultScalarValue>::new(
+                format!(
+                    r#"mutation {{
+                        deleteCar(id: "{}", expectedRevision: {})
+                    }}"#,
+                    Uuid::nil(),
+                    expected_revision
+                ),
+                None,
+                None,
+            );
+
+            let response =
+                serde_json::to_value(request.execute_async(&root_node, &context).await).unwrap();
+            assert!(
+                response.to_string().contains("Invalid expected revision"),
+                "{} should have been rejected",
+                expected_revision
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn document_as_of_should_follow_the_history() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let db = database(&logger).await;
+        let collection = db
+            .cache()
+            .schema(Uuid::nil())
+            .await
+            .unwrap()
+            .collection_by_name("Car")
+            .await
+            .unwrap();
+        let collection_id = collection.inner_collection().await.id;
+        let id = Uuid::from_u128(3);
+
+        let mut fields = HashMap::new();
+        fields.insert("brand".to_string(), "Volvo".into());
+        fields.insert("model".to_string(), "V70".into());
+        let before_insert = Utc::now();
+        let mut transaction = Transaction::new();
+        transaction.set(Uuid::nil(), collection_id, Document::new(id, fields), None);
+        db.commit(&logger, transaction).await.unwrap();
+
+        delay_for(Duration::from_millis(5)).await;
+        let after_insert = Utc::now();
+        let mut updated = Document::clone(&collection.document(id).await.unwrap().unwrap());
+        updated.fields.insert("model".to_string(), "V90".into());
+        let mut transaction = Transaction::new();
+        transaction.set(Uuid::nil(), collection_id, updated, Some(1));
+        db.commit(&logger, transaction).await.unwrap();
+
+        delay_for(Duration::from_millis(5)).await;
+        let after_update = Utc::now();
+        let mut transaction = Transaction::new();
+        transaction.delete(Uuid::nil(), collection_id, id, Some(2));
+        db.commit(&logger, transaction).await.unwrap();
+
+        let history = db
+            .history(&logger, Uuid::nil(), collection_id, id)
+            .await
+            .unwrap();
+        let revisions: Vec<_> = history.iter().map(|i| i.revision).collect();
+        assert_eq!(revisions, vec![1, 2, 3]);
+        assert!(history[2].document.is_none(), "The delete should be kept");
+
+        let model =
+            |doc: Option<Document>| doc.map(|i| i.fields["model"].as_str().unwrap().to_string());
+        let as_of = |time| db.document_as_of(&logger, Uuid::nil(), collection_id, id, time);
+        assert_eq!(
+            model(as_of(before_insert).await.unwrap()),
+            None,
+            "The document did not exist before it was inserted"
+        );
+        assert_eq!(
+            model(as_of(after_insert).await.unwrap()),
+            Some("V70".to_string())
+        );
+        assert_eq!(
+            model(as_of(after_update).await.unwrap()),
+            Some("V90".to_string())
+        );
+        assert_eq!(
+            model(as_of(Utc::now()).await.unwrap()),
+            None,
+            "The document was deleted"
+        );
+    }
+
     fn unwrap_data_tag(response: GraphQLResponse<DefaultScalarValue>) -> Map<String, Value> {
         if response.is_ok() {
             let result = serde_json::to_value(response).unwrap();
@@ -178,14 +426,23 @@ mod tests {
     ) -> Schema<'a, MemoryCache, TestStore> {
         let schema = db.cache().schema(Uuid::nil()).await.unwrap();
         let inner_schema = schema.inner_schema().await;
-        Schema::new_with_info(Query::new(schema), Mutation::new(), inner_schema, ())
+        Schema::new_with_info(
+            Query::new(schema.clone()),
+            Mutation::new(schema),
+            inner_schema.clone(),
+            inner_schema,
+        )
     }
 
     fn context(
         logger: &Logger,
         db: &Database<MemoryCache, TestStore>,
     ) -> Context<MemoryCache, TestStore> {
-        Context::new(&logger, Arc::new(Database::clone(&db)))
+        Context::new(
+            &logger,
+            Arc::new(Config::default()),
+            Arc::new(Database::clone(&db)),
+        )
     }
 
     async fn database(logger: &Logger) -> Database<MemoryCache, TestStore> {
@@ -193,8 +450,8 @@ mod tests {
         let db = Database::new(
             &logger,
             &config,
-            TestStore,
-            MemoryCache::new(&logger).await.unwrap(),
+            TestStore::default(),
+            MemoryCache::new(&logger, None).await.unwrap(),
         )
         .await
         .unwrap();
@@ -213,10 +470,7 @@ mod tests {
         fields.insert("brand".to_string(), "Tesla".into());
         fields.insert("model".to_string(), "Model S".into());
 
-        let doc = Document {
-            id: Uuid::nil(),
-            fields,
-        };
+        let doc = Document::new(Uuid::nil(), fields);
 
         db.cache()
             .schema(Uuid::nil())
@@ -226,7 +480,8 @@ mod tests {
             .await
             .unwrap()
             .set_document(doc)
-            .await;
+            .await
+            .unwrap();
 
         db
     }
@@ -1,32 +1,84 @@
+use failure::Error;
+use shelf_config::Config;
 use shelf_database::{
     Cache,
     Database,
     Store,
+    Transaction,
 };
 use slog::Logger;
-use std::sync::Arc;
+use std::{
+    mem,
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+};
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
 pub struct Context<C: Cache, S: Store> {
     pub db: Arc<Database<C, S>>,
+    pub config: Arc<Config>,
     pub logger: Logger,
+    /// Writes staged by the mutations of this request, they are committed
+    /// together once the whole request has been executed
+    pub transaction: Mutex<Transaction>,
+    /// Set for admin requests that carry the admin token, backups are only
+    /// written for those
+    pub admin: bool,
+    rollback: AtomicBool,
 }
 
 impl<C: Cache, S: Store> Context<C, S> {
-    pub fn new(logger: &Logger, db: Arc<Database<C, S>>) -> Self {
+    pub fn new(logger: &Logger, config: Arc<Config>, db: Arc<Database<C, S>>) -> Self {
         Self {
             db,
+            config,
             logger: logger.clone(),
+            transaction: Mutex::new(Transaction::new()),
+            admin: false,
+            rollback: AtomicBool::new(false),
         }
     }
 
     pub fn new_request(&self) -> Self {
         Self {
             db: Arc::clone(&self.db),
+            config: Arc::clone(&self.config),
             logger: self
                 .logger
                 .new(o!("request_id" => Uuid::new_v4().to_string())),
+            transaction: Mutex::new(Transaction::new()),
+            admin: false,
+            rollback: AtomicBool::new(false),
+        }
+    }
+
+    pub fn with_admin(self, admin: bool) -> Self {
+        Self { admin, ..self }
+    }
+
+    /// Makes sure nothing staged in this request gets committed
+    pub fn rollback(&self) {
+        self.rollback.store(true, Ordering::SeqCst);
+    }
+
+    /// Commits all writes staged during this request
+    pub async fn commit(&self) -> Result<(), Error> {
+        let transaction = mem::replace(&mut *self.transaction.lock().await, Transaction::new());
+
+        if self.rollback.load(Ordering::SeqCst) {
+            if !transaction.is_empty() {
+                warn!(self.logger, "Request failed, rolling back transaction"; "transaction_id" => transaction.id.to_string());
+            }
+            return Ok(());
         }
+
+        self.db.commit(&self.logger, transaction).await
     }
 }
 
@@ -14,7 +14,9 @@ extern crate serde_json;
 mod admin;
 mod client;
 mod context;
+mod transfer;
 mod util;
+mod webhooks;
 
 mod server;
 
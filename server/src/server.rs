@@ -9,11 +9,17 @@ use crate::{
         Schema as ClientSchema,
     },
     context::Context,
+    transfer::{
+        is_transfer_path,
+        transfer_route,
+    },
     util::{
         graphql_get,
         graphql_post,
+        is_authorized,
         playground,
     },
+    webhooks::start_webhook_dispatcher,
 };
 use colored::*;
 use failure::Error;
@@ -72,12 +78,16 @@ impl Server {
             let admin_root_node = Self::build_admin_root_node();
             let client_root_nodes = Self::build_client_root_nodes(&db).await;
 
+            start_webhook_dispatcher(&logger, &config, Arc::clone(&db));
+
             let db = Arc::clone(&db);
+            let config = Arc::new(config);
 
             let make_svc = make_service_fn(move |_conn| {
                 let client_root_nodes = Arc::clone(&client_root_nodes);
                 let admin_root_node = Arc::clone(&admin_root_node);
-                let context = Context::<C, S>::new(&other_logger, Arc::clone(&db));
+                let context =
+                    Context::<C, S>::new(&other_logger, Arc::clone(&config), Arc::clone(&db));
                 async move {
                     Ok::<_, Infallible>(service_fn(move |r| {
                         Self::map_route(
@@ -164,6 +174,9 @@ impl Server {
         debug!(logger, "Received request {}", method_and_uri);
 
         if req.uri().path().starts_with(&"/admin") {
+            let authorized = is_authorized(&req, context.config.admin_token.as_deref());
+            let context = context.with_admin(authorized);
+
             return match (req.method(), req.uri().path()) {
                 (&Method::GET, "/admin") => playground("/graphql"),
                 (&Method::GET, "/admin/graphql") => {
@@ -173,6 +186,7 @@ impl Server {
                 (&Method::POST, "/admin/graphql") => {
                     graphql_post(Arc::clone(&admin_root_node), context, req).await
                 }
+                (_, path) if is_transfer_path(path) => transfer_route(context, req).await,
                 _ => Ok(hello_world_response()),
             };
         }
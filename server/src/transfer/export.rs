@@ -0,0 +1,123 @@
+use crate::transfer::{
+    records::{
+        to_record,
+        Encoder,
+        COLLECTION_KEY,
+    },
+    transfer_format::TransferFormat,
+};
+use failure::Error;
+use futures::stream;
+use hyper::{
+    header,
+    header::HeaderValue,
+    Body,
+    Response,
+};
+use shelf_database::{
+    Cache,
+    CacheSchema,
+    Database,
+    Schema,
+    SnapshotCollection,
+    Store,
+};
+use slog::Logger;
+use std::iter;
+
+/// Streams every document of a schema, or of a single collection, in the
+/// chosen format. Only the requested schema or collection is read, and the
+/// documents are copied up front so the export is consistent even though
+/// sending it takes a while. Collections that are not in memory are read from
+/// the store, without reading them back into the cache
+pub async fn export<C: Cache, S: Store>(
+    logger: &Logger,
+    db: &Database<C, S>,
+    schema_name: &str,
+    collection_name: Option<&str>,
+    format: TransferFormat,
+) -> Result<Response<Body>, Error> {
+    let schema = match db.schema_by_name(schema_name).await {
+        Some(schema) => schema.inner_schema().await,
+        None => bail!("The schema \"{}\" does not exist", schema_name),
+    };
+
+    let collections = match collection_name {
+        Some(name) => match db.collection_snapshot(schema_name, name).await {
+            Some(collection) => vec![collection],
+            None => bail!("The collection \"{}\" does not exist", name),
+        },
+        None => match db.schema_snapshot(schema_name).await {
+            Some(snapshot) => snapshot.collections,
+            None => bail!("The schema \"{}\" does not exist", schema_name),
+        },
+    };
+
+    let whole_schema = collection_name.is_none();
+    let encoder = Encoder::new(format, csv_columns(&schema, &collections, whole_schema));
+    let start = encoder.start();
+    let end = encoder.end();
+
+    let mut documents = vec![];
+    for collection in &collections {
+        let read = db.snapshot_documents(logger, &schema, collection).await?;
+        documents.push((collection.collection.name.to_string(), read));
+    }
+
+    let rows = documents
+        .into_iter()
+        .flat_map(|(name, documents)| {
+            documents
+                .into_iter()
+                .map(move |document| (name.clone(), document))
+        })
+        .enumerate()
+        .map(move |(index, (name, document))| {
+            let collection_name = if whole_schema {
+                Some(name.as_str())
+            } else {
+                None
+            };
+            encoder.record(index, &to_record(collection_name, &document))
+        });
+    let chunks = iter::once(start).chain(rows).chain(iter::once(Ok(end)));
+
+    let mut response = Response::new(Body::wrap_stream(stream::iter(chunks)));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    Ok(response)
+}
+
+/// The csv columns, the id first followed by the fields in the order they are
+/// defined
+fn csv_columns(
+    schema: &Schema,
+    collections: &[SnapshotCollection],
+    whole_schema: bool,
+) -> Vec<String> {
+    let mut columns = vec![];
+    if whole_schema {
+        columns.push(COLLECTION_KEY.to_string());
+    }
+    columns.push("id".to_string());
+
+    if let Some(types) = schema.types() {
+        for collection in collections {
+            if let Some(definition) = types
+                .collections
+                .iter()
+                .find(|i| i.name == collection.collection.name)
+            {
+                for field in &definition.fields {
+                    if !columns.contains(&field.name) {
+                        columns.push(field.name.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    columns
+}
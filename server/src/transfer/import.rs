@@ -0,0 +1,420 @@
+use crate::transfer::{
+    records::{
+        decode,
+        parse_cell,
+        Record,
+        COLLECTION_KEY,
+    },
+    transfer_format::TransferFormat,
+};
+use failure::Error;
+use futures::{
+    channel::mpsc,
+    executor::{
+        block_on_stream,
+        BlockingStream,
+    },
+    SinkExt,
+    StreamExt,
+};
+use graphql_parser::schema::Type;
+use hyper::{
+    body::Bytes,
+    Body,
+};
+use serde_json::Value;
+use shelf_database::{
+    validate_document,
+    Cache,
+    CacheCollection,
+    CacheSchema,
+    Database,
+    Document,
+    Schema,
+    Store,
+    Transaction,
+};
+use slog::Logger;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    io,
+    io::Read,
+    str::FromStr,
+};
+use tokio::task;
+use uuid::Uuid;
+
+/// The collections of a schema by name, with their id and field types
+type Collections = HashMap<String, (Uuid, HashMap<String, Type>)>;
+
+/// Imports documents into a schema, or into a single collection. The body is
+/// decoded as it arrives and every record is validated against its
+/// collection, then all documents are committed in a single transaction. So
+/// an import that fails, at any record, leaves no trace. Documents that
+/// already exist are replaced
+///
+/// # Errors
+/// Returns an error if the body is larger than `max_size` bytes, if any record
+/// is malformed or invalid, or if the same document appears twice
+pub async fn import<C: Cache, S: Store>(
+    logger: &Logger,
+    db: &Database<C, S>,
+    schema_name: &str,
+    collection_name: Option<&str>,
+    format: TransferFormat,
+    mut body: Body,
+    max_size: usize,
+) -> Result<usize, Error> {
+    let schema = match db.schema_by_name(schema_name).await {
+        Some(schema) => schema,
+        None => bail!("The schema \"{}\" does not exist", schema_name),
+    };
+    let inner_schema = schema.inner_schema().await;
+    let types = match inner_schema.types() {
+        Some(types) => types,
+        None => bail!("The schema \"{}\" has no definition", schema_name),
+    };
+
+    let mut collections = Collections::new();
+    for collection in schema.collections().collect::<Vec<_>>().await {
+        let collection = collection.inner_collection().await;
+        let fields = types
+            .collections
+            .iter()
+            .find(|i| i.name == collection.name)
+            .map(|definition| {
+                definition
+                    .fields
+                    .iter()
+                    .map(|i| (i.name.to_string(), i.field_type.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        collections.insert(collection.name, (collection.id, fields));
+    }
+
+    // The records are decoded on a blocking thread, which is fed the body one
+    // chunk at a time
+    let (mut sender, receiver) = mpsc::channel(0);
+    let schema_id = inner_schema.id;
+    let target = collection_name.map(str::to_string);
+    let reading = task::spawn_blocking(move || {
+        let mut transaction = Transaction::new();
+        let mut ids = HashSet::new();
+        let mut count = 0;
+
+        decode(format, ChunkReader::new(receiver), |record| {
+            count += 1;
+            let (collection_id, document) = read_record(
+                &inner_schema,
+                &collections,
+                target.as_deref(),
+                format,
+                record,
+            )
+            .map_err(|e| format_err!("Record {}: {}", count, e))?;
+
+            if !ids.insert(document.id) {
+                bail!(
+                    "Record {}: The document {} appears more than once",
+                    count,
+                    document.id
+                );
+            }
+            transaction.set(schema_id, collection_id, document, None);
+            Ok(())
+        })?;
+
+        Ok::<_, Error>((transaction, count))
+    });
+
+    let mut size = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        size += chunk.len();
+        if size > max_size {
+            bail!("The import is larger than the limit of {} bytes", max_size);
+        }
+        if sender.send(chunk).await.is_err() {
+            // The decoding stopped early, the reason is returned below
+            break;
+        }
+    }
+    drop(sender);
+
+    let (transaction, count) = reading.await??;
+    db.commit(&logger, transaction).await?;
+
+    info!(logger, "📥 Imported documents"; "schema_name" => schema_name, "collection_name" => collection_name, "documents" => count);
+    Ok(count)
+}
+
+/// Lets the blocking decoder read the chunks of a body as they are sent over
+struct ChunkReader {
+    chunks: BlockingStream<mpsc::Receiver<Bytes>>,
+    current: Bytes,
+}
+
+impl ChunkReader {
+    fn new(receiver: mpsc::Receiver<Bytes>) -> Self {
+        Self {
+            chunks: block_on_stream(receiver),
+            current: Bytes::new(),
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
+
+fn read_record(
+    schema: &Schema,
+    collections: &Collections,
+    collection_name: Option<&str>,
+    format: TransferFormat,
+    mut record: Record,
+) -> Result<(Uuid, Document), Error> {
+    let name = match (collection_name, record.remove(COLLECTION_KEY)) {
+        (Some(name), None) => name.to_string(),
+        (Some(name), Some(Value::String(other))) if other == name => other,
+        (Some(name), Some(_)) => bail!("Belongs to another collection than \"{}\"", name),
+        (None, Some(Value::String(name))) => name,
+        (None, _) => bail!(
+            "The {} field is needed when importing a whole schema",
+            COLLECTION_KEY
+        ),
+    };
+    let (collection_id, field_types) = match collections.get(&name) {
+        Some(collection) => collection,
+        None => bail!("The collection \"{}\" does not exist", name),
+    };
+
+    let id = match record.remove("id") {
+        Some(Value::String(id)) => Uuid::from_str(&id)?,
+        None | Some(Value::Null) => Uuid::new_v4(),
+        Some(_) => bail!("The id has to be a string"),
+    };
+
+    let mut fields = HashMap::new();
+    for (key, value) in record {
+        let value = match (format, value, field_types.get(&key)) {
+            (TransferFormat::Csv, Value::String(cell), Some(field_type)) => {
+                parse_cell(&cell, field_type)
+                    .map_err(|e| format_err!("The field \"{}\" could not be read: {}", key, e))?
+            }
+            (_, value, _) => value,
+        };
+        fields.insert(key, value);
+    }
+
+    let document = Document::new(id, fields);
+    validate_document(schema, &name, &document)?;
+    Ok((*collection_id, document))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transfer::{
+        import::import,
+        transfer_format::TransferFormat,
+    };
+    use hyper::Body;
+    use shelf_config::Config;
+    use shelf_database::{
+        test::TestStore,
+        Cache,
+        CacheCollection,
+        CacheSchema,
+        Database,
+        DocumentResult,
+        Schema,
+    };
+    use shelf_memory_cache::MemoryCache;
+    use slog::Logger;
+    use sloggers::{
+        null::NullLoggerBuilder,
+        Build,
+    };
+    use uuid::Uuid;
+
+    const TEST_GRAPHQL_SCHEMA: &str = r#"
+        directive @collection on OBJECT
+
+        scalar Uuid
+
+        type Car @collection {
+            id: Uuid!
+            model: String!
+        }
+    "#;
+
+    const MAX_SIZE: usize = 1024 * 1024;
+
+    #[tokio::test]
+    async fn import_should_write_every_record() {
+        let (logger, db) = database().await;
+        let data = "{\"model\":\"V70\"}\n\n{\"model\":\"V90\"}\n";
+
+        let imported = import(
+            &logger,
+            &db,
+            "Test",
+            Some("Car"),
+            TransferFormat::Ndjson,
+            Body::from(data),
+            MAX_SIZE,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(imported, 2);
+        assert_eq!(car_count(&db).await, 2);
+    }
+
+    #[tokio::test]
+    async fn import_should_write_nothing_if_a_line_is_malformed() {
+        let (logger, db) = database().await;
+        let data = "{\"model\":\"V70\"}\n{\"model\":\n";
+
+        let err = import(
+            &logger,
+            &db,
+            "Test",
+            Some("Car"),
+            TransferFormat::Ndjson,
+            Body::from(data),
+            MAX_SIZE,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(format!("{}", err).starts_with("Line 2"), "{}", err);
+        assert_eq!(car_count(&db).await, 0);
+    }
+
+    #[tokio::test]
+    async fn import_should_write_nothing_if_a_collection_does_not_exist() {
+        let (logger, db) = database().await;
+        let data = r#"[
+            {"__collection": "Car", "model": "V70"},
+            {"__collection": "Boat", "model": "Buster"}
+        ]"#;
+
+        let err = import(
+            &logger,
+            &db,
+            "Test",
+            None,
+            TransferFormat::Json,
+            Body::from(data),
+            MAX_SIZE,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            format!("{}", err).contains("The collection \"Boat\" does not exist"),
+            "{}",
+            err
+        );
+        assert_eq!(car_count(&db).await, 0);
+    }
+
+    #[tokio::test]
+    async fn import_should_write_nothing_if_a_document_conflicts_with_an_earlier_one() {
+        let (logger, db) = database().await;
+        let id = Uuid::new_v4();
+        let data = format!(
+            "id,model\n{},V70\n{},V90\n{},XC60\n",
+            Uuid::new_v4(),
+            id,
+            id
+        );
+
+        let err = import(
+            &logger,
+            &db,
+            "Test",
+            Some("Car"),
+            TransferFormat::Csv,
+            Body::from(data),
+            MAX_SIZE,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(format!("{}", err).starts_with("Record 3"), "{}", err);
+        assert_eq!(car_count(&db).await, 0);
+    }
+
+    #[tokio::test]
+    async fn import_should_reject_a_body_larger_than_the_limit() {
+        let (logger, db) = database().await;
+        let data = "{\"model\":\"V70\"}\n".repeat(100);
+
+        let err = import(
+            &logger,
+            &db,
+            "Test",
+            Some("Car"),
+            TransferFormat::Ndjson,
+            Body::from(data),
+            1024,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(format!("{}", err).contains("limit"), "{}", err);
+        assert_eq!(car_count(&db).await, 0);
+    }
+
+    async fn car_count(db: &Database<MemoryCache, TestStore>) -> usize {
+        let collection = db
+            .schema(Uuid::nil())
+            .await
+            .unwrap()
+            .collection_by_name("Car")
+            .await
+            .unwrap();
+        let result = collection.documents().await.unwrap();
+        result.total()
+    }
+
+    async fn database() -> (Logger, Database<MemoryCache, TestStore>) {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let db = Database::new(
+            &logger,
+            &Config::default(),
+            TestStore::default(),
+            MemoryCache::new(&logger, None).await.unwrap(),
+        )
+        .await
+        .unwrap();
+
+        db.cache()
+            .insert_schema(
+                &logger,
+                Schema::new(Uuid::nil(), "Test", None),
+                TEST_GRAPHQL_SCHEMA,
+            )
+            .await
+            .unwrap();
+
+        (logger, db)
+    }
+}
@@ -0,0 +1,10 @@
+mod export;
+mod import;
+mod records;
+mod transfer_format;
+mod transfer_route;
+
+pub use self::transfer_route::{
+    is_transfer_path,
+    transfer_route,
+};
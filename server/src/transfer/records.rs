@@ -0,0 +1,245 @@
+use crate::transfer::transfer_format::TransferFormat;
+use failure::Error;
+use graphql_parser::schema::Type;
+use serde::de::{
+    Deserializer,
+    Error as DeError,
+    SeqAccess,
+    Visitor,
+};
+use serde_json::{
+    Map,
+    Value,
+};
+use shelf_database::Document;
+use std::{
+    error::Error as StdError,
+    fmt,
+    io::{
+        BufRead,
+        BufReader,
+        Read,
+    },
+};
+
+/// Tells which collection a record belongs to when a whole schema is
+/// exported or imported. GraphQL reserves names starting with two
+/// underscores, so it never collides with a field
+pub const COLLECTION_KEY: &str = "__collection";
+
+/// A document as it is exported, the id and the fields side by side
+pub type Record = Map<String, Value>;
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+pub fn to_record(collection_name: Option<&str>, document: &Document) -> Record {
+    let mut record = Map::new();
+    if let Some(name) = collection_name {
+        record.insert(COLLECTION_KEY.to_string(), Value::String(name.to_string()));
+    }
+    record.insert("id".to_string(), Value::String(document.id.to_string()));
+    for (key, value) in &document.fields {
+        record.insert(key.to_string(), value.clone());
+    }
+    record
+}
+
+/// Writes records one at a time, so that an export never has to be held in
+/// memory as a whole
+pub struct Encoder {
+    format: TransferFormat,
+    /// Only used for csv, decides the order of the cells
+    columns: Vec<String>,
+}
+
+impl Encoder {
+    pub fn new(format: TransferFormat, columns: Vec<String>) -> Self {
+        Self { format, columns }
+    }
+
+    pub fn start(&self) -> Result<Vec<u8>, BoxError> {
+        match self.format {
+            TransferFormat::Ndjson => Ok(vec![]),
+            TransferFormat::Json => Ok(b"[".to_vec()),
+            TransferFormat::Csv => csv_row(&self.columns),
+        }
+    }
+
+    pub fn record(&self, index: usize, record: &Record) -> Result<Vec<u8>, BoxError> {
+        match self.format {
+            TransferFormat::Ndjson => {
+                let mut line = serde_json::to_vec(record)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+            TransferFormat::Json => {
+                let mut data = if index == 0 { vec![] } else { b",".to_vec() };
+                data.append(&mut serde_json::to_vec(record)?);
+                Ok(data)
+            }
+            TransferFormat::Csv => {
+                let cells: Vec<_> = self
+                    .columns
+                    .iter()
+                    .map(|column| match record.get(column) {
+                        None | Some(Value::Null) => String::new(),
+                        Some(Value::String(value)) => value.to_string(),
+                        Some(value) => value.to_string(),
+                    })
+                    .collect();
+                csv_row(&cells)
+            }
+        }
+    }
+
+    pub fn end(&self) -> Vec<u8> {
+        match self.format {
+            TransferFormat::Json => b"]\n".to_vec(),
+            TransferFormat::Ndjson | TransferFormat::Csv => vec![],
+        }
+    }
+}
+
+fn csv_row(cells: &[String]) -> Result<Vec<u8>, BoxError> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(cells)?;
+    Ok(writer.into_inner()?)
+}
+
+/// Parses the body of an import as it is read, handing over one record at a
+/// time so that the raw body never has to be held in memory. Csv cells are
+/// all read as strings, they get their types from `parse_cell` once the
+/// collection is known
+pub fn decode<R: Read>(
+    format: TransferFormat,
+    reader: R,
+    mut on_record: impl FnMut(Record) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match format {
+        TransferFormat::Ndjson => {
+            for (index, line) in BufReader::new(reader).lines().enumerate() {
+                let line = line.map_err(|e| format_err!("Line {}: {}", index + 1, e))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = serde_json::from_str(&line)
+                    .map_err(|e| format_err!("Line {}: {}", index + 1, e))?;
+                on_record(record)?;
+            }
+        }
+        TransferFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_reader(reader);
+            (&mut deserializer).deserialize_seq(RecordVisitor(on_record))?;
+            deserializer.end()?;
+        }
+        TransferFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            let headers = reader.headers()?.clone();
+
+            for row in reader.records() {
+                let mut record = Map::new();
+                for (column, cell) in headers.iter().zip(row?.iter()) {
+                    // An empty cell is a missing value
+                    if !cell.is_empty() {
+                        record.insert(column.to_string(), Value::String(cell.to_string()));
+                    }
+                }
+                on_record(record)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a json array one element at a time, instead of building the whole
+/// array first
+struct RecordVisitor<F>(F);
+
+impl<'de, F: FnMut(Record) -> Result<(), Error>> Visitor<'de> for RecordVisitor<F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of objects")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(record) = seq.next_element()? {
+            (self.0)(record).map_err(DeError::custom)?;
+        }
+        Ok(())
+    }
+}
+
+/// Csv has no types, so cells are converted using the type of their field in
+/// the collection definition
+pub fn parse_cell(cell: &str, field_type: &Type) -> Result<Value, Error> {
+    Ok(match field_type {
+        Type::NonNullType(inner) => return parse_cell(cell, inner),
+        Type::ListType(_) => serde_json::from_str(cell)?,
+        Type::NamedType(name) => match name.as_str() {
+            "Int" | "i32" => Value::from(cell.parse::<i64>()?),
+            "Float" => Value::from(cell.parse::<f64>()?),
+            "Boolean" => Value::Bool(cell.parse()?),
+            _ => Value::String(cell.to_string()),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transfer::{
+        records::{
+            decode,
+            parse_cell,
+            to_record,
+            Encoder,
+        },
+        transfer_format::TransferFormat,
+    };
+    use graphql_parser::schema::Type;
+    use serde_json::Value;
+    use shelf_database::Document;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    #[test]
+    fn csv_should_read_back_what_it_wrote() {
+        let mut fields = HashMap::new();
+        fields.insert("model".to_string(), Value::from("Model S, Plaid"));
+        fields.insert("seats".to_string(), Value::from(5));
+        let document = Document::new(Uuid::new_v4(), fields);
+
+        let encoder = Encoder::new(
+            TransferFormat::Csv,
+            vec![
+                "id".to_string(),
+                "model".to_string(),
+                "seats".to_string(),
+                "color".to_string(),
+            ],
+        );
+        let mut data = encoder.start().unwrap();
+        data.append(&mut encoder.record(0, &to_record(None, &document)).unwrap());
+
+        let mut records = vec![];
+        decode(TransferFormat::Csv, &data[..], |record| {
+            records.push(record);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["id"], Value::from(document.id.to_string()));
+        assert_eq!(records[0]["model"], Value::from("Model S, Plaid"));
+        assert!(
+            !records[0].contains_key("color"),
+            "Empty cells should be left out"
+        );
+
+        let seats = parse_cell(
+            records[0]["seats"].as_str().unwrap(),
+            &Type::NonNullType(Box::new(Type::NamedType("Int".to_string()))),
+        )
+        .unwrap();
+        assert_eq!(seats, Value::from(5));
+    }
+}
@@ -0,0 +1,44 @@
+use failure::Error;
+
+/// The formats documents can be exported and imported in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferFormat {
+    /// One json object per line, the default
+    Ndjson,
+    /// A single json array
+    Json,
+    /// A header row with the field names followed by one row per document
+    Csv,
+}
+
+impl TransferFormat {
+    /// Reads the `format` query parameter, ndjson is used if it is left out
+    pub fn from_query(query: Option<&str>) -> Result<Self, Error> {
+        let format = query
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|i| {
+                let mut parts = i.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("format"), Some(value)) => Some(value),
+                    _ => None,
+                }
+            })
+            .next();
+
+        match format {
+            None | Some("ndjson") => Ok(TransferFormat::Ndjson),
+            Some("json") => Ok(TransferFormat::Json),
+            Some("csv") => Ok(TransferFormat::Csv),
+            Some(other) => bail!("Unknown format \"{}\", expected ndjson, json or csv", other),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            TransferFormat::Ndjson => "application/x-ndjson",
+            TransferFormat::Json => "application/json",
+            TransferFormat::Csv => "text/csv",
+        }
+    }
+}
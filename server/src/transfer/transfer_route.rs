@@ -0,0 +1,106 @@
+use crate::{
+    context::Context,
+    transfer::{
+        export::export,
+        import::import,
+        transfer_format::TransferFormat,
+    },
+};
+use hyper::{
+    header,
+    header::HeaderValue,
+    Body,
+    Method,
+    Request,
+    Response,
+    StatusCode,
+};
+use serde_json::Value;
+use shelf_database::{
+    Cache,
+    Store,
+};
+use std::convert::Infallible;
+
+const EXPORT_PREFIX: &str = "/admin/export/";
+const IMPORT_PREFIX: &str = "/admin/import/";
+
+pub fn is_transfer_path(path: &str) -> bool {
+    path.starts_with(EXPORT_PREFIX) || path.starts_with(IMPORT_PREFIX)
+}
+
+/// Handles `GET /admin/export/{schema}[/{collection}]` and
+/// `POST /admin/import/{schema}[/{collection}]`, the format is picked with
+/// the `format` query parameter
+pub async fn transfer_route<C: Cache, S: Store>(
+    context: Context<C, S>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_string();
+    let format = match TransferFormat::from_query(req.uri().query()) {
+        Ok(format) => format,
+        Err(err) => {
+            return Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": format!("{}", err) }),
+            ))
+        }
+    };
+
+    let result = if req.method() == Method::GET && path.starts_with(EXPORT_PREFIX) {
+        let (schema_name, collection_name) = target(&path[EXPORT_PREFIX.len()..]);
+        export(
+            &context.logger,
+            &context.db,
+            schema_name,
+            collection_name,
+            format,
+        )
+        .await
+    } else if req.method() == Method::POST && path.starts_with(IMPORT_PREFIX) {
+        let (schema_name, collection_name) = target(&path[IMPORT_PREFIX.len()..]);
+        import(
+            &context.logger,
+            &context.db,
+            schema_name,
+            collection_name,
+            format,
+            req.into_body(),
+            context.config.import_max_size_mb * 1024 * 1024,
+        )
+        .await
+        .map(|imported| json_response(StatusCode::OK, json!({ "imported": imported })))
+    } else {
+        return Ok(json_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            json!({ "error": "Exports are fetched with GET and imports sent with POST" }),
+        ));
+    };
+
+    match result {
+        Ok(response) => Ok(response),
+        Err(err) => {
+            warn!(context.logger, "Failed to transfer documents"; "path" => &path, "error" => format!("{}", err));
+            Ok(json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "error": format!("{}", err) }),
+            ))
+        }
+    }
+}
+
+/// Splits `{schema}/{collection}` where the collection is optional
+fn target(path: &str) -> (&str, Option<&str>) {
+    let mut parts = path.trim_end_matches('/').splitn(2, '/');
+    (parts.next().unwrap_or_default(), parts.next())
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(body.to_string()));
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    resp
+}
@@ -1,4 +1,7 @@
-use crate::util::parse_graphql_response::parse_graphql_response;
+use crate::{
+    context::Context,
+    util::parse_graphql_response::parse_graphql_response,
+};
 use chrono::Utc;
 use hyper::{
     body::to_bytes,
@@ -10,11 +13,20 @@ use hyper::{
     StatusCode,
 };
 use juniper::{
-    http::GraphQLRequest,
+    http::{
+        GraphQLRequest,
+        GraphQLResponse,
+    },
     DefaultScalarValue,
+    FieldError,
     GraphQLTypeAsync,
     RootNode,
 };
+use shelf_database::{
+    Cache,
+    RevisionConflict,
+    Store,
+};
 use std::{
     convert::Infallible,
     sync::Arc,
@@ -22,12 +34,13 @@ use std::{
 };
 
 pub async fn graphql_post<
-    Q: GraphQLTypeAsync<DefaultScalarValue, Context = Ctxt>,
-    M: GraphQLTypeAsync<DefaultScalarValue, Context = Ctxt>,
-    Ctxt: Send + Sync,
+    Q: GraphQLTypeAsync<DefaultScalarValue, Context = Context<C, S>>,
+    M: GraphQLTypeAsync<DefaultScalarValue, Context = Context<C, S>>,
+    C: Cache,
+    S: Store,
 >(
     root_node: Arc<RootNode<'_, Q, M>>,
-    context: Ctxt,
+    context: Context<C, S>,
     req: Request<Body>,
 ) -> Result<Response<Body>, Infallible>
 where
@@ -39,7 +52,18 @@ where
     match to_bytes(req.into_body()).await {
         Ok(body) => match serde_json::from_slice::<GraphQLRequest<DefaultScalarValue>>(&body) {
             Ok(request) => {
-                let resp = request.execute_async(&root_node, &context).await;
+                let mut resp = request.execute_async(&root_node, &context).await;
+
+                // Everything staged by the mutations of this request is applied in one go
+                if let Err(err) = context.commit().await {
+                    error!(context.logger, "Failed to commit transaction"; "error" => format!("{}", err));
+                    let extensions = if err.downcast_ref::<RevisionConflict>().is_some() {
+                        graphql_value!({ "conflict": "The document has been changed by someone else" })
+                    } else {
+                        graphql_value!({ "transaction": "The transaction was rolled back" })
+                    };
+                    resp = GraphQLResponse::error(FieldError::new(format!("{}", err), extensions));
+                }
 
                 Ok(parse_graphql_response(resp, start_time, start_instant))
             }
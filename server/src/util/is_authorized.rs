@@ -0,0 +1,86 @@
+use hyper::{
+    header,
+    Body,
+    Request,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
+
+/// Checks that a request carries the admin token as a bearer token. Every
+/// request passes if no token is configured
+///
+/// # Arguments
+///
+/// * `req` - The request to check
+/// * `token` - The configured admin token
+pub fn is_authorized(req: &Request<Body>, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return true,
+    };
+
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|i| i.to_str().ok())
+        .and_then(|i| {
+            if i.starts_with("Bearer ") {
+                Some(&i["Bearer ".len()..])
+            } else {
+                None
+            }
+        });
+
+    match given {
+        Some(given) => constant_time_eq(given.as_bytes(), token.as_bytes()),
+        None => false,
+    }
+}
+
+/// Compares the SHA-256 digests of both values without stopping at the first
+/// difference. The digests always have the same length, so the time it takes
+/// tells nothing about the token, not even how long it is
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let a = Sha256::digest(a);
+    let b = Sha256::digest(b);
+    a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::is_authorized::is_authorized;
+    use hyper::{
+        header,
+        Body,
+        Request,
+    };
+
+    fn request(authorization: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri("/admin/graphql");
+        if let Some(authorization) = authorization {
+            builder = builder.header(header::AUTHORIZATION, authorization);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn it_only_lets_through_requests_with_the_admin_token() {
+        assert!(is_authorized(&request(None), None));
+        assert!(is_authorized(
+            &request(Some("Bearer secret")),
+            Some("secret")
+        ));
+        assert!(!is_authorized(&request(None), Some("secret")));
+        assert!(!is_authorized(
+            &request(Some("Bearer secreT")),
+            Some("secret")
+        ));
+        assert!(!is_authorized(
+            &request(Some("Bearer secretsecret")),
+            Some("secret")
+        ));
+        assert!(!is_authorized(&request(Some("secret")), Some("secret")));
+    }
+}
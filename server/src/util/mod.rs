@@ -1,10 +1,12 @@
 mod graphql_get;
 mod graphql_post;
+mod is_authorized;
 mod parse_graphql_response;
 mod playground;
 
 pub use self::{
     graphql_get::graphql_get,
     graphql_post::graphql_post,
+    is_authorized::is_authorized,
     playground::playground,
 };
@@ -0,0 +1,359 @@
+use crate::webhooks::sign_payload::sign_payload;
+use chrono::Utc;
+use failure::Error;
+use hyper::{
+    client::HttpConnector,
+    header,
+    Body,
+    Client,
+    Method,
+    Request,
+};
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use shelf_database::{
+    Change,
+    DeadLetter,
+    Webhook,
+    WebhookEvent,
+};
+use slog::Logger;
+use std::time::Duration;
+use tokio::time::{
+    delay_for,
+    timeout,
+};
+use uuid::Uuid;
+
+pub type WebhookClient = Client<HttpsConnector<HttpConnector>>;
+
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    /// How long a single attempt may take
+    pub timeout: Duration,
+}
+
+/// Posts a change to a webhook, retrying with an exponential backoff. If all
+/// attempts fail the delivery is returned as a dead letter
+pub async fn deliver(
+    logger: &Logger,
+    client: &WebhookClient,
+    webhook: &Webhook,
+    change: &Change,
+    policy: RetryPolicy,
+) -> Result<(), DeadLetter> {
+    let event = match WebhookEvent::from_change(&change.kind) {
+        Some(event) => event,
+        None => return Ok(()),
+    };
+
+    let delivery_id = Uuid::new_v4();
+    let logger = logger
+        .new(o!("webhook_id" => webhook.id.to_string(), "delivery_id" => delivery_id.to_string()));
+
+    let payload = payload(webhook, event, delivery_id, change);
+    let body = serde_json::to_vec(&payload).expect("Changes are always serializable");
+    let signature = sign_payload(&webhook.secret, &body);
+
+    let max_attempts = policy.max_attempts.max(1);
+    let mut last_error = String::new();
+
+    for attempt in 1..=max_attempts {
+        match send(
+            client,
+            webhook,
+            event,
+            delivery_id,
+            &signature,
+            &body,
+            policy.timeout,
+        )
+        .await
+        {
+            Ok(()) => {
+                debug!(logger, "Delivered webhook"; "attempt" => attempt);
+                return Ok(());
+            }
+            Err(err) => {
+                warn!(logger, "Failed to deliver webhook"; "attempt" => attempt, "error" => format!("{}", err));
+                last_error = format!("{}", err);
+
+                if attempt < max_attempts {
+                    delay_for(policy.backoff * 2_u32.pow(attempt - 1)).await;
+                }
+            }
+        }
+    }
+
+    error!(logger, "Giving up on webhook delivery, moving it to the dead letter log"; "url" => &webhook.url);
+
+    Err(DeadLetter {
+        delivery_id,
+        webhook_id: webhook.id,
+        url: webhook.url.to_string(),
+        payload,
+        attempts: max_attempts,
+        last_error,
+        failed_at: Utc::now(),
+    })
+}
+
+/// Turns a change that could not be queued for a webhook into a dead letter,
+/// without attempting to deliver it
+pub fn undelivered(webhook: &Webhook, change: &Change, reason: &str) -> Option<DeadLetter> {
+    let event = WebhookEvent::from_change(&change.kind)?;
+    let delivery_id = Uuid::new_v4();
+
+    Some(DeadLetter {
+        delivery_id,
+        webhook_id: webhook.id,
+        url: webhook.url.to_string(),
+        payload: payload(webhook, event, delivery_id, change),
+        attempts: 0,
+        last_error: reason.to_string(),
+        failed_at: Utc::now(),
+    })
+}
+
+fn payload(webhook: &Webhook, event: WebhookEvent, delivery_id: Uuid, change: &Change) -> Value {
+    json!({
+        "deliveryId": delivery_id,
+        "webhookId": webhook.id,
+        "event": event.name(),
+        "change": change,
+    })
+}
+
+async fn send(
+    client: &WebhookClient,
+    webhook: &Webhook,
+    event: WebhookEvent,
+    delivery_id: Uuid,
+    signature: &str,
+    body: &[u8],
+    limit: Duration,
+) -> Result<(), Error> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(webhook.url.as_str())
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-Shelf-Event", event.name())
+        .header("X-Shelf-Delivery", delivery_id.to_string())
+        .header("X-Shelf-Signature", format!("sha256={}", signature))
+        .body(Body::from(body.to_vec()))?;
+
+    let response = match timeout(limit, client.request(request)).await {
+        Ok(response) => response?,
+        Err(_) => bail!("Webhook did not respond within {:?}", limit),
+    };
+
+    if !response.status().is_success() {
+        bail!("Webhook responded with status {}", response.status());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::webhooks::{
+        deliver::{
+            deliver,
+            RetryPolicy,
+        },
+        sign_payload::sign_payload,
+    };
+    use chrono::Utc;
+    use hyper::{
+        body::to_bytes,
+        service::{
+            make_service_fn,
+            service_fn,
+        },
+        Body,
+        Client,
+        Request,
+        Response,
+        Server,
+        StatusCode,
+    };
+    use hyper_tls::HttpsConnector;
+    use shelf_database::{
+        Change,
+        ChangeKind,
+        Document,
+        Webhook,
+    };
+    use sloggers::{
+        null::NullLoggerBuilder,
+        Build,
+    };
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::{
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    };
+    use tokio::time::{
+        delay_for,
+        timeout,
+    };
+    use uuid::Uuid;
+
+    fn change() -> Change {
+        Change {
+            sequence: 1,
+            timestamp: Utc::now(),
+            kind: ChangeKind::DocumentInserted {
+                schema_id: Uuid::nil(),
+                collection_id: Uuid::nil(),
+                after: Arc::new(Document::new(Uuid::new_v4(), HashMap::new())),
+            },
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_retries_failed_deliveries_and_signs_them() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let make_svc = {
+            let received = Arc::clone(&received);
+            make_service_fn(move |_conn| {
+                let received = Arc::clone(&received);
+                let attempts = Arc::clone(&attempts);
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let received = Arc::clone(&received);
+                        let attempts = Arc::clone(&attempts);
+                        async move {
+                            let signature = req.headers()["X-Shelf-Signature"]
+                                .to_str()
+                                .unwrap()
+                                .to_string();
+                            let body = to_bytes(req.into_body()).await.unwrap();
+                            received.lock().unwrap().push((signature, body.to_vec()));
+
+                            // The first attempt fails, so that we can see that it is retried
+                            let mut resp = Response::new(Body::empty());
+                            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                                *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                            }
+                            Ok::<_, Infallible>(resp)
+                        }
+                    }))
+                }
+            })
+        };
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let webhook = Webhook::new(
+            format!("http://{}/hook", addr),
+            "secret".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+
+        assert!(
+            deliver(&logger, &client, &webhook, &change(), policy())
+                .await
+                .is_ok(),
+            "The delivery should succeed on the second attempt"
+        );
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2, "The failed delivery was not retried");
+        for (signature, body) in received.iter() {
+            assert_eq!(
+                signature,
+                &format!("sha256={}", sign_payload("secret", body))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_returns_a_dead_letter_when_all_attempts_fail() {
+        let logger = NullLoggerBuilder.build().unwrap();
+
+        // Nothing is listening on the discard port
+        let webhook = Webhook::new(
+            "http://127.0.0.1:9/hook".to_string(),
+            "secret".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+
+        let dead_letter = deliver(&logger, &client, &webhook, &change(), policy())
+            .await
+            .unwrap_err();
+
+        assert_eq!(dead_letter.attempts, 3);
+        assert_eq!(dead_letter.webhook_id, webhook.id);
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_on_receivers_that_do_not_respond() {
+        let logger = NullLoggerBuilder.build().unwrap();
+
+        let make_svc = make_service_fn(|_conn| async {
+            Ok::<_, Infallible>(service_fn(|_req: Request<Body>| async {
+                delay_for(Duration::from_secs(60)).await;
+                Ok::<_, Infallible>(Response::new(Body::empty()))
+            }))
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let webhook = Webhook::new(
+            format!("http://{}/hook", addr),
+            "secret".to_string(),
+            None,
+            None,
+            vec![],
+        );
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            backoff: Duration::from_millis(10),
+            timeout: Duration::from_millis(50),
+        };
+
+        let dead_letter = timeout(
+            Duration::from_secs(5),
+            deliver(&logger, &client, &webhook, &change(), policy),
+        )
+        .await
+        .expect("The delivery should time out")
+        .unwrap_err();
+
+        assert_eq!(dead_letter.attempts, 2);
+        assert!(dead_letter.last_error.contains("did not respond"));
+    }
+}
@@ -0,0 +1,5 @@
+mod deliver;
+mod sign_payload;
+mod start_webhook_dispatcher;
+
+pub use self::start_webhook_dispatcher::start_webhook_dispatcher;
@@ -0,0 +1,32 @@
+use hmac::{
+    Hmac,
+    Mac,
+};
+use sha2::Sha256;
+
+/// Signs a webhook payload with HMAC-SHA256, the signature is hex encoded
+///
+/// # Arguments
+///
+/// * `secret` - The secret shared with the receiver of the webhook
+/// * `payload` - The exact bytes that are sent as the request body
+pub fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.input(payload);
+    hex::encode(mac.result().code())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::webhooks::sign_payload::sign_payload;
+
+    #[test]
+    fn it_produces_a_hmac_sha256_signature() {
+        // Test case 2 from RFC 4231
+        assert_eq!(
+            sign_payload("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
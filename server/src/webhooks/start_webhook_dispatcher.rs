@@ -0,0 +1,145 @@
+use crate::webhooks::deliver::{
+    deliver,
+    undelivered,
+    RetryPolicy,
+    WebhookClient,
+};
+use hyper::{
+    Body,
+    Client,
+};
+use hyper_tls::HttpsConnector;
+use shelf_config::Config;
+use shelf_database::{
+    Cache,
+    Change,
+    Database,
+    DeadLetter,
+    Store,
+    Webhook,
+};
+use slog::Logger;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+};
+use tokio::{
+    sync::{
+        broadcast::RecvError,
+        mpsc::{
+            channel,
+            Sender,
+        },
+    },
+    task::JoinHandle,
+};
+use uuid::Uuid;
+
+type Queue = Sender<(Webhook, Change)>;
+
+/// Listens to the change feed of the database and delivers all matching
+/// changes to the registered webhooks. Every webhook has its own queue and
+/// worker, so changes reach a receiver in order and a slow receiver does not
+/// hold up the others. Changes that do not fit in a full queue are moved to
+/// the dead letter log
+pub fn start_webhook_dispatcher<C: Cache, S: Store>(
+    logger: &Logger,
+    config: &Config,
+    db: Arc<Database<C, S>>,
+) -> JoinHandle<()> {
+    let logger = logger.new(o!("component" => "webhooks"));
+    let policy = RetryPolicy {
+        max_attempts: config.webhook_max_attempts,
+        backoff: config.webhook_retry_backoff,
+        timeout: config.webhook_timeout,
+    };
+    let queue_size = config.webhook_queue_size.max(1);
+
+    info!(logger, "📮 Starting webhook dispatcher");
+
+    tokio::spawn(async move {
+        let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+        let mut changes = db.changes();
+        let mut queues: HashMap<Uuid, Queue> = HashMap::new();
+
+        loop {
+            match changes.recv().await {
+                Ok(change) => {
+                    let webhooks = db.webhooks().await;
+                    // Dropping the queue of a removed webhook stops its worker
+                    // once it has delivered what was queued already
+                    queues.retain(|id, _| webhooks.iter().any(|w| w.id == *id));
+
+                    for webhook in webhooks {
+                        if !webhook.matches(&change.kind) {
+                            continue;
+                        }
+
+                        let queue = queues.entry(webhook.id).or_insert_with(|| {
+                            start_worker(&logger, &client, &db, policy, queue_size)
+                        });
+
+                        if let Err(err) = queue.try_send((webhook, change.clone())) {
+                            let closed = err.is_closed();
+                            let (webhook, change) = err.into_inner();
+                            let reason = if closed {
+                                // The worker is started again for the next change
+                                queues.remove(&webhook.id);
+                                "The delivery worker of the webhook has stopped"
+                            } else {
+                                "The delivery queue of the webhook was full"
+                            };
+                            warn!(logger, "Could not queue webhook delivery"; "webhook_id" => webhook.id.to_string(), "reason" => reason);
+
+                            if let Some(dead_letter) = undelivered(&webhook, &change, reason) {
+                                save_dead_letter(&logger, &db, &dead_letter).await;
+                            }
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    error!(logger, "Webhook dispatcher fell behind, changes were not delivered"; "skipped" => skipped);
+                }
+                Err(RecvError::Closed) => {
+                    info!(logger, "Change feed closed, stopping webhook dispatcher");
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Delivers the changes queued for a webhook one at a time, until the queue is
+/// dropped
+fn start_worker<C: Cache, S: Store>(
+    logger: &Logger,
+    client: &WebhookClient,
+    db: &Arc<Database<C, S>>,
+    policy: RetryPolicy,
+    queue_size: usize,
+) -> Queue {
+    let (queue, mut deliveries) = channel::<(Webhook, Change)>(queue_size);
+    let logger = logger.clone();
+    let client = client.clone();
+    let db = Arc::clone(db);
+
+    tokio::spawn(async move {
+        while let Some((webhook, change)) = deliveries.recv().await {
+            if let Err(dead_letter) = deliver(&logger, &client, &webhook, &change, policy).await {
+                save_dead_letter(&logger, &db, &dead_letter).await;
+            }
+        }
+    });
+
+    queue
+}
+
+async fn save_dead_letter<C: Cache, S: Store>(
+    logger: &Logger,
+    db: &Database<C, S>,
+    dead_letter: &DeadLetter,
+) {
+    if let Err(err) = db.save_dead_letter(logger, dead_letter).await {
+        error!(logger, "Failed to save dead letter"; "error" => format!("{}", err));
+    }
+}
@@ -0,0 +1,23 @@
+use failure::Error;
+use hyper::{
+    header,
+    http::request::Builder,
+    Method,
+    Request,
+};
+use shelf_config::Config;
+
+/// The url of an admin endpoint on the server described by the config
+pub fn admin_url(config: &Config, path: &str) -> Result<String, Error> {
+    Ok(format!("http://{}/admin/{}", config.host()?, path))
+}
+
+/// Starts a request to an admin endpoint, carrying the admin token if one is
+/// configured
+pub fn admin_request(config: &Config, method: Method, url: &str) -> Builder {
+    let builder = Request::builder().method(method).uri(url);
+    match &config.admin_token {
+        Some(token) => builder.header(header::AUTHORIZATION, format!("Bearer {}", token)),
+        None => builder,
+    }
+}
@@ -0,0 +1,56 @@
+use crate::commands::admin_url::{
+    admin_request,
+    admin_url,
+};
+use colored::*;
+use failure::Error;
+use hyper::{
+    body::to_bytes,
+    header,
+    Body,
+    Client,
+    Method,
+};
+use serde_json::{
+    json,
+    Value,
+};
+use shelf_config::Config;
+use slog::Logger;
+
+const BACKUP_MUTATION: &str = "mutation Backup($name: String!) { backup(name: $name) { path createdAt collections documents } }";
+
+/// Asks the running server to write a backup into its backup folder. It is
+/// the server that writes it, that way it is consistent with everything
+/// committed up to that point
+pub async fn backup(logger: &Logger, config: &Config, name: &str) -> Result<(), Error> {
+    let url = admin_url(config, "graphql")?;
+
+    info!(logger, "💾 Requesting backup"; "server" => &url, "name" => name);
+
+    let body = json!({
+        "query": BACKUP_MUTATION,
+        "variables": { "name": name },
+    });
+    let request = admin_request(config, Method::POST, &url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&body)?))?;
+
+    let response = match Client::new().request(request).await {
+        Ok(response) => response,
+        Err(err) => {
+            error!(logger, "Could not reach the server, backups can only be taken while shelf is running"; "error" => format!("{}", err));
+            return Err(err.into());
+        }
+    };
+    let response: Value = serde_json::from_slice(&to_bytes(response.into_body()).await?)?;
+
+    if let Some(message) = response["errors"][0]["message"].as_str() {
+        bail!("The server failed to write the backup: {}", message);
+    }
+
+    let backup = &response["data"]["backup"];
+    info!(logger, "{}", "Backup written".green(); "path" => backup["path"].as_str(), "created_at" => backup["createdAt"].as_str(), "collections" => backup["collections"].as_i64(), "documents" => backup["documents"].as_i64());
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::commands::admin_url::{
+    admin_request,
+    admin_url,
+};
+use failure::Error;
+use futures::StreamExt;
+use hyper::{
+    body::to_bytes,
+    Body,
+    Client,
+    Method,
+};
+use serde_json::Value;
+use shelf_config::Config;
+use slog::Logger;
+use tokio::{
+    fs::File,
+    io::{
+        stdout,
+        AsyncWrite,
+        AsyncWriteExt,
+    },
+};
+
+/// Exports a schema, or a single collection, from the running server to a
+/// file or to stdout
+pub async fn export(
+    logger: &Logger,
+    config: &Config,
+    schema_name: &str,
+    collection_name: Option<&str>,
+    format: &str,
+    output: Option<&str>,
+) -> Result<(), Error> {
+    let target = match collection_name {
+        Some(collection_name) => format!("{}/{}", schema_name, collection_name),
+        None => schema_name.to_string(),
+    };
+    let url = admin_url(config, &format!("export/{}?format={}", target, format))?;
+
+    info!(logger, "📤 Exporting documents"; "server" => &url);
+    let request = admin_request(config, Method::GET, &url).body(Body::empty())?;
+    let response = Client::new().request(request).await?;
+    if !response.status().is_success() {
+        let body: Value = serde_json::from_slice(&to_bytes(response.into_body()).await?)?;
+        bail!(
+            "The export failed: {}",
+            body["error"].as_str().unwrap_or("unknown error")
+        );
+    }
+
+    let mut writer: Box<dyn AsyncWrite + Unpin> = match output {
+        Some(path) => Box::new(File::create(path).await?),
+        None => Box::new(stdout()),
+    };
+
+    // Written as it arrives, so that large exports are never held in memory
+    let mut body = response.into_body();
+    while let Some(chunk) = body.next().await {
+        writer.write_all(&chunk?).await?;
+    }
+    writer.flush().await?;
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use colored::*;
+use failure::Error;
+use shelf_config::{
+    Config,
+    StoreKind,
+};
+use shelf_file_store::{
+    fsck as check_data_folder,
+    Encryption,
+};
+use shelf_sqlite_store::DATABASE_FILE;
+use slog::Logger;
+use std::path::Path;
+
+/// Returns if the data folder is usable, that is if nothing damaged is left
+/// in it
+pub async fn fsck(logger: &Logger, config: &Config, quarantine: bool) -> Result<bool, Error> {
+    match config.store {
+        StoreKind::File => {}
+        StoreKind::Sqlite => {
+            warn!(logger, "{} fsck only checks the files of the file store, run {} on {} with the sqlite3 tool instead", "Nothing was checked:".yellow(), "PRAGMA integrity_check".yellow(), Path::new(&config.data_folder).join(DATABASE_FILE).display(); "store" => "sqlite");
+            return Ok(true);
+        }
+        StoreKind::S3 => {
+            warn!(logger, "{} fsck only checks the files of the file store, the objects in the bucket are checked by S3 itself", "Nothing was checked:".yellow(); "store" => "s3", "bucket" => &config.s3_bucket);
+            return Ok(true);
+        }
+    }
+
+    info!(logger, "🔍 Checking data folder");
+    let encryption = Encryption::from_config(config)?;
+    let report = check_data_folder(
+        logger,
+        Path::new(&config.data_folder),
+        quarantine,
+        encryption.as_ref(),
+    )
+    .await?;
+
+    if report.is_clean() {
+        info!(logger, "{}", "All files are intact".green(); "checked" => report.checked);
+        return Ok(true);
+    }
+
+    for damaged in &report.damaged {
+        let state = if damaged.quarantined {
+            "quarantined"
+        } else {
+            "damaged"
+        };
+        error!(logger, "{} {}", state.red(), damaged.path.display(); "reason" => &damaged.reason);
+    }
+
+    if quarantine {
+        warn!(logger, "Moved {} damaged files to quarantine, the documents in them are missing until restored from a backup", report.damaged.len(); "checked" => report.checked);
+        Ok(true)
+    } else {
+        crit!(logger, "Found {} damaged files, run with {} to move them out of the way", report.damaged.len(), "--quarantine".yellow(); "checked" => report.checked);
+        Ok(false)
+    }
+}
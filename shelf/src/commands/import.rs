@@ -0,0 +1,70 @@
+use crate::commands::admin_url::{
+    admin_request,
+    admin_url,
+};
+use colored::*;
+use failure::Error;
+use futures::stream;
+use hyper::{
+    body::to_bytes,
+    Body,
+    Client,
+    Method,
+};
+use serde_json::Value;
+use shelf_config::Config;
+use slog::Logger;
+use tokio::{
+    fs::File,
+    io::AsyncReadExt,
+};
+
+/// How much of the file is read at a time while it is sent
+const READ_SIZE: usize = 64 * 1024;
+
+/// Sends a file to the running server to be imported into a schema, or into a
+/// single collection. The file is streamed, so it is never read into memory
+/// as a whole
+pub async fn import(
+    logger: &Logger,
+    config: &Config,
+    schema_name: &str,
+    collection_name: Option<&str>,
+    format: &str,
+    path: &str,
+) -> Result<(), Error> {
+    let target = match collection_name {
+        Some(collection_name) => format!("{}/{}", schema_name, collection_name),
+        None => schema_name.to_string(),
+    };
+    let url = admin_url(config, &format!("import/{}?format={}", target, format))?;
+
+    info!(logger, "📥 Importing documents"; "server" => &url, "path" => path);
+    let file = File::open(path).await?;
+    let chunks = stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buffer = vec![0; READ_SIZE];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(len) => {
+                buffer.truncate(len);
+                Some((Ok(buffer), Some(file)))
+            }
+            Err(err) => Some((Err(err), None)),
+        }
+    });
+    let request = admin_request(config, Method::POST, &url).body(Body::wrap_stream(chunks))?;
+
+    let response = Client::new().request(request).await?;
+    let status = response.status();
+    let body: Value = serde_json::from_slice(&to_bytes(response.into_body()).await?)?;
+    if !status.is_success() {
+        bail!(
+            "The import failed: {}",
+            body["error"].as_str().unwrap_or("unknown error")
+        );
+    }
+
+    info!(logger, "{}", "Import done".green(); "documents" => body["imported"].as_u64());
+    Ok(())
+}
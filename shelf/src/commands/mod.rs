@@ -0,0 +1,91 @@
+mod admin_url;
+mod backup;
+mod export;
+mod fsck;
+mod import;
+mod restore;
+mod rotate_key;
+mod serve;
+mod upgrade_data;
+
+pub use self::{
+    backup::backup,
+    export::export,
+    fsck::fsck,
+    import::import,
+    restore::restore,
+    rotate_key::rotate_key,
+    serve::serve,
+    upgrade_data::upgrade_data,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "The GraphQL database. Makes storing data easy!")]
+pub struct Opt {
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Starts the database, this is what happens if no command is given
+    Serve,
+    /// Verifies every file in the data folder and reports the damaged ones.
+    /// Shelf must not be running while this is done
+    Fsck {
+        /// Moves damaged files into the quarantine folder inside the data
+        /// folder, so that the database can start without them
+        #[structopt(long)]
+        quarantine: bool,
+    },
+    /// Asks the running server to write a consistent copy of all data into
+    /// its backup folder. The backup is a folder, or an archive if the name
+    /// ends with .tar, .tar.gz or .tgz
+    Backup { name: String },
+    /// Exports the documents of a schema, or of a single collection, from
+    /// the running server
+    Export {
+        schema: String,
+        /// Exports only this collection instead of the whole schema
+        collection: Option<String>,
+        /// ndjson, json or csv
+        #[structopt(long, default_value = "ndjson")]
+        format: String,
+        /// Writes to this file instead of stdout
+        #[structopt(long, short)]
+        output: Option<String>,
+    },
+    /// Imports documents into a schema, or into a single collection, on the
+    /// running server. Every document is validated before anything is written
+    Import {
+        schema: String,
+        /// Imports into this collection, without it every record needs a
+        /// __collection field
+        collection: Option<String>,
+        /// ndjson, json or csv
+        #[structopt(long, default_value = "ndjson")]
+        format: String,
+        /// The file to import
+        #[structopt(long, short)]
+        input: String,
+    },
+    /// Loads a backup into an empty data folder. Shelf must not be running
+    /// while this is done
+    Restore { path: String },
+    /// Encrypts every file in the data folder with a new key, the current
+    /// key is the one in the config. Shelf must not be running while this is
+    /// done
+    RotateKey {
+        /// A file holding the new key, 32 bytes encoded in base64
+        #[structopt(long)]
+        new_key_file: Option<String>,
+        /// The name of an environment variable holding the new key
+        #[structopt(long)]
+        new_key_env: Option<String>,
+    },
+    /// Upgrades a data folder written by an older version of shelf to the
+    /// current format, after copying it to a backup next to it. Shelf must
+    /// not be running while this is done
+    UpgradeData,
+}
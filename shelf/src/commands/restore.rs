@@ -0,0 +1,45 @@
+use colored::*;
+use failure::Error;
+use shelf_config::{
+    Config,
+    StoreKind,
+};
+use shelf_database::Snapshot;
+use shelf_file_store::FileStore;
+use shelf_s3_store::S3Store;
+use shelf_sqlite_store::SqliteStore;
+use slog::Logger;
+use std::{
+    fs::read_dir,
+    path::Path,
+};
+
+/// Loads a backup into the data folder, which has to be empty so that nothing
+/// is mixed with the restored data
+pub async fn restore(logger: &Logger, config: &Config, path: &str) -> Result<(), Error> {
+    let data_folder = Path::new(&config.data_folder);
+    if data_folder.is_dir() && read_dir(data_folder)?.next().is_some() {
+        crit!(logger, "The data folder is not empty, a backup can only be restored into an empty data folder"; "data_folder" => &config.data_folder);
+        bail!("The data folder {} is not empty", config.data_folder);
+    }
+
+    info!(logger, "📦 Restoring backup"; "path" => path);
+    let path = Path::new(path);
+    let report = match config.store {
+        StoreKind::File => {
+            let store = FileStore::new(logger, config).await?;
+            Snapshot::restore(logger, &store, path).await?
+        }
+        StoreKind::Sqlite => {
+            let store = SqliteStore::new(logger, config).await?;
+            Snapshot::restore(logger, &store, path).await?
+        }
+        StoreKind::S3 => {
+            let store = S3Store::new(logger, config).await?;
+            Snapshot::restore(logger, &store, path).await?
+        }
+    };
+
+    info!(logger, "{}", "Backup restored".green(); "created_at" => report.created_at.to_rfc3339(), "collections" => report.collections, "documents" => report.documents);
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use colored::*;
+use failure::Error;
+use shelf_config::{
+    Config,
+    StoreKind,
+};
+use shelf_file_store::{
+    rotate_key as reencrypt_data_folder,
+    Encryption,
+};
+use slog::Logger;
+use std::path::Path;
+
+/// Encrypts the data folder with a new key. The current key, if any, is the
+/// one in the config
+pub async fn rotate_key(
+    logger: &Logger,
+    config: &Config,
+    new_key_file: Option<&str>,
+    new_key_env: Option<&str>,
+) -> Result<(), Error> {
+    if config.store != StoreKind::File {
+        bail!(
+            "Nothing was encrypted, only the file store encrypts its files and the {:?} store keeps its data as it is",
+            config.store
+        );
+    }
+
+    let new = match Encryption::load(config.encryption_cipher, new_key_file, new_key_env)? {
+        Some(new) => new,
+        None => bail!("The new key has to be given with --new-key-file or --new-key-env"),
+    };
+    let current = Encryption::from_config(config)?;
+
+    info!(logger, "\u{1f511} Encrypting data folder with the new key"; "data_folder" => &config.data_folder, "cipher" => format!("{:?}", config.encryption_cipher));
+    let rotated = reencrypt_data_folder(
+        logger,
+        Path::new(&config.data_folder),
+        current.as_ref(),
+        &new,
+    )
+    .await?;
+
+    info!(logger, "{}", "Data folder encrypted with the new key".green(); "files" => rotated);
+    warn!(
+        logger,
+        "Point {} or {} at the new key before starting shelf again",
+        "encryptionKeyFile".yellow(),
+        "encryptionKeyEnv".yellow()
+    );
+    Ok(())
+}
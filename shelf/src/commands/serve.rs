@@ -0,0 +1,63 @@
+use colored::*;
+use failure::Error;
+use graceful::SignalGuard;
+use shelf_config::{
+    Config,
+    StoreKind,
+};
+use shelf_database::{
+    Database,
+    Store,
+};
+use shelf_file_store::FileStore;
+use shelf_memory_cache::MemoryCache;
+use shelf_s3_store::S3Store;
+use shelf_server::Server;
+use shelf_sqlite_store::SqliteStore;
+use slog::Logger;
+
+pub async fn serve(logger: Logger, config: Config, signal_guard: SignalGuard) -> Result<(), Error> {
+    debug!(
+        logger,
+        "Running on: {} {}",
+        sys_info::os_type().unwrap().yellow(),
+        sys_info::os_release().unwrap().yellow()
+    );
+    match config.store {
+        StoreKind::File => {
+            let store = FileStore::new(&logger, &config).await?;
+            run(logger, config, signal_guard, store).await
+        }
+        StoreKind::Sqlite => {
+            let store = SqliteStore::new(&logger, &config).await?;
+            run(logger, config, signal_guard, store).await
+        }
+        StoreKind::S3 => {
+            let store = S3Store::new(&logger, &config).await?;
+            run(logger, config, signal_guard, store).await
+        }
+    }
+}
+
+async fn run<S: Store>(
+    logger: Logger,
+    config: Config,
+    signal_guard: SignalGuard,
+    store: S,
+) -> Result<(), Error> {
+    let cache = MemoryCache::new(
+        &logger,
+        config.cache_memory_budget_mb.map(|i| i * 1024 * 1024),
+    )
+    .await?;
+    let database = Database::new(&logger, &config, store, cache).await?;
+    let server = Server::start(&logger, &config, database).await?;
+
+    signal_guard.at_exit(move |_sig| {
+        info!(logger, "{}", "↘️ Initiating shutdown...".cyan());
+        server.stop();
+        info!(logger, "👋 Bye, Bye!");
+    });
+
+    Ok(())
+}
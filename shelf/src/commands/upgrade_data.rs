@@ -0,0 +1,29 @@
+use colored::*;
+use failure::Error;
+use shelf_config::{
+    Config,
+    StoreKind,
+};
+use shelf_file_store::{
+    upgrade_data_folder,
+    FORMAT_VERSION,
+};
+use slog::Logger;
+
+/// Upgrades a data folder written by an older version of shelf, without
+/// starting the server
+pub async fn upgrade_data(logger: &Logger, config: &Config) -> Result<(), Error> {
+    if config.store != StoreKind::File {
+        bail!("Only the file store keeps a data folder to upgrade");
+    }
+
+    match upgrade_data_folder(logger, config).await? {
+        Some(from) => {
+            info!(logger, "{}", "Data folder upgraded".green(); "from" => from, "to" => FORMAT_VERSION)
+        }
+        None => {
+            info!(logger, "The data folder is already up to date"; "format" => FORMAT_VERSION)
+        }
+    }
+    Ok(())
+}
@@ -2,15 +2,26 @@
 extern crate slog;
 #[macro_use]
 extern crate human_panic;
+#[macro_use]
+extern crate failure;
+
+mod commands;
 
-use colored::*;
+use crate::commands::{
+    backup,
+    export,
+    fsck,
+    import,
+    restore,
+    rotate_key,
+    serve,
+    upgrade_data,
+    Command,
+    Opt,
+};
 use failure::Error;
 use graceful::SignalGuard;
 use shelf_config::Config;
-use shelf_database::Database;
-use shelf_file_store::FileStore;
-use shelf_memory_cache::MemoryCache;
-use shelf_server::Server;
 use sloggers::{
     terminal::TerminalLoggerBuilder,
     types::Severity,
@@ -20,10 +31,12 @@ use std::{
     process,
     str::FromStr,
 };
+use structopt::StructOpt;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     setup_panic!();
+    let opt = Opt::from_args();
     let signal_guard = SignalGuard::new();
 
     let temp_log = TerminalLoggerBuilder::new()
@@ -39,22 +52,61 @@ async fn main() -> Result<(), Error> {
                 .build()
                 .unwrap();
 
-            debug!(
-                logger,
-                "Running on: {} {}",
-                sys_info::os_type().unwrap().yellow(),
-                sys_info::os_release().unwrap().yellow()
-            );
-            let store = FileStore::new(&logger, &config).await?;
-            let cache = MemoryCache::new(&logger).await?;
-            let database = Database::new(&logger, &config, store, cache).await?;
-            let server = Server::start(&logger, &config, database).await?;
-
-            signal_guard.at_exit(move |_sig| {
-                info!(logger, "{}", "↘️ Initiating shutdown...".cyan());
-                server.stop();
-                info!(logger, "👋 Bye, Bye!");
-            });
+            match opt.command.unwrap_or(Command::Serve) {
+                Command::Serve => serve(logger, config, signal_guard).await?,
+                Command::Fsck { quarantine } => {
+                    if !fsck(&logger, &config, quarantine).await? {
+                        process::exit(1);
+                    }
+                }
+                Command::Backup { name } => backup(&logger, &config, &name).await?,
+                Command::Restore { path } => restore(&logger, &config, &path).await?,
+                Command::Export {
+                    schema,
+                    collection,
+                    format,
+                    output,
+                } => {
+                    export(
+                        &logger,
+                        &config,
+                        &schema,
+                        collection.as_deref(),
+                        &format,
+                        output.as_deref(),
+                    )
+                    .await?
+                }
+                Command::Import {
+                    schema,
+                    collection,
+                    format,
+                    input,
+                } => {
+                    import(
+                        &logger,
+                        &config,
+                        &schema,
+                        collection.as_deref(),
+                        &format,
+                        &input,
+                    )
+                    .await?
+                }
+                Command::RotateKey {
+                    new_key_file,
+                    new_key_env,
+                } => {
+                    rotate_key(
+                        &logger,
+                        &config,
+                        new_key_file.as_deref(),
+                        new_key_env.as_deref(),
+                    )
+                    .await?
+                }
+                Command::UpgradeData => upgrade_data(&logger, &config).await?,
+            }
         }
         Err(err) => {
             error!(temp_log, "Failed to load config"; "error" => format!("{}", err));
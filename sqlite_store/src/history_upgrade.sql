@@ -0,0 +1,7 @@
+-- Databases written before revisions were unique could hold the same
+-- revision twice, the copies are dropped before the unique index is created
+DELETE FROM history WHERE rowid NOT IN (
+    SELECT MIN(rowid) FROM history GROUP BY collection_id, document_id, revision
+);
+
+DROP INDEX IF EXISTS history_by_document;
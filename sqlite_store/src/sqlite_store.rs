@@ -0,0 +1,846 @@
+use failure::Error;
+use futures::{
+    future::BoxFuture,
+    lock::Mutex,
+    FutureExt,
+};
+use rusqlite::{
+    params,
+    Connection,
+    ToSql,
+    NO_PARAMS,
+};
+use serde::de::DeserializeOwned;
+use shelf_config::{
+    Config,
+    WalFsync,
+};
+use shelf_database::{
+    Collection,
+    CompactionReport,
+    DeadLetter,
+    Document,
+    DocumentDelta,
+    DocumentVersion,
+    Schema,
+    Store,
+    Transaction,
+    Webhook,
+};
+use slog::Logger;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::create_dir_all,
+    path::Path,
+    sync::{
+        Arc,
+        Mutex as SyncMutex,
+    },
+    time::Instant,
+};
+use tokio::task;
+use uuid::Uuid;
+
+/// The name of the database file in the data folder
+pub const DATABASE_FILE: &str = "shelf.sqlite";
+const SETUP: &str = include_str!("setup.sql");
+const HISTORY_UPGRADE: &str = include_str!("history_upgrade.sql");
+
+/// Document changes for a collection, `None` marks a deleted document
+type Changes = HashMap<Uuid, Option<Arc<Document>>>;
+
+struct Inner {
+    connection: Connection,
+    /// The write-ahead log segment new transactions are appended to
+    segment: i64,
+}
+
+/// Collections and documents handed over since the last flush, they are all
+/// written in one transaction
+#[derive(Clone, Default)]
+struct Pending {
+    collections: Vec<(Uuid, Collection)>,
+    documents: HashMap<Uuid, Changes>,
+}
+
+/// Keeps everything in a single SQLite database in the data folder. Every
+/// row holds the same json the file store writes, so the data can be looked
+/// at with the SQLite json functions
+pub struct SqliteStore {
+    inner: Arc<SyncMutex<Inner>>,
+    pending: Mutex<Pending>,
+}
+
+impl SqliteStore {
+    /// Opens the database, creating it and its tables if needed
+    ///
+    /// # Errors
+    /// Might return an error if the data folder can not be created or the
+    /// database can not be opened
+    pub async fn new(logger: &Logger, config: &Config) -> Result<Self, Error> {
+        let data_folder = Path::new(&config.data_folder);
+        if !data_folder.is_dir() {
+            create_dir_all(data_folder)?;
+        }
+        let path = data_folder.join(DATABASE_FILE);
+
+        info!(logger, "Setting up SQLite store"; "path" => path.display().to_string());
+
+        // An interval fsync trades the last writes for speed, which is what
+        // the normal synchronous mode does in SQLite
+        let synchronous = match config.wal_fsync {
+            WalFsync::Always | WalFsync::Batched => "FULL",
+            WalFsync::Interval => "NORMAL",
+        };
+
+        let inner = task::spawn_blocking(move || {
+            let connection = Connection::open(path)?;
+            connection.execute_batch(&format!(
+                "PRAGMA journal_mode = WAL; PRAGMA synchronous = {};",
+                synchronous
+            ))?;
+            let has_old_history_index: bool = connection.query_row(
+                "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'index' AND name = 'history_by_document'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )?;
+            if has_old_history_index {
+                connection.execute_batch(HISTORY_UPGRADE)?;
+            }
+            connection.execute_batch(SETUP)?;
+
+            let segment = connection.query_row(
+                "SELECT COALESCE(MAX(segment), 0) + 1 FROM wal",
+                NO_PARAMS,
+                |row| row.get(0),
+            )?;
+
+            Result::<_, Error>::Ok(Inner {
+                connection,
+                segment,
+            })
+        })
+        .await??;
+
+        Ok(Self {
+            inner: Arc::new(SyncMutex::new(inner)),
+            pending: Mutex::new(Pending::default()),
+        })
+    }
+
+    /// Runs `f` with the connection on a thread where it is fine to block
+    async fn run<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Inner) -> Result<T, Error> + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        task::spawn_blocking(move || match inner.lock() {
+            Ok(mut inner) => f(&mut inner),
+            Err(_) => bail!("The SQLite connection was poisoned by an earlier panic"),
+        })
+        .await?
+    }
+}
+
+/// Reads the first column of every row as json
+fn query_json<T: DeserializeOwned>(
+    connection: &Connection,
+    sql: &str,
+    params: &[&dyn ToSql],
+) -> Result<Vec<T>, Error> {
+    let mut statement = connection.prepare(sql)?;
+    let rows = statement.query_map(params, |row| row.get::<_, String>(0))?;
+
+    let mut values = vec![];
+    for row in rows {
+        values.push(serde_json::from_str(&row?)?);
+    }
+    Ok(values)
+}
+
+impl Store for SqliteStore {
+    fn get_schemas<'a>(
+        &'a self,
+        logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<HashMap<Uuid, Schema>, Error>> {
+        async move {
+            let schemas: Vec<Schema> = self
+                .run(|inner| query_json(&inner.connection, "SELECT data FROM schemas", &[]))
+                .await?;
+            debug!(logger, "Fetched {} schemas from SQLite", schemas.len());
+
+            Ok(schemas.into_iter().map(|i| (i.id, i)).collect())
+        }
+        .boxed()
+    }
+
+    fn get_collections<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        schema: &'a Schema,
+    ) -> BoxFuture<'a, Result<Vec<Collection>, Error>> {
+        let schema_id = schema.id.to_string();
+        self.run(move |inner| {
+            query_json(
+                &inner.connection,
+                "SELECT data FROM collections WHERE schema_id = ?",
+                &[&schema_id],
+            )
+        })
+        .boxed()
+    }
+
+    fn get_documents<'a>(
+        &'a self,
+        logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<Document>, Error>> {
+        async move {
+            let collection_id = collection.id.to_string();
+            let documents: Vec<Document> = self
+                .run(move |inner| {
+                    query_json(
+                        &inner.connection,
+                        "SELECT data FROM documents WHERE collection_id = ?",
+                        &[&collection_id],
+                    )
+                })
+                .await?;
+
+            if documents.is_empty() {
+                warn!(logger, "No documents found"; "collection_name" => &collection.name);
+            }
+            Ok(documents)
+        }
+        .boxed()
+    }
+
+    fn save_schema<'a>(
+        &'a self,
+        logger: &'a Logger,
+        schema: &'a Schema,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let id = schema.id.to_string();
+            let data = serde_json::to_string(schema)?;
+            self.run(move |inner| {
+                inner.connection.execute(
+                    "INSERT OR REPLACE INTO schemas (id, data) VALUES (?, ?)",
+                    params![id, data],
+                )?;
+                Ok(())
+            })
+            .await?;
+
+            debug!(logger, "Saved schema \"{}\"", schema.name);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_collection<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            self.pending
+                .lock()
+                .await
+                .collections
+                .push((schema.id, collection.clone()));
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_delta<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+        delta: DocumentDelta,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let mut pending = self.pending.lock().await;
+            let changes = pending.documents.entry(collection.id).or_default();
+
+            for document in delta.upserted {
+                changes.insert(document.id, Some(document));
+            }
+            for id in delta.deleted {
+                changes.insert(id, None);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn flush<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let start_time = Instant::now();
+            // The changes are kept until they are written, so that a failed
+            // flush is retried by the next one
+            let mut pending = self.pending.lock().await;
+            let written = pending.clone();
+            let count: usize = written.documents.values().map(HashMap::len).sum();
+
+            self.run(move |inner| {
+                // Either everything handed over since the last flush is
+                // written, or nothing is
+                let transaction = inner.connection.transaction()?;
+                for (schema_id, collection) in written.collections {
+                    transaction.execute(
+                        "INSERT OR REPLACE INTO collections (id, schema_id, data) VALUES (?, ?, ?)",
+                        params![
+                            collection.id.to_string(),
+                            schema_id.to_string(),
+                            serde_json::to_string(&collection)?
+                        ],
+                    )?;
+                }
+                for (collection_id, changes) in written.documents {
+                    for (id, change) in changes {
+                        match change {
+                            Some(document) => transaction.execute(
+                                "INSERT OR REPLACE INTO documents (collection_id, id, data) VALUES (?, ?, ?)",
+                                params![
+                                    collection_id.to_string(),
+                                    id.to_string(),
+                                    serde_json::to_string(&document)?
+                                ],
+                            )?,
+                            None => transaction.execute(
+                                "DELETE FROM documents WHERE collection_id = ? AND id = ?",
+                                params![collection_id.to_string(), id.to_string()],
+                            )?,
+                        };
+                    }
+                }
+                transaction.commit()?;
+                Ok(())
+            })
+            .await?;
+            *pending = Pending::default();
+
+            info!(logger, "\u{1f4bf} Saved data to SQLite"; "documents" => count, "save_time" => format!("{:#?}", Instant::now().duration_since(start_time)));
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get_webhooks<'a>(
+        &'a self,
+        _logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<Vec<Webhook>, Error>> {
+        self.run(|inner| query_json(&inner.connection, "SELECT data FROM webhooks", &[]))
+            .boxed()
+    }
+
+    fn save_webhooks<'a>(
+        &'a self,
+        logger: &'a Logger,
+        webhooks: &'a [Webhook],
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let mut rows = vec![];
+            for webhook in webhooks {
+                rows.push((webhook.id.to_string(), serde_json::to_string(webhook)?));
+            }
+
+            self.run(move |inner| {
+                let transaction = inner.connection.transaction()?;
+                transaction.execute("DELETE FROM webhooks", NO_PARAMS)?;
+                for (id, data) in rows {
+                    transaction.execute(
+                        "INSERT INTO webhooks (id, data) VALUES (?, ?)",
+                        params![id, data],
+                    )?;
+                }
+                transaction.commit()?;
+                Ok(())
+            })
+            .await?;
+
+            debug!(logger, "Saved {} webhooks", webhooks.len());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_dead_letter<'a>(
+        &'a self,
+        logger: &'a Logger,
+        dead_letter: &'a DeadLetter,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let data = serde_json::to_string(dead_letter)?;
+            self.run(move |inner| {
+                inner
+                    .connection
+                    .execute("INSERT INTO dead_letters (data) VALUES (?)", params![data])?;
+                Ok(())
+            })
+            .await?;
+
+            debug!(logger, "Wrote dead letter to SQLite"; "webhook_id" => dead_letter.webhook_id.to_string(), "delivery_id" => dead_letter.delivery_id.to_string());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn save_history<'a>(
+        &'a self,
+        logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+        versions: &'a [DocumentVersion],
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let collection_id = collection.id.to_string();
+            let mut rows = vec![];
+            for version in versions {
+                rows.push((
+                    version.document_id.to_string(),
+                    i64::try_from(version.revision)?,
+                    serde_json::to_string(version)?,
+                ));
+            }
+
+            self.run(move |inner| {
+                let transaction = inner.connection.transaction()?;
+                for (document_id, revision, data) in rows {
+                    transaction.execute(
+                        "INSERT OR IGNORE INTO history (collection_id, document_id, revision, data) VALUES (?, ?, ?, ?)",
+                        params![collection_id, document_id, revision, data],
+                    )?;
+                }
+                transaction.commit()?;
+                Ok(())
+            })
+            .await?;
+
+            trace!(logger, "Wrote {} versions to history", versions.len(); "collection_name" => &collection.name);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn get_history<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+        id: Uuid,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>> {
+        let collection_id = collection.id.to_string();
+        let document_id = id.to_string();
+        self.run(move |inner| {
+            query_json(
+                &inner.connection,
+                "SELECT data FROM history WHERE collection_id = ? AND document_id = ? ORDER BY revision",
+                &[&collection_id, &document_id],
+            )
+        })
+        .boxed()
+    }
+
+    fn get_collection_history<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        _schema: &'a Schema,
+        collection: &'a Collection,
+    ) -> BoxFuture<'a, Result<Vec<DocumentVersion>, Error>> {
+        let collection_id = collection.id.to_string();
+        self.run(move |inner| {
+            query_json(
+                &inner.connection,
+                "SELECT data FROM history WHERE collection_id = ? ORDER BY document_id, revision",
+                &[&collection_id],
+            )
+        })
+        .boxed()
+    }
+
+    fn append_wal<'a>(
+        &'a self,
+        _logger: &'a Logger,
+        transaction: &'a Transaction,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let data = serde_json::to_string(transaction)?;
+            self.run(move |inner| {
+                inner.connection.execute(
+                    "INSERT INTO wal (segment, data) VALUES (?, ?)",
+                    params![inner.segment, data],
+                )?;
+                Ok(())
+            })
+            .await
+        }
+        .boxed()
+    }
+
+    fn read_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+    ) -> BoxFuture<'a, Result<Vec<Transaction>, Error>> {
+        async move {
+            let rows: Vec<String> = self
+                .run(|inner| {
+                    let mut statement = inner
+                        .connection
+                        .prepare("SELECT data FROM wal ORDER BY id")?;
+                    let rows = statement.query_map(NO_PARAMS, |row| row.get::<_, String>(0))?;
+                    Ok(rows.collect::<Result<_, _>>()?)
+                })
+                .await?;
+
+            let mut transactions = vec![];
+            for row in rows {
+                match serde_json::from_str::<Transaction>(&row) {
+                    Ok(transaction) => transactions.push(transaction),
+                    Err(e) => {
+                        warn!(logger, "Skipping unreadable write-ahead log entry"; "error" => format!("{}", e));
+                    }
+                }
+            }
+            Ok(transactions)
+        }
+        .boxed()
+    }
+
+    fn rotate_wal<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<u64, Error>> {
+        async move {
+            let closed = self
+                .run(|inner| {
+                    let closed = inner.segment;
+                    inner.segment += 1;
+                    Ok(closed)
+                })
+                .await?;
+
+            debug!(logger, "Rotated write-ahead log"; "closed_segment" => closed);
+            Ok(u64::try_from(closed)?)
+        }
+        .boxed()
+    }
+
+    fn truncate_wal<'a>(
+        &'a self,
+        logger: &'a Logger,
+        segment: u64,
+    ) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let segment = i64::try_from(segment)?;
+            let removed = self
+                .run(move |inner| {
+                    Ok(inner
+                        .connection
+                        .execute("DELETE FROM wal WHERE segment <= ?", params![segment])?)
+                })
+                .await?;
+
+            trace!(logger, "Removed write-ahead log entries"; "segment" => segment, "removed" => removed);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn compact<'a>(&'a self, logger: &'a Logger) -> BoxFuture<'a, Result<CompactionReport, Error>> {
+        async move {
+            let start_time = Instant::now();
+            let collections: i64 = self
+                .run(|inner| {
+                    inner.connection.execute_batch("VACUUM")?;
+                    Ok(inner.connection.query_row(
+                        "SELECT COUNT(*) FROM collections",
+                        NO_PARAMS,
+                        |row| row.get(0),
+                    )?)
+                })
+                .await?;
+
+            info!(logger, "\u{1f9f9} Vacuumed SQLite database"; "compaction_time" => format!("{:#?}", Instant::now().duration_since(start_time)));
+
+            // Everything is in one file, however much is vacuumed
+            Ok(CompactionReport {
+                collections: usize::try_from(collections)?,
+                files_before: 1,
+                files_after: 1,
+            })
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        sqlite_store::DATABASE_FILE,
+        SqliteStore,
+    };
+    use chrono::Utc;
+    use rusqlite::{
+        Connection,
+        NO_PARAMS,
+    };
+    use shelf_config::Config;
+    use shelf_database::{
+        test::StoreFixture,
+        Collection,
+        Document,
+        DocumentDelta,
+        DocumentVersion,
+        Schema,
+        Store,
+        Transaction,
+    };
+    use sloggers::{
+        null::NullLoggerBuilder,
+        Build,
+    };
+    use std::{
+        collections::HashMap,
+        ops::Deref,
+        path::Path,
+        sync::Arc,
+    };
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    /// The database file is kept until the test ends, so that it can be
+    /// opened again
+    struct Setup {
+        fixture: StoreFixture,
+        config: Config,
+        _dir: TempDir,
+    }
+
+    impl Setup {
+        async fn new() -> (Self, SqliteStore) {
+            let dir = TempDir::new().unwrap();
+            let setup = Self {
+                fixture: StoreFixture::new(),
+                config: Config {
+                    data_folder: dir.path().to_string_lossy().to_string(),
+                    ..Config::default()
+                },
+                _dir: dir,
+            };
+
+            let store = setup.reopen().await;
+            setup.create(&store).await;
+            (setup, store)
+        }
+
+        async fn reopen(&self) -> SqliteStore {
+            SqliteStore::new(&self.logger, &self.config).await.unwrap()
+        }
+
+        /// A second connection to the database, to break it behind the back
+        /// of the store
+        fn connection(&self) -> Connection {
+            Connection::open(Path::new(&self.config.data_folder).join(DATABASE_FILE)).unwrap()
+        }
+    }
+
+    impl Deref for Setup {
+        type Target = StoreFixture;
+
+        fn deref(&self) -> &StoreFixture {
+            &self.fixture
+        }
+    }
+
+    fn document() -> Arc<Document> {
+        Arc::new(Document::new(Uuid::new_v4(), HashMap::new()))
+    }
+
+    #[tokio::test]
+    async fn documents_should_be_read_back_after_a_flush() {
+        let logger = NullLoggerBuilder.build().unwrap();
+        let dir = TempDir::new().unwrap();
+        let config = Config {
+            data_folder: dir.path().to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        let store = SqliteStore::new(&logger, &config).await.unwrap();
+
+        let schema = Schema::new(Uuid::new_v4(), "shelf", None);
+        let collection = Collection::new("Car".to_string(), None);
+        let kept = Arc::new(Document::new(Uuid::new_v4(), HashMap::new()));
+        let deleted = Arc::new(Document::new(Uuid::new_v4(), HashMap::new()));
+
+        store.save_schema(&logger, &schema).await.unwrap();
+        store
+            .save_collection(&logger, &schema, &collection)
+            .await
+            .unwrap();
+        let delta = DocumentDelta {
+            upserted: vec![Arc::clone(&kept), Arc::clone(&deleted)],
+            deleted: vec![],
+        };
+        store
+            .save_delta(&logger, &schema, &collection, delta)
+            .await
+            .unwrap();
+        store.flush(&logger).await.unwrap();
+
+        let delta = DocumentDelta {
+            upserted: vec![],
+            deleted: vec![deleted.id],
+        };
+        store
+            .save_delta(&logger, &schema, &collection, delta)
+            .await
+            .unwrap();
+        store.flush(&logger).await.unwrap();
+
+        assert!(store
+            .get_schemas(&logger)
+            .await
+            .unwrap()
+            .contains_key(&schema.id));
+        assert_eq!(
+            store.get_collections(&logger, &schema).await.unwrap()[0].id,
+            collection.id
+        );
+        let documents = store
+            .get_documents(&logger, &schema, &collection)
+            .await
+            .unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].id, kept.id);
+
+        store
+            .append_wal(&logger, &Transaction::new())
+            .await
+            .unwrap();
+        let closed = store.rotate_wal(&logger).await.unwrap();
+        store
+            .append_wal(&logger, &Transaction::new())
+            .await
+            .unwrap();
+        store.truncate_wal(&logger, closed).await.unwrap();
+        assert_eq!(store.read_wal(&logger).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn documents_should_be_read_back_after_a_reopen() {
+        let (setup, store) = Setup::new().await;
+        setup
+            .save(&store, vec![document(), document()], vec![])
+            .await;
+        store.flush(&setup.logger).await.unwrap();
+        drop(store);
+
+        let store = setup.reopen().await;
+        assert!(store
+            .get_schemas(&setup.logger)
+            .await
+            .unwrap()
+            .contains_key(&setup.schema.id));
+        assert_eq!(setup.documents(&store).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn the_wal_should_be_replayed_after_a_reopen() {
+        let (setup, store) = Setup::new().await;
+        let first = Transaction::new();
+        let second = Transaction::new();
+        store.append_wal(&setup.logger, &first).await.unwrap();
+        store.rotate_wal(&setup.logger).await.unwrap();
+        store.append_wal(&setup.logger, &second).await.unwrap();
+        drop(store);
+
+        let store = setup.reopen().await;
+        let ids: Vec<_> = store
+            .read_wal(&setup.logger)
+            .await
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(ids, vec![first.id, second.id]);
+
+        // New entries go to a segment after the ones already in the log, so
+        // truncating what was replayed keeps them
+        let closed = store.rotate_wal(&setup.logger).await.unwrap();
+        let third = Transaction::new();
+        store.append_wal(&setup.logger, &third).await.unwrap();
+        store.truncate_wal(&setup.logger, closed).await.unwrap();
+        let ids: Vec<_> = store
+            .read_wal(&setup.logger)
+            .await
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(ids, vec![third.id]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_flush_should_be_retried_by_the_next() {
+        let (setup, store) = Setup::new().await;
+        setup.save(&store, vec![document()], vec![]).await;
+
+        let connection = setup.connection();
+        connection
+            .execute_batch("ALTER TABLE documents RENAME TO documents_away")
+            .unwrap();
+        assert!(store.flush(&setup.logger).await.is_err());
+
+        connection
+            .execute_batch("ALTER TABLE documents_away RENAME TO documents")
+            .unwrap();
+        setup.save(&store, vec![document()], vec![]).await;
+        store.flush(&setup.logger).await.unwrap();
+
+        assert_eq!(setup.documents(&store).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn saving_the_same_history_twice_should_keep_one_copy() {
+        let (setup, store) = Setup::new().await;
+        let document = document();
+        let versions = vec![
+            DocumentVersion {
+                document_id: document.id,
+                revision: 1,
+                timestamp: Utc::now(),
+                document: Some(Document::clone(&document)),
+            },
+            DocumentVersion {
+                document_id: document.id,
+                revision: 2,
+                timestamp: Utc::now(),
+                document: None,
+            },
+        ];
+
+        for _ in 0..2 {
+            store
+                .save_history(&setup.logger, &setup.schema, &setup.collection, &versions)
+                .await
+                .unwrap();
+        }
+
+        let history = store
+            .get_history(&setup.logger, &setup.schema, &setup.collection, document.id)
+            .await
+            .unwrap();
+        let revisions: Vec<_> = history.iter().map(|i| i.revision).collect();
+        assert_eq!(revisions, vec![1, 2]);
+
+        let count: i64 = setup
+            .connection()
+            .query_row("SELECT COUNT(*) FROM history", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+}